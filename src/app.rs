@@ -1,5 +1,6 @@
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use log::info;
 use rand::rngs::OsRng;
@@ -9,6 +10,7 @@ use tokio::task::spawn_blocking;
 
 use crate::app::legacy::*;
 use crate::paths::Paths;
+use crate::sonos;
 
 pub mod auth;
 pub mod config;
@@ -16,11 +18,15 @@ pub mod ddns;
 pub mod formats;
 pub mod index;
 pub mod legacy;
+pub mod library;
 pub mod ndb;
 pub mod peaks;
 pub mod playlist;
+pub mod resume;
 pub mod scanner;
+pub mod sonos_history;
 pub mod thumbnail;
+pub mod url;
 
 #[cfg(test)]
 pub mod test;
@@ -93,6 +99,28 @@ pub enum Error {
 	IndexAlbumArtPatternInvalid,
 	#[error("DDNS update URL is invalid")]
 	DDNSUpdateURLInvalid,
+	#[error("Sonos API URL must be an http(s) URL: `{0}`")]
+	SonosApiURLInvalid(String),
+	#[error("Sonos MP3 server must be a `host/share` path: `{0}`")]
+	SonosMp3ServerInvalid(String),
+	#[error("Polaris URL Sonos speakers stream from must be an http(s) URL: `{0}`")]
+	SonosStreamURLInvalid(String),
+	#[error("Sonos share mapping for `{0}` does not name a mount")]
+	SonosShareMappingInvalid(String),
+	#[error("Sonos authentication header is invalid: `{0}`")]
+	SonosAuthHeaderInvalid(String),
+	#[error("Sonos CA certificate is not a valid PEM file: `{0}`")]
+	SonosCACertificateInvalid(PathBuf),
+	#[error("Default volume for Sonos speaker `{0}` must be between 0 and 100, got {1}")]
+	SonosDefaultVolumeInvalid(String, u8),
+	#[error("Sonos scene name cannot be empty")]
+	SonosSceneNameEmpty,
+	#[error("Volume for Sonos speaker `{1}` in scene `{0}` must be between 0 and 100, got {2}")]
+	SonosSceneVolumeInvalid(String, String, u8),
+	#[error("Sonos scene not found: `{0}`")]
+	SonosSceneNotFound(String),
+	#[error("Control URL of DLNA renderer `{0}` must be an http(s) URL: `{1}`")]
+	DlnaControlURLInvalid(String, String),
 
 	#[error("Could not deserialize configuration: `{0}`")]
 	ConfigDeserialization(toml::de::Error),
@@ -125,6 +153,8 @@ pub enum Error {
 	SearchQueryParseError,
 	#[error("Playlist not found")]
 	PlaylistNotFound,
+	#[error("Nothing to resume on this speaker")]
+	ResumePointNotFound,
 	#[error("No embedded artwork was found in `{0}`")]
 	EmbeddedArtworkNotFound(PathBuf),
 
@@ -160,6 +190,9 @@ pub struct App {
 	pub config_manager: config::Manager,
 	pub peaks_manager: peaks::Manager,
 	pub playlist_manager: playlist::Manager,
+	pub resume_manager: resume::Manager,
+	pub sonos_history_manager: sonos_history::Manager,
+	pub sonos_manager: sonos::Manager,
 	pub thumbnail_manager: thumbnail::Manager,
 }
 
@@ -181,14 +214,26 @@ impl App {
 		let auth_secret_file_path = paths.data_dir_path.join("auth.secret");
 		Self::migrate_legacy_auth_secret(&paths.db_file_path, &auth_secret_file_path).await?;
 		let auth_secret = Self::get_or_create_auth_secret(&auth_secret_file_path).await?;
+		let sonos_stream_secret_file_path = paths.data_dir_path.join("sonos_stream.secret");
+		let sonos_stream_secret =
+			Self::get_or_create_auth_secret(&sonos_stream_secret_file_path).await?;
 
-		let config_manager = config::Manager::new(&paths.config_file_path, auth_secret).await?;
+		let config_manager =
+			config::Manager::new(&paths.config_file_path, auth_secret, sonos_stream_secret).await?;
 		let ddns_manager = ddns::Manager::new(config_manager.clone());
 		let ndb_manager = ndb::Manager::new(&paths.data_dir_path)?;
 		let index_manager = index::Manager::new(&paths.data_dir_path).await?;
 		let scanner = scanner::Scanner::new(index_manager.clone(), config_manager.clone()).await?;
 		let peaks_manager = peaks::Manager::new(peaks_dir_path);
-		let playlist_manager = playlist::Manager::new(ndb_manager);
+		let playlist_manager = playlist::Manager::new(ndb_manager.clone());
+		let resume_manager = resume::Manager::new(ndb_manager.clone());
+		let sonos_history_manager = sonos_history::Manager::new(ndb_manager);
+		let http_client = reqwest::Client::new();
+		let sonos_manager = sonos::Manager::new(
+			config_manager.clone(),
+			Arc::new(index_manager.clone()),
+			http_client,
+		);
 		let thumbnail_manager = thumbnail::Manager::new(thumbnails_dir_path);
 
 		let app = Self {
@@ -200,6 +245,9 @@ impl App {
 			config_manager,
 			peaks_manager,
 			playlist_manager,
+			resume_manager,
+			sonos_history_manager,
+			sonos_manager,
 			thumbnail_manager,
 		};
 
@@ -1,4 +1,5 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use pbkdf2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
 use pbkdf2::Pbkdf2;
@@ -8,7 +9,7 @@ use serde::{Deserialize, Serialize};
 
 use crate::app::Error;
 
-#[derive(Clone, Default)]
+#[derive(Clone, Default, PartialEq, Eq)]
 pub struct Secret(pub [u8; 32]);
 
 impl AsRef<[u8]> for Secret {
@@ -29,6 +30,8 @@ pub struct Token(pub String);
 #[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub enum Scope {
 	PolarisAuth,
+	/// Lets Sonos speakers stream one audio file from Polaris, and nothing else
+	SonosStream,
 }
 
 #[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
@@ -75,6 +78,69 @@ pub fn generate_auth_token(
 	.map(Token)
 }
 
+/// Signs the URLs Sonos speakers stream audio files from. Each signature is only valid for one file. Its secret
+/// is kept next to the authentication secret, so URLs saved in Sonos playlists and favorites survive restarts.
+#[derive(Clone, PartialEq, Eq)]
+pub struct StreamSigner(Secret);
+
+impl std::fmt::Debug for StreamSigner {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "StreamSigner(***)")
+	}
+}
+
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize)]
+struct StreamGrant {
+	path: String,
+	scope: Scope,
+}
+
+impl StreamSigner {
+	pub fn new(secret: Secret) -> Self {
+		Self(secret)
+	}
+
+	/// Token granting access to the audio file at `path` (within the collection)
+	pub fn sign(&self, path: &str) -> Result<Token, Error> {
+		self.sign_at(
+			path,
+			SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.unwrap_or_default()
+				.as_secs() as u32,
+		)
+	}
+
+	fn sign_at(&self, path: &str, timestamp: u32) -> Result<Token, Error> {
+		let grant = StreamGrant {
+			path: path.to_owned(),
+			scope: Scope::SonosStream,
+		};
+		let serialized_grant =
+			serde_json::to_string(&grant).or(Err(Error::AuthorizationTokenEncoding))?;
+		branca::encode(serialized_grant.as_bytes(), self.0.as_ref(), timestamp)
+			.or(Err(Error::BrancaTokenEncoding))
+			.map(Token)
+	}
+
+	/// Checks that `token` grants access to `path` and, if there is a `ttl`, was signed less than `ttl` ago
+	pub fn verify(&self, token: &Token, path: &Path, ttl: Option<Duration>) -> Result<(), Error> {
+		let Token(data) = token;
+		let ttl = ttl.map(|t| t.as_secs().clamp(1, u32::MAX as u64) as u32);
+		let grant = branca::decode(data, self.0.as_ref(), ttl.unwrap_or(0))
+			.map_err(|_| Error::InvalidAuthToken)?;
+		let grant: StreamGrant =
+			serde_json::from_slice(&grant[..]).map_err(|_| Error::InvalidAuthToken)?;
+		if grant.scope != Scope::SonosStream {
+			return Err(Error::IncorrectAuthorizationScope);
+		}
+		if Path::new(&grant.path) != path {
+			return Err(Error::InvalidAuthToken);
+		}
+		Ok(())
+	}
+}
+
 pub fn decode_auth_token(
 	auth_token: &Token,
 	scope: Scope,
@@ -83,6 +149,7 @@ pub fn decode_auth_token(
 	let Token(data) = auth_token;
 	let ttl = match scope {
 		Scope::PolarisAuth => 0, // permanent
+		Scope::SonosStream => 0, // checked by `StreamSigner::verify`
 	};
 	let authorization =
 		branca::decode(data, auth_secret.as_ref(), ttl).map_err(|_| Error::InvalidAuthToken)?;
@@ -93,3 +160,48 @@ pub fn decode_auth_token(
 	}
 	Ok(authorization)
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn stream_tokens_only_grant_one_file() {
+		let signer = StreamSigner::new(Secret([1; 32]));
+		let token = signer.sign("root/Beatles/Help/Yesterday.mp3").unwrap();
+		assert!(signer
+			.verify(&token, Path::new("root/Beatles/Help/Yesterday.mp3"), None)
+			.is_ok());
+		assert!(matches!(
+			signer.verify(&token, Path::new("root/Beatles/Help/Help!.mp3"), None),
+			Err(Error::InvalidAuthToken)
+		));
+		assert!(matches!(
+			StreamSigner::new(Secret([2; 32])).verify(
+				&token,
+				Path::new("root/Beatles/Help/Yesterday.mp3"),
+				None
+			),
+			Err(Error::InvalidAuthToken)
+		));
+	}
+
+	#[test]
+	fn stream_tokens_expire_after_ttl() {
+		let signer = StreamSigner::new(Secret([1; 32]));
+		let ttl = Duration::from_secs(24 * 60 * 60);
+		let now = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.unwrap()
+			.as_secs() as u32;
+		let expired = now - ttl.as_secs() as u32 - 60;
+		let token = signer.sign_at("root/song.mp3", expired).unwrap();
+		assert!(matches!(
+			signer.verify(&token, Path::new("root/song.mp3"), Some(ttl)),
+			Err(Error::InvalidAuthToken)
+		));
+		assert!(signer
+			.verify(&token, Path::new("root/song.mp3"), None)
+			.is_ok());
+	}
+}
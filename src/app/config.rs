@@ -1,10 +1,13 @@
 use std::{
 	path::{Path, PathBuf},
-	sync::Arc,
+	sync::{
+		atomic::{AtomicBool, Ordering},
+		Arc,
+	},
 	time::Duration,
 };
 
-use log::{error, info};
+use log::{error, info, warn};
 use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 use notify_debouncer_full::{Debouncer, FileIdMap};
 use regex::Regex;
@@ -13,21 +16,25 @@ use tokio::sync::{futures::Notified, Notify, RwLock};
 use crate::app::Error;
 
 mod mounts;
-pub mod storage;
 mod sonos;
+pub mod storage;
 mod user;
 
 pub use mounts::*;
+pub use sonos::*;
 pub use user::*;
 
 use super::auth;
 
+/// How long in-flight requests can take to complete when the server shuts down
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[derive(Debug, Clone, Default)]
 pub struct Config {
 	pub album_art_pattern: Option<Regex>,
 	pub ddns_update_url: Option<http::Uri>,
-	pub sonos_api_url: Option<String>,
-	pub sonos_mp3_server: Option<String>,
+	pub shutdown_timeout_secs: Option<u64>,
+	pub sonos: SonosConfig,
 	pub mount_dirs: Vec<MountDir>,
 	pub users: Vec<User>,
 }
@@ -52,8 +59,17 @@ impl TryFrom<storage::Config> for Config {
 			None => None,
 		};
 
-		config.sonos_api_url = c.sonos_api_url;
-		config.sonos_mp3_server = c.sonos_mp3_server;
+		config.shutdown_timeout_secs = c.shutdown_timeout_secs;
+
+		let mut sonos = c.sonos.unwrap_or_default();
+		sonos.api_url = sonos.api_url.or(c.sonos_api_url);
+		sonos.mp3_server = sonos.mp3_server.or(c.sonos_mp3_server);
+		sonos.load_api_token();
+		sonos.load_api_password();
+		if sonos.legacy_password.take().is_some() {
+			warn!("The Sonos `password` setting is no longer read from the config file and will be removed from it. Set the {SONOS_API_PASSWORD_ENV_VAR} environment variable instead.");
+		}
+		config.sonos = sonos;
 
 		Ok(config)
 	}
@@ -65,8 +81,16 @@ impl From<Config> for storage::Config {
 			album_art_pattern: c.album_art_pattern.map(|p| p.as_str().to_owned()),
 			mount_dirs: c.mount_dirs.into_iter().map(|d| d.into()).collect(),
 			ddns_update_url: c.ddns_update_url.map(|u| u.to_string()),
-			sonos_api_url: c.sonos_api_url,
-			sonos_mp3_server: c.sonos_mp3_server,
+			shutdown_timeout_secs: c.shutdown_timeout_secs,
+			sonos_api_url: None,
+			sonos_mp3_server: None,
+			sonos: Some(SonosConfig {
+				api_token: None,
+				api_password: None,
+				stream_signer: None,
+				..c.sonos
+			})
+			.filter(|s| *s != SonosConfig::default()),
 			users: c.users.into_iter().map(|u| u.into()).collect(),
 		}
 	}
@@ -77,13 +101,20 @@ pub struct Manager {
 	config_file_path: PathBuf,
 	config: Arc<RwLock<Config>>,
 	auth_secret: auth::Secret,
+	/// Signs the URLs Sonos speakers stream tracks from
+	sonos_stream_signer: auth::StreamSigner,
 	#[allow(dead_code)]
 	file_watcher: Arc<Debouncer<RecommendedWatcher, FileIdMap>>,
 	change_notify: Arc<Notify>,
+	warned_about_example_api_url: Arc<AtomicBool>,
 }
 
 impl Manager {
-	pub async fn new(config_file_path: &Path, auth_secret: auth::Secret) -> Result<Self, Error> {
+	pub async fn new(
+		config_file_path: &Path,
+		auth_secret: auth::Secret,
+		sonos_stream_secret: auth::Secret,
+	) -> Result<Self, Error> {
 		if let Some(parent) = config_file_path.parent() {
 			tokio::fs::create_dir_all(parent)
 				.await
@@ -115,8 +146,10 @@ impl Manager {
 			config_file_path: config_file_path.to_owned(),
 			config: Arc::new(RwLock::new(Config::default())),
 			auth_secret,
+			sonos_stream_signer: auth::StreamSigner::new(sonos_stream_secret),
 			file_watcher: Arc::new(debouncer),
 			change_notify: Arc::default(),
+			warned_about_example_api_url: Arc::default(),
 		};
 
 		tokio::task::spawn({
@@ -144,7 +177,26 @@ impl Manager {
 
 	async fn reload_config(&self) -> Result<(), Error> {
 		let config = Self::read_config(&self.config_file_path).await?;
-		self.apply_config(config).await
+		self.apply_config(config).await?;
+		self.warn_about_example_api_url().await;
+		Ok(())
+	}
+
+	/// Warn once that the Sonos bridge URL is still the example from the documentation, as it is unlikely to be
+	/// the address of node-sonos-http-api on this network. Returns whether the warning was written.
+	async fn warn_about_example_api_url(&self) -> bool {
+		if !self.get_sonos_config().await.has_example_api_url()
+			|| self
+				.warned_about_example_api_url
+				.swap(true, Ordering::Relaxed)
+		{
+			return false;
+		}
+		warn!(
+			"The Sonos bridge URL is {EXAMPLE_SONOS_API_URL}, the example address from the documentation. Set `api_url` in the [sonos] section of {:#?} to the address of node-sonos-http-api on your network.",
+			self.config_file_path
+		);
+		true
 	}
 
 	async fn read_config(config_file_path: &Path) -> Result<storage::Config, Error> {
@@ -210,12 +262,70 @@ impl Manager {
 		self.config.read().await.ddns_update_url.clone()
 	}
 
-	pub async fn get_sonos_api_url(&self) -> Option<String> {
-		self.config.read().await.sonos_api_url.clone()
+	pub async fn get_shutdown_timeout(&self) -> Duration {
+		self.config
+			.read()
+			.await
+			.shutdown_timeout_secs
+			.map(Duration::from_secs)
+			.unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT)
+	}
+
+	pub async fn get_sonos_config(&self) -> SonosConfig {
+		SonosConfig {
+			stream_signer: Some(self.sonos_stream_signer.clone()),
+			..self.config.read().await.sonos.clone()
+		}
+	}
+
+	pub async fn set_sonos_config(&self, sonos: SonosConfig) -> Result<(), Error> {
+		self.mutate_fallible(|c| {
+			sonos.validate()?;
+			c.sonos = SonosConfig {
+				stream_signer: None,
+				..sonos
+			};
+			Ok(())
+		})
+		.await
 	}
 
-	pub async fn get_sonos_mp3_server(&self) -> Option<String> {
-		self.config.read().await.sonos_mp3_server.clone()
+	pub async fn get_sonos_scene(&self, name: &str) -> Result<SonosScene, Error> {
+		self.config
+			.read()
+			.await
+			.sonos
+			.get_scene(name)
+			.cloned()
+			.ok_or_else(|| Error::SonosSceneNotFound(name.to_owned()))
+	}
+
+	/// Add or replace a Sonos scene, provided the result is valid
+	pub async fn set_sonos_scene(&self, scene: SonosScene) -> Result<(), Error> {
+		self.mutate_fallible(|c| {
+			let mut sonos = c.sonos.clone();
+			sonos.set_scene(scene);
+			sonos.validate()?;
+			c.sonos = sonos;
+			Ok(())
+		})
+		.await
+	}
+
+	pub async fn delete_sonos_scene(&self, name: &str) -> Result<(), Error> {
+		self.mutate_fallible(|c| c.sonos.remove_scene(name)).await
+	}
+
+	/// Apply `patch` to the Sonos settings, provided the result is valid
+	pub async fn patch_sonos_config(&self, patch: SonosConfigPatch) -> Result<(), Error> {
+		self.mutate_fallible(|c| {
+			let mut sonos = c.sonos.clone();
+			sonos.apply_patch(patch);
+			sonos.validate()?;
+			c.sonos = sonos;
+			Ok(())
+		})
+		.await
 	}
 
 	pub async fn set_ddns_update_url(&self, url: Option<http::Uri>) -> Result<(), Error> {
@@ -257,6 +367,20 @@ impl Manager {
 			.await
 	}
 
+	pub async fn set_allow_sonos(&self, username: &str, allow_sonos: bool) -> Result<(), Error> {
+		self.mutate_fallible(|c| c.set_allow_sonos(username, allow_sonos))
+			.await
+	}
+
+	pub async fn set_allowed_speakers(
+		&self,
+		username: &str,
+		speakers: Option<Vec<String>>,
+	) -> Result<(), Error> {
+		self.mutate_fallible(|c| c.set_allowed_speakers(username, speakers))
+			.await
+	}
+
 	pub async fn set_password(&self, username: &str, password: &str) -> Result<(), Error> {
 		self.mutate_fallible(|c| c.set_password(username, password))
 			.await
@@ -271,6 +395,16 @@ impl Manager {
 		config.authenticate(auth_token, scope, &self.auth_secret)
 	}
 
+	/// Checks a token from the URL Sonos speakers stream the audio file at `path` from
+	pub async fn authenticate_sonos_stream(
+		&self,
+		auth_token: &auth::Token,
+		path: &Path,
+	) -> Result<(), Error> {
+		let ttl = self.config.read().await.sonos.get_stream_token_ttl();
+		self.sonos_stream_signer.verify(auth_token, path, ttl)
+	}
+
 	pub async fn delete_user(&self, username: &str) -> Result<(), Error> {
 		self.mutate(|c| c.delete_user(username)).await
 	}
@@ -303,7 +437,7 @@ mod test {
 	#[tokio::test]
 	async fn blank_config_round_trip() {
 		let config_path = PathBuf::from_iter(["test-data", "blank.toml"]);
-		let manager = Manager::new(&config_path, auth::Secret([0; 32]))
+		let manager = Manager::new(&config_path, auth::Secret([0; 32]), auth::Secret([1; 32]))
 			.await
 			.unwrap();
 		let config: storage::Config = manager.config.read().await.clone().into();
@@ -313,7 +447,7 @@ mod test {
 	#[tokio::test]
 	async fn can_read_config() {
 		let config_path = PathBuf::from_iter(["test-data", "config.toml"]);
-		let manager = Manager::new(&config_path, auth::Secret([0; 32]))
+		let manager = Manager::new(&config_path, auth::Secret([0; 32]), auth::Secret([1; 32]))
 			.await
 			.unwrap();
 		let config: storage::Config = manager.config.read().await.clone().into();
@@ -338,6 +472,86 @@ mod test {
 		assert!(config.users[0].hashed_password.is_some());
 	}
 
+	#[tokio::test]
+	async fn sonos_stream_token_only_grants_streaming() {
+		let ctx = test::ContextBuilder::new(test_name!()).build().await;
+		let signer = ctx.config_manager.get_sonos_config().await.stream_signer;
+		let token = signer.unwrap().sign("root/song.mp3").unwrap();
+		assert!(ctx
+			.config_manager
+			.authenticate_sonos_stream(&token, Path::new("root/song.mp3"))
+			.await
+			.is_ok());
+		assert!(ctx
+			.config_manager
+			.authenticate_sonos_stream(&token, Path::new("root/other.mp3"))
+			.await
+			.is_err());
+		assert!(ctx
+			.config_manager
+			.authenticate(&token, auth::Scope::PolarisAuth)
+			.await
+			.is_err());
+	}
+
+	#[tokio::test]
+	async fn sonos_stream_tokens_survive_restarts() {
+		let ctx = test::ContextBuilder::new(test_name!()).build().await;
+		let signer = ctx.config_manager.get_sonos_config().await.stream_signer;
+		let token = signer.unwrap().sign("root/song.mp3").unwrap();
+
+		let manager = Manager::new(
+			&ctx.config_manager.config_file_path,
+			auth::Secret([0; 32]),
+			auth::Secret([1; 32]),
+		)
+		.await
+		.unwrap();
+		assert!(manager
+			.authenticate_sonos_stream(&token, Path::new("root/song.mp3"))
+			.await
+			.is_ok());
+	}
+
+	#[tokio::test]
+	async fn legacy_sonos_settings_are_migrated() {
+		let ctx = test::ContextBuilder::new(test_name!()).build().await;
+		ctx.config_manager
+			.apply_config(storage::Config {
+				sonos_api_url: Some("http://10.0.0.2:5005".to_owned()),
+				sonos_mp3_server: Some("10.0.0.3/music".to_owned()),
+				..Default::default()
+			})
+			.await
+			.unwrap();
+
+		let sonos = ctx.config_manager.get_sonos_config().await;
+		assert_eq!(sonos.api_url, Some("http://10.0.0.2:5005".to_owned()));
+		assert_eq!(sonos.mp3_server, Some("10.0.0.3/music".to_owned()));
+
+		let config: storage::Config = ctx.config_manager.config.read().await.clone().into();
+		assert_eq!(config.sonos_api_url, None);
+		assert_eq!(config.sonos_mp3_server, None);
+		assert_eq!(config.sonos, Some(sonos));
+	}
+
+	#[tokio::test]
+	async fn example_api_url_is_reported_once() {
+		let ctx = test::ContextBuilder::new(test_name!()).build().await;
+		let manager = &ctx.config_manager;
+		assert!(!manager.warn_about_example_api_url().await);
+
+		let sonos = SonosConfig {
+			api_url: Some(EXAMPLE_SONOS_API_URL.to_owned()),
+			..Default::default()
+		};
+		manager.set_sonos_config(sonos).await.unwrap();
+		assert!(manager.warn_about_example_api_url().await);
+		assert!(!manager.warn_about_example_api_url().await);
+		manager.reload_config().await.unwrap();
+		assert!(!manager.warn_about_example_api_url().await);
+	}
+
 	#[tokio::test]
 	async fn can_write_config() {
 		let ctx = test::ContextBuilder::new(test_name!()).build().await;
@@ -346,9 +560,52 @@ mod test {
 			.await
 			.unwrap();
 
-		let manager = Manager::new(&ctx.config_manager.config_file_path, auth::Secret([0; 32]))
+		let manager = Manager::new(
+			&ctx.config_manager.config_file_path,
+			auth::Secret([0; 32]),
+			auth::Secret([1; 32]),
+		)
+		.await
+		.unwrap();
+		assert!(manager.get_user("Walter").await.is_ok());
+	}
+
+	#[tokio::test]
+	async fn sonos_scenes_are_saved() {
+		let ctx = test::ContextBuilder::new(test_name!()).build().await;
+		let scene = SonosScene {
+			name: "Movie night".to_owned(),
+			entries: vec![
+				SonosSceneEntry {
+					speaker: "Playbar".to_owned(),
+					volume: Some(40),
+					muted: None,
+				},
+				SonosSceneEntry {
+					speaker: "Kitchen".to_owned(),
+					volume: None,
+					muted: Some(true),
+				},
+			],
+		};
+		ctx.config_manager
+			.set_sonos_scene(scene.clone())
 			.await
 			.unwrap();
-		assert!(manager.get_user("Walter").await.is_ok());
+
+		let manager = Manager::new(
+			&ctx.config_manager.config_file_path,
+			auth::Secret([0; 32]),
+			auth::Secret([1; 32]),
+		)
+		.await
+		.unwrap();
+		assert_eq!(manager.get_sonos_scene("Movie night").await.unwrap(), scene);
+
+		manager.delete_sonos_scene("Movie night").await.unwrap();
+		assert!(matches!(
+			manager.get_sonos_scene("Movie night").await,
+			Err(Error::SonosSceneNotFound(_))
+		));
 	}
 }
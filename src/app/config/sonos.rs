@@ -1,22 +1,1343 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use log::warn;
 use serde::{Deserialize, Serialize};
 
-pub const DEFAULT_SONOS_API_URL: &str = "http://192.168.0.5:5005";
-pub const DEFAULT_SONOS_MP3_SERVER: &str = "192.168.0.6/mp3";
+use crate::app::{auth, Error};
+
+pub const DEFAULT_SONOS_MAX_BATCH_SIZE: usize = 500;
+pub const DEFAULT_SONOS_POLL_INTERVAL: Duration = Duration::from_millis(1000);
+pub const DEFAULT_SONOS_HANDLER_TIMEOUT: Duration = Duration::from_secs(8);
+pub const DEFAULT_SONOS_SPEAKER_CACHE_TTL: Duration = Duration::from_secs(30);
+pub const DEFAULT_SONOS_WEBHOOK_CACHE_TTL: Duration = Duration::from_secs(60);
+pub const DEFAULT_SONOS_ART_CACHE_TTL: Duration = Duration::from_secs(30);
+pub const DEFAULT_SONOS_VOLUME_COALESCING_WINDOW: Duration = Duration::from_millis(150);
+pub const DEFAULT_SONOS_REQUEST_SPACING: Duration = Duration::ZERO;
+pub const DEFAULT_SONOS_RESUME_RETENTION_DAYS: u64 = 30;
+/// Stream URLs never expire by default, as Sonos keeps them in saved playlists and favorites
+pub const DEFAULT_SONOS_STREAM_TOKEN_TTL_DAYS: u64 = 0;
+pub const DEFAULT_SONOS_HISTORY_MAX_ENTRIES: usize = 1000;
+pub const DEFAULT_SONOS_SNAPSHOT_MAX_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+pub const DEFAULT_SONOS_DISCOVERY_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// Environment variable holding the bearer token sent to node-sonos-http-api
+pub const SONOS_API_TOKEN_ENV_VAR: &str = "POLARIS_SONOS_API_TOKEN";
+
+/// Environment variable holding the basic auth password sent to node-sonos-http-api along with `username`
+pub const SONOS_API_PASSWORD_ENV_VAR: &str = "POLARIS_SONOS_API_PASSWORD";
+
+/// Bridge URL given as an example in the documentation, which deployments sometimes keep instead of their own
+pub const EXAMPLE_SONOS_API_URL: &str = "http://192.168.0.5:5005";
+
+/// Documented settings, all commented out, written by `SonosConfig::load_or_default` when there is no TOML file yet
+const SONOS_CONFIG_TEMPLATE: &str = include_str!("sonos_template.toml");
+
+/// Failure to read or write a standalone Sonos config file
+#[derive(thiserror::Error, Debug)]
+#[error("Sonos config file `{}`: {source}", .path.display())]
+pub struct ConfigLoadError {
+	pub path: PathBuf,
+	pub source: ConfigLoadSource,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ConfigLoadSource {
+	#[error("Filesystem error: `{0}`")]
+	Io(std::io::Error),
+	#[error("Could not parse TOML: `{0}`")]
+	Toml(toml::de::Error),
+	#[error("Could not serialize TOML: `{0}`")]
+	TomlSerialization(toml::ser::Error),
+	#[error("Could not read or write JSON: `{0}`")]
+	Json(serde_json::Error),
+	#[error("Invalid settings: {0}")]
+	Validation(Error),
+}
+
+/// Check that `api_url` is an http(s) URL node-sonos-http-api could be reached at
+pub fn validate_api_url(api_url: &str) -> Result<(), Error> {
+	let valid = reqwest::Url::parse(api_url)
+		.map(|u| matches!(u.scheme(), "http" | "https") && u.has_host())
+		.unwrap_or(false);
+	if valid {
+		Ok(())
+	} else {
+		Err(Error::SonosApiURLInvalid(api_url.to_owned()))
+	}
+}
+
+/// Whether `server` is a `host/share` path, like `192.168.0.6/mp3`
+fn is_share_path(server: &str) -> bool {
+	!server.contains("://")
+		&& !server.contains('\\')
+		&& server
+			.split_once('/')
+			.is_some_and(|(host, share)| !host.is_empty() && !share.is_empty())
+}
+
+/// Files ending in `.json` hold JSON, any other file holds TOML
+fn is_json(path: &Path) -> bool {
+	path.extension()
+		.is_some_and(|e| e.eq_ignore_ascii_case("json"))
+}
 
 #[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
 pub struct SonosConfig {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub api_url: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub mp3_server: Option<String>,
+	/// Whether the Sonos endpoints are available. Defaults to whether a bridge URL is set.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub enabled: Option<bool>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub api_url: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub mp3_server: Option<String>,
+	/// Speaker used by requests which do not name one
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub default_speaker: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub share_scheme: Option<ShareScheme>,
+	/// Shares holding the songs of some mounts, for collections spread over several shares.
+	/// Songs of the other mounts are read from `mp3_server`.
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub share_mappings: Vec<ShareMapping>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub crossfade_enabled: Option<bool>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub max_batch_size: Option<usize>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub poll_interval_ms: Option<u64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub request_timeout_ms: Option<u64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub handler_timeout_ms: Option<u64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub rate_limit_rps: Option<u32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub speaker_cache_ttl_secs: Option<u64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub availability_check: Option<bool>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub art_cache_ttl_secs: Option<u64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub volume_coalescing_ms: Option<u64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub request_spacing_ms: Option<u64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub resume_retention_days: Option<u64>,
+	/// Number of days the URL of a track streamed over HTTP keeps working. 0 means URLs never expire.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub stream_token_ttl_days: Option<u64>,
+	/// Number of commands kept in the Sonos history. The oldest ones are forgotten first, and 0 disables the history.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub history_max_entries: Option<usize>,
+	/// Directory where playback snapshots are saved. Snapshots are disabled when it is not set.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub snapshot_dir: Option<PathBuf>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub snapshot_max_age_secs: Option<u64>,
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub webhook_enabled: bool,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub webhook_cache_ttl_secs: Option<u64>,
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub smapi_enabled: bool,
+	/// Look for speakers on the local network when node-sonos-http-api does not report any
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub enable_upnp_fallback: bool,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub discovery_cache_ttl_secs: Option<u64>,
+	/// Control speakers found on the local network directly over UPnP, without node-sonos-http-api
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub native_control: bool,
+	/// Let API requests pick another bridge with the `X-Sonos-Api-Override-Url` header
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub allow_url_override: bool,
+	/// Count operations run on each speaker in the Sonos metrics, on top of requests sent to the bridge
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub enable_metrics: bool,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub retry_policy: Option<RetryPolicy>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub username: Option<String>,
+	/// Basic auth password written to config files by earlier versions. It is ignored, and dropped from the
+	/// config file, as the password is only read from `SONOS_API_PASSWORD_ENV_VAR`.
+	#[serde(default, rename = "password", skip_serializing)]
+	pub legacy_password: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub auth_header: Option<AuthHeader>,
+	/// Bearer token sent to the bridge. It is read from `SONOS_API_TOKEN_ENV_VAR` and never written to the config file.
+	#[serde(skip)]
+	pub api_token: Option<String>,
+	/// Basic auth password read from `SONOS_API_PASSWORD_ENV_VAR`. It is never written to the config file.
+	#[serde(skip)]
+	pub api_password: Option<String>,
+	/// Signs the URLs Sonos speakers stream tracks from when the share scheme is `Http`. Its secret is kept in
+	/// the data directory and never written to the config file.
+	#[serde(skip)]
+	pub stream_signer: Option<auth::StreamSigner>,
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	pub accept_invalid_certs: bool,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub ca_cert_path: Option<PathBuf>,
+	/// Settings applied before playing on a speaker, keyed by room name
+	#[serde(default, skip_serializing_if = "HashMap::is_empty")]
+	pub speaker_defaults: HashMap<String, SpeakerDefaults>,
+	/// Volumes and mute states which can be applied to several speakers at once
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub scenes: Vec<SonosScene>,
+	/// UPnP/DLNA renderers which are not Sonos speakers, controlled without node-sonos-http-api
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub renderers: Vec<DlnaDevice>,
+}
+
+/// UPnP/DLNA renderer Polaris can play tracks on, such as an AV receiver
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DlnaDevice {
+	/// Identifies the renderer in the Sonos API, like the room name of a Sonos speaker
+	pub name: String,
+	/// URL of the AVTransport control endpoint of the renderer, as listed in its device description
+	pub control_url: String,
+}
+
+/// Settings applied to a speaker each time Polaris starts playing on it
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SpeakerDefaults {
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub volume: Option<u8>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub play_mode: Option<PlayMode>,
+}
+
+/// Volumes and mute states applied to several speakers at once, such as "movie night"
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SonosScene {
+	pub name: String,
+	#[serde(default)]
+	pub entries: Vec<SonosSceneEntry>,
+}
+
+/// What a scene changes on one speaker. Settings left out are not changed.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SonosSceneEntry {
+	pub speaker: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub volume: Option<u8>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub muted: Option<bool>,
+}
+
+impl SpeakerDefaults {
+	pub fn is_empty(&self) -> bool {
+		self.volume.is_none() && self.play_mode.is_none()
+	}
+}
+
+/// Repeat and shuffle settings of a speaker
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlayMode {
+	Normal,
+	RepeatAll,
+	RepeatOne,
+	Shuffle,
+	ShuffleRepeatAll,
+}
+
+/// How Sonos speakers access the network share holding the music collection
+#[derive(Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ShareScheme {
+	/// Anonymous SMB share
+	#[default]
+	Cifs,
+	/// SMB share requiring a user account. The credentials are embedded in each track URI.
+	CifsWithCredentials { username: String, password: String },
+	/// NFS export
+	Nfs,
+	/// No share: speakers stream tracks from the audio endpoint of Polaris, with a signed token in each URL
+	Http {
+		/// Address of Polaris as speakers reach it, such as `http://192.168.0.4:5050`
+		polaris_url: String,
+	},
+}
+
+impl std::fmt::Debug for ShareScheme {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Cifs => write!(f, "Cifs"),
+			Self::CifsWithCredentials { username, .. } => f
+				.debug_struct("CifsWithCredentials")
+				.field("username", username)
+				.field("password", &"***")
+				.finish(),
+			Self::Nfs => write!(f, "Nfs"),
+			Self::Http { polaris_url } => f
+				.debug_struct("Http")
+				.field("polaris_url", polaris_url)
+				.finish(),
+		}
+	}
+}
+
+/// Network share holding the source directory of one mount of the collection
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ShareMapping {
+	/// Name of the mount, which starts the virtual path of its songs
+	pub mount: String,
+	/// Host and path of the share, such as `192.168.0.7/classical`
+	pub server: String,
+}
+
+/// Network share Sonos speakers read music from
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MusicShare {
+	/// Host and path of the share, such as `192.168.0.6/mp3`, or the address of Polaris when streaming over HTTP.
+	/// It is empty when every song is read from the shares of `mappings`.
+	pub server: String,
+	pub scheme: ShareScheme,
+	/// Shares used instead of `server` for the songs of some mounts
+	pub mappings: Vec<ShareMapping>,
+	/// Signs the URL of each track streamed over HTTP
+	pub stream_signer: Option<auth::StreamSigner>,
+}
+
+/// Settings which require a new HTTP client when they change
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SonosClientSettings {
+	pub timeout: Option<Duration>,
+	pub accept_invalid_certs: bool,
+	pub ca_cert_path: Option<PathBuf>,
+}
+
+/// Extra header sent with every request to node-sonos-http-api, such as an API key expected by a reverse proxy
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AuthHeader {
+	pub name: String,
+	pub value: String,
+}
+
+/// How requests to node-sonos-http-api are retried when the bridge cannot be reached
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+	pub max_attempts: u8,
+	pub base_delay_ms: u64,
+	pub max_delay_ms: u64,
+}
+
+impl Default for RetryPolicy {
+	fn default() -> Self {
+		Self {
+			max_attempts: 3,
+			base_delay_ms: 200,
+			max_delay_ms: 2000,
+		}
+	}
+}
+
+impl RetryPolicy {
+	/// Delay to wait for after the given (1-based) failed attempt
+	pub fn delay(&self, attempt: u8) -> Duration {
+		let exponent = u32::from(attempt.saturating_sub(1)).min(63);
+		let delay_ms = self
+			.base_delay_ms
+			.saturating_mul(1u64.checked_shl(exponent).unwrap_or(u64::MAX));
+		Duration::from_millis(delay_ms.min(self.max_delay_ms))
+	}
+}
+
+/// Changes to some of the Sonos settings. Absent fields leave the corresponding setting untouched.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SonosConfigPatch {
+	pub enabled: Option<bool>,
+	/// An empty value removes the bridge URL
+	pub api_url: Option<String>,
+	/// An empty value removes the file server
+	pub mp3_server: Option<String>,
+	/// An empty value removes the default speaker
+	pub default_speaker: Option<String>,
+	pub share_scheme: Option<ShareScheme>,
+	pub crossfade_enabled: Option<bool>,
+	pub max_batch_size: Option<usize>,
+	pub poll_interval_ms: Option<u64>,
+	pub request_timeout_ms: Option<u64>,
+	pub handler_timeout_ms: Option<u64>,
+	/// Zero removes the limit
+	pub rate_limit_rps: Option<u32>,
+	pub speaker_cache_ttl_secs: Option<u64>,
+	pub availability_check: Option<bool>,
+	pub art_cache_ttl_secs: Option<u64>,
+	pub volume_coalescing_ms: Option<u64>,
+	pub request_spacing_ms: Option<u64>,
+	pub resume_retention_days: Option<u64>,
+	/// Zero makes stream URLs permanent
+	pub stream_token_ttl_days: Option<u64>,
+	pub history_max_entries: Option<usize>,
+	/// An empty value disables snapshots
+	pub snapshot_dir: Option<String>,
+	pub snapshot_max_age_secs: Option<u64>,
+	pub webhook_enabled: Option<bool>,
+	pub webhook_cache_ttl_secs: Option<u64>,
+	pub smapi_enabled: Option<bool>,
+	pub enable_upnp_fallback: Option<bool>,
+	pub discovery_cache_ttl_secs: Option<u64>,
+	pub native_control: Option<bool>,
+	pub allow_url_override: Option<bool>,
+	pub enable_metrics: Option<bool>,
+	pub retry_policy: Option<RetryPolicy>,
+	/// An empty value removes the username
+	pub username: Option<String>,
+	/// A header without name removes the extra header
+	pub auth_header: Option<AuthHeader>,
+	pub accept_invalid_certs: Option<bool>,
+	/// An empty value removes the custom certificate authority
+	pub ca_cert_path: Option<String>,
+	/// Defaults of the listed speakers only. Empty defaults remove those of a speaker.
+	pub speaker_defaults: Option<HashMap<String, SpeakerDefaults>>,
+	/// Replaces every renderer
+	pub renderers: Option<Vec<DlnaDevice>>,
+	/// Replaces every share mapping
+	pub share_mappings: Option<Vec<ShareMapping>>,
 }
 
 impl SonosConfig {
-    pub fn get_api_url(&self) -> String {
-        self.api_url.clone().unwrap_or_else(|| DEFAULT_SONOS_API_URL.to_string())
-    }
+	/// Read and validate settings from a TOML or JSON file
+	pub fn from_file(path: &Path) -> Result<SonosConfig, ConfigLoadError> {
+		let error = |source| ConfigLoadError {
+			path: path.to_owned(),
+			source,
+		};
+		let content = std::fs::read_to_string(path).map_err(|e| error(ConfigLoadSource::Io(e)))?;
+		let config: SonosConfig = if is_json(path) {
+			serde_json::from_str(&content).map_err(|e| error(ConfigLoadSource::Json(e)))?
+		} else {
+			toml::de::from_str(&content).map_err(|e| error(ConfigLoadSource::Toml(e)))?
+		};
+		config
+			.validate()
+			.map_err(|e| error(ConfigLoadSource::Validation(e)))?;
+		Ok(config)
+	}
+
+	/// Read settings like `from_file`, unless there is no file at `path` yet.
+	/// The file is then created with the default settings, which are returned. TOML files are written with the
+	/// available settings commented out, along with their documentation.
+	pub fn load_or_default(path: &Path) -> Result<SonosConfig, ConfigLoadError> {
+		if path.exists() {
+			return Self::from_file(path);
+		}
+		let config = SonosConfig::default();
+		if is_json(path) {
+			config.save_to_file(path)?;
+		} else {
+			std::fs::write(path, SONOS_CONFIG_TEMPLATE).map_err(|e| ConfigLoadError {
+				path: path.to_owned(),
+				source: ConfigLoadSource::Io(e),
+			})?;
+		}
+		Ok(config)
+	}
+
+	/// Write settings to a TOML or JSON file, which `from_file` can read back.
+	/// The API token is not written, it always comes from the environment.
+	pub fn save_to_file(&self, path: &Path) -> Result<(), ConfigLoadError> {
+		let error = |source| ConfigLoadError {
+			path: path.to_owned(),
+			source,
+		};
+		let serialized = if is_json(path) {
+			serde_json::to_string_pretty(self).map_err(|e| error(ConfigLoadSource::Json(e)))?
+		} else {
+			toml::ser::to_string_pretty(self)
+				.map_err(|e| error(ConfigLoadSource::TomlSerialization(e)))?
+		};
+		std::fs::write(path, serialized).map_err(|e| error(ConfigLoadSource::Io(e)))
+	}
+
+	/// Whether the bridge URL is still the example from the documentation
+	pub fn has_example_api_url(&self) -> bool {
+		self.api_url
+			.as_deref()
+			.is_some_and(|u| u.trim_end_matches('/') == EXAMPLE_SONOS_API_URL)
+	}
+
+	/// Check that the bridge URL and file server look usable
+	pub fn validate(&self) -> Result<(), Error> {
+		if let Some(api_url) = &self.api_url {
+			validate_api_url(api_url)?;
+			if api_url.starts_with("https:") && self.api_token.is_none() {
+				warn!(
+					"Sonos bridge `{api_url}` uses HTTPS but {SONOS_API_TOKEN_ENV_VAR} is not set"
+				);
+			}
+		}
+
+		let mapped_servers = self.share_mappings.iter().map(|m| &m.server);
+		for server in self.mp3_server.iter().chain(mapped_servers) {
+			if !is_share_path(server) {
+				return Err(Error::SonosMp3ServerInvalid(server.clone()));
+			}
+		}
+		if let Some(mapping) = self.share_mappings.iter().find(|m| m.mount.is_empty()) {
+			return Err(Error::SonosShareMappingInvalid(mapping.server.clone()));
+		}
+
+		if let Some(ShareScheme::Http { polaris_url }) = &self.share_scheme {
+			let valid = reqwest::Url::parse(polaris_url)
+				.map(|u| matches!(u.scheme(), "http" | "https") && u.has_host())
+				.unwrap_or(false);
+			if !valid {
+				return Err(Error::SonosStreamURLInvalid(polaris_url.clone()));
+			}
+		}
+
+		if let Some(header) = &self.auth_header {
+			let valid = http::HeaderName::try_from(&header.name).is_ok()
+				&& http::HeaderValue::try_from(&header.value).is_ok();
+			if !valid {
+				return Err(Error::SonosAuthHeaderInvalid(header.name.clone()));
+			}
+		}
+
+		if let Some(path) = &self.ca_cert_path {
+			let pem = std::fs::read(path).map_err(|e| Error::Io(path.clone(), e))?;
+			reqwest::Certificate::from_pem(&pem)
+				.map_err(|_| Error::SonosCACertificateInvalid(path.clone()))?;
+		}
+
+		for (speaker, defaults) in &self.speaker_defaults {
+			if let Some(volume) = defaults.volume.filter(|v| *v > 100) {
+				return Err(Error::SonosDefaultVolumeInvalid(speaker.clone(), volume));
+			}
+		}
+
+		for scene in &self.scenes {
+			if scene.name.trim().is_empty() {
+				return Err(Error::SonosSceneNameEmpty);
+			}
+			for entry in &scene.entries {
+				if let Some(volume) = entry.volume.filter(|v| *v > 100) {
+					return Err(Error::SonosSceneVolumeInvalid(
+						scene.name.clone(),
+						entry.speaker.clone(),
+						volume,
+					));
+				}
+			}
+		}
+
+		for renderer in &self.renderers {
+			if validate_api_url(&renderer.control_url).is_err() {
+				return Err(Error::DlnaControlURLInvalid(
+					renderer.name.clone(),
+					renderer.control_url.clone(),
+				));
+			}
+		}
+
+		Ok(())
+	}
+
+	pub fn get_scene(&self, name: &str) -> Option<&SonosScene> {
+		self.scenes.iter().find(|s| s.name == name)
+	}
+
+	/// Add `scene`, or replace the scene with the same name
+	pub fn set_scene(&mut self, scene: SonosScene) {
+		match self.scenes.iter_mut().find(|s| s.name == scene.name) {
+			Some(existing) => *existing = scene,
+			None => self.scenes.push(scene),
+		}
+	}
+
+	pub fn remove_scene(&mut self, name: &str) -> Result<(), Error> {
+		let count = self.scenes.len();
+		self.scenes.retain(|s| s.name != name);
+		if self.scenes.len() == count {
+			return Err(Error::SonosSceneNotFound(name.to_owned()));
+		}
+		Ok(())
+	}
+
+	/// Apply the settings present in `patch`. The result is not validated.
+	pub fn apply_patch(&mut self, patch: SonosConfigPatch) {
+		fn non_empty(s: String) -> Option<String> {
+			Some(s.trim().to_owned()).filter(|s| !s.is_empty())
+		}
+
+		if let Some(enabled) = patch.enabled {
+			self.enabled = Some(enabled);
+		}
+		if let Some(api_url) = patch.api_url {
+			self.api_url = non_empty(api_url);
+		}
+		if let Some(mp3_server) = patch.mp3_server {
+			self.mp3_server = non_empty(mp3_server);
+		}
+		if let Some(default_speaker) = patch.default_speaker {
+			self.default_speaker = non_empty(default_speaker);
+		}
+		if let Some(share_scheme) = patch.share_scheme {
+			self.share_scheme = Some(share_scheme);
+		}
+		if let Some(crossfade_enabled) = patch.crossfade_enabled {
+			self.crossfade_enabled = Some(crossfade_enabled);
+		}
+		if let Some(max_batch_size) = patch.max_batch_size {
+			self.max_batch_size = Some(max_batch_size);
+		}
+		if let Some(poll_interval_ms) = patch.poll_interval_ms {
+			self.poll_interval_ms = Some(poll_interval_ms);
+		}
+		if let Some(request_timeout_ms) = patch.request_timeout_ms {
+			self.request_timeout_ms = Some(request_timeout_ms);
+		}
+		if let Some(handler_timeout_ms) = patch.handler_timeout_ms {
+			self.handler_timeout_ms = Some(handler_timeout_ms);
+		}
+		if let Some(rate_limit_rps) = patch.rate_limit_rps {
+			self.rate_limit_rps = Some(rate_limit_rps).filter(|rps| *rps > 0);
+		}
+		if let Some(speaker_cache_ttl_secs) = patch.speaker_cache_ttl_secs {
+			self.speaker_cache_ttl_secs = Some(speaker_cache_ttl_secs);
+		}
+		if let Some(availability_check) = patch.availability_check {
+			self.availability_check = Some(availability_check);
+		}
+		if let Some(art_cache_ttl_secs) = patch.art_cache_ttl_secs {
+			self.art_cache_ttl_secs = Some(art_cache_ttl_secs);
+		}
+		if let Some(volume_coalescing_ms) = patch.volume_coalescing_ms {
+			self.volume_coalescing_ms = Some(volume_coalescing_ms);
+		}
+		if let Some(request_spacing_ms) = patch.request_spacing_ms {
+			self.request_spacing_ms = Some(request_spacing_ms);
+		}
+		if let Some(resume_retention_days) = patch.resume_retention_days {
+			self.resume_retention_days = Some(resume_retention_days);
+		}
+		if let Some(stream_token_ttl_days) = patch.stream_token_ttl_days {
+			self.stream_token_ttl_days = Some(stream_token_ttl_days);
+		}
+		if let Some(history_max_entries) = patch.history_max_entries {
+			self.history_max_entries = Some(history_max_entries);
+		}
+		if let Some(snapshot_dir) = patch.snapshot_dir {
+			self.snapshot_dir = non_empty(snapshot_dir).map(PathBuf::from);
+		}
+		if let Some(snapshot_max_age_secs) = patch.snapshot_max_age_secs {
+			self.snapshot_max_age_secs = Some(snapshot_max_age_secs);
+		}
+		if let Some(webhook_enabled) = patch.webhook_enabled {
+			self.webhook_enabled = webhook_enabled;
+		}
+		if let Some(webhook_cache_ttl_secs) = patch.webhook_cache_ttl_secs {
+			self.webhook_cache_ttl_secs = Some(webhook_cache_ttl_secs);
+		}
+		if let Some(smapi_enabled) = patch.smapi_enabled {
+			self.smapi_enabled = smapi_enabled;
+		}
+		if let Some(enable_upnp_fallback) = patch.enable_upnp_fallback {
+			self.enable_upnp_fallback = enable_upnp_fallback;
+		}
+		if let Some(discovery_cache_ttl_secs) = patch.discovery_cache_ttl_secs {
+			self.discovery_cache_ttl_secs = Some(discovery_cache_ttl_secs);
+		}
+		if let Some(native_control) = patch.native_control {
+			self.native_control = native_control;
+		}
+		if let Some(allow_url_override) = patch.allow_url_override {
+			self.allow_url_override = allow_url_override;
+		}
+		if let Some(enable_metrics) = patch.enable_metrics {
+			self.enable_metrics = enable_metrics;
+		}
+		if let Some(retry_policy) = patch.retry_policy {
+			self.retry_policy = Some(retry_policy);
+		}
+		if let Some(username) = patch.username {
+			self.username = Some(username).filter(|u| !u.is_empty());
+		}
+		if let Some(auth_header) = patch.auth_header {
+			self.auth_header = Some(auth_header).filter(|h| !h.name.is_empty());
+		}
+		if let Some(accept_invalid_certs) = patch.accept_invalid_certs {
+			self.accept_invalid_certs = accept_invalid_certs;
+		}
+		if let Some(ca_cert_path) = patch.ca_cert_path {
+			self.ca_cert_path = non_empty(ca_cert_path).map(PathBuf::from);
+		}
+		if let Some(renderers) = patch.renderers {
+			self.renderers = renderers;
+		}
+		if let Some(share_mappings) = patch.share_mappings {
+			self.share_mappings = share_mappings;
+		}
+		for (speaker, defaults) in patch.speaker_defaults.unwrap_or_default() {
+			if defaults.is_empty() {
+				self.speaker_defaults.remove(&speaker);
+			} else {
+				self.speaker_defaults.insert(speaker, defaults);
+			}
+		}
+	}
+
+	/// Read the bridge bearer token from the environment
+	pub fn load_api_token(&mut self) {
+		self.api_token = std::env::var(SONOS_API_TOKEN_ENV_VAR)
+			.ok()
+			.filter(|t| !t.is_empty());
+	}
+
+	/// Read the bridge basic auth password from the environment
+	pub fn load_api_password(&mut self) {
+		self.api_password = std::env::var(SONOS_API_PASSWORD_ENV_VAR)
+			.ok()
+			.filter(|p| !p.is_empty());
+	}
+
+	/// Password sent to the bridge along with `username`
+	pub fn get_password(&self) -> Option<&str> {
+		self.api_password.as_deref()
+	}
+
+	pub fn is_configured(&self) -> bool {
+		self.api_url.is_some() || self.native_control
+	}
+
+	/// Whether the Sonos endpoints can be used. They cannot without a bridge URL or native control, even when explicitly enabled.
+	pub fn is_enabled(&self) -> bool {
+		self.is_configured() && self.enabled != Some(false)
+	}
+
+	pub fn get_api_url(&self) -> Option<String> {
+		self.api_url.clone()
+	}
+
+	pub fn get_mp3_server(&self) -> Option<String> {
+		self.mp3_server.clone()
+	}
+
+	pub fn get_default_speaker(&self) -> Option<String> {
+		self.default_speaker.clone().filter(|s| !s.is_empty())
+	}
+
+	pub fn get_share_scheme(&self) -> ShareScheme {
+		self.share_scheme.clone().unwrap_or_default()
+	}
+
+	/// Share Sonos reads tracks from, if a file server is set or tracks are streamed from Polaris
+	pub fn get_music_share(&self) -> Option<MusicShare> {
+		let scheme = self.get_share_scheme();
+		let server = match &scheme {
+			ShareScheme::Http { polaris_url } => polaris_url.clone(),
+			_ if self.share_mappings.is_empty() => self.get_mp3_server()?,
+			_ => self.get_mp3_server().unwrap_or_default(),
+		};
+		Some(MusicShare {
+			server,
+			scheme,
+			mappings: self.share_mappings.clone(),
+			stream_signer: self.stream_signer.clone(),
+		})
+	}
+
+	pub fn get_max_batch_size(&self) -> usize {
+		self.max_batch_size.unwrap_or(DEFAULT_SONOS_MAX_BATCH_SIZE)
+	}
+
+	pub fn get_poll_interval(&self) -> Duration {
+		self.poll_interval_ms
+			.map(Duration::from_millis)
+			.unwrap_or(DEFAULT_SONOS_POLL_INTERVAL)
+	}
+
+	pub fn get_request_timeout(&self) -> Option<Duration> {
+		self.request_timeout_ms.map(Duration::from_millis)
+	}
+
+	/// Longest time an API request can spend waiting for node-sonos-http-api, retries included
+	pub fn get_handler_timeout(&self) -> Duration {
+		self.handler_timeout_ms
+			.map(Duration::from_millis)
+			.unwrap_or(DEFAULT_SONOS_HANDLER_TIMEOUT)
+	}
+
+	/// Requests per second each client can send to the Sonos endpoints, if limited
+	pub fn get_rate_limit_rps(&self) -> Option<u32> {
+		self.rate_limit_rps.filter(|rps| *rps > 0)
+	}
+
+	pub fn get_client_settings(&self) -> SonosClientSettings {
+		SonosClientSettings {
+			timeout: self.get_request_timeout(),
+			accept_invalid_certs: self.accept_invalid_certs,
+			ca_cert_path: self.ca_cert_path.clone(),
+		}
+	}
+
+	pub fn get_speaker_cache_ttl(&self) -> Duration {
+		self.speaker_cache_ttl_secs
+			.map(Duration::from_secs)
+			.unwrap_or(DEFAULT_SONOS_SPEAKER_CACHE_TTL)
+	}
+
+	pub fn get_discovery_cache_ttl(&self) -> Duration {
+		self.discovery_cache_ttl_secs
+			.map(Duration::from_secs)
+			.unwrap_or(DEFAULT_SONOS_DISCOVERY_CACHE_TTL)
+	}
+
+	pub fn is_availability_check_enabled(&self) -> bool {
+		self.availability_check == Some(true)
+	}
+
+	pub fn get_retry_policy(&self) -> RetryPolicy {
+		self.retry_policy.unwrap_or_default()
+	}
+
+	pub fn get_webhook_cache_ttl(&self) -> Duration {
+		self.webhook_cache_ttl_secs
+			.map(Duration::from_secs)
+			.unwrap_or(DEFAULT_SONOS_WEBHOOK_CACHE_TTL)
+	}
+
+	pub fn get_art_cache_ttl(&self) -> Duration {
+		self.art_cache_ttl_secs
+			.map(Duration::from_secs)
+			.unwrap_or(DEFAULT_SONOS_ART_CACHE_TTL)
+	}
+
+	pub fn get_resume_retention_days(&self) -> u64 {
+		self.resume_retention_days
+			.unwrap_or(DEFAULT_SONOS_RESUME_RETENTION_DAYS)
+	}
+
+	pub fn get_resume_retention(&self) -> Duration {
+		Duration::from_secs(
+			self.get_resume_retention_days()
+				.saturating_mul(24 * 60 * 60),
+		)
+	}
+
+	pub fn get_stream_token_ttl_days(&self) -> u64 {
+		self.stream_token_ttl_days
+			.unwrap_or(DEFAULT_SONOS_STREAM_TOKEN_TTL_DAYS)
+	}
+
+	/// How long stream URLs keep working, or `None` if they never expire
+	pub fn get_stream_token_ttl(&self) -> Option<Duration> {
+		match self.get_stream_token_ttl_days() {
+			0 => None,
+			days => Some(Duration::from_secs(days.saturating_mul(24 * 60 * 60))),
+		}
+	}
+
+	pub fn get_history_max_entries(&self) -> usize {
+		self.history_max_entries
+			.unwrap_or(DEFAULT_SONOS_HISTORY_MAX_ENTRIES)
+	}
+
+	pub fn get_snapshot_max_age(&self) -> Duration {
+		self.snapshot_max_age_secs
+			.map(Duration::from_secs)
+			.unwrap_or(DEFAULT_SONOS_SNAPSHOT_MAX_AGE)
+	}
+
+	pub fn get_volume_coalescing_window(&self) -> Duration {
+		self.volume_coalescing_ms
+			.map(Duration::from_millis)
+			.unwrap_or(DEFAULT_SONOS_VOLUME_COALESCING_WINDOW)
+	}
+
+	/// Minimum time between two requests which change the state of speakers
+	pub fn get_request_spacing(&self) -> Duration {
+		self.request_spacing_ms
+			.map(Duration::from_millis)
+			.unwrap_or(DEFAULT_SONOS_REQUEST_SPACING)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::test::prepare_test_directory;
+	use crate::test_name;
+
+	#[test]
+	fn validates_api_url() {
+		let config = |url: &str| SonosConfig {
+			api_url: Some(url.to_owned()),
+			..Default::default()
+		};
+		assert!(config("http://192.168.0.5:5005").validate().is_ok());
+		assert!(config("https://sonos.example.com").validate().is_ok());
+		assert!(matches!(
+			config("192.168.0.5:5005").validate(),
+			Err(Error::SonosApiURLInvalid(_))
+		));
+		assert!(matches!(
+			config("ftp://192.168.0.5").validate(),
+			Err(Error::SonosApiURLInvalid(_))
+		));
+	}
+
+	#[test]
+	fn validates_mp3_server() {
+		let config = |server: &str| SonosConfig {
+			mp3_server: Some(server.to_owned()),
+			..Default::default()
+		};
+		assert!(config("192.168.0.6/mp3").validate().is_ok());
+		assert!(config("nas/music/My Albums").validate().is_ok());
+		for invalid in ["nas", "/mp3", "nas/", "smb://nas/mp3", "\\\\nas\\mp3"] {
+			assert!(matches!(
+				config(invalid).validate(),
+				Err(Error::SonosMp3ServerInvalid(_))
+			));
+		}
+	}
+
+	#[test]
+	fn enabled_by_bridge_url() {
+		let mut config = SonosConfig::default();
+		assert!(!config.is_enabled());
+		assert_eq!(config.get_api_url(), None);
+		assert_eq!(config.get_music_share(), None);
+
+		config.api_url = Some("http://192.168.0.5:5005".to_owned());
+		assert!(config.is_enabled());
+
+		config.enabled = Some(false);
+		assert!(!config.is_enabled());
+
+		config.enabled = Some(true);
+		config.api_url = None;
+		assert!(!config.is_enabled());
+
+		config.native_control = true;
+		assert!(config.is_enabled());
+	}
+
+	#[test]
+	fn applies_patches() {
+		let mut config = SonosConfig {
+			api_url: Some("http://192.168.0.5:5005".to_owned()),
+			mp3_server: Some("nas/mp3".to_owned()),
+			rate_limit_rps: Some(5),
+			speaker_defaults: HashMap::from([(
+				"Kitchen".to_owned(),
+				SpeakerDefaults {
+					volume: Some(20),
+					play_mode: None,
+				},
+			)]),
+			..Default::default()
+		};
+
+		let patch: SonosConfigPatch =
+			serde_json::from_str(r#"{ "api_url": " http://sonos.lan:5005 " }"#).unwrap();
+		config.apply_patch(patch);
+		assert_eq!(config.api_url.as_deref(), Some("http://sonos.lan:5005"));
+		assert_eq!(config.mp3_server.as_deref(), Some("nas/mp3"));
+		assert_eq!(config.rate_limit_rps, Some(5));
+		assert_eq!(config.speaker_defaults.len(), 1);
+
+		config.apply_patch(SonosConfigPatch {
+			mp3_server: Some(String::new()),
+			rate_limit_rps: Some(0),
+			speaker_defaults: Some(HashMap::from([(
+				"Kitchen".to_owned(),
+				SpeakerDefaults::default(),
+			)])),
+			..Default::default()
+		});
+		assert_eq!(config.api_url.as_deref(), Some("http://sonos.lan:5005"));
+		assert_eq!(config.mp3_server, None);
+		assert_eq!(config.rate_limit_rps, None);
+		assert!(config.speaker_defaults.is_empty());
+	}
+
+	#[test]
+	fn validates_auth_header() {
+		let config = |name: &str, value: &str| SonosConfig {
+			auth_header: Some(AuthHeader {
+				name: name.to_owned(),
+				value: value.to_owned(),
+			}),
+			..Default::default()
+		};
+		assert!(config("X-Api-Key", "abcdef").validate().is_ok());
+		assert!(matches!(
+			config("X Api Key", "abcdef").validate(),
+			Err(Error::SonosAuthHeaderInvalid(_))
+		));
+		assert!(matches!(
+			config("X-Api-Key", "abc\ndef").validate(),
+			Err(Error::SonosAuthHeaderInvalid(_))
+		));
+	}
+
+	#[test]
+	fn validates_ca_certificate() {
+		let directory = prepare_test_directory(test_name!());
+		let config = |path: PathBuf| SonosConfig {
+			ca_cert_path: Some(path),
+			..Default::default()
+		};
+
+		assert!(matches!(
+			config(directory.join("missing.pem")).validate(),
+			Err(Error::Io(_, _))
+		));
+
+		let path = directory.join("invalid.pem");
+		std::fs::write(&path, "not a certificate").unwrap();
+		assert!(matches!(
+			config(path).validate(),
+			Err(Error::SonosCACertificateInvalid(_))
+		));
+	}
+
+	#[test]
+	fn validates_default_volumes() {
+		let config = |volume: u8| SonosConfig {
+			speaker_defaults: HashMap::from([(
+				"Kitchen".to_owned(),
+				SpeakerDefaults {
+					volume: Some(volume),
+					play_mode: None,
+				},
+			)]),
+			..Default::default()
+		};
+		assert!(config(0).validate().is_ok());
+		assert!(config(100).validate().is_ok());
+		assert!(matches!(
+			config(101).validate(),
+			Err(Error::SonosDefaultVolumeInvalid(s, 101)) if s == "Kitchen"
+		));
+	}
+
+	#[test]
+	fn speaker_defaults_are_keyed_by_room() {
+		let config: SonosConfig = toml::from_str(
+			r#"
+			[speaker_defaults."Living Room"]
+			volume = 25
+			play_mode = "shuffle_repeat_all"
+			"#,
+		)
+		.unwrap();
+		assert_eq!(
+			config.speaker_defaults.get("Living Room"),
+			Some(&SpeakerDefaults {
+				volume: Some(25),
+				play_mode: Some(PlayMode::ShuffleRepeatAll),
+			})
+		);
+	}
+
+	#[test]
+	fn share_mappings_replace_file_server() {
+		let config: SonosConfig = toml::from_str(
+			r#"
+			[[share_mappings]]
+			mount = "classical"
+			server = "192.168.0.7/classical"
+			"#,
+		)
+		.unwrap();
+		assert!(config.validate().is_ok());
+		let share = config.get_music_share().unwrap();
+		assert_eq!(share.server, "");
+		assert_eq!(share.mappings, config.share_mappings);
+
+		let validate = |mount: &str, server: &str| {
+			SonosConfig {
+				share_mappings: vec![ShareMapping {
+					mount: mount.to_owned(),
+					server: server.to_owned(),
+				}],
+				..Default::default()
+			}
+			.validate()
+		};
+		assert!(matches!(
+			validate("classical", "smb://nas/classical"),
+			Err(Error::SonosMp3ServerInvalid(_))
+		));
+		assert!(matches!(
+			validate("", "nas/classical"),
+			Err(Error::SonosShareMappingInvalid(_))
+		));
+	}
+
+	#[test]
+	fn streams_over_http_without_file_server() {
+		let config: SonosConfig = toml::from_str(
+			r#"
+			[share_scheme]
+			type = "http"
+			polaris_url = "http://192.168.0.4:5050"
+			"#,
+		)
+		.unwrap();
+		assert!(config.validate().is_ok());
+		let share = SonosConfig {
+			stream_signer: Some(auth::StreamSigner::new(auth::Secret([1; 32]))),
+			..config
+		}
+		.get_music_share()
+		.unwrap();
+		assert_eq!(share.server, "http://192.168.0.4:5050");
+		assert!(share.stream_signer.is_some());
+
+		let config = SonosConfig {
+			share_scheme: Some(ShareScheme::Http {
+				polaris_url: "192.168.0.4:5050".to_owned(),
+			}),
+			..Default::default()
+		};
+		assert!(matches!(
+			config.validate(),
+			Err(Error::SonosStreamURLInvalid(_))
+		));
+	}
+
+	#[test]
+	fn share_scheme_is_tagged() {
+		let config: SonosConfig = toml::from_str(
+			r#"
+			[share_scheme]
+			type = "cifs_with_credentials"
+			username = "sonos"
+			password = "hunter2"
+			"#,
+		)
+		.unwrap();
+		assert_eq!(
+			config.get_share_scheme(),
+			ShareScheme::CifsWithCredentials {
+				username: "sonos".to_owned(),
+				password: "hunter2".to_owned(),
+			}
+		);
+		assert!(!format!("{config:?}").contains("hunter2"));
+		assert_eq!(SonosConfig::default().get_share_scheme(), ShareScheme::Cifs);
+	}
+
+	#[test]
+	fn api_token_is_not_serialized() {
+		let config = SonosConfig {
+			api_url: Some("https://sonos.example.com".to_owned()),
+			api_token: Some("s2-token".to_owned()),
+			..Default::default()
+		};
+		let serialized = toml::to_string(&config).unwrap();
+		assert!(!serialized.contains("s2-token"));
+
+		let config: SonosConfig = toml::from_str("api_token = \"s2-token\"").unwrap();
+		assert_eq!(config.api_token, None);
+	}
+
+	#[test]
+	fn api_password_is_not_serialized() {
+		let config = SonosConfig {
+			username: Some("polaris".to_owned()),
+			api_password: Some("from-env".to_owned()),
+			..Default::default()
+		};
+		assert_eq!(config.get_password(), Some("from-env"));
+		let serialized = toml::to_string(&config).unwrap();
+		assert!(!serialized.contains("from-env"));
+
+		let config: SonosConfig = toml::from_str("api_password = \"from-env\"").unwrap();
+		assert_eq!(config.api_password, None);
+		assert_eq!(config.get_password(), None);
+	}
+
+	#[test]
+	fn stored_password_is_ignored() {
+		let config: SonosConfig = toml::from_str("password = \"stored\"").unwrap();
+		assert_eq!(config.legacy_password.as_deref(), Some("stored"));
+		assert_eq!(config.get_password(), None);
+		assert!(!toml::to_string(&config).unwrap().contains("stored"));
+	}
+
+	#[test]
+	fn validates_scenes() {
+		let config = |name: &str, volume| SonosConfig {
+			scenes: vec![SonosScene {
+				name: name.to_owned(),
+				entries: vec![SonosSceneEntry {
+					speaker: "Playbar".to_owned(),
+					volume: Some(volume),
+					muted: None,
+				}],
+			}],
+			..Default::default()
+		};
+		assert!(config("Movie night", 40).validate().is_ok());
+		assert!(matches!(
+			config(" ", 40).validate(),
+			Err(Error::SonosSceneNameEmpty)
+		));
+		assert!(matches!(
+			config("Movie night", 140).validate(),
+			Err(Error::SonosSceneVolumeInvalid(_, _, 140))
+		));
+	}
+
+	#[test]
+	fn validates_renderers() {
+		let config = |control_url: &str| SonosConfig {
+			renderers: vec![DlnaDevice {
+				name: "Receiver".to_owned(),
+				control_url: control_url.to_owned(),
+			}],
+			..Default::default()
+		};
+		assert!(config("http://192.168.0.30:8080/AVTransport/ctrl")
+			.validate()
+			.is_ok());
+		assert!(matches!(
+			config("192.168.0.30:8080/AVTransport/ctrl").validate(),
+			Err(Error::DlnaControlURLInvalid(name, _)) if name == "Receiver"
+		));
+	}
+
+	#[test]
+	fn scenes_are_replaced_by_name() {
+		let scene = |name: &str, volume| SonosScene {
+			name: name.to_owned(),
+			entries: vec![SonosSceneEntry {
+				speaker: "Kitchen".to_owned(),
+				volume: Some(volume),
+				muted: None,
+			}],
+		};
+		let mut config = SonosConfig::default();
+		config.set_scene(scene("Dinner", 20));
+		config.set_scene(scene("Party", 60));
+		config.set_scene(scene("Dinner", 15));
+		assert_eq!(config.scenes, vec![scene("Dinner", 15), scene("Party", 60)]);
+		assert_eq!(config.get_scene("Party"), Some(&scene("Party", 60)));
+
+		config.remove_scene("Dinner").unwrap();
+		assert_eq!(config.get_scene("Dinner"), None);
+		assert!(matches!(
+			config.remove_scene("Dinner"),
+			Err(Error::SonosSceneNotFound(_))
+		));
+	}
+
+	#[test]
+	fn saved_file_reads_back() {
+		let directory = prepare_test_directory(test_name!());
+		let config = SonosConfig {
+			api_url: Some("http://sonos.lan:5005".to_owned()),
+			mp3_server: Some("nas/mp3".to_owned()),
+			default_speaker: Some("Kitchen".to_owned()),
+			crossfade_enabled: Some(true),
+			handler_timeout_ms: Some(2500),
+			speaker_defaults: HashMap::from([(
+				"Kitchen".to_owned(),
+				SpeakerDefaults {
+					volume: Some(25),
+					..Default::default()
+				},
+			)]),
+			..Default::default()
+		};
+
+		for name in ["sonos.toml", "sonos.json"] {
+			let path = directory.join(name);
+			config.save_to_file(&path).unwrap();
+			assert_eq!(SonosConfig::from_file(&path).unwrap(), config);
+		}
+	}
+
+	#[test]
+	fn missing_file_is_created_from_template() {
+		let directory = prepare_test_directory(test_name!());
+
+		let path = directory.join("sonos.toml");
+		assert_eq!(
+			SonosConfig::load_or_default(&path).unwrap(),
+			SonosConfig::default()
+		);
+		let content = std::fs::read_to_string(&path).unwrap();
+		assert!(content.contains("# api_url = \"http://192.168.0.5:5005\""));
+		assert_eq!(
+			SonosConfig::from_file(&path).unwrap(),
+			SonosConfig::default()
+		);
+
+		std::fs::write(&path, "api_url = \"http://sonos.lan:5005\"").unwrap();
+		let config = SonosConfig::load_or_default(&path).unwrap();
+		assert_eq!(config.api_url, Some("http://sonos.lan:5005".to_owned()));
+
+		let path = directory.join("sonos.json");
+		SonosConfig::load_or_default(&path).unwrap();
+		assert_eq!(
+			SonosConfig::from_file(&path).unwrap(),
+			SonosConfig::default()
+		);
+	}
+
+	#[test]
+	fn detects_example_api_url() {
+		let config = |api_url: Option<&str>| SonosConfig {
+			api_url: api_url.map(str::to_owned),
+			..Default::default()
+		};
+		assert!(config(Some("http://192.168.0.5:5005")).has_example_api_url());
+		assert!(config(Some("http://192.168.0.5:5005/")).has_example_api_url());
+		assert!(!config(Some("http://192.168.1.20:5005")).has_example_api_url());
+		assert!(!config(None).has_example_api_url());
+	}
+
+	#[test]
+	fn loading_file_reports_failures() {
+		let directory = prepare_test_directory(test_name!());
+		let load = |name: &str, content: Option<&str>| {
+			let path = directory.join(name);
+			if let Some(content) = content {
+				std::fs::write(&path, content).unwrap();
+			}
+			SonosConfig::from_file(&path).unwrap_err()
+		};
+
+		let error = load("missing.toml", None);
+		assert_eq!(error.path, directory.join("missing.toml"));
+		assert!(matches!(error.source, ConfigLoadSource::Io(_)));
+		assert!(matches!(
+			load("broken.toml", Some("api_url = ")).source,
+			ConfigLoadSource::Toml(_)
+		));
+		assert!(matches!(
+			load("broken.json", Some("{ \"api_url\": ")).source,
+			ConfigLoadSource::Json(_)
+		));
+		assert!(matches!(
+			load("invalid.toml", Some("api_url = \"sonos.lan\"")).source,
+			ConfigLoadSource::Validation(Error::SonosApiURLInvalid(_))
+		));
+	}
 
-    pub fn get_mp3_server(&self) -> String {
-        self.mp3_server.clone().unwrap_or_else(|| DEFAULT_SONOS_MP3_SERVER.to_string())
-    }
+	#[test]
+	fn retry_delay_doubles_up_to_max() {
+		let policy = RetryPolicy {
+			max_attempts: 10,
+			base_delay_ms: 100,
+			max_delay_ms: 500,
+		};
+		assert_eq!(policy.delay(1), Duration::from_millis(100));
+		assert_eq!(policy.delay(2), Duration::from_millis(200));
+		assert_eq!(policy.delay(3), Duration::from_millis(400));
+		assert_eq!(policy.delay(4), Duration::from_millis(500));
+		assert_eq!(policy.delay(100), Duration::from_millis(500));
+	}
 }
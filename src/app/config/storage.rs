@@ -2,6 +2,8 @@ use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 
+use super::SonosConfig;
+
 #[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
 pub struct User {
 	pub name: String,
@@ -11,6 +13,10 @@ pub struct User {
 	pub initial_password: Option<String>,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub hashed_password: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub allow_sonos: Option<bool>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub allowed_speakers: Option<Vec<String>>,
 }
 
 #[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
@@ -28,9 +34,13 @@ pub struct Config {
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub ddns_update_url: Option<String>,
 	#[serde(skip_serializing_if = "Option::is_none")]
+	pub shutdown_timeout_secs: Option<u64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
 	pub sonos_api_url: Option<String>,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub sonos_mp3_server: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub sonos: Option<SonosConfig>,
 	#[serde(default, skip_serializing_if = "Vec::is_empty")]
 	pub users: Vec<User>,
 }
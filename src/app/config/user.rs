@@ -9,12 +9,32 @@ pub struct User {
 	pub admin: Option<bool>,
 	pub initial_password: Option<String>,
 	pub hashed_password: String,
+	pub allow_sonos: Option<bool>,
+	pub allowed_speakers: Option<Vec<String>>,
 }
 
 impl User {
 	pub fn is_admin(&self) -> bool {
 		self.admin == Some(true)
 	}
+
+	/// Users can control Sonos speakers unless this was explicitly disabled
+	pub fn can_use_sonos(&self) -> bool {
+		self.allow_sonos != Some(false)
+	}
+
+	pub fn can_control_sonos_speaker(&self, speaker_id: &str) -> bool {
+		if !self.can_use_sonos() {
+			return false;
+		}
+		match &self.allowed_speakers {
+			// node-sonos-http-api matches room names case-insensitively
+			Some(speakers) => speakers
+				.iter()
+				.any(|s| s.to_lowercase() == speaker_id.to_lowercase()),
+			None => true,
+		}
+	}
 }
 
 impl TryFrom<storage::User> for User {
@@ -32,6 +52,8 @@ impl TryFrom<storage::User> for User {
 			admin: user.admin,
 			initial_password: user.initial_password,
 			hashed_password,
+			allow_sonos: user.allow_sonos,
+			allowed_speakers: user.allowed_speakers,
 		})
 	}
 }
@@ -43,6 +65,8 @@ impl From<User> for storage::User {
 			admin: user.admin,
 			initial_password: user.initial_password,
 			hashed_password: Some(user.hashed_password),
+			allow_sonos: user.allow_sonos,
+			allowed_speakers: user.allowed_speakers,
 		}
 	}
 }
@@ -80,6 +104,8 @@ impl Config {
 			admin: Some(admin),
 			initial_password: None,
 			hashed_password: password_hash,
+			allow_sonos: None,
+			allowed_speakers: None,
 		});
 
 		Ok(())
@@ -135,6 +161,22 @@ impl Config {
 		Ok(())
 	}
 
+	pub fn set_allow_sonos(&mut self, username: &str, allow_sonos: bool) -> Result<(), Error> {
+		let user = self.get_user_mut(username).ok_or(Error::UserNotFound)?;
+		user.allow_sonos = Some(allow_sonos);
+		Ok(())
+	}
+
+	pub fn set_allowed_speakers(
+		&mut self,
+		username: &str,
+		speakers: Option<Vec<String>>,
+	) -> Result<(), Error> {
+		let user = self.get_user_mut(username).ok_or(Error::UserNotFound)?;
+		user.allowed_speakers = speakers;
+		Ok(())
+	}
+
 	pub fn set_password(&mut self, username: &str, password: &str) -> Result<(), Error> {
 		let user = self.get_user_mut(username).ok_or(Error::UserNotFound)?;
 		user.hashed_password = auth::hash_password(password)?;
@@ -185,6 +227,35 @@ mod test {
 		assert_eq!(user_out, user_in);
 	}
 
+	#[test]
+	fn preserves_sonos_permissions() {
+		let user_in = storage::User {
+			name: TEST_USERNAME.to_owned(),
+			hashed_password: Some("hash".to_owned()),
+			allow_sonos: Some(true),
+			allowed_speakers: Some(vec!["Playroom".to_owned()]),
+			..Default::default()
+		};
+		let user: User = user_in.clone().try_into().unwrap();
+		let user_out: storage::User = user.into();
+		assert_eq!(user_out, user_in);
+	}
+
+	#[test]
+	fn checks_sonos_permissions() {
+		let mut user = User::default();
+		assert!(user.can_control_sonos_speaker("Kitchen"));
+
+		user.allowed_speakers = Some(vec!["Playroom".to_owned()]);
+		assert!(user.can_control_sonos_speaker("playroom"));
+		assert!(!user.can_control_sonos_speaker("Kitchen"));
+		assert!(!user.can_control_sonos_speaker("all"));
+
+		user.allow_sonos = Some(false);
+		assert!(!user.can_use_sonos());
+		assert!(!user.can_control_sonos_speaker("Playroom"));
+	}
+
 	#[tokio::test]
 	async fn create_delete_user_golden_path() {
 		let ctx = test::ContextBuilder::new(test_name!()).build().await;
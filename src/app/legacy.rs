@@ -37,8 +37,10 @@ pub fn read_legacy_config(
 		album_art_pattern: Some(album_art_pattern),
 		mount_dirs,
 		ddns_update_url: None,
+		shutdown_timeout_secs: None,
 		sonos_api_url: None,
 		sonos_mp3_server: None,
+		sonos: None,
 		users: users.into_values().collect(),
 	}))
 }
@@ -77,6 +79,8 @@ fn read_users(db_file_path: &PathBuf) -> Result<HashMap<u32, config::storage::Us
 				admin: row.get(3)?,
 				initial_password: None,
 				hashed_password: row.get(2)?,
+				allow_sonos: None,
+				allowed_speakers: None,
 			},
 		))
 	})?;
@@ -213,7 +217,10 @@ mod test {
 			album_art_pattern: Some("Folder.(jpeg|jpg|png)".to_owned()),
 			mount_dirs: vec![],
 			ddns_update_url: None,
+			shutdown_timeout_secs: None,
 			sonos_api_url: None,
+			sonos_mp3_server: None,
+			sonos: None,
 			users: vec![],
 		};
 
@@ -236,12 +243,17 @@ mod test {
 				name: "root".to_owned(),
 			}],
 			ddns_update_url: None,
+			shutdown_timeout_secs: None,
 			sonos_api_url: None,
+			sonos_mp3_server: None,
+			sonos: None,
 			users: vec![config::storage::User {
 				name: "example_user".to_owned(),
 				admin: Some(true),
 				initial_password: None,
 				hashed_password: Some("$pbkdf2-sha256$i=10000,l=32$ADvDnwBv3kLUtjTJEwGcFA$oK43ICpNt2rbH21diMo6cSXL62qqLWOM7qs8f0s/9Oo".to_owned()),
+				allow_sonos: None,
+				allowed_speakers: None,
 			}],
 		};
 
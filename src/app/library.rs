@@ -0,0 +1,210 @@
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+
+use crate::app::index::{self, Album, Song};
+use crate::app::Error;
+
+pub type LibraryFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Songs of the collection, for features which play them outside of Polaris (such as Sonos speakers).
+/// Tracks are identified by their virtual path.
+pub trait MusicLibrary: Send + Sync {
+	/// Songs matching `query`, written in the same syntax as collection searches
+	fn search_tracks<'a>(&'a self, query: &'a str) -> LibraryFuture<'a, Result<Vec<Song>, Error>>;
+
+	/// Virtual path of a track, if it is part of the collection
+	fn track_path<'a>(&'a self, track_id: &'a str) -> LibraryFuture<'a, Option<PathBuf>>;
+
+	/// Up to `count` albums picked at random, with their songs
+	fn random_albums(&self, count: usize) -> LibraryFuture<'_, Result<Vec<Album>, Error>>;
+
+	/// Up to `count` albums, most recently added first, with their songs
+	fn recent_albums(&self, count: usize) -> LibraryFuture<'_, Result<Vec<Album>, Error>>;
+}
+
+impl MusicLibrary for index::Manager {
+	fn search_tracks<'a>(&'a self, query: &'a str) -> LibraryFuture<'a, Result<Vec<Song>, Error>> {
+		Box::pin(self.search(query.to_owned()))
+	}
+
+	fn track_path<'a>(&'a self, track_id: &'a str) -> LibraryFuture<'a, Option<PathBuf>> {
+		Box::pin(async move {
+			let songs = self.get_songs(vec![PathBuf::from(track_id)]).await;
+			songs.into_iter().next()?.ok().map(|s| s.virtual_path)
+		})
+	}
+
+	fn random_albums(&self, count: usize) -> LibraryFuture<'_, Result<Vec<Album>, Error>> {
+		Box::pin(self.get_random_albums(None, 0, count))
+	}
+
+	fn recent_albums(&self, count: usize) -> LibraryFuture<'_, Result<Vec<Album>, Error>> {
+		Box::pin(self.get_recent_albums(0, count))
+	}
+}
+
+/// In-memory library, so features built on `MusicLibrary` can be tested without indexing a collection
+#[cfg(test)]
+#[derive(Default)]
+pub struct MockMusicLibrary {
+	tracks: std::collections::HashMap<String, Song>,
+}
+
+#[cfg(test)]
+impl MockMusicLibrary {
+	pub fn new(songs: impl IntoIterator<Item = Song>) -> Self {
+		let tracks = songs
+			.into_iter()
+			.map(|s| (s.virtual_path.to_string_lossy().into_owned(), s))
+			.collect();
+		Self { tracks }
+	}
+
+	/// Songs grouped by album, sorted by album name and path
+	fn albums(&self, count: usize) -> Vec<Album> {
+		let mut songs = self.tracks.values().collect::<Vec<_>>();
+		songs.sort_by(|a, b| (&a.album, &a.virtual_path).cmp(&(&b.album, &b.virtual_path)));
+		let mut albums = Vec::<Album>::new();
+		for song in songs {
+			let Some(name) = &song.album else {
+				continue;
+			};
+			match albums.last_mut() {
+				Some(album) if album.header.name == *name => album.songs.push(song.clone()),
+				_ => albums.push(Album {
+					header: index::AlbumHeader {
+						name: name.clone(),
+						artists: song.album_artists.clone(),
+						..Default::default()
+					},
+					songs: vec![song.clone()],
+				}),
+			}
+		}
+		albums.truncate(count);
+		albums
+	}
+}
+
+#[cfg(test)]
+impl MusicLibrary for MockMusicLibrary {
+	/// Songs whose path, title, album or artists contain the query, ignoring case, sorted by path
+	fn search_tracks<'a>(&'a self, query: &'a str) -> LibraryFuture<'a, Result<Vec<Song>, Error>> {
+		let query = query.to_lowercase();
+		let matches = |s: &Song| {
+			std::iter::once(s.virtual_path.to_string_lossy().into_owned())
+				.chain(s.title.clone())
+				.chain(s.album.clone())
+				.chain(s.artists.iter().cloned())
+				.any(|field| field.to_lowercase().contains(&query))
+		};
+		let mut songs = self
+			.tracks
+			.values()
+			.filter(|s| matches(s))
+			.cloned()
+			.collect::<Vec<_>>();
+		songs.sort_by(|a, b| a.virtual_path.cmp(&b.virtual_path));
+		Box::pin(std::future::ready(Ok(songs)))
+	}
+
+	fn track_path<'a>(&'a self, track_id: &'a str) -> LibraryFuture<'a, Option<PathBuf>> {
+		let path = self.tracks.get(track_id).map(|s| s.virtual_path.clone());
+		Box::pin(std::future::ready(path))
+	}
+
+	/// Albums in the same order as `recent_albums`, so tests can tell which are picked
+	fn random_albums(&self, count: usize) -> LibraryFuture<'_, Result<Vec<Album>, Error>> {
+		Box::pin(std::future::ready(Ok(self.albums(count))))
+	}
+
+	fn recent_albums(&self, count: usize) -> LibraryFuture<'_, Result<Vec<Album>, Error>> {
+		Box::pin(std::future::ready(Ok(self.albums(count))))
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn library() -> MockMusicLibrary {
+		MockMusicLibrary::new([
+			Song {
+				virtual_path: PathBuf::from("my_music/Abbey Road/02.mp3"),
+				title: Some("Something".to_owned()),
+				artists: vec!["The Beatles".to_owned()],
+				..Default::default()
+			},
+			Song {
+				virtual_path: PathBuf::from("my_music/Abbey Road/01.mp3"),
+				title: Some("Come Together".to_owned()),
+				artists: vec!["The Beatles".to_owned()],
+				..Default::default()
+			},
+			Song {
+				virtual_path: PathBuf::from("my_music/Kind of Blue/01.mp3"),
+				title: Some("So What".to_owned()),
+				artists: vec!["Miles Davis".to_owned()],
+				..Default::default()
+			},
+		])
+	}
+
+	#[tokio::test]
+	async fn mock_library_searches_tracks() {
+		let library = library();
+		let songs = library.search_tracks("beatles").await.unwrap();
+		let paths = songs
+			.iter()
+			.map(|s| s.virtual_path.clone())
+			.collect::<Vec<_>>();
+		assert_eq!(
+			paths,
+			vec![
+				PathBuf::from("my_music/Abbey Road/01.mp3"),
+				PathBuf::from("my_music/Abbey Road/02.mp3"),
+			]
+		);
+		assert!(library.search_tracks("mozart").await.unwrap().is_empty());
+	}
+
+	#[tokio::test]
+	async fn mock_library_finds_track_paths() {
+		let library = library();
+		assert_eq!(
+			library.track_path("my_music/Kind of Blue/01.mp3").await,
+			Some(PathBuf::from("my_music/Kind of Blue/01.mp3"))
+		);
+		assert_eq!(library.track_path("my_music/missing.mp3").await, None);
+	}
+
+	#[tokio::test]
+	async fn mock_library_groups_albums() {
+		let library = MockMusicLibrary::new([
+			Song {
+				virtual_path: PathBuf::from("my_music/Kind of Blue/01.mp3"),
+				album: Some("Kind of Blue".to_owned()),
+				..Default::default()
+			},
+			Song {
+				virtual_path: PathBuf::from("my_music/Abbey Road/02.mp3"),
+				album: Some("Abbey Road".to_owned()),
+				..Default::default()
+			},
+			Song {
+				virtual_path: PathBuf::from("my_music/Abbey Road/01.mp3"),
+				album: Some("Abbey Road".to_owned()),
+				..Default::default()
+			},
+		]);
+		let albums = library.recent_albums(10).await.unwrap();
+		assert_eq!(albums.len(), 2);
+		assert_eq!(albums[0].header.name, "Abbey Road");
+		assert_eq!(
+			albums[0].songs[0].virtual_path,
+			PathBuf::from("my_music/Abbey Road/01.mp3")
+		);
+		assert_eq!(library.random_albums(1).await.unwrap().len(), 1);
+	}
+}
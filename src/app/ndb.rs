@@ -6,11 +6,15 @@ use std::{
 
 use native_db::{Database, Models};
 
-use crate::app::{playlist, Error};
+use crate::app::{playlist, resume, sonos_history, Error};
 
 static MODELS: LazyLock<Models> = LazyLock::new(|| {
 	let mut models = Models::new();
 	models.define::<playlist::v1::PlaylistModel>().unwrap();
+	models.define::<resume::v1::ResumePointModel>().unwrap();
+	models
+		.define::<sonos_history::v1::HistoryEntryModel>()
+		.unwrap();
 	models
 });
 
@@ -0,0 +1,232 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use native_db::*;
+use native_model::{native_model, Model};
+use serde::{Deserialize, Serialize};
+use tokio::task::spawn_blocking;
+
+use crate::app::{ndb, Error};
+
+/// Remembers where each user stopped listening on each Sonos speaker, so playback can pick up from there later
+#[derive(Clone)]
+pub struct Manager {
+	db: ndb::Manager,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResumePoint {
+	pub speaker_id: String,
+	pub virtual_path: PathBuf,
+	/// Playback position in seconds
+	pub position: u32,
+	/// Seconds since the Unix epoch
+	pub saved_at: u64,
+}
+
+pub type ResumePointModel = v1::ResumePointModel;
+
+pub mod v1 {
+
+	use super::*;
+
+	#[derive(Debug, Default, Serialize, Deserialize)]
+	#[native_model(id = 2, version = 1)]
+	#[native_db(primary_key(custom_id -> (&str, &str)))]
+	pub struct ResumePointModel {
+		#[secondary_key]
+		pub owner: String,
+		pub speaker_id: String,
+		pub virtual_path: PathBuf,
+		pub position: u32,
+		pub saved_at: u64,
+	}
+
+	impl ResumePointModel {
+		fn custom_id(&self) -> (&str, &str) {
+			(&self.owner, &self.speaker_id)
+		}
+	}
+}
+
+impl From<ResumePointModel> for ResumePoint {
+	fn from(r: ResumePointModel) -> Self {
+		Self {
+			speaker_id: r.speaker_id,
+			virtual_path: r.virtual_path,
+			position: r.position,
+			saved_at: r.saved_at,
+		}
+	}
+}
+
+fn now() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or_default()
+}
+
+impl Manager {
+	pub fn new(db: ndb::Manager) -> Self {
+		Self { db }
+	}
+
+	pub async fn save_resume_point(
+		&self,
+		owner: &str,
+		speaker_id: &str,
+		virtual_path: PathBuf,
+		position: u32,
+	) -> Result<(), Error> {
+		spawn_blocking({
+			let manager = self.clone();
+			let owner = owner.to_owned();
+			let speaker_id = speaker_id.to_owned();
+			move || {
+				let transaction = manager.db.rw_transaction()?;
+				transaction.upsert::<ResumePointModel>(ResumePointModel {
+					owner,
+					speaker_id,
+					virtual_path,
+					position,
+					saved_at: now(),
+				})?;
+				transaction.commit()?;
+				Ok(())
+			}
+		})
+		.await?
+	}
+
+	pub async fn read_resume_point(
+		&self,
+		owner: &str,
+		speaker_id: &str,
+	) -> Result<ResumePoint, Error> {
+		spawn_blocking({
+			let manager = self.clone();
+			let owner = owner.to_owned();
+			let speaker_id = speaker_id.to_owned();
+			move || {
+				let transaction = manager.db.r_transaction()?;
+				match transaction
+					.get()
+					.primary::<ResumePointModel>((owner, speaker_id))
+				{
+					Ok(Some(r)) => Ok(ResumePoint::from(r)),
+					Ok(None) => Err(Error::ResumePointNotFound),
+					Err(e) => Err(Error::NativeDatabase(e)),
+				}
+			}
+		})
+		.await?
+	}
+
+	/// Forget resume points saved more than `max_age` ago. Returns how many were removed.
+	pub async fn prune_resume_points(&self, max_age: Duration) -> Result<usize, Error> {
+		spawn_blocking({
+			let manager = self.clone();
+			move || {
+				let oldest = now().saturating_sub(max_age.as_secs());
+				let transaction = manager.db.rw_transaction()?;
+				let expired = transaction
+					.scan()
+					.primary::<ResumePointModel>()?
+					.all()?
+					.filter_map(|r| r.ok())
+					.filter(|r| r.saved_at < oldest)
+					.collect::<Vec<_>>();
+				let num_expired = expired.len();
+				for resume_point in expired {
+					transaction.remove::<ResumePointModel>(resume_point)?;
+				}
+				transaction.commit()?;
+				Ok(num_expired)
+			}
+		})
+		.await?
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::path::PathBuf;
+	use std::time::Duration;
+
+	use crate::app::test;
+	use crate::app::Error;
+	use crate::test_name;
+
+	const TEST_USER: &str = "test_user";
+	const TEST_PASSWORD: &str = "password";
+
+	#[tokio::test]
+	async fn save_resume_point_golden_path() {
+		let ctx = test::ContextBuilder::new(test_name!())
+			.user(TEST_USER, TEST_PASSWORD, false)
+			.build()
+			.await;
+
+		let path = PathBuf::from("root/Audiobooks/Chapter 3.mp3");
+		ctx.resume_manager
+			.save_resume_point(TEST_USER, "Bedroom", path.clone(), 754)
+			.await
+			.unwrap();
+
+		let resume_point = ctx
+			.resume_manager
+			.read_resume_point(TEST_USER, "Bedroom")
+			.await
+			.unwrap();
+		assert_eq!(resume_point.virtual_path, path);
+		assert_eq!(resume_point.position, 754);
+
+		assert!(matches!(
+			ctx.resume_manager
+				.read_resume_point(TEST_USER, "Kitchen")
+				.await,
+			Err(Error::ResumePointNotFound)
+		));
+		assert!(matches!(
+			ctx.resume_manager
+				.read_resume_point("other_user", "Bedroom")
+				.await,
+			Err(Error::ResumePointNotFound)
+		));
+	}
+
+	#[tokio::test]
+	async fn prune_resume_points_keeps_recent_ones() {
+		let ctx = test::ContextBuilder::new(test_name!())
+			.user(TEST_USER, TEST_PASSWORD, false)
+			.build()
+			.await;
+
+		ctx.resume_manager
+			.save_resume_point(TEST_USER, "Bedroom", PathBuf::from("root/a.mp3"), 10)
+			.await
+			.unwrap();
+
+		let pruned = ctx
+			.resume_manager
+			.prune_resume_points(Duration::from_secs(3600))
+			.await
+			.unwrap();
+		assert_eq!(pruned, 0);
+
+		// Timestamps are in whole seconds
+		tokio::time::sleep(Duration::from_millis(1100)).await;
+		let pruned = ctx
+			.resume_manager
+			.prune_resume_points(Duration::ZERO)
+			.await
+			.unwrap();
+		assert_eq!(pruned, 1);
+		assert!(ctx
+			.resume_manager
+			.read_resume_point(TEST_USER, "Bedroom")
+			.await
+			.is_err());
+	}
+}
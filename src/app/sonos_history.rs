@@ -0,0 +1,218 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use native_db::*;
+use native_model::{native_model, Model};
+use serde::{Deserialize, Serialize};
+use tokio::task::spawn_blocking;
+
+use crate::app::{ndb, Error};
+
+/// Remembers the commands users sent to Sonos speakers, whether they succeeded or not
+#[derive(Clone)]
+pub struct Manager {
+	db: ndb::Manager,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HistoryEntry {
+	/// Milliseconds since the Unix epoch
+	pub recorded_at: u64,
+	pub username: String,
+	pub speaker_id: Option<String>,
+	/// What the command does, such as `volume` or `play-album`
+	pub action: String,
+	/// Path parameters and request body of the command, as a JSON object
+	pub parameters: String,
+	pub success: bool,
+	/// HTTP status Polaris answered the command with
+	pub status: u16,
+	pub error: Option<String>,
+}
+
+pub type HistoryEntryModel = v1::HistoryEntryModel;
+
+pub mod v1 {
+
+	use super::*;
+
+	#[derive(Debug, Default, Serialize, Deserialize)]
+	#[native_model(id = 3, version = 1)]
+	#[native_db]
+	pub struct HistoryEntryModel {
+		/// Entries are numbered in the order they were recorded
+		#[primary_key]
+		pub id: u64,
+		pub recorded_at: u64,
+		pub username: String,
+		pub speaker_id: Option<String>,
+		pub action: String,
+		pub parameters: String,
+		pub success: bool,
+		pub status: u16,
+		pub error: Option<String>,
+	}
+}
+
+impl From<HistoryEntryModel> for HistoryEntry {
+	fn from(e: HistoryEntryModel) -> Self {
+		Self {
+			recorded_at: e.recorded_at,
+			username: e.username,
+			speaker_id: e.speaker_id,
+			action: e.action,
+			parameters: e.parameters,
+			success: e.success,
+			status: e.status,
+			error: e.error,
+		}
+	}
+}
+
+pub fn now_millis() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_millis() as u64)
+		.unwrap_or_default()
+}
+
+impl Manager {
+	pub fn new(db: ndb::Manager) -> Self {
+		Self { db }
+	}
+
+	/// Add `entry` to the history, then forget the oldest entries so that at most `max_entries` are kept
+	pub async fn record(&self, entry: HistoryEntry, max_entries: usize) -> Result<(), Error> {
+		spawn_blocking({
+			let manager = self.clone();
+			move || {
+				let transaction = manager.db.rw_transaction()?;
+				// Entries are scanned in the order of their IDs, so the newest one comes last
+				let id = transaction
+					.scan()
+					.primary::<HistoryEntryModel>()?
+					.all()?
+					.rev()
+					.next()
+					.transpose()?
+					.map(|e| e.id + 1)
+					.unwrap_or_default();
+
+				let num_entries = transaction.len().primary::<HistoryEntryModel>()? as usize;
+				let num_expired = (num_entries + 1).saturating_sub(max_entries);
+				if num_expired > 0 {
+					let expired = transaction
+						.scan()
+						.primary::<HistoryEntryModel>()?
+						.all()?
+						.take(num_expired)
+						.filter_map(|e| e.ok())
+						.collect::<Vec<_>>();
+					for expired in expired {
+						transaction.remove::<HistoryEntryModel>(expired)?;
+					}
+				}
+				if max_entries > 0 {
+					transaction.insert::<HistoryEntryModel>(HistoryEntryModel {
+						id,
+						recorded_at: entry.recorded_at,
+						username: entry.username,
+						speaker_id: entry.speaker_id,
+						action: entry.action,
+						parameters: entry.parameters,
+						success: entry.success,
+						status: entry.status,
+						error: entry.error,
+					})?;
+				}
+				transaction.commit()?;
+				Ok(())
+			}
+		})
+		.await?
+	}
+
+	/// Up to `limit` of the most recent entries, newest first. When `speaker_id` is set, only commands sent to this speaker are listed.
+	pub async fn read(
+		&self,
+		speaker_id: Option<&str>,
+		limit: usize,
+	) -> Result<Vec<HistoryEntry>, Error> {
+		spawn_blocking({
+			let manager = self.clone();
+			let speaker_id = speaker_id.map(|s| s.to_owned());
+			move || {
+				let transaction = manager.db.r_transaction()?;
+				let entries = transaction
+					.scan()
+					.primary::<HistoryEntryModel>()?
+					.all()?
+					.rev()
+					.filter_map(|e| e.ok())
+					.filter(|e| speaker_id.is_none() || e.speaker_id == speaker_id)
+					.take(limit)
+					.map(HistoryEntry::from)
+					.collect();
+				Ok(entries)
+			}
+		})
+		.await?
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::app::test;
+	use crate::test_name;
+
+	fn entry(speaker_id: &str, action: &str) -> HistoryEntry {
+		HistoryEntry {
+			recorded_at: now_millis(),
+			username: "test_user".to_owned(),
+			speaker_id: Some(speaker_id.to_owned()),
+			action: action.to_owned(),
+			parameters: "{}".to_owned(),
+			success: true,
+			status: 200,
+			error: None,
+		}
+	}
+
+	#[tokio::test]
+	async fn reads_newest_entries_first() {
+		let ctx = test::ContextBuilder::new(test_name!()).build().await;
+		let history = &ctx.sonos_history_manager;
+		history.record(entry("Kitchen", "pause"), 10).await.unwrap();
+		history
+			.record(entry("Bedroom", "volume"), 10)
+			.await
+			.unwrap();
+		history.record(entry("Kitchen", "mute"), 10).await.unwrap();
+
+		let actions =
+			|entries: Vec<HistoryEntry>| entries.into_iter().map(|e| e.action).collect::<Vec<_>>();
+		assert_eq!(
+			actions(history.read(None, 2).await.unwrap()),
+			vec!["mute", "volume"]
+		);
+		assert_eq!(
+			actions(history.read(Some("Kitchen"), 10).await.unwrap()),
+			vec!["mute", "pause"]
+		);
+	}
+
+	#[tokio::test]
+	async fn forgets_oldest_entries() {
+		let ctx = test::ContextBuilder::new(test_name!()).build().await;
+		let history = &ctx.sonos_history_manager;
+		for action in ["play", "pause", "stop"] {
+			history.record(entry("Kitchen", action), 2).await.unwrap();
+		}
+		let entries = history.read(None, 10).await.unwrap();
+		assert_eq!(entries.len(), 2);
+		assert_eq!(entries[1].action, "pause");
+
+		history.record(entry("Kitchen", "play"), 0).await.unwrap();
+		assert!(history.read(None, 10).await.unwrap().is_empty());
+	}
+}
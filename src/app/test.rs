@@ -1,7 +1,7 @@
 use std::path::PathBuf;
 
 use crate::app::config::storage::*;
-use crate::app::{auth, config, index, ndb, playlist, scanner};
+use crate::app::{auth, config, index, ndb, playlist, resume, scanner, sonos_history};
 use crate::test::*;
 
 pub struct Context {
@@ -9,6 +9,8 @@ pub struct Context {
 	pub scanner: scanner::Scanner,
 	pub config_manager: config::Manager,
 	pub playlist_manager: playlist::Manager,
+	pub resume_manager: resume::Manager,
+	pub sonos_history_manager: sonos_history::Manager,
 }
 
 pub struct ContextBuilder {
@@ -46,7 +48,8 @@ impl ContextBuilder {
 		let config_path = self.test_directory.join("polaris.toml");
 
 		let auth_secret = auth::Secret::default();
-		let config_manager = config::Manager::new(&config_path, auth_secret)
+		let sonos_stream_secret = auth::Secret([1; 32]);
+		let config_manager = config::Manager::new(&config_path, auth_secret, sonos_stream_secret)
 			.await
 			.unwrap();
 		let ndb_manager = ndb::Manager::new(&self.test_directory).unwrap();
@@ -55,6 +58,8 @@ impl ContextBuilder {
 			.await
 			.unwrap();
 		let playlist_manager = playlist::Manager::new(ndb_manager.clone());
+		let resume_manager = resume::Manager::new(ndb_manager.clone());
+		let sonos_history_manager = sonos_history::Manager::new(ndb_manager.clone());
 
 		config_manager.apply_config(self.config).await.unwrap();
 
@@ -63,6 +68,8 @@ impl ContextBuilder {
 			scanner,
 			config_manager,
 			playlist_manager,
+			resume_manager,
+			sonos_history_manager,
 		}
 	}
 }
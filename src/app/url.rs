@@ -0,0 +1,249 @@
+/// Path segment under which the Polaris API serves audio files
+const AUDIO_ENDPOINT: &str = "audio";
+const API_ROOT: &str = "api";
+
+/// What a track URL sent by a client points to
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TrackUrl {
+	/// Song served by the audio endpoint of any API version, such as `/api/audio/<path>` or `/api/v7/audio/<path>`
+	Audio(String),
+	/// Path of a song within the collection, sent as-is instead of a URL
+	Relative(String),
+	/// URL of something which is not audio, such as the `peaks` or `thumbnail` endpoints
+	NotAudio { endpoint: String },
+}
+
+/// Builds and parses URLs pointing to the Polaris API
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PolarisUrlBuilder {
+	/// Root of the API, such as `http://192.168.0.4:5050/api`
+	pub base_url: String,
+}
+
+impl PolarisUrlBuilder {
+	pub fn new(base_url: String) -> Self {
+		Self { base_url }
+	}
+
+	/// URL from which the song at `relative_path` (within the collection) can be streamed
+	pub fn audio_url(&self, relative_path: &str) -> String {
+		format!(
+			"{}/{}/{}",
+			self.base_url.trim_end_matches('/'),
+			AUDIO_ENDPOINT,
+			urlencoding::encode(relative_path)
+		)
+	}
+
+	/// Work out what a track URL points to. Query strings and fragments are ignored.
+	/// Returns `None` if the URL points to audio but its path is not correctly percent-encoded.
+	pub fn parse_track_url(track_url: &str) -> Option<TrackUrl> {
+		let is_url = track_url.contains("://")
+			|| track_url.starts_with('/')
+			|| track_url.starts_with("api/");
+		if !is_url {
+			return Some(TrackUrl::Relative(track_url.to_owned()));
+		}
+
+		let without_query = track_url.split(['?', '#']).next().unwrap_or_default();
+		// Skip the scheme and host so that only the path is searched
+		let path = match without_query.split_once("://") {
+			Some((_, rest)) => rest.find('/').map(|i| &rest[i..]).unwrap_or_default(),
+			None => without_query,
+		};
+
+		let segments = path.split('/').collect::<Vec<_>>();
+		let endpoint_index = match segments.iter().position(|s| *s == API_ROOT) {
+			Some(i) if segments.get(i + 1).is_some_and(|s| is_api_version(s)) => i + 2,
+			Some(i) => i + 1,
+			None => match segments.iter().position(|s| *s == AUDIO_ENDPOINT) {
+				Some(i) => i,
+				None => {
+					return Some(TrackUrl::NotAudio {
+						endpoint: path.to_owned(),
+					})
+				}
+			},
+		};
+
+		match segments.get(endpoint_index) {
+			Some(&AUDIO_ENDPOINT) => {
+				let encoded_path = segments[endpoint_index + 1..].join("/");
+				if encoded_path.is_empty() {
+					return Some(TrackUrl::NotAudio {
+						endpoint: AUDIO_ENDPOINT.to_owned(),
+					});
+				}
+				percent_decode_strict(&encoded_path).map(TrackUrl::Audio)
+			}
+			Some(endpoint) => Some(TrackUrl::NotAudio {
+				endpoint: endpoint.to_string(),
+			}),
+			None => Some(TrackUrl::NotAudio {
+				endpoint: path.to_owned(),
+			}),
+		}
+	}
+}
+
+/// Version segment of legacy API URLs, such as `v7`
+fn is_api_version(segment: &str) -> bool {
+	segment
+		.strip_prefix('v')
+		.is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Percent-decode a string, rejecting malformed escapes such as `%GG` instead of passing them through
+fn percent_decode_strict(input: &str) -> Option<String> {
+	let bytes = input.as_bytes();
+	let malformed = bytes.iter().enumerate().any(|(i, b)| {
+		*b == b'%'
+			&& bytes[i + 1..]
+				.iter()
+				.take(2)
+				.filter(|c| c.is_ascii_hexdigit())
+				.count() != 2
+	});
+	if malformed {
+		return None;
+	}
+	urlencoding::decode(input).ok().map(|s| s.into_owned())
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn builder() -> PolarisUrlBuilder {
+		PolarisUrlBuilder::new("http://192.168.0.4:5050/api/".to_owned())
+	}
+
+	#[test]
+	fn round_trips_audio_paths() {
+		for path in [
+			"my_music/Khemmis/Hunted/01 - Above The Water.flac",
+			"my_music/Björk/Homogenic/Jóga.mp3",
+			"my_music/東京事変/教育/林檎の唄.mp3",
+			"my_music/100%/50% off?/#1 & more+.mp3",
+			"my_music/Already%20Encoded.mp3",
+		] {
+			let url = builder().audio_url(path);
+			assert_eq!(
+				PolarisUrlBuilder::parse_track_url(&url),
+				Some(TrackUrl::Audio(path.to_owned()))
+			);
+		}
+	}
+
+	#[test]
+	fn builds_audio_urls() {
+		assert_eq!(
+			builder().audio_url("my_music/Test Song.mp3"),
+			"http://192.168.0.4:5050/api/audio/my_music%2FTest%20Song.mp3"
+		);
+	}
+
+	fn audio(path: &str) -> Option<TrackUrl> {
+		Some(TrackUrl::Audio(path.to_owned()))
+	}
+
+	fn not_audio(endpoint: &str) -> Option<TrackUrl> {
+		Some(TrackUrl::NotAudio {
+			endpoint: endpoint.to_owned(),
+		})
+	}
+
+	#[test]
+	fn parses_v8_audio_urls() {
+		assert_eq!(
+			PolarisUrlBuilder::parse_track_url(
+				"http://localhost:5050/api/audio/Test%2FKinderlieder%2FTest.mp3"
+			),
+			audio("Test/Kinderlieder/Test.mp3")
+		);
+		assert_eq!(
+			PolarisUrlBuilder::parse_track_url("http://audio/api/audio/a.mp3"),
+			audio("a.mp3")
+		);
+		assert_eq!(
+			PolarisUrlBuilder::parse_track_url("/api/audio/my_music%2Fa.mp3"),
+			audio("my_music/a.mp3")
+		);
+	}
+
+	#[test]
+	fn parses_versioned_audio_urls() {
+		assert_eq!(
+			PolarisUrlBuilder::parse_track_url(
+				"http://localhost:5050/api/v7/audio/my_music/Khemmis/Hunted%2001.mp3"
+			),
+			audio("my_music/Khemmis/Hunted 01.mp3")
+		);
+		assert_eq!(
+			PolarisUrlBuilder::parse_track_url("/api/v12/audio/my_music%2Fa.mp3"),
+			audio("my_music/a.mp3")
+		);
+	}
+
+	#[test]
+	fn ignores_query_strings_and_fragments() {
+		assert_eq!(
+			PolarisUrlBuilder::parse_track_url(
+				"http://localhost:5050/api/audio/Test%2FTest.mp3?auth_token=abc&x=1"
+			),
+			audio("Test/Test.mp3")
+		);
+		assert_eq!(
+			PolarisUrlBuilder::parse_track_url("http://localhost:5050/api/v7/audio/a.mp3#start"),
+			audio("a.mp3")
+		);
+		assert_eq!(
+			PolarisUrlBuilder::parse_track_url("http://localhost:5050/api/audio/50%25%3F.mp3"),
+			audio("50%?.mp3")
+		);
+	}
+
+	#[test]
+	fn parses_relative_paths() {
+		assert_eq!(
+			PolarisUrlBuilder::parse_track_url("my_music/Khemmis/Hunted/01 - Why?.mp3"),
+			Some(TrackUrl::Relative(
+				"my_music/Khemmis/Hunted/01 - Why?.mp3".to_owned()
+			))
+		);
+	}
+
+	#[test]
+	fn rejects_other_endpoints() {
+		assert_eq!(
+			PolarisUrlBuilder::parse_track_url("http://localhost:5050/api/thumbnail/a.jpg"),
+			not_audio("thumbnail")
+		);
+		assert_eq!(
+			PolarisUrlBuilder::parse_track_url(
+				"http://localhost:5050/api/peaks/my_music%2Fa.mp3?auth_token=abc"
+			),
+			not_audio("peaks")
+		);
+		assert_eq!(
+			PolarisUrlBuilder::parse_track_url("http://localhost:5050/api/audio/"),
+			not_audio("audio")
+		);
+		assert_eq!(
+			PolarisUrlBuilder::parse_track_url("http://radio.example.com/stream.mp3"),
+			not_audio("/stream.mp3")
+		);
+	}
+
+	#[test]
+	fn rejects_malformed_audio_paths() {
+		assert_eq!(
+			PolarisUrlBuilder::parse_track_url("http://localhost:5050/api/audio/Song%GG.mp3"),
+			None
+		);
+		assert_eq!(
+			PolarisUrlBuilder::parse_track_url("http://localhost:5050/api/audio/Song%FF.mp3"),
+			None
+		);
+	}
+}
@@ -13,6 +13,7 @@ mod app;
 mod options;
 mod paths;
 mod server;
+mod smapi;
 mod sonos;
 #[cfg(test)]
 mod test;
@@ -145,21 +146,38 @@ fn main() -> Result<(), Error> {
 async fn async_main(cli_options: CLIOptions, paths: paths::Paths) -> Result<(), Error> {
 	// Create and run app
 	let app = app::App::new(cli_options.port.unwrap_or(5050), paths).await?;
+	if let Err(e) = app.config_manager.get_sonos_config().await.validate() {
+		if cli_options.strict_config {
+			return Err(e.into());
+		}
+		error!("Invalid Sonos configuration: {e}");
+	}
 	app.scanner.queue_scan();
 	app.ddns_manager.begin_periodic_updates();
+	app.sonos_manager.begin_polling();
+	app.sonos_manager.begin_discovery();
 
 	// Start server
 	info!("Starting up server");
-	if let Err(e) = server::launch(app).await {
-		return Err(Error::ServiceStartup(e));
-	}
+	let server = match server::launch(app).await {
+		Ok(s) => s,
+		Err(e) => return Err(Error::ServiceStartup(e)),
+	};
 
 	// Send readiness notification
 	#[cfg(unix)]
 	notify_ready()?;
 
-	// Run UI
-	ui::run();
+	// Run UI until it exits or the server shuts down
+	let (ui_exited, ui_exit) = tokio::sync::oneshot::channel();
+	std::thread::spawn(move || {
+		ui::run();
+		let _ = ui_exited.send(());
+	});
+	tokio::select! {
+		_ = ui_exit => {},
+		_ = server => {},
+	}
 
 	info!("Shutting down server");
 	Ok(())
@@ -14,6 +14,7 @@ pub struct CLIOptions {
 	pub web_dir_path: Option<PathBuf>,
 	pub port: Option<u16>,
 	pub log_level: Option<LevelFilter>,
+	pub strict_config: bool,
 }
 
 pub struct Manager {
@@ -46,6 +47,7 @@ impl Manager {
 			web_dir_path: matches.opt_str("w").map(PathBuf::from),
 			port: matches.opt_str("p").and_then(|p| p.parse().ok()),
 			log_level: matches.opt_str("log-level").and_then(|l| l.parse().ok()),
+			strict_config: matches.opt_present("strict-config"),
 		})
 	}
 
@@ -88,6 +90,12 @@ fn get_options() -> getopts::Options {
 		"run polaris in the foreground instead of daemonizing",
 	);
 
+	options.optflag(
+		"",
+		"strict-config",
+		"refuse to start if the configuration contains invalid settings",
+	);
+
 	options.optflag("h", "help", "print this help menu");
 	options
 }
@@ -27,6 +27,8 @@ impl TryFrom<i32> for APIMajorVersion {
 pub const API_MAJOR_VERSION: i32 = 8;
 pub const API_MINOR_VERSION: i32 = 0;
 pub const API_ARRAY_SEPARATOR: &str = "\u{000C}";
+/// Header naming a node-sonos-http-api instance to use instead of the configured one
+pub const SONOS_API_URL_OVERRIDE_HEADER: &str = "X-Sonos-Api-Override-Url";
 
 mod axum;
 pub use axum::*;
@@ -1,6 +1,7 @@
 use crate::app::{self, App};
 use crate::server::doc;
-use axum::{extract::FromRef, Router, ServiceExt};
+use crate::sonos;
+use axum::{extract::FromRef, routing::post, Router};
 use tower::Layer;
 use tower_http::{
 	compression::CompressionLayer,
@@ -14,6 +15,9 @@ mod api;
 mod auth;
 mod error;
 mod logger;
+mod rate_limit;
+mod shutdown;
+mod smapi;
 mod version;
 
 #[cfg(test)]
@@ -25,10 +29,11 @@ pub fn make_router(app: App) -> NormalizePath<Router> {
 		.layer(CompressionLayer::new());
 
 	let (open_api_router, open_api) = OpenApiRouter::with_openapi(doc::open_api())
-		.nest("/api", api::router())
+		.nest("/api", api::router(&app))
 		.split_for_parts();
 
 	let router = open_api_router
+		.route("/smapi", post(smapi::post_smapi))
 		.with_state(app.clone())
 		.merge(Scalar::with_url("/api-docs", open_api))
 		.fallback_service(static_files)
@@ -37,15 +42,20 @@ pub fn make_router(app: App) -> NormalizePath<Router> {
 	NormalizePathLayer::trim_trailing_slash().layer(router)
 }
 
-pub async fn launch(app: App) -> Result<(), std::io::Error> {
+/// Start serving requests. The returned task completes after a shutdown signal is received
+/// and requests in progress have completed or timed out.
+pub async fn launch(app: App) -> Result<tokio::task::JoinHandle<()>, std::io::Error> {
 	let port = app.port;
+	let timeout = app.config_manager.get_shutdown_timeout().await;
+	let sonos_manager = app.sonos_manager.clone();
 	let router = make_router(app);
-	let make_service = ServiceExt::<axum::extract::Request>::into_make_service(router);
 	let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{port}")).await?;
-	tokio::spawn(async {
-		axum::serve(listener, make_service).await.unwrap();
-	});
-	Ok(())
+	Ok(tokio::spawn(async move {
+		if let Err(e) = shutdown::serve(listener, router, shutdown::signal(), timeout).await {
+			log::error!("Server error: {e}");
+		}
+		sonos_manager.service().await.shutdown();
+	}))
 }
 
 impl FromRef<App> for app::index::Manager {
@@ -84,6 +94,24 @@ impl FromRef<App> for app::playlist::Manager {
 	}
 }
 
+impl FromRef<App> for app::resume::Manager {
+	fn from_ref(app: &App) -> Self {
+		app.resume_manager.clone()
+	}
+}
+
+impl FromRef<App> for app::sonos_history::Manager {
+	fn from_ref(app: &App) -> Self {
+		app.sonos_history_manager.clone()
+	}
+}
+
+impl FromRef<App> for sonos::Manager {
+	fn from_ref(app: &App) -> Self {
+		app.sonos_manager.clone()
+	}
+}
+
 impl FromRef<App> for app::thumbnail::Manager {
 	fn from_ref(app: &App) -> Self {
 		app.thumbnail_manager.clone()
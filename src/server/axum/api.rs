@@ -1,30 +1,56 @@
-use std::path::PathBuf;
+use std::{collections::HashMap, convert::Infallible, path::PathBuf, sync::Arc};
 
 use axum::{
-	extract::{DefaultBodyLimit, Path, Query, State},
-	response::{IntoResponse, Response},
+	extract::{
+		DefaultBodyLimit, FromRequestParts, MatchedPath, Path, Query, RawPathParams, Request, State,
+	},
+	middleware::{self, Next},
+	response::{
+		sse::{Event, KeepAlive, Sse},
+		IntoResponse, Response,
+	},
 	routing::{get, post},
 	Json,
 };
 use axum_extra::headers::Range;
 use axum_extra::TypedHeader;
 use axum_range::{KnownSize, Ranged};
+use http::request::Parts;
 use regex::Regex;
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
 use tower_http::{compression::CompressionLayer, CompressionLevel};
+use tracing::{error, warn};
 use utoipa_axum::{router::OpenApiRouter, routes};
 
 use crate::{
-	app::{auth, config, ddns, index, peaks, playlist, scanner, thumbnail, App},
+	app::{
+		auth, config, ddns, index, peaks, playlist, resume, scanner, sonos_history, thumbnail,
+		url::{PolarisUrlBuilder, TrackUrl},
+		App,
+	},
 	server::{
 		dto, error::APIError, APIMajorVersion, API_ARRAY_SEPARATOR, API_MAJOR_VERSION,
-		API_MINOR_VERSION,
+		API_MINOR_VERSION, SONOS_API_URL_OVERRIDE_HEADER,
+	},
+	sonos::{
+		self, AlbumSelection, AnnounceRequest, CrossfadeQueueRequest, CrossfadeRequest, EqSettings,
+		ExportPlaylistRequest, JoinGroupRequest, MoveQueueEntryRequest, MuteRequest, NativeSonos,
+		PlayAlbumRequest, PlayAlbumsRequest, PlayDirectoryRequest, PlayFavoriteRequest,
+		PlayPlaylistRequest, PlaySavedPlaylistRequest, PlaySearchRequest, PlayTrackMode,
+		PlayTrackRequest, PlayUriRequest, Renderer, RepeatRequest, ResumeRequest, SeekRequest,
+		ShuffleRequest, SleepTimerRequest, SonosAlbumsResult, SonosEvent, SonosExportResponse,
+		SonosFavorite, SonosNowPlaying, SonosPlayResponse, SonosPlaylistPlayResponse,
+		SonosPlaylistResult, SonosQueueEntry, SonosResponse, SonosSceneResult, SonosService,
+		SonosSession, SonosSpeaker, SonosSpeakerResponse, SonosState, SonosStatus,
+		SonosTrackResult, SonosVolumeResponse, SonosZone, TrackMetadata, VolumeAdjustRequest,
+		VolumeRequest,
 	},
-	sonos::{SonosSpeaker, PlayTrackRequest, SonosResponse, SonosState, SonosService},
 };
 
-use super::auth::{AdminRights, Auth};
+use super::auth::{AdminRights, AudioAuth, Auth, SonosRights};
+use super::rate_limit::RateLimitLayer;
 
-pub fn router() -> OpenApiRouter<App> {
+pub fn router(app: &App) -> OpenApiRouter<App> {
 	OpenApiRouter::new()
 		// Configuration
 		.routes(routes!(get_version))
@@ -67,15 +93,315 @@ pub fn router() -> OpenApiRouter<App> {
 		.routes(routes!(get_peaks))
 		.routes(routes!(get_thumbnail))
 		// Sonos
-		.routes(routes!(post_sonos_play))
-		.routes(routes!(get_sonos_speakers))
-		.routes(routes!(get_sonos_state))
+		.routes(routes!(
+			get_sonos_config,
+			put_sonos_config,
+			patch_sonos_config
+		))
+		.routes(routes!(get_sonos_scenes))
+		.routes(routes!(
+			put_sonos_scene,
+			get_sonos_scene,
+			delete_sonos_scene
+		))
+		.routes(routes!(delete_sonos_album_art_cache))
+		.routes(routes!(get_sonos_metrics))
+		.routes(routes!(get_sonos_history))
+		.routes(routes!(get_sonos_session))
+		.routes(routes!(get_sonos_events, post_sonos_events))
+		.routes(routes!(get_sonos_events_stream))
+		.routes(routes!(post_sonos_webhook))
+		// Sonos endpoints which contact node-sonos-http-api
+		.merge(sonos_bridge_router(app))
 		.layer(CompressionLayer::new().quality(CompressionLevel::Fastest))
 		.layer(DefaultBodyLimit::max(10 * 1024 * 1024)) // 10MB
 		// Uncompressed
 		.routes(routes!(get_audio))
 }
 
+fn sonos_bridge_router(app: &App) -> OpenApiRouter<App> {
+	OpenApiRouter::new()
+		.routes(routes!(post_sonos_play))
+		.routes(routes!(post_sonos_play_uri))
+		.routes(routes!(post_sonos_play_search))
+		.routes(routes!(post_sonos_play_album))
+		.routes(routes!(post_sonos_play_playlist))
+		.routes(routes!(put_sonos_play_playlist))
+		.routes(routes!(put_sonos_play_directory))
+		.routes(routes!(post_sonos_play_random_album))
+		.routes(routes!(post_sonos_play_recent_album))
+		.routes(routes!(post_sonos_export_playlist))
+		.routes(routes!(get_sonos_status))
+		.routes(routes!(get_sonos_speakers))
+		.routes(routes!(get_sonos_speakers_by_name))
+		.routes(routes!(get_sonos_zones))
+		.routes(routes!(post_sonos_join))
+		.routes(routes!(post_sonos_leave))
+		.routes(routes!(get_sonos_firmware))
+		.routes(routes!(post_sonos_speakers_refresh))
+		.routes(routes!(get_sonos_state))
+		.routes(routes!(get_sonos_now_playing))
+		.routes(routes!(get_sonos_album_art))
+		.routes(routes!(get_sonos_queue, delete_sonos_queue))
+		.routes(routes!(get_sonos_favorites))
+		.routes(routes!(post_sonos_play_favorite))
+		.routes(routes!(post_sonos_queue_index))
+		.routes(routes!(patch_sonos_queue_move))
+		.routes(routes!(delete_sonos_queue_entry))
+		.routes(routes!(put_sonos_crossfade))
+		.routes(routes!(put_sonos_shuffle))
+		.routes(routes!(put_sonos_repeat))
+		.routes(routes!(post_sonos_crossfade_queue))
+		.routes(routes!(put_sonos_volume))
+		.routes(routes!(post_sonos_volume_adjust))
+		.routes(routes!(put_sonos_sleep))
+		.routes(routes!(post_sonos_announce))
+		.routes(routes!(post_sonos_pause))
+		.routes(routes!(post_sonos_end_interrupt))
+		.routes(routes!(post_sonos_stop))
+		.routes(routes!(post_sonos_resume))
+		.routes(routes!(post_sonos_next))
+		.routes(routes!(post_sonos_seek))
+		.routes(routes!(post_sonos_previous))
+		.routes(routes!(post_sonos_resume_last))
+		.routes(routes!(post_sonos_default_pause))
+		.routes(routes!(post_sonos_default_stop))
+		.routes(routes!(post_sonos_default_resume_last))
+		.routes(routes!(post_sonos_scene_apply))
+		.routes(routes!(post_sonos_pause_all))
+		.routes(routes!(post_sonos_stop_all))
+		.routes(routes!(get_sonos_eq, put_sonos_eq))
+		.routes(routes!(get_sonos_transport))
+		.routes(routes!(post_sonos_snapshot))
+		.routes(routes!(post_sonos_restore))
+		.routes(routes!(post_sonos_mute, put_sonos_mute))
+		.routes(routes!(post_sonos_unmute))
+		.routes(routes!(post_sonos_toggle_mute))
+		.route_layer(middleware::from_fn_with_state(
+			app.config_manager.clone(),
+			sonos_api_url_override,
+		))
+		.route_layer(middleware::from_fn_with_state(
+			app.config_manager.clone(),
+			sonos_timeout,
+		))
+		.route_layer(middleware::from_fn_with_state(
+			app.clone(),
+			record_sonos_history,
+		))
+		.route_layer(RateLimitLayer::new(app.config_manager.clone()))
+		.route_layer(middleware::from_fn_with_state(
+			app.config_manager.clone(),
+			require_sonos_enabled,
+		))
+}
+
+/// Answers requests to the endpoints contacting the Sonos bridge when Sonos is disabled,
+/// without building any client for it
+async fn require_sonos_enabled(
+	State(config_manager): State<config::Manager>,
+	request: Request,
+	next: Next,
+) -> Result<Response, APIError> {
+	if !config_manager.get_sonos_config().await.is_enabled() {
+		return Err(APIError::SonosDisabled);
+	}
+	Ok(next.run(request).await)
+}
+
+/// Bridge a request is sent to, as picked by `sonos_api_url_override`
+#[derive(Clone, Default)]
+struct SonosBridge {
+	api_url: Option<String>,
+}
+
+impl SonosBridge {
+	async fn service(&self, sonos_manager: &sonos::Manager) -> Arc<SonosService> {
+		match &self.api_url {
+			Some(api_url) => sonos_manager.service_with_api_url(api_url).await,
+			None => sonos_manager.service().await,
+		}
+	}
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for SonosBridge {
+	type Rejection = Infallible;
+
+	async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+		Ok(parts
+			.extensions
+			.get::<SonosBridge>()
+			.cloned()
+			.unwrap_or_default())
+	}
+}
+
+/// Sends requests with a `X-Sonos-Api-Override-Url` header to the node-sonos-http-api instance it names,
+/// when the `allow_url_override` setting is on
+async fn sonos_api_url_override(
+	State(config_manager): State<config::Manager>,
+	mut request: Request,
+	next: Next,
+) -> Result<Response, APIError> {
+	let Some(header) = request.headers().get(SONOS_API_URL_OVERRIDE_HEADER) else {
+		return Ok(next.run(request).await);
+	};
+	let api_url = String::from_utf8_lossy(header.as_bytes()).trim().to_owned();
+	if !config_manager.get_sonos_config().await.allow_url_override {
+		return Err(APIError::SonosUrlOverrideDisabled);
+	}
+	config::validate_api_url(&api_url)
+		.map_err(|_| APIError::SonosUrlOverrideInvalid(api_url.clone()))?;
+	request.extensions_mut().insert(SonosBridge {
+		api_url: Some(api_url),
+	});
+	Ok(next.run(request).await)
+}
+
+/// Gives up on requests node-sonos-http-api takes too long to answer, as it does during firmware updates.
+/// Dropping the handler also cancels its pending requests to the bridge.
+async fn sonos_timeout(
+	State(config_manager): State<config::Manager>,
+	matched_path: MatchedPath,
+	params: RawPathParams,
+	request: Request,
+	next: Next,
+) -> Result<Response, APIError> {
+	let timeout = config_manager
+		.get_sonos_config()
+		.await
+		.get_handler_timeout();
+	match tokio::time::timeout(timeout, next.run(request)).await {
+		Ok(response) => Ok(response),
+		Err(_) => {
+			let action = sonos_action(matched_path.as_str()).to_owned();
+			let speaker = params
+				.iter()
+				.find(|(name, _)| *name == "speaker_id")
+				.map(|(_, value)| value.to_string());
+			warn!(
+				"Abandoned Sonos `{action}` request after {}ms",
+				timeout.as_millis()
+			);
+			Err(APIError::SonosTimeout { action, speaker })
+		}
+	}
+}
+
+/// Largest request body of a Sonos command which is recorded in the history
+const MAX_SONOS_COMMAND_SIZE: usize = 1024 * 1024;
+
+/// Records the commands authenticated users send to speakers in the Sonos history, along with their outcome.
+/// Commands which fail are recorded as well, and requests which only read from the bridge are not recorded.
+/// Credentials found in the parameters or error of a command, such as those of CIFS shares, are not stored.
+async fn record_sonos_history(
+	State(app): State<App>,
+	matched_path: MatchedPath,
+	params: RawPathParams,
+	request: Request,
+	next: Next,
+) -> Response {
+	if request.method() == http::Method::GET {
+		return next.run(request).await;
+	}
+	let max_entries = app
+		.config_manager
+		.get_sonos_config()
+		.await
+		.get_history_max_entries();
+	if max_entries == 0 {
+		return next.run(request).await;
+	}
+	let (mut parts, body) = request.into_parts();
+	let Ok(auth) = Auth::from_request_parts(&mut parts, &app).await else {
+		return next.run(Request::from_parts(parts, body)).await;
+	};
+	let Ok(body) = axum::body::to_bytes(body, MAX_SONOS_COMMAND_SIZE).await else {
+		return http::StatusCode::PAYLOAD_TOO_LARGE.into_response();
+	};
+
+	let mut parameters = serde_json::Map::new();
+	for (name, value) in params.iter() {
+		parameters.insert(name.to_owned(), value.into());
+	}
+	if let Ok(serde_json::Value::Object(fields)) = serde_json::from_slice(&body) {
+		parameters.extend(fields);
+	}
+	let speaker_id = parameters
+		.get("speaker_id")
+		.and_then(|s| s.as_str())
+		.map(|s| s.to_owned());
+	let recorded_at = sonos_history::now_millis();
+
+	let response = next
+		.run(Request::from_parts(parts, axum::body::Body::from(body)))
+		.await;
+	let (response_parts, response_body) = response.into_parts();
+	let response_body = axum::body::to_bytes(response_body, usize::MAX)
+		.await
+		.unwrap_or_default();
+	let speaker_id = match speaker_id {
+		Some(speaker_id) => Some(resolve_history_speaker(&app.sonos_manager, speaker_id).await),
+		None => None,
+	};
+
+	// Some commands answer with a success status while reporting that they failed
+	let response_json = serde_json::from_slice::<serde_json::Value>(&response_body).ok();
+	let reported_failure = response_json
+		.as_ref()
+		.filter(|r| r.get("success") == Some(&serde_json::Value::Bool(false)));
+	let json_message = response_json
+		.as_ref()
+		.and_then(|r| r.get("message"))
+		.and_then(|m| m.as_str());
+	let status = response_parts.status;
+	let error = match (reported_failure, json_message) {
+		(Some(_), message) => message.map(|m| sonos::redact_credentials(m).into_owned()),
+		// Errors are answered with an `ErrorResponse`, or in plain text
+		(None, Some(message)) if !status.is_success() => {
+			Some(sonos::redact_credentials(message).into_owned())
+		}
+		(None, None) if !status.is_success() => {
+			Some(sonos::redact_credentials(&String::from_utf8_lossy(&response_body)).into_owned())
+		}
+		_ => None,
+	};
+	let parameters = serde_json::Value::Object(parameters).to_string();
+	let entry = sonos_history::HistoryEntry {
+		recorded_at,
+		username: auth.get_username().clone(),
+		speaker_id,
+		action: sonos_action(matched_path.as_str()).to_owned(),
+		parameters: sonos::redact_credentials(&parameters).into_owned(),
+		success: status.is_success() && reported_failure.is_none(),
+		status: status.as_u16(),
+		error,
+	};
+	if let Err(e) = app.sonos_history_manager.record(entry, max_entries).await {
+		warn!("Could not record Sonos command in the history: {e}");
+	}
+	Response::from_parts(response_parts, axum::body::Body::from(response_body))
+}
+
+/// Speakers are kept in the history by UUID, so that commands naming them by room name are listed along.
+/// Speakers which cannot be told apart, such as DLNA renderers, are kept as they were named.
+async fn resolve_history_speaker(sonos_manager: &sonos::Manager, speaker_id: String) -> String {
+	sonos_manager
+		.service()
+		.await
+		.resolve_speaker_id(&speaker_id)
+		.await
+		.unwrap_or(speaker_id)
+}
+
+/// Last segment of a Sonos route which is not a parameter, such as `volume` for `/sonos/{speaker_id}/volume`
+fn sonos_action(route: &str) -> &str {
+	route
+		.rsplit('/')
+		.find(|s| !s.is_empty() && !s.starts_with('{'))
+		.unwrap_or(route)
+}
+
 #[utoipa::path(
 	get,
 	path = "/version",
@@ -344,6 +670,15 @@ async fn put_user(
 		config_manager.set_is_admin(&name, *is_admin).await?;
 	}
 
+	if let Some(allow_sonos) = &user_update.new_allow_sonos {
+		config_manager.set_allow_sonos(&name, *allow_sonos).await?;
+	}
+
+	if let Some(speakers) = &user_update.new_allowed_speakers {
+		let speakers = Some(speakers.clone()).filter(|s| !s.is_empty());
+		config_manager.set_allowed_speakers(&name, speakers).await?;
+	}
+
 	Ok(())
 }
 
@@ -1128,7 +1463,7 @@ async fn delete_playlist(
 	)
 )]
 async fn get_audio(
-	_auth: Auth,
+	_auth: AudioAuth,
 	State(config_manager): State<config::Manager>,
 	Path(path): Path<PathBuf>,
 	range: Option<TypedHeader<Range>>,
@@ -1219,88 +1554,2359 @@ async fn get_thumbnail(
 
 // === Sonos endpoints ===
 
+/// Speaker a request applies to: the one it names, or else the configured default speaker
+fn requested_speaker(
+	speaker_id: Option<String>,
+	config: &config::SonosConfig,
+) -> Result<String, APIError> {
+	speaker_id
+		.filter(|s| !s.is_empty())
+		.or_else(|| config.get_default_speaker())
+		.ok_or(APIError::SonosNoDefaultSpeaker)
+}
+
+fn music_share(config: &config::SonosConfig) -> Result<config::MusicShare, APIError> {
+	config.get_music_share().ok_or(APIError::SonosNoMusicShare)
+}
+
+/// The default speaker may be set by UUID or by room name
+fn mark_default_speaker(speakers: &mut [SonosSpeaker], config: &config::SonosConfig) {
+	let default_speaker = config.get_default_speaker();
+	for speaker in speakers {
+		speaker.is_default = default_speaker
+			.as_ref()
+			.is_some_and(|d| *d == speaker.id || *d == speaker.name);
+	}
+}
+
 #[utoipa::path(
-    get,
-    path = "/sonos/speakers",
-    tag = "Sonos",
-    description = "List available Sonos speakers from node-sonos-http-api.",
-    security(("auth_token" = []), ("auth_query_param" = [])),
-    responses((status = 200, body = [SonosSpeaker]))
+	get,
+	path = "/sonos/speakers",
+	tag = "Sonos",
+	description = "List available Sonos speakers from node-sonos-http-api, followed by the DLNA renderers of the Sonos settings. The `backend` of each speaker tells which is which. The default speaker is flagged with `is_default`.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	responses((status = 200, body = [SonosSpeaker]))
 )]
 async fn get_sonos_speakers(
-    _auth: Auth,
-    State(config_manager): State<config::Manager>
+	_auth: Auth,
+	State(config_manager): State<config::Manager>,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
 ) -> Result<Json<Vec<SonosSpeaker>>, APIError> {
-    let base_url = config_manager.get_sonos_api_url().await
-        .unwrap_or_else(|| "http://192.168.0.5:5005".to_string());
+	let config = config_manager.get_sonos_config().await;
+	let service = bridge.service(&sonos_manager).await;
+	let dlna = sonos_manager.dlna_renderer().await;
+	let native = sonos_manager.native_sonos().await;
+	let mut renderers = Vec::<&dyn Renderer>::new();
+	if let Some(native) = &native {
+		renderers.push(native);
+	}
+	if native.is_none() || config.get_api_url().is_some() {
+		renderers.push(&*service);
+	}
+	renderers.push(&dlna);
+	let mut speakers = sonos::list_all(&renderers)
+		.await
+		.map_err(|_| APIError::Internal)?;
+	mark_default_speaker(&mut speakers, &config);
+	Ok(Json(speakers))
+}
 
-    let service = SonosService::new(base_url);
-    let speakers = service
-        .get_speakers()
-        .await
-        .map_err(|_| APIError::Internal)?;
-    Ok(Json(speakers))
+/// Natively controlled Sonos speaker `speaker_id`, if any. Speakers are only controlled natively when `native_control` is set.
+async fn native_speaker(sonos_manager: &sonos::Manager, speaker_id: &str) -> Option<NativeSonos> {
+	let native = sonos_manager.native_sonos().await?;
+	native.has_speaker(speaker_id).await.then_some(native)
 }
 
 #[utoipa::path(
-    post,
-    path = "/sonos/play",
-    tag = "Sonos",
-    description = "Play a track URL on a specific Sonos speaker via node-sonos-http-api.",
-    security(("auth_token" = []), ("auth_query_param" = [])),
-    request_body = PlayTrackRequest,
-    responses((status = 200, body = SonosResponse))
+	get,
+	path = "/sonos/zones",
+	tag = "Sonos",
+	description = "List groups of Sonos speakers from node-sonos-http-api, with every member of each group.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	responses(
+		(status = 200, body = [SonosZone]),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
 )]
-async fn post_sonos_play(
-    _auth: Auth,
-    State(config_manager): State<config::Manager>,
-    Json(req): Json<PlayTrackRequest>,
+async fn get_sonos_zones(
+	_auth: Auth,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+) -> Result<Json<Vec<SonosZone>>, APIError> {
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.get_zones().await?))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/{speaker_id}/join",
+	tag = "Sonos",
+	description = "Add a Sonos speaker to the group of another speaker via node-sonos-http-api. The speaker leaves its current group first, then plays whatever its new group plays.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker joining the group")
+	),
+	request_body = JoinGroupRequest,
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 400, description = "Both speakers are the same"),
+		(status = 403, description = "User is not allowed to control one of the Sonos speakers"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_join(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+	Json(req): Json<JoinGroupRequest>,
 ) -> Result<Json<SonosResponse>, APIError> {
-    let base_url = config_manager.get_sonos_api_url().await
-        .unwrap_or_else(|| "http://192.168.0.5:5005".to_string());
-
-    let service = SonosService::new(base_url);
-    let mp3_server = config_manager.get_sonos_mp3_server().await
-        .unwrap_or_else(|| "http://192.168.0.5:5005".to_string());
-
-    let res = service
-        .play_track(&req.speaker_id, &req.track_url, &mp3_server)
-        .await
-        .map_err(|_| APIError::Internal)?;
-    Ok(Json(res))
-}
-
-#[utoipa::path(
-    get,
-    path = "/sonos/state/{speaker_id}",
-    tag = "Sonos",
-    description = "Get the current playback state of a specific Sonos speaker via node-sonos-http-api.",
-    security(("auth_token" = []), ("auth_query_param" = [])),
-    params(
-        ("speaker_id", example = "Living Room", description = "The ID/name of the Sonos speaker")
-    ),
-    responses(
-        (status = 200, body = SonosState),
-        (status = 404, description = "Speaker not found or Sonos service unavailable")
-    )
+	sonos_rights.check_speaker(&speaker_id).await?;
+	sonos_rights.check_speaker(&req.speaker_id).await?;
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(
+		service.join_group(&speaker_id, &req.speaker_id).await?,
+	))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/{speaker_id}/leave",
+	tag = "Sonos",
+	description = "Take a Sonos speaker out of its group via node-sonos-http-api, so it plays on its own.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
 )]
-async fn get_sonos_state(
-    _auth: Auth,
-    State(config_manager): State<config::Manager>,
-    Path(speaker_id): Path<String>,
-) -> Result<Json<SonosState>, APIError> {
-    let base_url = config_manager.get_sonos_api_url().await
-       .unwrap_or_else(|| "http://192.168.0.5:5005".to_string());
+async fn post_sonos_leave(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.leave_group(&speaker_id).await?))
+}
+
+#[utoipa::path(
+	get,
+	path = "/sonos/firmware",
+	tag = "Sonos",
+	description = "List the firmware version of every Sonos speaker, by speaker id. Speakers which do not report their version are left out.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	responses(
+		(status = 200, body = HashMap<String, String>),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn get_sonos_firmware(
+	_auth: Auth,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+) -> Result<Json<HashMap<String, String>>, APIError> {
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.get_firmware_versions().await?))
+}
+
+#[utoipa::path(
+	get,
+	path = "/sonos/config",
+	tag = "Sonos",
+	description = "Reads the current Sonos settings, with default values filled in. Secrets used to authenticate to node-sonos-http-api are not included.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	responses(
+		(status = 200, body = dto::SonosSettings),
+	)
+)]
+async fn get_sonos_config(
+	_admin_rights: AdminRights,
+	State(config_manager): State<config::Manager>,
+) -> Json<dto::SonosSettings> {
+	Json(config_manager.get_sonos_config().await.into())
+}
+
+#[utoipa::path(
+	put,
+	path = "/sonos/config",
+	tag = "Sonos",
+	description = "Amends the Sonos settings. Changes apply to subsequent Sonos requests without restarting Polaris.\n\n`null` fields are left unchanged. Empty `api_url` or `mp3_server` values reset them to their defaults.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	request_body = dto::NewSonosSettings,
+	responses(
+		(status = 200),
+		(status = 422, description = "The new settings are invalid")
+	)
+)]
+async fn put_sonos_config(
+	_admin_rights: AdminRights,
+	State(config_manager): State<config::Manager>,
+	Json(new_settings): Json<dto::NewSonosSettings>,
+) -> Result<(), APIError> {
+	config_manager
+		.patch_sonos_config(new_settings.into())
+		.await?;
+	Ok(())
+}
+
+#[utoipa::path(
+	patch,
+	path = "/sonos/config",
+	tag = "Sonos",
+	description = "Changes the Sonos settings present in the request, leaving the others untouched. Changes apply to subsequent Sonos requests without restarting Polaris.\n\nEmpty `api_url` or `mp3_server` values reset them to their defaults. Nothing is saved if the resulting settings are invalid.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	request_body = dto::NewSonosSettings,
+	responses(
+		(status = 200),
+		(status = 422, description = "The new settings are invalid")
+	)
+)]
+async fn patch_sonos_config(
+	_admin_rights: AdminRights,
+	State(config_manager): State<config::Manager>,
+	Json(new_settings): Json<dto::NewSonosSettings>,
+) -> Result<(), APIError> {
+	config_manager
+		.patch_sonos_config(new_settings.into())
+		.await?;
+	Ok(())
+}
+
+#[utoipa::path(
+	get,
+	path = "/sonos/scenes",
+	tag = "Sonos",
+	description = "Lists the Sonos scenes, which set the volume and mute state of several speakers at once.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	responses(
+		(status = 200, body = Vec<dto::SonosScene>),
+		(status = 403, description = "User is not allowed to use Sonos")
+	)
+)]
+async fn get_sonos_scenes(
+	_sonos_rights: SonosRights,
+	State(config_manager): State<config::Manager>,
+) -> Json<Vec<dto::SonosScene>> {
+	let scenes = config_manager.get_sonos_config().await.scenes;
+	Json(scenes.into_iter().map(|s| s.into()).collect())
+}
+
+#[utoipa::path(
+	put,
+	path = "/sonos/scenes/{name}",
+	tag = "Sonos",
+	description = "Creates or replaces a Sonos scene.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(("name", example = "Movie night")),
+	request_body = dto::SaveSonosSceneInput,
+	responses(
+		(status = 200),
+		(status = 422, description = "The scene name is empty or a volume is above 100")
+	)
+)]
+async fn put_sonos_scene(
+	_admin_rights: AdminRights,
+	State(config_manager): State<config::Manager>,
+	Path(name): Path<String>,
+	Json(scene): Json<dto::SaveSonosSceneInput>,
+) -> Result<(), APIError> {
+	config_manager
+		.set_sonos_scene(config::SonosScene {
+			name,
+			entries: scene.entries.into_iter().map(|e| e.into()).collect(),
+		})
+		.await?;
+	Ok(())
+}
+
+#[utoipa::path(
+	get,
+	path = "/sonos/scenes/{name}",
+	tag = "Sonos",
+	description = "Reads a Sonos scene.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(("name", example = "Movie night")),
+	responses(
+		(status = 200, body = dto::SonosScene),
+		(status = 403, description = "User is not allowed to use Sonos"),
+		(status = 404, description = "There is no scene with this name")
+	)
+)]
+async fn get_sonos_scene(
+	_sonos_rights: SonosRights,
+	State(config_manager): State<config::Manager>,
+	Path(name): Path<String>,
+) -> Result<Json<dto::SonosScene>, APIError> {
+	Ok(Json(config_manager.get_sonos_scene(&name).await?.into()))
+}
+
+#[utoipa::path(
+	delete,
+	path = "/sonos/scenes/{name}",
+	tag = "Sonos",
+	description = "Deletes a Sonos scene.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(("name", example = "Movie night")),
+	responses(
+		(status = 200),
+		(status = 404, description = "There is no scene with this name")
+	)
+)]
+async fn delete_sonos_scene(
+	_admin_rights: AdminRights,
+	State(config_manager): State<config::Manager>,
+	Path(name): Path<String>,
+) -> Result<(), APIError> {
+	config_manager.delete_sonos_scene(&name).await?;
+	Ok(())
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/scenes/{name}/apply",
+	tag = "Sonos",
+	description = "Sets the volume and mute state of every speaker of a Sonos scene, all at once. Speakers which node-sonos-http-api does not know about are skipped and listed in `warnings`, and a speaker which cannot be changed is reported with `success: false`, without affecting the others.\n\nUsers restricted to some speakers must be allowed to control every speaker of the scene.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(("name", example = "Movie night")),
+	responses(
+		(status = 200, body = SonosSceneResult),
+		(status = 403, description = "User is not allowed to control every speaker of the scene"),
+		(status = 404, description = "There is no scene with this name"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_scene_apply(
+	sonos_rights: SonosRights,
+	State(config_manager): State<config::Manager>,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(name): Path<String>,
+) -> Result<Json<SonosSceneResult>, APIError> {
+	let scene = config_manager.get_sonos_scene(&name).await?;
+	for entry in &scene.entries {
+		sonos_rights.check_speaker(&entry.speaker).await?;
+	}
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.apply_scene(&scene).await?))
+}
+
+#[utoipa::path(
+	get,
+	path = "/sonos/status",
+	tag = "Sonos",
+	description = "Check the health of the Sonos integration by contacting node-sonos-http-api.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	responses(
+		(status = 200, body = SonosStatus),
+	)
+)]
+async fn get_sonos_status(
+	_auth: Auth,
+	State(sonos_manager): State<sonos::Manager>,
+) -> Json<SonosStatus> {
+	Json(sonos_manager.status().await)
+}
 
+#[utoipa::path(
+	get,
+	path = "/sonos/speakers/by-name",
+	tag = "Sonos",
+	description = "Deprecated: list speakers like `/sonos/speakers` did before speakers were identified by UUID, with their room name as `id`. Room names are still accepted wherever a `speaker_id` is expected, so clients can migrate at their own pace.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	responses((status = 200, body = [SonosSpeaker]))
+)]
+async fn get_sonos_speakers_by_name(
+	auth: Auth,
+	config_manager: State<config::Manager>,
+	sonos_manager: State<sonos::Manager>,
+	bridge: SonosBridge,
+) -> Result<Json<Vec<SonosSpeaker>>, APIError> {
+	let Json(mut speakers) =
+		get_sonos_speakers(auth, config_manager, sonos_manager, bridge).await?;
+	for speaker in &mut speakers {
+		speaker.id = speaker.name.clone();
+	}
+	Ok(Json(speakers))
+}
 
-    let service = SonosService::new(base_url);
-    let state = service
-        .get_state(&speaker_id)
-        .await
-        .map_err(|_| APIError::Internal)?;
-    
-    Ok(Json(state))
+#[utoipa::path(
+	post,
+	path = "/sonos/speakers/refresh",
+	tag = "Sonos",
+	description = "Fetch the list of Sonos speakers from node-sonos-http-api, bypassing the speaker cache. Use this after adding or removing a speaker.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	responses(
+		(status = 200, body = [SonosSpeaker]),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_speakers_refresh(
+	_auth: Auth,
+	State(config_manager): State<config::Manager>,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+) -> Result<Json<Vec<SonosSpeaker>>, APIError> {
+	let service = bridge.service(&sonos_manager).await;
+	let mut speakers = service.refresh_speakers().await?;
+	mark_default_speaker(&mut speakers, &config_manager.get_sonos_config().await);
+	Ok(Json(speakers))
 }
 
+#[utoipa::path(
+	post,
+	path = "/sonos/play",
+	tag = "Sonos",
+	description = "Play tracks on a specific Sonos speaker via node-sonos-http-api. Tracks play on the default speaker when `speaker_id` is omitted.\n\nA single `track_url` starts playing immediately. The title, artist and album of songs from the collection are sent along with it, so that the speaker displays them right away. A list of `track_urls` replaces the queue of the speaker and plays it in order.\n\nWith `dry_run`, the URIs which would be sent to the speaker are returned without contacting node-sonos-http-api.\n\nWith `idempotent`, a single `track_url` is not sent to the speaker if it is already playing a track with the same title and artist, so that playback does not start over.\n\nThe `mode` of a single `track_url` picks what happens to what the speaker is playing: `replace` plays the track in its place, `enqueue_next` and `enqueue_end` add the track to the queue after the current track or at its end, and `interrupt` plays the track then goes back to what was playing once it ends or `end_interrupt` is called.\n\nDLNA renderers fetch tracks from Polaris themselves, so they only play a single `track_url` pointing to the Polaris audio endpoint, in `replace` mode. So do Sonos speakers when `native_control` is set.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	request_body = PlayTrackRequest,
+	responses(
+		(status = 200, body = SonosPlayResponse),
+		(status = 400, description = "Neither or both of `track_url` and `track_urls` are set, `mode` is set along with `track_urls`, too many tracks are requested, or no speaker is named and there is no default speaker"),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_play(
+	sonos_rights: SonosRights,
+	State(config_manager): State<config::Manager>,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	State(index_manager): State<index::Manager>,
+	Json(req): Json<PlayTrackRequest>,
+) -> Result<Json<SonosPlayResponse>, APIError> {
+	let config = config_manager.get_sonos_config().await;
+	let speaker_id = requested_speaker(req.speaker_id.clone(), &config)?;
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let dlna = sonos_manager.dlna_renderer().await;
+	if dlna.has_renderer(&speaker_id) {
+		return Ok(Json(play_on_renderer(&dlna, &speaker_id, req).await?));
+	}
+	if let Some(native) = native_speaker(&sonos_manager, &speaker_id).await {
+		return Ok(Json(play_on_renderer(&native, &speaker_id, req).await?));
+	}
+	let share = music_share(&config)?;
+	let service = bridge.service(&sonos_manager).await;
+
+	match (req.track_url, req.track_urls) {
+		(Some(track_url), None) if req.dry_run => {
+			Ok(Json(SonosPlayResponse::dry_run(&[track_url], &share)))
+		}
+		(Some(track_url), None) => {
+			let metadata = track_metadata(&index_manager, &track_url).await;
+			let res = match (req.mode, &metadata) {
+				(PlayTrackMode::Replace, Some(metadata)) if req.idempotent => {
+					service
+						.play_track_idempotent(&speaker_id, &track_url, &share, metadata)
+						.await?
+				}
+				(PlayTrackMode::Replace, _) => {
+					service
+						.play_track(&speaker_id, &track_url, &share, metadata.as_ref())
+						.await?
+				}
+				(PlayTrackMode::EnqueueNext | PlayTrackMode::EnqueueEnd, _) => {
+					let next = req.mode == PlayTrackMode::EnqueueNext;
+					service
+						.enqueue_track(&speaker_id, &track_url, &share, metadata.as_ref(), next)
+						.await?
+				}
+				(PlayTrackMode::Interrupt, _) => {
+					sonos_manager
+						.interrupt(&speaker_id, &track_url, &share, metadata.as_ref())
+						.await?
+				}
+			};
+			let uri = sonos::track_url_to_share_uri(&track_url, &share)
+				.ok()
+				.map(|u| sonos::redact_credentials(&u).into_owned());
+			Ok(Json(SonosPlayResponse {
+				success: res.success,
+				playback_uri: uri.clone(),
+				share_uri: sonos::redact_credentials(&sonos::share_uri_prefix(&share)).into_owned(),
+				dry_run: false,
+				tracks: vec![SonosTrackResult {
+					track_url,
+					uri,
+					success: res.success,
+					error: (!res.success).then(|| res.message.clone()),
+				}],
+				message: res.message,
+			}))
+		}
+		(None, Some(_)) if req.mode != PlayTrackMode::Replace => Err(
+			APIError::SonosInvalidPlayRequest("`mode` only applies to `track_url`".to_owned()),
+		),
+		(None, Some(track_urls)) => {
+			check_track_urls(&track_urls, &config)?;
+			if req.dry_run {
+				return Ok(Json(SonosPlayResponse::dry_run(&track_urls, &share)));
+			}
+			let res = service
+				.play_tracks(&speaker_id, &track_urls, &share)
+				.await?;
+			Ok(Json(res))
+		}
+		_ => Err(APIError::SonosInvalidPlayRequest(
+			"Exactly one of `track_url` and `track_urls` must be set".to_owned(),
+		)),
+	}
+}
 
+/// Play a track on a DLNA renderer or a natively controlled Sonos speaker, which fetches it from the Polaris audio endpoint
+async fn play_on_renderer(
+	renderer: &dyn Renderer,
+	speaker_id: &str,
+	req: PlayTrackRequest,
+) -> Result<SonosPlayResponse, APIError> {
+	let track_url = match (req.track_url, req.track_urls, req.mode) {
+		(Some(track_url), None, PlayTrackMode::Replace) => track_url,
+		_ => {
+			return Err(APIError::SonosInvalidPlayRequest(
+				"DLNA renderers and natively controlled Sonos speakers only play a single `track_url` in `replace` mode".to_owned(),
+			))
+		}
+	};
+	if let Some(TrackUrl::NotAudio { endpoint }) = PolarisUrlBuilder::parse_track_url(&track_url) {
+		return Err(sonos::SonosError::NotAudioUrl {
+			url: track_url,
+			endpoint,
+		}
+		.into());
+	}
 
+	let res = match req.dry_run {
+		true => SonosResponse {
+			success: true,
+			message: "1 of 1 tracks can be played".to_owned(),
+			..Default::default()
+		},
+		false => renderer.play(speaker_id, &track_url).await?,
+	};
+	Ok(SonosPlayResponse {
+		success: res.success,
+		playback_uri: Some(track_url.clone()),
+		share_uri: String::new(),
+		dry_run: req.dry_run,
+		tracks: vec![SonosTrackResult {
+			uri: Some(track_url.clone()),
+			track_url,
+			success: res.success,
+			error: None,
+		}],
+		message: res.message,
+	})
+}
+
+/// Reject lists of tracks which are empty or longer than `max_batch_size`
+fn check_track_urls(track_urls: &[String], config: &config::SonosConfig) -> Result<(), APIError> {
+	let max_batch_size = config.get_max_batch_size();
+	if track_urls.is_empty() {
+		return Err(APIError::SonosInvalidPlayRequest(
+			"`track_urls` is empty".to_owned(),
+		));
+	}
+	if track_urls.len() > max_batch_size {
+		return Err(APIError::SonosInvalidPlayRequest(format!(
+			"Cannot play more than {max_batch_size} tracks at once"
+		)));
+	}
+	Ok(())
+}
+
+/// Now-playing details of the collection song `track_url` points to, if its title and artist are known
+async fn track_metadata(index_manager: &index::Manager, track_url: &str) -> Option<TrackMetadata> {
+	let path = match PolarisUrlBuilder::parse_track_url(track_url)? {
+		TrackUrl::Audio(path) | TrackUrl::Relative(path) => PathBuf::from(path),
+		TrackUrl::NotAudio { .. } => return None,
+	};
+	let song = index_manager
+		.get_songs(vec![path])
+		.await
+		.into_iter()
+		.next()?
+		.ok()?;
+	Some(TrackMetadata {
+		title: song.title?,
+		artist: song.artists.into_iter().next()?,
+		album: song.album,
+		album_art_url: None,
+	})
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/{speaker_id}/play-uri",
+	tag = "Sonos",
+	description = "Play an arbitrary URI (internet radio stream, HTTP(S) file, CIFS path...) on a specific Sonos speaker via node-sonos-http-api.\n\nURIs listed in `next_uris` are played one after the other once the current one ends. The session stops when the last one ends, or when the speaker starts playing something else.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	request_body = PlayUriRequest,
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 400, description = "The URI could not be parsed"),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_play_uri(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	Path(speaker_id): Path<String>,
+	Json(req): Json<PlayUriRequest>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let response = sonos_manager
+		.play_session(&speaker_id, &req.uri, req.next_uris)
+		.await?;
+	Ok(Json(response))
+}
+
+#[utoipa::path(
+	get,
+	path = "/sonos/{speaker_id}/session",
+	tag = "Sonos",
+	description = "Get the tracks Polaris plays one after the other on a specific Sonos speaker, as started with `next_uris`.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	responses(
+		(status = 200, body = SonosSession),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 404, description = "No session is running on this speaker")
+	)
+)]
+async fn get_sonos_session(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	Path(speaker_id): Path<String>,
+) -> Result<Json<SonosSession>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let room_name = sonos_manager.service().await.room_name(&speaker_id).await;
+	Ok(Json(sonos_manager.get_session(&room_name)?))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/{speaker_id}/play-search",
+	tag = "Sonos",
+	description = "Search the collection and play the matching songs on a specific Sonos speaker, replacing its queue. At most `max_batch_size` songs are queued.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	request_body = PlaySearchRequest,
+	responses(
+		(status = 200, body = SonosPlaylistResult),
+		(status = 400, description = "The search query could not be parsed"),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_play_search(
+	sonos_rights: SonosRights,
+	State(config_manager): State<config::Manager>,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+	Json(req): Json<PlaySearchRequest>,
+) -> Result<Json<SonosPlaylistResult>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let config = config_manager.get_sonos_config().await;
+	let service = bridge.service(&sonos_manager).await;
+	let result = service
+		.play_search(
+			&speaker_id,
+			&req.query,
+			config.get_max_batch_size(),
+			&music_share(&config)?,
+		)
+		.await?;
+	Ok(Json(result))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/{speaker_id}/play-album",
+	tag = "Sonos",
+	description = "Play the tracks of an album on a specific Sonos speaker, replacing its queue. Tracks are played in the order they are listed, and at most `max_batch_size` of them can be sent.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	request_body = PlayAlbumRequest,
+	responses(
+		(status = 200, body = SonosPlayResponse),
+		(status = 400, description = "No track or too many tracks are requested, or no Sonos file server is configured"),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_play_album(
+	sonos_rights: SonosRights,
+	State(config_manager): State<config::Manager>,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+	Json(req): Json<PlayAlbumRequest>,
+) -> Result<Json<SonosPlayResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let config = config_manager.get_sonos_config().await;
+	check_track_urls(&req.track_urls, &config)?;
+	let share = music_share(&config)?;
+	let service = bridge.service(&sonos_manager).await;
+	let response = service
+		.play_album(&speaker_id, &req.track_urls, &share)
+		.await?;
+	Ok(Json(response))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/{speaker_id}/play-playlist",
+	tag = "Sonos",
+	description = "Play the tracks of a playlist on a specific Sonos speaker, replacing its queue. At most `max_batch_size` tracks can be sent.\n\nWith `shuffle`, tracks are queued in random order. The response includes the seed of this order, which can be sent back as `seed` to queue the tracks in the same order again.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	request_body = PlayPlaylistRequest,
+	responses(
+		(status = 200, body = SonosPlaylistPlayResponse),
+		(status = 400, description = "No track or too many tracks are requested, or no Sonos file server is configured"),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_play_playlist(
+	sonos_rights: SonosRights,
+	State(config_manager): State<config::Manager>,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+	Json(req): Json<PlayPlaylistRequest>,
+) -> Result<Json<SonosPlaylistPlayResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let config = config_manager.get_sonos_config().await;
+	check_track_urls(&req.track_urls, &config)?;
+	let share = music_share(&config)?;
+	let service = bridge.service(&sonos_manager).await;
+	let response = service
+		.play_playlist(&speaker_id, &req.track_urls, &share, req.shuffle, req.seed)
+		.await?;
+	Ok(Json(response))
+}
+
+/// Tracks of the collection to send to Sonos, by path, which is accepted wherever a track URL is
+fn collection_tracks(
+	paths: &[PathBuf],
+	config: &config::SonosConfig,
+) -> Result<Vec<String>, APIError> {
+	let max_batch_size = config.get_max_batch_size();
+	if paths.is_empty() {
+		return Err(APIError::SonosInvalidPlayRequest(
+			"There is no track to play".to_owned(),
+		));
+	}
+	if paths.len() > max_batch_size {
+		return Err(APIError::SonosInvalidPlayRequest(format!(
+			"Cannot play more than {max_batch_size} tracks at once"
+		)));
+	}
+	Ok(paths
+		.iter()
+		.map(|p| p.to_string_lossy().into_owned())
+		.collect())
+}
+
+#[utoipa::path(
+	put,
+	path = "/sonos/play_playlist",
+	tag = "Sonos",
+	description = "Play one of the current user's playlists on a Sonos speaker, replacing its queue. Tracks are looked up by Polaris and queued in playlist order, or in random order with `shuffle`. At most `max_batch_size` tracks can be played.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	request_body = PlaySavedPlaylistRequest,
+	responses(
+		(status = 200, body = SonosPlaylistPlayResponse),
+		(status = 400, description = "No speaker is named and there is no default speaker, the playlist is empty or too long, or no Sonos file server is configured"),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 404, description = "Playlist not found"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn put_sonos_play_playlist(
+	sonos_rights: SonosRights,
+	State(config_manager): State<config::Manager>,
+	State(playlist_manager): State<playlist::Manager>,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Json(req): Json<PlaySavedPlaylistRequest>,
+) -> Result<Json<SonosPlaylistPlayResponse>, APIError> {
+	let config = config_manager.get_sonos_config().await;
+	let speaker_id = requested_speaker(req.speaker_id, &config)?;
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let playlist = playlist_manager
+		.read_playlist(&req.name, sonos_rights.get_username())
+		.await?;
+	let track_urls = collection_tracks(&playlist.songs, &config)?;
+	let share = music_share(&config)?;
+	let service = bridge.service(&sonos_manager).await;
+	let response = service
+		.play_playlist(&speaker_id, &track_urls, &share, req.shuffle, req.seed)
+		.await?;
+	Ok(Json(response))
+}
+
+#[utoipa::path(
+	put,
+	path = "/sonos/play_directory",
+	tag = "Sonos",
+	description = "Play every track under a directory of the collection, such as an album, on a Sonos speaker, replacing its queue. Tracks are looked up by Polaris and queued in collection order. At most `max_batch_size` tracks can be played.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	request_body = PlayDirectoryRequest,
+	responses(
+		(status = 200, body = SonosPlayResponse),
+		(status = 400, description = "No speaker is named and there is no default speaker, the directory has no track or too many, or no Sonos file server is configured"),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 404, description = "Directory not found"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn put_sonos_play_directory(
+	sonos_rights: SonosRights,
+	State(config_manager): State<config::Manager>,
+	State(index_manager): State<index::Manager>,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Json(req): Json<PlayDirectoryRequest>,
+) -> Result<Json<SonosPlayResponse>, APIError> {
+	let config = config_manager.get_sonos_config().await;
+	let speaker_id = requested_speaker(req.speaker_id, &config)?;
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let paths = index_manager.flatten(PathBuf::from(req.path)).await?;
+	let track_urls = collection_tracks(&paths, &config)?;
+	let share = music_share(&config)?;
+	let service = bridge.service(&sonos_manager).await;
+	let response = service.play_album(&speaker_id, &track_urls, &share).await?;
+	Ok(Json(response))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/play_random_album",
+	tag = "Sonos",
+	description = "Pick albums from the collection at random and play them on a Sonos speaker, replacing its queue. Albums with no track on the music share are passed over.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	request_body = PlayAlbumsRequest,
+	responses(
+		(status = 200, body = SonosAlbumsResult),
+		(status = 400, description = "No speaker is named and there is no default speaker"),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 404, description = "No album of the collection can be played"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_play_random_album(
+	sonos_rights: SonosRights,
+	State(config_manager): State<config::Manager>,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Json(req): Json<PlayAlbumsRequest>,
+) -> Result<Json<SonosAlbumsResult>, APIError> {
+	play_albums(
+		sonos_rights,
+		config_manager,
+		sonos_manager,
+		bridge,
+		AlbumSelection::Random,
+		req,
+	)
+	.await
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/play_recent_album",
+	tag = "Sonos",
+	description = "Play the albums most recently added to the collection on a Sonos speaker, replacing its queue. Albums with no track on the music share are passed over.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	request_body = PlayAlbumsRequest,
+	responses(
+		(status = 200, body = SonosAlbumsResult),
+		(status = 400, description = "No speaker is named and there is no default speaker"),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 404, description = "No album of the collection can be played"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_play_recent_album(
+	sonos_rights: SonosRights,
+	State(config_manager): State<config::Manager>,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Json(req): Json<PlayAlbumsRequest>,
+) -> Result<Json<SonosAlbumsResult>, APIError> {
+	play_albums(
+		sonos_rights,
+		config_manager,
+		sonos_manager,
+		bridge,
+		AlbumSelection::Recent,
+		req,
+	)
+	.await
+}
+
+async fn play_albums(
+	sonos_rights: SonosRights,
+	config_manager: config::Manager,
+	sonos_manager: sonos::Manager,
+	bridge: SonosBridge,
+	selection: AlbumSelection,
+	req: PlayAlbumsRequest,
+) -> Result<Json<SonosAlbumsResult>, APIError> {
+	let config = config_manager.get_sonos_config().await;
+	let speaker_id = requested_speaker(req.speaker_id, &config)?;
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let share = music_share(&config)?;
+	let count = req.count.unwrap_or(1) as usize;
+	let service = bridge.service(&sonos_manager).await;
+	let result = service
+		.play_albums(&speaker_id, selection, count, &share)
+		.await?;
+	Ok(Json(result))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/export_playlist",
+	tag = "Sonos",
+	description = "Save one of the current user's playlists as a Sonos playlist, so it can be picked from the Sonos app. The tracks are added to the queue of the given speaker, which is saved as a playlist and then restored.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	request_body = ExportPlaylistRequest,
+	responses(
+		(status = 200, body = SonosExportResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 404, description = "Playlist not found"),
+		(status = 409, description = "A Sonos playlist with this name already exists and `overwrite` is not set"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_export_playlist(
+	sonos_rights: SonosRights,
+	State(config_manager): State<config::Manager>,
+	State(playlist_manager): State<playlist::Manager>,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Json(req): Json<ExportPlaylistRequest>,
+) -> Result<Json<SonosExportResponse>, APIError> {
+	sonos_rights.check_speaker(&req.speaker_id).await?;
+	let playlist = playlist_manager
+		.read_playlist(&req.playlist_name, sonos_rights.get_username())
+		.await?;
+	let config = config_manager.get_sonos_config().await;
+	let service = bridge.service(&sonos_manager).await;
+	let response = service
+		.export_playlist(
+			&req.speaker_id,
+			&req.sonos_playlist_name,
+			&playlist.songs,
+			&music_share(&config)?,
+			req.overwrite,
+		)
+		.await?;
+	Ok(Json(response))
+}
+
+#[utoipa::path(
+	get,
+	path = "/sonos/state/{speaker_id}",
+	tag = "Sonos",
+	description = "Get the current playback state of a specific Sonos speaker via node-sonos-http-api.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	responses(
+		(status = 200, body = SonosState),
+		(status = 404, body = dto::ErrorResponse, description = "Speaker not found"),
+		(status = 502, body = dto::ErrorResponse, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn get_sonos_state(
+	_auth: Auth,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+) -> Result<Json<SonosState>, APIError> {
+	let dlna = sonos_manager.dlna_renderer().await;
+	if dlna.has_renderer(&speaker_id) {
+		return Ok(Json(dlna.state(&speaker_id).await?));
+	}
+	if let Some(native) = native_speaker(&sonos_manager, &speaker_id).await {
+		return Ok(Json(native.get_state(&speaker_id).await?));
+	}
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.get_state(&speaker_id).await?))
+}
+
+#[utoipa::path(
+	get,
+	path = "/sonos/now_playing",
+	tag = "Sonos",
+	description = "Get the playback state of every Sonos speaker at once.\n\nSpeakers whose state cannot be read are still listed, marked as unavailable and with an empty state.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	responses(
+		(status = 200, body = [SonosNowPlaying]),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn get_sonos_now_playing(
+	_auth: Auth,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+) -> Result<Json<Vec<SonosNowPlaying>>, APIError> {
+	let service = bridge.service(&sonos_manager).await;
+	let states = service.get_all_states().await?;
+	Ok(Json(
+		states
+			.into_iter()
+			.map(|(speaker, state)| SonosNowPlaying { speaker, state })
+			.collect(),
+	))
+}
+
+#[utoipa::path(
+	get,
+	path = "/sonos/{speaker_id}/albumart",
+	tag = "Sonos",
+	description = "Get the album art of the track currently playing on a Sonos speaker. The image is downloaded from the speaker, so clients do not need to reach it directly.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	responses(
+		(status = 200, body = [u8], content_type = "image/*"),
+		(status = 404, description = "The current track has no album art"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn get_sonos_album_art(
+	_auth: Auth,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+) -> Result<Response, APIError> {
+	let service = bridge.service(&sonos_manager).await;
+	let art = service.proxy_album_art(&speaker_id).await?;
+	let content_type = art
+		.content_type
+		.unwrap_or_else(|| "application/octet-stream".to_owned());
+	Ok(([(http::header::CONTENT_TYPE, content_type)], art.data).into_response())
+}
+
+#[utoipa::path(
+	delete,
+	path = "/sonos/albumart/cache",
+	tag = "Sonos",
+	description = "Forget album art downloaded from Sonos speakers, so it is fetched again on the next request.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	responses(
+		(status = 200),
+	)
+)]
+async fn delete_sonos_album_art_cache(_auth: Auth, State(sonos_manager): State<sonos::Manager>) {
+	sonos_manager.clear_album_art_cache();
+}
+
+#[utoipa::path(
+	get,
+	path = "/sonos/metrics",
+	tag = "Sonos",
+	description = "Number and duration of requests sent to node-sonos-http-api since Polaris started, by action and outcome, in the Prometheus text format. When the `enable_metrics` Sonos setting is `true`, operations run on speakers are also counted, by speaker and status.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	responses(
+		(status = 200, body = String, content_type = "text/plain"),
+		(status = 403, description = "User is not an administrator")
+	)
+)]
+async fn get_sonos_metrics(
+	_admin_rights: AdminRights,
+	State(sonos_manager): State<sonos::Manager>,
+) -> Response {
+	(
+		[(http::header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+		sonos_manager.metrics().render(),
+	)
+		.into_response()
+}
+
+#[utoipa::path(
+	get,
+	path = "/sonos/history",
+	tag = "Sonos",
+	description = "Most recent commands sent to Sonos speakers through Polaris, newest first, with the user who sent them and whether they succeeded. Up to `history_max_entries` commands are kept, and `limit` defaults to 50.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(dto::GetSonosHistoryParameters),
+	responses(
+		(status = 200, body = Vec<dto::SonosHistoryEntry>),
+		(status = 403, description = "User is not an administrator")
+	)
+)]
+async fn get_sonos_history(
+	_admin_rights: AdminRights,
+	State(sonos_history_manager): State<sonos_history::Manager>,
+	State(sonos_manager): State<sonos::Manager>,
+	Query(options): Query<dto::GetSonosHistoryParameters>,
+) -> Result<Json<Vec<dto::SonosHistoryEntry>>, APIError> {
+	let speaker_id = match options.speaker_id {
+		Some(speaker_id) => Some(resolve_history_speaker(&sonos_manager, speaker_id).await),
+		None => None,
+	};
+	let entries = sonos_history_manager
+		.read(speaker_id.as_deref(), options.limit.unwrap_or(50))
+		.await?;
+	Ok(Json(entries.into_iter().map(|e| e.into()).collect()))
+}
+
+#[utoipa::path(
+	get,
+	path = "/sonos/{speaker_id}/queue",
+	tag = "Sonos",
+	description = "List the tracks in the playback queue of a specific Sonos speaker via node-sonos-http-api.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	responses(
+		(status = 200, body = [SonosQueueEntry]),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn get_sonos_queue(
+	_auth: Auth,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+) -> Result<Json<Vec<SonosQueueEntry>>, APIError> {
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.get_queue(&speaker_id).await?))
+}
+
+#[utoipa::path(
+	delete,
+	path = "/sonos/{speaker_id}/queue",
+	tag = "Sonos",
+	description = "Remove every track from the queue of a specific Sonos speaker via node-sonos-http-api. Grouped speakers share the queue of the coordinator of their group, which is the one cleared.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn delete_sonos_queue(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.clear_queue(&speaker_id).await?))
+}
+
+#[utoipa::path(
+	get,
+	path = "/sonos/{speaker_id}/favorites",
+	tag = "Sonos",
+	description = "List the radio stations, playlists and tracks saved in the Sonos favorites via node-sonos-http-api.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	responses(
+		(status = 200, body = [SonosFavorite]),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn get_sonos_favorites(
+	_auth: Auth,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+) -> Result<Json<Vec<SonosFavorite>>, APIError> {
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.get_favorites(&speaker_id).await?))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/{speaker_id}/favorites/play",
+	tag = "Sonos",
+	description = "Play one of the Sonos favorites on a specific Sonos speaker via node-sonos-http-api.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	request_body = PlayFavoriteRequest,
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 404, description = "No favorite has this title"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_play_favorite(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+	Json(req): Json<PlayFavoriteRequest>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.play_favorite(&speaker_id, &req.title).await?))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/{speaker_id}/queue/index/{index}",
+	tag = "Sonos",
+	description = "Play the track at the given position of the queue of a specific Sonos speaker via node-sonos-http-api.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker"),
+		("index", example = 1, description = "Position of the track in the queue, starting at 1")
+	),
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_queue_index(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path((speaker_id, index)): Path<(String, u32)>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.play_queue_index(&speaker_id, index).await?))
+}
+
+#[utoipa::path(
+	patch,
+	path = "/sonos/{speaker_id}/queue/{index}/move",
+	tag = "Sonos",
+	description = "Move a track of the queue of a specific Sonos speaker to another position via node-sonos-http-api.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker"),
+		("index", example = 1, description = "Current position of the track in the queue, starting at 1")
+	),
+	request_body = MoveQueueEntryRequest,
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 422, description = "A position is outside of the current queue"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn patch_sonos_queue_move(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path((speaker_id, index)): Path<(String, u32)>,
+	Json(req): Json<MoveQueueEntryRequest>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(
+		service.move_queue_entry(&speaker_id, index, req.to).await?,
+	))
+}
+
+#[utoipa::path(
+	delete,
+	path = "/sonos/{speaker_id}/queue/{index}",
+	tag = "Sonos",
+	description = "Remove a track from the queue of a specific Sonos speaker via node-sonos-http-api.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker"),
+		("index", example = 1, description = "Position of the track in the queue, starting at 1")
+	),
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 422, description = "The position is outside of the current queue"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn delete_sonos_queue_entry(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path((speaker_id, index)): Path<(String, u32)>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.remove_queue_entry(&speaker_id, index).await?))
+}
+
+#[utoipa::path(
+	put,
+	path = "/sonos/{speaker_id}/volume",
+	tag = "Sonos",
+	description = "Set the volume of a specific Sonos speaker via node-sonos-http-api.\n\nVolume changes requested in quick succession (within the `volume_coalescing_ms` Sonos setting) are merged into one, and every request reports the volume which was finally applied.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	request_body = VolumeRequest,
+	responses(
+		(status = 200, body = SonosVolumeResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 422, description = "Volume is above 100"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn put_sonos_volume(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+	Json(req): Json<VolumeRequest>,
+) -> Result<Json<SonosVolumeResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	if let Some(native) = native_speaker(&sonos_manager, &speaker_id).await {
+		return Ok(Json(native.set_volume(&speaker_id, req.volume).await?));
+	}
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.set_volume(&speaker_id, req.volume).await?))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/{speaker_id}/volume/adjust",
+	tag = "Sonos",
+	description = "Raise or lower the volume of a specific Sonos speaker by a number of steps via node-sonos-http-api.\n\nThe change is applied to the volume the speaker currently reports, and the result stops at 0 and 100. Like volume changes, adjustments requested in quick succession are merged.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	request_body = VolumeAdjustRequest,
+	responses(
+		(status = 200, body = SonosVolumeResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable, returned an error or did not report the current volume")
+	)
+)]
+async fn post_sonos_volume_adjust(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+	Json(req): Json<VolumeAdjustRequest>,
+) -> Result<Json<SonosVolumeResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.adjust_volume(&speaker_id, req.delta).await?))
+}
+
+#[utoipa::path(
+	put,
+	path = "/sonos/{speaker_id}/crossfade",
+	tag = "Sonos",
+	description = "Turn crossfade between tracks on or off for a specific Sonos speaker via node-sonos-http-api.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	request_body = CrossfadeRequest,
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn put_sonos_crossfade(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+	Json(req): Json<CrossfadeRequest>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.set_crossfade(&speaker_id, req.enabled).await?))
+}
+
+#[utoipa::path(
+	put,
+	path = "/sonos/{speaker_id}/shuffle",
+	tag = "Sonos",
+	description = "Turn shuffle on or off for the queue of a specific Sonos speaker via node-sonos-http-api. Grouped speakers share the queue of the coordinator of their group, which is the one changed.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	request_body = ShuffleRequest,
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn put_sonos_shuffle(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+	Json(req): Json<ShuffleRequest>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.set_shuffle(&speaker_id, req.enabled).await?))
+}
+
+#[utoipa::path(
+	put,
+	path = "/sonos/{speaker_id}/repeat",
+	tag = "Sonos",
+	description = "Change whether the queue or the current track of a specific Sonos speaker starts over once it ends, via node-sonos-http-api. Grouped speakers share the queue of the coordinator of their group, which is the one changed.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	request_body = RepeatRequest,
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn put_sonos_repeat(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+	Json(req): Json<RepeatRequest>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.set_repeat(&speaker_id, req.mode).await?))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/{speaker_id}/crossfade-queue",
+	tag = "Sonos",
+	description = "Play tracks one after the other on a specific Sonos speaker, starting each track `overlap_seconds` seconds before the previous one ends. At most `max_batch_size` tracks can be sent.\n\nPolaris answers once the first track is scheduled, and plays the following tracks in the background until another transport or play command is sent to the speaker.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	request_body = CrossfadeQueueRequest,
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 400, description = "No track or too many tracks are requested, the overlap is longer than 15 seconds, or no Sonos file server is configured"),
+		(status = 403, description = "User is not allowed to control this Sonos speaker")
+	)
+)]
+async fn post_sonos_crossfade_queue(
+	sonos_rights: SonosRights,
+	State(config_manager): State<config::Manager>,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+	Json(req): Json<CrossfadeQueueRequest>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let config = config_manager.get_sonos_config().await;
+	check_track_urls(&req.tracks, &config)?;
+	let share = music_share(&config)?;
+	let service = bridge.service(&sonos_manager).await;
+	service
+		.crossfade_queue(&speaker_id, &req.tracks, &share, req.overlap_seconds)
+		.await?;
+	Ok(Json(SonosResponse {
+		success: true,
+		message: format!(
+			"Playing {} tracks with a {} second crossfade",
+			req.tracks.len(),
+			req.overlap_seconds
+		),
+		..Default::default()
+	}))
+}
+
+#[utoipa::path(
+	put,
+	path = "/sonos/{speaker_id}/sleep",
+	tag = "Sonos",
+	description = "Stop playback on a specific Sonos speaker after a delay, or cancel the current sleep timer when `seconds` is 0.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	request_body = SleepTimerRequest,
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 400, description = "Sleep timer is longer than Sonos allows"),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn put_sonos_sleep(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+	Json(req): Json<SleepTimerRequest>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let service = bridge.service(&sonos_manager).await;
+	let response = match req.seconds {
+		0 => service.clear_sleep_timer(&speaker_id).await?,
+		seconds => service.set_sleep_timer(&speaker_id, seconds).await?,
+	};
+	Ok(Json(response))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/{speaker_id}/announce",
+	tag = "Sonos",
+	description = "Speak a short message on a specific Sonos speaker, or on every available speaker when `speaker_id` is `all`. Music playing on the speaker is lowered during the announcement and resumes afterwards.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000002400", description = "UUID or room name of the Sonos speaker, or `all`")
+	),
+	request_body = AnnounceRequest,
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 400, description = "Announcement text is empty or longer than 200 characters, or volume is above 100"),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_announce(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+	Json(req): Json<AnnounceRequest>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let service = bridge.service(&sonos_manager).await;
+	let response = service
+		.announce(&speaker_id, &req.text, req.language, req.volume)
+		.await?;
+	Ok(Json(response))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/pause_all",
+	tag = "Sonos",
+	description = "Pause every Sonos speaker. A speaker which cannot be reached is reported with `success: false` without affecting the others.\n\nUsers restricted to some speakers must be allowed to control `all`.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	responses(
+		(status = 200, body = Vec<SonosSpeakerResponse>),
+		(status = 403, description = "User is not allowed to control every Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_pause_all(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+) -> Result<Json<Vec<SonosSpeakerResponse>>, APIError> {
+	sonos_rights.check_speaker(sonos::ALL_SPEAKERS).await?;
+	let service = bridge.service(&sonos_manager).await;
+	let results = service.pause_all().await?;
+	Ok(Json(speaker_responses(results)))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/stop_all",
+	tag = "Sonos",
+	description = "Stop playback on every Sonos speaker. A speaker which cannot be reached is reported with `success: false` without affecting the others.\n\nUsers restricted to some speakers must be allowed to control `all`.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	responses(
+		(status = 200, body = Vec<SonosSpeakerResponse>),
+		(status = 403, description = "User is not allowed to control every Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_stop_all(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+) -> Result<Json<Vec<SonosSpeakerResponse>>, APIError> {
+	sonos_rights.check_speaker(sonos::ALL_SPEAKERS).await?;
+	let service = bridge.service(&sonos_manager).await;
+	let results = service.stop_all().await?;
+	Ok(Json(speaker_responses(results)))
+}
+
+fn speaker_responses(results: Vec<(String, SonosResponse)>) -> Vec<SonosSpeakerResponse> {
+	results
+		.into_iter()
+		.map(|(speaker_id, response)| SonosSpeakerResponse {
+			speaker_id,
+			success: response.success,
+			message: response.message,
+		})
+		.collect()
+}
+
+#[utoipa::path(
+	get,
+	path = "/sonos/{speaker_id}/eq",
+	tag = "Sonos",
+	description = "Read the bass, treble, night mode and speech enhancement settings of a specific Sonos speaker via node-sonos-http-api. Settings the speaker does not report are `null`.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	responses(
+		(status = 200, body = EqSettings),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn get_sonos_eq(
+	_auth: Auth,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+) -> Result<Json<EqSettings>, APIError> {
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.get_eq(&speaker_id).await?))
+}
+
+#[utoipa::path(
+	get,
+	path = "/sonos/{speaker_id}/transport",
+	tag = "Sonos",
+	description = "Read the settings of a specific Sonos speaker which persist from one track to the next: its repeat and shuffle mode, and whether crossfade is on. Settings the bridge does not report are read as turned off.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	responses(
+		(status = 200, body = dto::SonosTransportSettings),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn get_sonos_transport(
+	_auth: Auth,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+) -> Result<Json<dto::SonosTransportSettings>, APIError> {
+	let service = bridge.service(&sonos_manager).await;
+	let settings = service.get_transport_settings(&speaker_id).await?;
+	Ok(Json(dto::SonosTransportSettings {
+		play_mode: settings.play_mode.into(),
+		crossfade: settings.crossfade,
+	}))
+}
+
+#[utoipa::path(
+	put,
+	path = "/sonos/{speaker_id}/eq",
+	tag = "Sonos",
+	description = "Change the equalizer settings of a specific Sonos speaker via node-sonos-http-api.\n\nOnly the provided fields are applied. Bass and treble levels outside -10..10 are clamped.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	request_body = EqSettings,
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn put_sonos_eq(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+	Json(settings): Json<EqSettings>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.set_eq(&speaker_id, &settings).await?))
+}
+
+/// Remember the Polaris track playing on a speaker and how far into it playback is,
+/// so `resume_last` can pick up from there. Failures only prevent resuming later.
+async fn save_resume_point(
+	service: &sonos::SonosService,
+	config: &config::SonosConfig,
+	resume_manager: &resume::Manager,
+	username: &str,
+	speaker_id: &str,
+) {
+	let state = match service.get_state(speaker_id).await {
+		Ok(state) => state,
+		Err(e) => {
+			warn!("Could not read state of Sonos speaker `{speaker_id}` to resume it later: {e}");
+			return;
+		}
+	};
+	let Some(virtual_path) = state
+		.track_uri
+		.zip(config.get_music_share())
+		.and_then(|(uri, share)| sonos::share_uri_to_path(&uri, &share))
+	else {
+		return;
+	};
+
+	let position = state.position.unwrap_or_default();
+	if let Err(e) = resume_manager
+		.save_resume_point(username, speaker_id, virtual_path, position)
+		.await
+	{
+		error!("Could not save resume point for Sonos speaker `{speaker_id}`: {e}");
+	}
+	if let Err(e) = resume_manager
+		.prune_resume_points(config.get_resume_retention())
+		.await
+	{
+		error!("Could not prune Sonos resume points: {e}");
+	}
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/{speaker_id}/snapshot",
+	tag = "Sonos",
+	description = "Save what a specific Sonos speaker is playing, along with its queue and play mode, so that it can be restored later. The previous snapshot of the speaker is replaced.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	responses(
+		(status = 200, body = dto::SonosSnapshot),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 404, description = "No snapshot directory is configured"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_snapshot(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+) -> Result<Json<dto::SonosSnapshot>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.snapshot_state(&speaker_id).await?.into()))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/{speaker_id}/restore",
+	tag = "Sonos",
+	description = "Play what a specific Sonos speaker was playing when its last snapshot was taken. Its queue is replaced with the saved one, and the current track continues from the saved position with the saved play mode. The speaker stays paused if it was not playing.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 404, description = "No snapshot directory is configured, or the speaker has no snapshot"),
+		(status = 410, description = "The snapshot is older than `snapshot_max_age_secs`"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_restore(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.restore_state(&speaker_id).await?))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/{speaker_id}/end_interrupt",
+	tag = "Sonos",
+	description = "Stop the track interrupting a specific Sonos speaker, as played with the `interrupt` mode, and go back to what the speaker was playing before. Interrupted tracks resume where they were, radio streams are tuned in again.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 404, description = "The speaker is not playing an interrupting track"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_end_interrupt(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	Path(speaker_id): Path<String>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	Ok(Json(sonos_manager.end_interrupt(&speaker_id).await?))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/{speaker_id}/pause",
+	tag = "Sonos",
+	description = "Pause a specific Sonos speaker via node-sonos-http-api. The current track and position are remembered for `resume_last`.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_pause(
+	sonos_rights: SonosRights,
+	State(config_manager): State<config::Manager>,
+	State(resume_manager): State<resume::Manager>,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let dlna = sonos_manager.dlna_renderer().await;
+	if dlna.has_renderer(&speaker_id) {
+		return Ok(Json(Renderer::pause(&dlna, &speaker_id).await?));
+	}
+	if let Some(native) = native_speaker(&sonos_manager, &speaker_id).await {
+		return Ok(Json(native.pause(&speaker_id).await?));
+	}
+	let config = config_manager.get_sonos_config().await;
+	let service = bridge.service(&sonos_manager).await;
+	let username = sonos_rights.get_username();
+	save_resume_point(&service, &config, &resume_manager, username, &speaker_id).await;
+	Ok(Json(service.pause(&speaker_id).await?))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/{speaker_id}/stop",
+	tag = "Sonos",
+	description = "Stop a specific Sonos speaker via node-sonos-http-api. The current track and position are remembered for `resume_last`.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_stop(
+	sonos_rights: SonosRights,
+	State(config_manager): State<config::Manager>,
+	State(resume_manager): State<resume::Manager>,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let config = config_manager.get_sonos_config().await;
+	let service = bridge.service(&sonos_manager).await;
+	let username = sonos_rights.get_username();
+	save_resume_point(&service, &config, &resume_manager, username, &speaker_id).await;
+	Ok(Json(service.stop(&speaker_id).await?))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/{speaker_id}/resume",
+	tag = "Sonos",
+	description = "Resume playback of whatever is paused or stopped on a specific Sonos speaker via node-sonos-http-api. Grouped speakers are resumed through the coordinator of their group.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_resume(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.resume(&speaker_id).await?))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/{speaker_id}/next",
+	tag = "Sonos",
+	description = "Skip to the next track in the queue of a specific Sonos speaker via node-sonos-http-api. Grouped speakers skip through the coordinator of their group.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_next(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.next(&speaker_id).await?))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/{speaker_id}/seek",
+	tag = "Sonos",
+	description = "Jump to another position of the track playing on a specific Sonos speaker via node-sonos-http-api. Grouped speakers seek through the coordinator of their group.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	request_body = SeekRequest,
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 422, description = "The position is past the end of the track, or the track cannot be sought"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_seek(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+	Json(req): Json<SeekRequest>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	if let Some(native) = native_speaker(&sonos_manager, &speaker_id).await {
+		return Ok(Json(native.seek(&speaker_id, req.position).await?));
+	}
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.seek(&speaker_id, req.position).await?))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/{speaker_id}/previous",
+	tag = "Sonos",
+	description = "Go back to the previous track in the queue of a specific Sonos speaker via node-sonos-http-api. Grouped speakers go back through the coordinator of their group.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_previous(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.previous(&speaker_id).await?))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/{speaker_id}/resume_last",
+	tag = "Sonos",
+	description = "Play the track the current user last paused or stopped on a specific Sonos speaker, from where it was left off.\n\nIf the speaker is playing another track, this fails unless `force` is set.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "Bedroom", description = "UUID or room name of the Sonos speaker")
+	),
+	request_body = ResumeRequest,
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 404, description = "Nothing to resume on this speaker"),
+		(status = 409, description = "The speaker is playing another track"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_resume_last(
+	sonos_rights: SonosRights,
+	State(config_manager): State<config::Manager>,
+	State(resume_manager): State<resume::Manager>,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+	Json(req): Json<ResumeRequest>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let config = config_manager.get_sonos_config().await;
+	resume_manager
+		.prune_resume_points(config.get_resume_retention())
+		.await?;
+	let resume_point = resume_manager
+		.read_resume_point(sonos_rights.get_username(), &speaker_id)
+		.await?;
+
+	let share = music_share(&config)?;
+	let uri = sonos::path_to_share_uri(&resume_point.virtual_path, &share).ok_or_else(|| {
+		APIError::SonosInvalidTrackUrl(resume_point.virtual_path.to_string_lossy().into_owned())
+	})?;
+
+	let service = bridge.service(&sonos_manager).await;
+	if !req.force {
+		let state = service.get_state(&speaker_id).await?;
+		let current = state
+			.track_uri
+			.and_then(|uri| sonos::share_uri_to_path(&uri, &share));
+		if state.is_playing && current.as_ref() != Some(&resume_point.virtual_path) {
+			return Err(APIError::SonosSpeakerBusy);
+		}
+	}
+
+	Ok(Json(
+		service
+			.resume_uri(&speaker_id, &uri, resume_point.position)
+			.await?,
+	))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/pause",
+	tag = "Sonos",
+	description = "Pause the default Sonos speaker. The current track and position are remembered for `resume_last`.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 400, description = "No default speaker is configured"),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_default_pause(
+	sonos_rights: SonosRights,
+	State(config_manager): State<config::Manager>,
+	State(resume_manager): State<resume::Manager>,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+) -> Result<Json<SonosResponse>, APIError> {
+	let speaker_id = requested_speaker(None, &config_manager.get_sonos_config().await)?;
+	post_sonos_pause(
+		sonos_rights,
+		State(config_manager),
+		State(resume_manager),
+		State(sonos_manager),
+		bridge,
+		Path(speaker_id),
+	)
+	.await
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/stop",
+	tag = "Sonos",
+	description = "Stop the default Sonos speaker. The current track and position are remembered for `resume_last`.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 400, description = "No default speaker is configured"),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_default_stop(
+	sonos_rights: SonosRights,
+	State(config_manager): State<config::Manager>,
+	State(resume_manager): State<resume::Manager>,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+) -> Result<Json<SonosResponse>, APIError> {
+	let speaker_id = requested_speaker(None, &config_manager.get_sonos_config().await)?;
+	post_sonos_stop(
+		sonos_rights,
+		State(config_manager),
+		State(resume_manager),
+		State(sonos_manager),
+		bridge,
+		Path(speaker_id),
+	)
+	.await
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/resume_last",
+	tag = "Sonos",
+	description = "Play the track the current user last paused or stopped on the default Sonos speaker, from where it was left off.\n\nIf the speaker is playing another track, this fails unless `force` is set.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	request_body = ResumeRequest,
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 400, description = "No default speaker is configured"),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 404, description = "Nothing to resume on this speaker"),
+		(status = 409, description = "The speaker is playing another track"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_default_resume_last(
+	sonos_rights: SonosRights,
+	State(config_manager): State<config::Manager>,
+	State(resume_manager): State<resume::Manager>,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Json(req): Json<ResumeRequest>,
+) -> Result<Json<SonosResponse>, APIError> {
+	let speaker_id = requested_speaker(None, &config_manager.get_sonos_config().await)?;
+	post_sonos_resume_last(
+		sonos_rights,
+		State(config_manager),
+		State(resume_manager),
+		State(sonos_manager),
+		bridge,
+		Path(speaker_id),
+		Json(req),
+	)
+	.await
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/{speaker_id}/mute",
+	tag = "Sonos",
+	description = "Mute a specific Sonos speaker via node-sonos-http-api.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_mute(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.mute(&speaker_id).await?))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/{speaker_id}/unmute",
+	tag = "Sonos",
+	description = "Unmute a specific Sonos speaker via node-sonos-http-api.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_unmute(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.unmute(&speaker_id).await?))
+}
+
+#[utoipa::path(
+	put,
+	path = "/sonos/{speaker_id}/mute",
+	tag = "Sonos",
+	description = "Mute or unmute a specific Sonos speaker via node-sonos-http-api, whatever its current mute state.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	request_body = MuteRequest,
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn put_sonos_mute(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+	Json(req): Json<MuteRequest>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.set_mute(&speaker_id, req.muted).await?))
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/{speaker_id}/toggle_mute",
+	tag = "Sonos",
+	description = "Flip the mute state of a specific Sonos speaker via node-sonos-http-api.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	params(
+		("speaker_id", example = "RINCON_000E58A0000001400", description = "UUID or room name of the Sonos speaker")
+	),
+	responses(
+		(status = 200, body = SonosResponse),
+		(status = 403, description = "User is not allowed to control this Sonos speaker"),
+		(status = 502, description = "Sonos service unavailable or returned an error")
+	)
+)]
+async fn post_sonos_toggle_mute(
+	sonos_rights: SonosRights,
+	State(sonos_manager): State<sonos::Manager>,
+	bridge: SonosBridge,
+	Path(speaker_id): Path<String>,
+) -> Result<Json<SonosResponse>, APIError> {
+	sonos_rights.check_speaker(&speaker_id).await?;
+	let service = bridge.service(&sonos_manager).await;
+	Ok(Json(service.toggle_mute(&speaker_id).await?))
+}
+
+#[utoipa::path(
+	get,
+	path = "/sonos/events",
+	tag = "Sonos",
+	description = "Stream playback state changes of all Sonos speakers as server-sent events.\n\nEach `state` event carries a JSON-encoded `SonosEvent`, whose `changes` tell whether playback started or stopped, the track changed, the position moved or the volume changed. Speakers are only polled while at least one client is connected to this stream. When the `webhook_enabled` Sonos setting is set, events come from node-sonos-http-api instead of polling.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	responses(
+		(status = 200, content_type = "text/event-stream", body = SonosEvent)
+	)
+)]
+async fn get_sonos_events(
+	_auth: Auth,
+	State(sonos_manager): State<sonos::Manager>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+	sonos_event_stream(&sonos_manager)
+}
+
+#[utoipa::path(
+	get,
+	path = "/sonos/events/stream",
+	tag = "Sonos",
+	description = "Same as `GET /sonos/events`: stream playback state changes of all Sonos speakers as server-sent events.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	responses(
+		(status = 200, content_type = "text/event-stream", body = SonosEvent)
+	)
+)]
+async fn get_sonos_events_stream(
+	_auth: Auth,
+	State(sonos_manager): State<sonos::Manager>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+	sonos_event_stream(&sonos_manager)
+}
+
+fn sonos_event_stream(
+	sonos_manager: &sonos::Manager,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+	let stream = BroadcastStream::new(sonos_manager.subscribe()).filter_map(|event| {
+		let event = event.ok()?;
+		Event::default()
+			.event("state")
+			.json_data(event)
+			.ok()
+			.map(Ok)
+	});
+	Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/webhook",
+	tag = "Sonos",
+	description = "Receive events pushed by node-sonos-http-api, so speaker and state queries can be answered without contacting the bridge.\n\nThis endpoint must be enabled with the `webhook_enabled` Sonos setting. As the bridge cannot send headers, point its webhook to this URL with an `auth_token` query parameter.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	request_body = SonosWebhookPayload,
+	responses(
+		(status = 200),
+		(status = 404, description = "The Sonos webhook is disabled")
+	)
+)]
+async fn post_sonos_webhook(
+	_auth: Auth,
+	State(sonos_manager): State<sonos::Manager>,
+	Json(payload): Json<SonosWebhookPayload>,
+) -> Result<(), APIError> {
+	sonos_manager.handle_webhook(payload).await?;
+	Ok(())
+}
+
+#[utoipa::path(
+	post,
+	path = "/sonos/events",
+	tag = "Sonos",
+	description = "Same as `POST /sonos/webhook`: receive events pushed by node-sonos-http-api. Playback state changes are broadcast to `/sonos/events/stream` subscribers.\n\nThis endpoint must be enabled with the `webhook_enabled` Sonos setting.",
+	security(("auth_token" = []), ("auth_query_param" = [])),
+	request_body = SonosWebhookPayload,
+	responses(
+		(status = 200),
+		(status = 404, description = "The Sonos webhook is disabled")
+	)
+)]
+async fn post_sonos_events(
+	_auth: Auth,
+	State(sonos_manager): State<sonos::Manager>,
+	Json(payload): Json<SonosWebhookPayload>,
+) -> Result<(), APIError> {
+	sonos_manager.handle_webhook(payload).await?;
+	Ok(())
+}
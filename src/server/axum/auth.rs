@@ -1,10 +1,13 @@
-use axum::extract::{FromRef, FromRequestParts, Query};
+use std::path::PathBuf;
+
+use axum::extract::{FromRef, FromRequestParts, Path, Query};
 use headers::authorization::{Bearer, Credentials};
 use http::request::Parts;
 
 use crate::{
 	app::{auth, config},
 	server::{dto, error::APIError},
+	sonos,
 };
 
 #[derive(Debug)]
@@ -28,17 +31,7 @@ where
 	async fn from_request_parts(parts: &mut Parts, app: &S) -> Result<Self, Self::Rejection> {
 		let config_manager = config::Manager::from_ref(app);
 
-		let header_token = parts
-			.headers
-			.get(http::header::AUTHORIZATION)
-			.and_then(Bearer::decode)
-			.map(|b| b.token().to_string());
-
-		let query_token = Query::<dto::AuthQueryParameters>::try_from_uri(&parts.uri)
-			.ok()
-			.map(|p| p.auth_token.to_string());
-
-		let Some(token) = query_token.or(header_token) else {
+		let Some(token) = request_token(parts) else {
 			return Err(APIError::AuthenticationRequired);
 		};
 
@@ -52,6 +45,104 @@ where
 	}
 }
 
+/// Token sent with a request, in its query string or else in its `Authorization` header
+fn request_token(parts: &Parts) -> Option<String> {
+	let header_token = parts
+		.headers
+		.get(http::header::AUTHORIZATION)
+		.and_then(Bearer::decode)
+		.map(|b| b.token().to_string());
+
+	let query_token = Query::<dto::AuthQueryParameters>::try_from_uri(&parts.uri)
+		.ok()
+		.map(|p| p.auth_token.to_string());
+
+	query_token.or(header_token)
+}
+
+/// Access to audio files, granted to authenticated users and to Sonos speakers streaming a file with a token signed for it
+#[derive(Debug)]
+pub struct AudioAuth;
+
+impl<S> FromRequestParts<S> for AudioAuth
+where
+	config::Manager: FromRef<S>,
+	S: Send + Sync,
+{
+	type Rejection = APIError;
+
+	async fn from_request_parts(parts: &mut Parts, app: &S) -> Result<Self, Self::Rejection> {
+		if Auth::from_request_parts(parts, app).await.is_ok() {
+			return Ok(AudioAuth);
+		}
+
+		let Some(token) = request_token(parts) else {
+			return Err(APIError::AuthenticationRequired);
+		};
+		let Path(path) = Path::<PathBuf>::from_request_parts(parts, app)
+			.await
+			.map_err(|_| APIError::AuthenticationRequired)?;
+		config::Manager::from_ref(app)
+			.authenticate_sonos_stream(&auth::Token(token), &path)
+			.await?;
+		Ok(AudioAuth)
+	}
+}
+
+/// Authenticated user who is allowed to control Sonos speakers
+pub struct SonosRights {
+	user: config::User,
+	sonos_manager: sonos::Manager,
+}
+
+impl SonosRights {
+	pub fn get_username(&self) -> &str {
+		&self.user.name
+	}
+
+	/// Users are allowed speakers by room name, so speakers given by UUID are checked against their room name
+	pub async fn check_speaker(&self, speaker_id: &str) -> Result<(), APIError> {
+		if self.user.can_control_sonos_speaker(speaker_id)
+			|| self.user.can_control_sonos_speaker(
+				&self
+					.sonos_manager
+					.service()
+					.await
+					.room_name(speaker_id)
+					.await,
+			) {
+			Ok(())
+		} else {
+			Err(APIError::SonosSpeakerPermissionRequired(
+				speaker_id.to_owned(),
+			))
+		}
+	}
+}
+
+impl<S> FromRequestParts<S> for SonosRights
+where
+	config::Manager: FromRef<S>,
+	sonos::Manager: FromRef<S>,
+	S: Send + Sync,
+{
+	type Rejection = APIError;
+
+	async fn from_request_parts(parts: &mut Parts, app: &S) -> Result<Self, Self::Rejection> {
+		let config_manager = config::Manager::from_ref(app);
+		let auth = Auth::from_request_parts(parts, app).await?;
+		let user = config_manager.get_user(&auth.username).await?;
+		if user.can_use_sonos() {
+			Ok(SonosRights {
+				user,
+				sonos_manager: sonos::Manager::from_ref(app),
+			})
+		} else {
+			Err(APIError::SonosPermissionRequired)
+		}
+	}
+}
+
 #[derive(Debug)]
 pub struct AdminRights {
 	auth: Option<Auth>,
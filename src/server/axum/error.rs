@@ -1,17 +1,74 @@
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
+use axum::Json;
 
+use crate::server::dto;
 use crate::server::error::APIError;
 
+impl APIError {
+	/// Code of the `ErrorResponse` body Sonos errors are answered with. Other errors are answered in plain text.
+	fn sonos_code(&self) -> Option<&'static str> {
+		let code = match self {
+			APIError::SonosPermissionRequired => "sonos_permission_required",
+			APIError::SonosSpeakerPermissionRequired(_) => "sonos_speaker_permission_required",
+			APIError::InvalidSonosSettings(_) => "invalid_sonos_settings",
+			APIError::SonosResumePointNotFound => "sonos_resume_point_not_found",
+			APIError::SonosSessionNotFound(_) => "sonos_session_not_found",
+			APIError::SonosInterruptionNotFound(_) => "sonos_interruption_not_found",
+			APIError::SonosSnapshotsDisabled => "sonos_snapshots_disabled",
+			APIError::SonosSnapshotNotFound(_) => "sonos_snapshot_not_found",
+			APIError::SonosSnapshotExpired(_) => "sonos_snapshot_expired",
+			APIError::SonosFavoriteNotFound(_) => "sonos_favorite_not_found",
+			APIError::SonosRendererNotFound(_) => "sonos_renderer_not_found",
+			APIError::SonosSpeakerNotFound(_) => "sonos_speaker_not_found",
+			APIError::SonosJoinOwnGroup(_) => "sonos_join_own_group",
+			APIError::SonosNoPlayableAlbum => "sonos_no_playable_album",
+			APIError::SonosSpeakerBusy => "sonos_speaker_busy",
+			APIError::SonosDisabled => "sonos_disabled",
+			APIError::SonosSceneNotFound(_) => "sonos_scene_not_found",
+			APIError::SonosUrlOverrideDisabled => "sonos_url_override_disabled",
+			APIError::SonosUrlOverrideInvalid(_) => "sonos_url_override_invalid",
+			APIError::SonosNoDefaultSpeaker => "sonos_no_default_speaker",
+			APIError::SonosNoMusicShare => "sonos_no_music_share",
+			APIError::SonosConnectionFailed => "sonos_connection_failed",
+			APIError::SonosTimeout { .. } => "sonos_timeout",
+			APIError::SonosHttpError(_) => "sonos_http_error",
+			APIError::SonosInvalidResponse => "sonos_invalid_response",
+			APIError::SonosInvalidUri(_) => "sonos_invalid_uri",
+			APIError::SonosWebhookDisabled => "sonos_webhook_disabled",
+			APIError::SonosAlbumArtNotFound => "sonos_album_art_not_found",
+			APIError::SonosInvalidPlayRequest(_) => "sonos_invalid_play_request",
+			APIError::SonosSleepTimerTooLong(_) => "sonos_sleep_timer_too_long",
+			APIError::SonosCrossfadeOverlapTooLong(_) => "sonos_crossfade_overlap_too_long",
+			APIError::SonosInvalidAnnouncement(_) => "sonos_invalid_announcement",
+			APIError::SonosInvalidTrackUrl(_) => "sonos_invalid_track_url",
+			APIError::SonosPlaylistExists(_) => "sonos_playlist_exists",
+			APIError::SonosInvalidVolume(_) => "sonos_invalid_volume",
+			APIError::SonosVolumeChangeFailed(_) => "sonos_volume_change_failed",
+			APIError::SonosQueuePositionOutOfRange(_) => "sonos_queue_position_out_of_range",
+			APIError::SonosInvalidSeek(_) => "sonos_invalid_seek",
+			_ => return None,
+		};
+		Some(code)
+	}
+}
+
 impl IntoResponse for APIError {
 	fn into_response(self) -> Response {
 		let message = self.to_string();
+		let sonos_code = self.sonos_code();
+		let (action, speaker) = match &self {
+			APIError::SonosTimeout { action, speaker } => (Some(action.clone()), speaker.clone()),
+			_ => (None, None),
+		};
 		let status_code = match self {
 			APIError::InvalidAPIVersionHeader => StatusCode::BAD_REQUEST,
 			APIError::APIVersionHeaderParseError => StatusCode::BAD_REQUEST,
 			APIError::UnsupportedAPIVersion => StatusCode::NOT_ACCEPTABLE,
 			APIError::AuthorizationTokenEncoding => StatusCode::INTERNAL_SERVER_ERROR,
 			APIError::AdminPermissionRequired => StatusCode::FORBIDDEN,
+			APIError::SonosPermissionRequired => StatusCode::FORBIDDEN,
+			APIError::SonosSpeakerPermissionRequired(_) => StatusCode::FORBIDDEN,
 			APIError::AudioFileIOError => StatusCode::NOT_FOUND,
 			APIError::AuthenticationRequired => StatusCode::UNAUTHORIZED,
 			APIError::BrancaTokenEncoding => StatusCode::INTERNAL_SERVER_ERROR,
@@ -33,11 +90,49 @@ impl IntoResponse for APIError {
 			APIError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
 			APIError::InvalidAlbumArtPattern => StatusCode::BAD_REQUEST,
 			APIError::InvalidDDNSURL => StatusCode::BAD_REQUEST,
+			APIError::InvalidSonosSettings(_) => StatusCode::UNPROCESSABLE_ENTITY,
 			APIError::Io(_, _) => StatusCode::INTERNAL_SERVER_ERROR,
 			APIError::OwnAdminPrivilegeRemoval => StatusCode::CONFLICT,
 			APIError::PasswordHashing => StatusCode::INTERNAL_SERVER_ERROR,
 			APIError::PlaylistNotFound => StatusCode::NOT_FOUND,
+			APIError::SonosResumePointNotFound => StatusCode::NOT_FOUND,
+			APIError::SonosSessionNotFound(_) => StatusCode::NOT_FOUND,
+			APIError::SonosInterruptionNotFound(_) => StatusCode::NOT_FOUND,
+			APIError::SonosSnapshotsDisabled => StatusCode::NOT_FOUND,
+			APIError::SonosSnapshotNotFound(_) => StatusCode::NOT_FOUND,
+			APIError::SonosSnapshotExpired(_) => StatusCode::GONE,
+			APIError::SonosFavoriteNotFound(_) => StatusCode::NOT_FOUND,
+			APIError::SonosRendererNotFound(_) => StatusCode::NOT_FOUND,
+			APIError::SonosSpeakerNotFound(_) => StatusCode::NOT_FOUND,
+			APIError::SonosJoinOwnGroup(_) => StatusCode::BAD_REQUEST,
+			APIError::SonosNoPlayableAlbum => StatusCode::NOT_FOUND,
+			APIError::SonosSpeakerBusy => StatusCode::CONFLICT,
+			APIError::SonosDisabled => StatusCode::NOT_FOUND,
+			APIError::SonosSceneNotFound(_) => StatusCode::NOT_FOUND,
+			APIError::SonosUrlOverrideDisabled => StatusCode::FORBIDDEN,
+			APIError::SonosUrlOverrideInvalid(_) => StatusCode::BAD_REQUEST,
+			APIError::SonosNoDefaultSpeaker => StatusCode::BAD_REQUEST,
+			APIError::SonosNoMusicShare => StatusCode::BAD_REQUEST,
 			APIError::SearchQueryParseError => StatusCode::BAD_REQUEST,
+			APIError::SonosConnectionFailed => StatusCode::BAD_GATEWAY,
+			APIError::SonosTimeout { .. } => StatusCode::GATEWAY_TIMEOUT,
+			// The bridge being temporarily unavailable is reported as such, so clients know to retry
+			APIError::SonosHttpError(503) => StatusCode::SERVICE_UNAVAILABLE,
+			APIError::SonosHttpError(_) => StatusCode::BAD_GATEWAY,
+			APIError::SonosInvalidResponse => StatusCode::BAD_GATEWAY,
+			APIError::SonosInvalidUri(_) => StatusCode::BAD_REQUEST,
+			APIError::SonosWebhookDisabled => StatusCode::NOT_FOUND,
+			APIError::SonosAlbumArtNotFound => StatusCode::NOT_FOUND,
+			APIError::SonosInvalidPlayRequest(_) => StatusCode::BAD_REQUEST,
+			APIError::SonosSleepTimerTooLong(_) => StatusCode::BAD_REQUEST,
+			APIError::SonosCrossfadeOverlapTooLong(_) => StatusCode::BAD_REQUEST,
+			APIError::SonosInvalidAnnouncement(_) => StatusCode::BAD_REQUEST,
+			APIError::SonosInvalidTrackUrl(_) => StatusCode::BAD_REQUEST,
+			APIError::SonosPlaylistExists(_) => StatusCode::CONFLICT,
+			APIError::SonosInvalidVolume(_) => StatusCode::UNPROCESSABLE_ENTITY,
+			APIError::SonosVolumeChangeFailed(_) => StatusCode::BAD_GATEWAY,
+			APIError::SonosQueuePositionOutOfRange(_) => StatusCode::UNPROCESSABLE_ENTITY,
+			APIError::SonosInvalidSeek(_) => StatusCode::UNPROCESSABLE_ENTITY,
 			APIError::ThumbnailFlacDecoding(_, _) => StatusCode::INTERNAL_SERVER_ERROR,
 			APIError::ThumbnailFileIOError => StatusCode::NOT_FOUND,
 			APIError::ThumbnailId3Decoding(_, _) => StatusCode::INTERNAL_SERVER_ERROR,
@@ -50,6 +145,81 @@ impl IntoResponse for APIError {
 			APIError::VFSPathNotFound => StatusCode::NOT_FOUND,
 		};
 
-		(status_code, message).into_response()
+		match sonos_code {
+			Some(code) => (
+				status_code,
+				Json(dto::ErrorResponse {
+					code: code.to_owned(),
+					message,
+					action,
+					speaker,
+				}),
+			)
+				.into_response(),
+			None => (status_code, message).into_response(),
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::sonos::SonosError;
+
+	fn status(error: SonosError) -> StatusCode {
+		APIError::from(error).into_response().status()
+	}
+
+	#[tokio::test]
+	async fn sonos_errors_have_json_bodies() {
+		let response =
+			APIError::from(SonosError::SpeakerNotFound("Garage".to_owned())).into_response();
+		assert_eq!(response.status(), StatusCode::NOT_FOUND);
+		let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+			.await
+			.unwrap();
+		let body: dto::ErrorResponse = serde_json::from_slice(&body).unwrap();
+		assert_eq!(
+			body,
+			dto::ErrorResponse {
+				code: "sonos_speaker_not_found".to_owned(),
+				message: "No Sonos speaker with UUID or room name `Garage`".to_owned(),
+				action: None,
+				speaker: None,
+			}
+		);
+
+		let response = APIError::AlbumNotFound.into_response();
+		let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+			.await
+			.unwrap();
+		assert_eq!(&body[..], b"Album not found");
+	}
+
+	#[test]
+	fn sonos_errors_have_matching_status() {
+		let http_error = |status| SonosError::HttpError {
+			status,
+			body: String::new(),
+		};
+		assert_eq!(status(http_error(503)), StatusCode::SERVICE_UNAVAILABLE);
+		assert_eq!(status(http_error(500)), StatusCode::BAD_GATEWAY);
+		assert_eq!(status(http_error(404)), StatusCode::BAD_GATEWAY);
+		assert_eq!(
+			status(SonosError::InvalidVolume(101)),
+			StatusCode::UNPROCESSABLE_ENTITY
+		);
+		assert_eq!(
+			status(SonosError::SessionNotFound("Kitchen".to_owned())),
+			StatusCode::NOT_FOUND
+		);
+		assert_eq!(
+			status(SonosError::LibraryUnavailable),
+			StatusCode::INTERNAL_SERVER_ERROR
+		);
+		assert_eq!(
+			status(SonosError::SnapshotExpired("Kitchen".to_owned())),
+			StatusCode::GONE
+		);
 	}
 }
@@ -1,4 +1,8 @@
-use axum::{extract::Request, response::Response};
+use axum::{
+	extract::Request,
+	http::{HeaderName, HeaderValue},
+	response::Response,
+};
 use log::{log, Level};
 use std::{
 	future::Future,
@@ -6,6 +10,26 @@ use std::{
 	task::{Context, Poll},
 };
 use tower::{Layer, Service};
+use tracing::Instrument;
+
+static REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Identifies a request in logs, so that log lines from the same user action can be correlated.
+/// Clients may supply their own ID in the `X-Request-ID` header. The ID is echoed in the response.
+#[derive(Clone, Debug)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+	fn from_request(request: &Request) -> Self {
+		let id = request
+			.headers()
+			.get(&REQUEST_ID_HEADER)
+			.and_then(|v| v.to_str().ok())
+			.filter(|v| !v.is_empty() && v.len() <= 64)
+			.map(|v| v.to_owned());
+		Self(id.unwrap_or_else(|| format!("{:016x}", rand::random::<u64>())))
+	}
+}
 
 #[derive(Clone)]
 pub struct LogLayer;
@@ -43,19 +67,34 @@ where
 		self.inner.poll_ready(cx)
 	}
 
-	fn call(&mut self, request: Request) -> Self::Future {
+	fn call(&mut self, mut request: Request) -> Self::Future {
 		let path = request.uri().path().to_owned();
 		let method = request.method().clone();
-		let future = self.inner.call(request);
+		let request_id = RequestId::from_request(&request);
+		request.extensions_mut().insert(request_id.clone());
+		let span = tracing::debug_span!("request", request_id = %request_id.0);
+		let future = self.inner.call(request).instrument(span);
 		Box::pin(async move {
-			let response: Response = future.await?;
+			let mut response: Response = future.await?;
+			if let Ok(value) = HeaderValue::from_str(&request_id.0) {
+				response
+					.headers_mut()
+					.insert(REQUEST_ID_HEADER.clone(), value);
+			}
 			let status = response.status();
 			let level = if status.is_client_error() || status.is_server_error() {
 				Level::Error
 			} else {
 				Level::Info
 			};
-			log!(level, "[{}] {} {}", response.status(), method, path);
+			log!(
+				level,
+				"[{}] {} {} ({})",
+				response.status(),
+				method,
+				path,
+				request_id.0
+			);
 			Ok(response)
 		})
 	}
@@ -0,0 +1,219 @@
+use std::{
+	collections::HashMap,
+	future::Future,
+	net::{IpAddr, Ipv4Addr, SocketAddr},
+	pin::Pin,
+	sync::{Arc, Mutex},
+	task::{Context, Poll},
+	time::{Duration, Instant},
+};
+
+use axum::{
+	extract::{ConnectInfo, Request},
+	http::{header::RETRY_AFTER, StatusCode},
+	response::{IntoResponse, Response},
+};
+use tower::{Layer, Service};
+
+use crate::app::config;
+
+/// Number of clients above which idle ones are forgotten
+const MAX_TRACKED_CLIENTS: usize = 1024;
+
+/// Limits how many requests per second each client IP can send, so that a misbehaving client
+/// cannot overwhelm node-sonos-http-api. The limit is read from the Sonos settings on every request.
+#[derive(Clone)]
+pub struct RateLimitLayer {
+	config_manager: config::Manager,
+	buckets: Buckets,
+}
+
+impl RateLimitLayer {
+	pub fn new(config_manager: config::Manager) -> Self {
+		Self {
+			config_manager,
+			buckets: Buckets::default(),
+		}
+	}
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+	type Service = RateLimit<S>;
+
+	fn layer(&self, inner: S) -> Self::Service {
+		RateLimit {
+			inner,
+			config_manager: self.config_manager.clone(),
+			buckets: self.buckets.clone(),
+		}
+	}
+}
+
+#[derive(Clone)]
+pub struct RateLimit<S> {
+	inner: S,
+	config_manager: config::Manager,
+	buckets: Buckets,
+}
+
+impl<S> Service<Request> for RateLimit<S>
+where
+	S: Service<Request, Response = Response> + Clone + Send + 'static,
+	S::Future: Send + 'static,
+{
+	type Response = S::Response;
+	type Error = S::Error;
+	type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+	fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		self.inner.poll_ready(cx)
+	}
+
+	fn call(&mut self, request: Request) -> Self::Future {
+		let client = client_ip(&request);
+		let config_manager = self.config_manager.clone();
+		let buckets = self.buckets.clone();
+		// The instance which was polled ready handles the request
+		let clone = self.inner.clone();
+		let mut inner = std::mem::replace(&mut self.inner, clone);
+		Box::pin(async move {
+			let limit = config_manager.get_sonos_config().await.get_rate_limit_rps();
+			if let Some(rps) = limit {
+				if let Err(retry_after) = buckets.acquire(client, rps, Instant::now()) {
+					return Ok(too_many_requests(retry_after));
+				}
+			}
+			inner.call(request).await
+		})
+	}
+}
+
+/// Clients whose address is unknown share a single budget
+fn client_ip(request: &Request) -> IpAddr {
+	request
+		.extensions()
+		.get::<ConnectInfo<SocketAddr>>()
+		.map(|ConnectInfo(addr)| addr.ip())
+		.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED))
+}
+
+fn too_many_requests(retry_after: Duration) -> Response {
+	let secs = retry_after.as_secs_f64().ceil().max(1.0) as u64;
+	(
+		StatusCode::TOO_MANY_REQUESTS,
+		[(RETRY_AFTER, secs.to_string())],
+		format!("Too many Sonos requests, retry in {secs} seconds"),
+	)
+		.into_response()
+}
+
+/// Token bucket of a client. It holds up to one second worth of requests.
+struct Bucket {
+	tokens: f64,
+	updated: Instant,
+}
+
+#[derive(Clone, Default)]
+struct Buckets(Arc<Mutex<HashMap<IpAddr, Bucket>>>);
+
+impl Buckets {
+	/// Take a token from the bucket of `client`, or return how long until one is available
+	fn acquire(&self, client: IpAddr, rps: u32, now: Instant) -> Result<(), Duration> {
+		let capacity = rps as f64;
+		let mut buckets = self.0.lock().unwrap();
+
+		if buckets.len() >= MAX_TRACKED_CLIENTS && !buckets.contains_key(&client) {
+			// Buckets idle for a second are full again, so forgetting them changes nothing
+			buckets.retain(|_, b| now.duration_since(b.updated) < Duration::from_secs(1));
+		}
+
+		let bucket = buckets.entry(client).or_insert(Bucket {
+			tokens: capacity,
+			updated: now,
+		});
+		let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+		bucket.tokens = (bucket.tokens + elapsed * capacity).min(capacity);
+		bucket.updated = now;
+
+		if bucket.tokens >= 1.0 {
+			bucket.tokens -= 1.0;
+			Ok(())
+		} else {
+			Err(Duration::from_secs_f64((1.0 - bucket.tokens) / capacity))
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use axum::{body::Body, routing::get, Router};
+	use tokio::task::JoinSet;
+	use tower::ServiceExt;
+
+	use super::*;
+	use crate::app::test;
+	use crate::test_name;
+
+	#[test]
+	fn refills_over_time() {
+		let buckets = Buckets::default();
+		let client = IpAddr::V4(Ipv4Addr::new(192, 168, 0, 10));
+		let start = Instant::now();
+
+		for _ in 0..5 {
+			assert!(buckets.acquire(client, 5, start).is_ok());
+		}
+		let retry_after = buckets.acquire(client, 5, start).unwrap_err();
+		assert_eq!(retry_after, Duration::from_millis(200));
+
+		let other_client = IpAddr::V4(Ipv4Addr::new(192, 168, 0, 11));
+		assert!(buckets.acquire(other_client, 5, start).is_ok());
+
+		assert!(buckets
+			.acquire(client, 5, start + Duration::from_millis(200))
+			.is_ok());
+		assert!(buckets
+			.acquire(client, 5, start + Duration::from_millis(200))
+			.is_err());
+	}
+
+	#[tokio::test]
+	async fn rejects_bursts() {
+		let ctx = test::ContextBuilder::new(test_name!()).build().await;
+		ctx.config_manager
+			.set_sonos_config(config::SonosConfig {
+				rate_limit_rps: Some(5),
+				..Default::default()
+			})
+			.await
+			.unwrap();
+
+		let router = Router::new()
+			.route("/state", get(|| async { "PLAYING" }))
+			.route_layer(RateLimitLayer::new(ctx.config_manager.clone()));
+
+		let client: SocketAddr = "192.168.0.10:50000".parse().unwrap();
+		let mut requests = JoinSet::new();
+		for _ in 0..100 {
+			let router = router.clone();
+			requests.spawn(async move {
+				let mut request = Request::builder()
+					.uri("/state")
+					.body(Body::empty())
+					.unwrap();
+				request.extensions_mut().insert(ConnectInfo(client));
+				router.oneshot(request).await.unwrap()
+			});
+		}
+
+		let mut rejected = 0;
+		while let Some(response) = requests.join_next().await {
+			let response = response.unwrap();
+			if response.status() == StatusCode::TOO_MANY_REQUESTS {
+				assert!(response.headers().contains_key(RETRY_AFTER));
+				rejected += 1;
+			}
+		}
+		assert!(rejected > 50, "only {rejected} requests were rejected");
+	}
+}
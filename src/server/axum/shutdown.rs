@@ -0,0 +1,225 @@
+use std::{
+	convert::Infallible,
+	future::{Future, IntoFuture},
+	net::SocketAddr,
+	pin::Pin,
+	sync::{
+		atomic::{AtomicUsize, Ordering},
+		Arc,
+	},
+	task::{Context, Poll},
+	time::Duration,
+};
+
+use axum::{extract::Request, response::Response, ServiceExt};
+use tokio::{net::TcpListener, sync::Notify};
+use tower::{Layer, Service};
+use tracing::{info, warn};
+
+/// How serving ended after a shutdown was requested
+#[derive(Debug, PartialEq, Eq)]
+pub enum ShutdownOutcome {
+	/// Every in-flight request completed
+	Drained,
+	/// This many requests were still in flight when the timeout expired
+	Dropped(usize),
+}
+
+/// Wait for Ctrl+C, or SIGTERM on Unix
+pub async fn signal() {
+	let ctrl_c = async {
+		if let Err(e) = tokio::signal::ctrl_c().await {
+			warn!("Could not listen for Ctrl+C: {e}");
+			std::future::pending::<()>().await;
+		}
+	};
+
+	#[cfg(unix)]
+	let terminate = async {
+		use tokio::signal::unix::{signal, SignalKind};
+		match signal(SignalKind::terminate()) {
+			Ok(mut s) => {
+				s.recv().await;
+			}
+			Err(e) => {
+				warn!("Could not listen for SIGTERM: {e}");
+				std::future::pending::<()>().await;
+			}
+		}
+	};
+
+	#[cfg(not(unix))]
+	let terminate = std::future::pending::<()>();
+
+	tokio::select! {
+		_ = ctrl_c => {},
+		_ = terminate => {},
+	}
+}
+
+/// Serve `service` until `signal` completes. New connections are then refused,
+/// and requests in progress get up to `timeout` to complete before being dropped.
+pub async fn serve<S>(
+	listener: TcpListener,
+	service: S,
+	signal: impl Future<Output = ()> + Send + 'static,
+	timeout: Duration,
+) -> Result<ShutdownOutcome, std::io::Error>
+where
+	S: Service<Request, Response = Response, Error = Infallible> + Clone + Send + 'static,
+	S::Future: Send + 'static,
+{
+	let in_flight = Arc::new(AtomicUsize::new(0));
+	let service = InFlightLayer::new(in_flight.clone()).layer(service);
+	let make_service =
+		ServiceExt::<Request>::into_make_service_with_connect_info::<SocketAddr>(service);
+
+	let requested = Arc::new(Notify::new());
+	let server = axum::serve(listener, make_service).with_graceful_shutdown({
+		let requested = requested.clone();
+		async move {
+			signal.await;
+			info!("Shutting down, waiting up to {timeout:?} for requests in progress");
+			requested.notify_one();
+		}
+	});
+
+	let deadline = async {
+		requested.notified().await;
+		tokio::time::sleep(timeout).await;
+	};
+
+	tokio::select! {
+		result = server.into_future() => {
+			result?;
+			Ok(ShutdownOutcome::Drained)
+		}
+		_ = deadline => {
+			let dropped = in_flight.load(Ordering::SeqCst);
+			if dropped > 0 {
+				warn!("Dropping {dropped} requests which did not complete within {timeout:?}");
+			}
+			Ok(ShutdownOutcome::Dropped(dropped))
+		}
+	}
+}
+
+/// Keeps count of the requests being handled by the wrapped service
+#[derive(Clone)]
+struct InFlightLayer {
+	count: Arc<AtomicUsize>,
+}
+
+impl InFlightLayer {
+	fn new(count: Arc<AtomicUsize>) -> Self {
+		Self { count }
+	}
+}
+
+impl<S> Layer<S> for InFlightLayer {
+	type Service = InFlight<S>;
+
+	fn layer(&self, inner: S) -> Self::Service {
+		InFlight {
+			inner,
+			count: self.count.clone(),
+		}
+	}
+}
+
+#[derive(Clone)]
+struct InFlight<S> {
+	inner: S,
+	count: Arc<AtomicUsize>,
+}
+
+struct InFlightGuard(Arc<AtomicUsize>);
+
+impl Drop for InFlightGuard {
+	fn drop(&mut self) {
+		self.0.fetch_sub(1, Ordering::SeqCst);
+	}
+}
+
+impl<S> Service<Request> for InFlight<S>
+where
+	S: Service<Request>,
+	S::Future: Send + 'static,
+{
+	type Response = S::Response;
+	type Error = S::Error;
+	type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+	fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+		self.inner.poll_ready(cx)
+	}
+
+	fn call(&mut self, request: Request) -> Self::Future {
+		self.count.fetch_add(1, Ordering::SeqCst);
+		let guard = InFlightGuard(self.count.clone());
+		let response = self.inner.call(request);
+		Box::pin(async move {
+			let _guard = guard;
+			response.await
+		})
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use axum::{routing::get, Router};
+	use tokio::sync::oneshot;
+
+	use super::*;
+
+	async fn start(
+		handler_delay: Duration,
+		timeout: Duration,
+	) -> (
+		String,
+		oneshot::Sender<()>,
+		tokio::task::JoinHandle<ShutdownOutcome>,
+	) {
+		let router = Router::new().route(
+			"/slow",
+			get(move || async move {
+				tokio::time::sleep(handler_delay).await;
+				"done"
+			}),
+		);
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let url = format!("http://{}/slow", listener.local_addr().unwrap());
+		let (stop, stopped) = oneshot::channel::<()>();
+		let server = tokio::spawn(async move {
+			let signal = async move {
+				let _ = stopped.await;
+			};
+			serve(listener, router, signal, timeout).await.unwrap()
+		});
+		(url, stop, server)
+	}
+
+	#[tokio::test]
+	async fn waits_for_requests_in_progress() {
+		let (url, stop, server) = start(Duration::from_millis(200), Duration::from_secs(5)).await;
+
+		let request = tokio::spawn(async move { reqwest::get(url).await?.text().await });
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		stop.send(()).unwrap();
+
+		assert_eq!(request.await.unwrap().unwrap(), "done");
+		assert_eq!(server.await.unwrap(), ShutdownOutcome::Drained);
+	}
+
+	#[tokio::test]
+	async fn drops_requests_past_timeout() {
+		let (url, stop, server) = start(Duration::from_secs(10), Duration::from_millis(100)).await;
+
+		let request = tokio::spawn(async move { reqwest::get(url).await });
+		tokio::time::sleep(Duration::from_millis(50)).await;
+		stop.send(()).unwrap();
+
+		assert_eq!(server.await.unwrap(), ShutdownOutcome::Dropped(1));
+		request.abort();
+	}
+}
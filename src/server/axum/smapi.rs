@@ -0,0 +1,48 @@
+use axum::{
+	extract::State,
+	http::{header::CONTENT_TYPE, StatusCode},
+	response::{IntoResponse, Response},
+};
+use tracing::warn;
+
+use crate::{
+	app::{config, index, scanner},
+	smapi::{self, SmapiError, SmapiService, SMAPI_CONTENT_TYPE},
+};
+
+/// SOAP endpoint queried by Sonos players when the collection is added to the Sonos app as a music service.
+/// Players log in with the credentials of a Polaris user before anything else is answered.
+pub async fn post_smapi(
+	State(config_manager): State<config::Manager>,
+	State(index_manager): State<index::Manager>,
+	State(scanner): State<scanner::Scanner>,
+	body: String,
+) -> Response {
+	let sonos_config = config_manager.get_sonos_config().await;
+	// Tracks cannot be located without a file server
+	let share = sonos_config
+		.get_music_share()
+		.filter(|_| sonos_config.smapi_enabled);
+	let result = match share {
+		Some(share) => {
+			let service = SmapiService::new(config_manager, index_manager, scanner, share);
+			service.handle(&body).await
+		}
+		None => Err(SmapiError::Disabled),
+	};
+
+	let (status, content) = match result {
+		Ok(content) => (StatusCode::OK, content),
+		Err(e) => {
+			warn!("Could not answer SMAPI request: {e}");
+			(StatusCode::INTERNAL_SERVER_ERROR, smapi::fault(&e))
+		}
+	};
+
+	(
+		status,
+		[(CONTENT_TYPE, SMAPI_CONTENT_TYPE)],
+		smapi::envelope(&content),
+	)
+		.into_response()
+}
@@ -54,6 +54,7 @@ impl TestService for AxumTestService {
 			Method::GET => self.server.get(&url),
 			Method::POST => self.server.post(&url),
 			Method::PUT => self.server.put(&url),
+			Method::PATCH => self.server.patch(&url),
 			Method::DELETE => self.server.delete(&url),
 			_ => unimplemented!(),
 		};
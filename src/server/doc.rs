@@ -4,6 +4,8 @@ use utoipa::openapi::{
 	ComponentsBuilder, ContactBuilder, InfoBuilder, License, OpenApi, OpenApiBuilder,
 };
 
+use crate::server::dto;
+
 pub fn open_api() -> OpenApi {
 	let auth_token_description = "Authentication token acquired from the `/auth` endpoint";
 
@@ -43,9 +45,14 @@ pub fn open_api() -> OpenApi {
 			.name("Playlists")
 			.description(Some("These endpoints allow users to create, retrieve, update or delete playlists."))
 			.build(),
+            TagBuilder::new()
+			.name("Sonos")
+			.description(Some("These endpoints control Sonos speakers through node-sonos-http-api.\n\nSonos is disabled until the `api_url` Sonos setting is set, or when the `enabled` Sonos setting is `false`. While disabled, endpoints which contact the bridge answer with a 404 status.\n\nErrors from the bridge are reported with a 502 status, except when it answers with a 503 status, which is passed on so clients can retry later. Requests which wait for the bridge longer than the `handler_timeout_ms` Sonos setting are abandoned and answered with a 504 status.\n\nErrors are answered with an `ErrorResponse` JSON body, whose `code` tells errors with the same status apart.\n\nWhen the `allow_url_override` Sonos setting is `true`, requests can be sent to another node-sonos-http-api instance by naming it in a `X-Sonos-Api-Override-Url` header. Otherwise, requests with this header are rejected with a 403 status."))
+			.build(),
         ]))
 		.components(Some(
 			ComponentsBuilder::new()
+				.schema_from::<dto::ErrorResponse>()
 				.security_scheme(
 					"auth_header",
 					SecurityScheme::Http(
@@ -1,7 +1,8 @@
 use serde::{Deserialize, Serialize};
 use utoipa::{IntoParams, ToSchema};
 
-use crate::app::{config, index, peaks, playlist, scanner, thumbnail};
+use crate::app::{config, index, peaks, playlist, scanner, sonos_history, thumbnail};
+use crate::sonos;
 use std::{collections::HashMap, convert::From, path::PathBuf, time::UNIX_EPOCH};
 
 #[derive(PartialEq, Eq, Debug, Serialize, Deserialize, ToSchema)]
@@ -132,13 +133,20 @@ pub struct User {
 	pub name: String,
 	#[schema(examples(true, false))]
 	pub is_admin: bool,
+	#[schema(examples(true, false))]
+	pub allow_sonos: bool,
+	/// Sonos speakers this user can control, or `null` for all speakers
+	#[schema(examples(json!(["Playroom"])))]
+	pub allowed_speakers: Option<Vec<String>>,
 }
 
 impl From<config::User> for User {
 	fn from(u: config::User) -> Self {
 		Self {
-			name: u.name,
 			is_admin: u.admin == Some(true),
+			allow_sonos: u.can_use_sonos(),
+			name: u.name,
+			allowed_speakers: u.allowed_speakers,
 		}
 	}
 }
@@ -159,6 +167,11 @@ pub struct UserUpdate {
 	pub new_password: Option<String>,
 	#[schema(examples(true, false))]
 	pub new_is_admin: Option<bool>,
+	#[schema(examples(true, false))]
+	pub new_allow_sonos: Option<bool>,
+	/// Restricts the Sonos speakers this user can control. An empty list lifts the restriction.
+	#[schema(examples(json!(["Playroom"])))]
+	pub new_allowed_speakers: Option<Vec<String>>,
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize, ToSchema)]
@@ -203,6 +216,681 @@ pub struct Settings {
 	pub ddns_update_url: String,
 }
 
+/// Body of the error responses of Sonos endpoints
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct ErrorResponse {
+	/// Identifies the kind of error, for clients to tell errors with the same status apart
+	#[schema(examples("sonos_speaker_not_found", "sonos_connection_failed"))]
+	pub code: String,
+	#[schema(examples("No Sonos speaker with UUID or room name `Garage`"))]
+	pub message: String,
+	/// Request to the bridge which did not complete in time, for `sonos_timeout` errors
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	#[schema(examples("state", "play"))]
+	pub action: Option<String>,
+	/// Speaker the request which did not complete in time was sent to, for `sonos_timeout` errors
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	#[schema(examples("Kitchen"))]
+	pub speaker: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SonosRetryPolicy {
+	#[schema(examples(3))]
+	pub max_attempts: u8,
+	#[schema(examples(200))]
+	pub base_delay_ms: u64,
+	#[schema(examples(2000))]
+	pub max_delay_ms: u64,
+}
+
+impl From<config::RetryPolicy> for SonosRetryPolicy {
+	fn from(p: config::RetryPolicy) -> Self {
+		Self {
+			max_attempts: p.max_attempts,
+			base_delay_ms: p.base_delay_ms,
+			max_delay_ms: p.max_delay_ms,
+		}
+	}
+}
+
+impl From<SonosRetryPolicy> for config::RetryPolicy {
+	fn from(p: SonosRetryPolicy) -> Self {
+		Self {
+			max_attempts: p.max_attempts,
+			base_delay_ms: p.base_delay_ms,
+			max_delay_ms: p.max_delay_ms,
+		}
+	}
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SonosAuthHeader {
+	#[schema(examples("X-Api-Key"))]
+	pub name: String,
+	#[schema(examples("abcdef"))]
+	pub value: String,
+}
+
+impl From<SonosAuthHeader> for config::AuthHeader {
+	fn from(h: SonosAuthHeader) -> Self {
+		Self {
+			name: h.name,
+			value: h.value,
+		}
+	}
+}
+
+/// How Sonos speakers access the music share, as submitted when changing settings
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NewSonosShareScheme {
+	Cifs,
+	CifsWithCredentials {
+		#[schema(examples("sonos"))]
+		username: String,
+		#[schema(examples("secret"))]
+		password: String,
+	},
+	Nfs,
+	Http {
+		#[schema(examples("http://192.168.0.4:5050"))]
+		polaris_url: String,
+	},
+}
+
+impl std::fmt::Debug for NewSonosShareScheme {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		config::ShareScheme::from(self.clone()).fmt(f)
+	}
+}
+
+impl From<NewSonosShareScheme> for config::ShareScheme {
+	fn from(s: NewSonosShareScheme) -> Self {
+		match s {
+			NewSonosShareScheme::Cifs => Self::Cifs,
+			NewSonosShareScheme::CifsWithCredentials { username, password } => {
+				Self::CifsWithCredentials { username, password }
+			}
+			NewSonosShareScheme::Nfs => Self::Nfs,
+			NewSonosShareScheme::Http { polaris_url } => Self::Http { polaris_url },
+		}
+	}
+}
+
+/// How Sonos speakers access the music share. The share password is never returned.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SonosShareScheme {
+	Cifs,
+	CifsWithCredentials {
+		#[schema(examples("sonos"))]
+		username: String,
+	},
+	Nfs,
+	Http {
+		#[schema(examples("http://192.168.0.4:5050"))]
+		polaris_url: String,
+	},
+}
+
+impl From<config::ShareScheme> for SonosShareScheme {
+	fn from(s: config::ShareScheme) -> Self {
+		match s {
+			config::ShareScheme::Cifs => Self::Cifs,
+			config::ShareScheme::CifsWithCredentials { username, .. } => {
+				Self::CifsWithCredentials { username }
+			}
+			config::ShareScheme::Nfs => Self::Nfs,
+			config::ShareScheme::Http { polaris_url } => Self::Http { polaris_url },
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SonosPlayMode {
+	Normal,
+	RepeatAll,
+	RepeatOne,
+	Shuffle,
+	ShuffleRepeatAll,
+}
+
+impl From<config::PlayMode> for SonosPlayMode {
+	fn from(m: config::PlayMode) -> Self {
+		match m {
+			config::PlayMode::Normal => Self::Normal,
+			config::PlayMode::RepeatAll => Self::RepeatAll,
+			config::PlayMode::RepeatOne => Self::RepeatOne,
+			config::PlayMode::Shuffle => Self::Shuffle,
+			config::PlayMode::ShuffleRepeatAll => Self::ShuffleRepeatAll,
+		}
+	}
+}
+
+impl From<SonosPlayMode> for config::PlayMode {
+	fn from(m: SonosPlayMode) -> Self {
+		match m {
+			SonosPlayMode::Normal => Self::Normal,
+			SonosPlayMode::RepeatAll => Self::RepeatAll,
+			SonosPlayMode::RepeatOne => Self::RepeatOne,
+			SonosPlayMode::Shuffle => Self::Shuffle,
+			SonosPlayMode::ShuffleRepeatAll => Self::ShuffleRepeatAll,
+		}
+	}
+}
+
+/// Settings of a Sonos speaker which persist from one track to the next
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SonosTransportSettings {
+	pub play_mode: SonosPlayMode,
+	/// Whether tracks fade into each other
+	#[schema(examples(true, false))]
+	pub crossfade: bool,
+}
+
+/// What a Sonos speaker was playing when a snapshot of it was taken
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SonosSnapshot {
+	#[schema(examples("Living Room"))]
+	pub speaker_id: String,
+	/// When the snapshot was taken, in seconds since the Unix epoch
+	#[schema(examples(1760428800))]
+	pub taken_at: u64,
+	pub state: sonos::SonosState,
+	pub queue: Vec<sonos::SonosQueueEntry>,
+	/// Position in `queue` of the current track, starting at 1
+	#[schema(examples(2))]
+	pub track_no: u32,
+	pub play_mode: SonosPlayMode,
+	#[schema(examples(true, false))]
+	pub crossfade: bool,
+}
+
+impl From<sonos::PlaybackSnapshot> for SonosSnapshot {
+	fn from(s: sonos::PlaybackSnapshot) -> Self {
+		Self {
+			speaker_id: s.speaker_id,
+			taken_at: s.taken_at,
+			state: s.state,
+			queue: s.queue,
+			track_no: s.track_no,
+			play_mode: s.play_mode.into(),
+			crossfade: s.crossfade,
+		}
+	}
+}
+
+/// Command sent to a Sonos speaker through Polaris
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct SonosHistoryEntry {
+	/// When the command was received, in milliseconds since the Unix epoch
+	#[schema(examples(1760428800000_u64))]
+	pub timestamp: u64,
+	/// User who sent the command
+	#[schema(examples("alice"))]
+	pub username: String,
+	/// UUID of the speaker the command was sent to, whether it named the speaker by UUID or by room name
+	#[schema(examples("RINCON_000E58A0000001400"))]
+	pub speaker_id: Option<String>,
+	/// Last segment of the route of the command which is not a parameter
+	#[schema(examples("volume", "play-album"))]
+	pub action: String,
+	/// Path parameters and request body of the command
+	#[schema(value_type = Object, examples(json!({ "speaker_id": "Living Room", "volume": 80 })))]
+	pub parameters: serde_json::Value,
+	#[schema(examples(true, false))]
+	pub success: bool,
+	/// HTTP status of the answer to the command
+	#[schema(examples(200, 502))]
+	pub status: u16,
+	#[schema(examples("Sonos service unavailable"))]
+	pub error: Option<String>,
+}
+
+impl From<sonos_history::HistoryEntry> for SonosHistoryEntry {
+	fn from(e: sonos_history::HistoryEntry) -> Self {
+		Self {
+			timestamp: e.recorded_at,
+			username: e.username,
+			speaker_id: e.speaker_id,
+			action: e.action,
+			parameters: serde_json::from_str(&e.parameters).unwrap_or_default(),
+			success: e.success,
+			status: e.status,
+			error: e.error,
+		}
+	}
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, IntoParams, ToSchema)]
+pub struct GetSonosHistoryParameters {
+	/// Only list commands sent to this speaker, given by UUID or room name
+	#[schema(examples("RINCON_000E58A0000001400", "Living Room"))]
+	pub speaker_id: Option<String>,
+	#[schema(examples(50))]
+	pub limit: Option<usize>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SonosSpeakerDefaults {
+	#[schema(examples(25), maximum = 100)]
+	pub volume: Option<u8>,
+	pub play_mode: Option<SonosPlayMode>,
+}
+
+impl From<config::SpeakerDefaults> for SonosSpeakerDefaults {
+	fn from(d: config::SpeakerDefaults) -> Self {
+		Self {
+			volume: d.volume,
+			play_mode: d.play_mode.map(|m| m.into()),
+		}
+	}
+}
+
+impl From<SonosSpeakerDefaults> for config::SpeakerDefaults {
+	fn from(d: SonosSpeakerDefaults) -> Self {
+		Self {
+			volume: d.volume,
+			play_mode: d.play_mode.map(|m| m.into()),
+		}
+	}
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SonosDlnaDevice {
+	#[schema(examples("Receiver"))]
+	pub name: String,
+	#[schema(examples("http://192.168.0.30:8080/MediaRenderer/AVTransport/Control"))]
+	pub control_url: String,
+}
+
+impl From<config::DlnaDevice> for SonosDlnaDevice {
+	fn from(d: config::DlnaDevice) -> Self {
+		Self {
+			name: d.name,
+			control_url: d.control_url,
+		}
+	}
+}
+
+impl From<SonosDlnaDevice> for config::DlnaDevice {
+	fn from(d: SonosDlnaDevice) -> Self {
+		Self {
+			name: d.name,
+			control_url: d.control_url,
+		}
+	}
+}
+
+/// Network share holding the songs of one mount of the collection
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SonosShareMapping {
+	#[schema(examples("classical"))]
+	pub mount: String,
+	#[schema(examples("192.168.0.7/classical"))]
+	pub server: String,
+}
+
+impl From<config::ShareMapping> for SonosShareMapping {
+	fn from(m: config::ShareMapping) -> Self {
+		Self {
+			mount: m.mount,
+			server: m.server,
+		}
+	}
+}
+
+impl From<SonosShareMapping> for config::ShareMapping {
+	fn from(m: SonosShareMapping) -> Self {
+		Self {
+			mount: m.mount,
+			server: m.server,
+		}
+	}
+}
+
+/// What a scene changes on one speaker. Settings left out are not changed.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SonosSceneEntry {
+	#[schema(examples("Playbar", "Kitchen"))]
+	pub speaker: String,
+	#[schema(examples(40, 25), maximum = 100)]
+	pub volume: Option<u8>,
+	#[schema(examples(true, false))]
+	pub muted: Option<bool>,
+}
+
+impl From<config::SonosSceneEntry> for SonosSceneEntry {
+	fn from(e: config::SonosSceneEntry) -> Self {
+		Self {
+			speaker: e.speaker,
+			volume: e.volume,
+			muted: e.muted,
+		}
+	}
+}
+
+impl From<SonosSceneEntry> for config::SonosSceneEntry {
+	fn from(e: SonosSceneEntry) -> Self {
+		Self {
+			speaker: e.speaker,
+			volume: e.volume,
+			muted: e.muted,
+		}
+	}
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SonosScene {
+	#[schema(examples("Movie night", "Dinner"))]
+	pub name: String,
+	pub entries: Vec<SonosSceneEntry>,
+}
+
+impl From<config::SonosScene> for SonosScene {
+	fn from(s: config::SonosScene) -> Self {
+		Self {
+			name: s.name,
+			entries: s.entries.into_iter().map(|e| e.into()).collect(),
+		}
+	}
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SaveSonosSceneInput {
+	pub entries: Vec<SonosSceneEntry>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct NewSonosSettings {
+	/// Whether the Sonos endpoints are available. They stay unavailable until `api_url` is set.
+	#[schema(examples(true, false))]
+	pub enabled: Option<bool>,
+	/// URL of node-sonos-http-api. An empty value removes it.
+	#[schema(examples("http://192.168.0.5:5005"))]
+	pub api_url: Option<String>,
+	/// Network share Sonos reads tracks from, as `host/share`. An empty value removes it.
+	#[schema(examples("192.168.0.6/mp3"))]
+	pub mp3_server: Option<String>,
+	/// Speaker used by requests which do not name one. An empty value removes it.
+	#[schema(examples("Living Room", ""))]
+	pub default_speaker: Option<String>,
+	pub share_scheme: Option<NewSonosShareScheme>,
+	#[schema(examples(true, false))]
+	pub crossfade_enabled: Option<bool>,
+	#[schema(examples(500))]
+	pub max_batch_size: Option<usize>,
+	#[schema(examples(1000))]
+	pub poll_interval_ms: Option<u64>,
+	#[schema(examples(5000))]
+	pub request_timeout_ms: Option<u64>,
+	#[schema(examples(8000))]
+	pub handler_timeout_ms: Option<u64>,
+	#[schema(examples(5, 0))]
+	pub rate_limit_rps: Option<u32>,
+	#[schema(examples(30))]
+	pub speaker_cache_ttl_secs: Option<u64>,
+	#[schema(examples(true, false))]
+	pub availability_check: Option<bool>,
+	#[schema(examples(30))]
+	pub art_cache_ttl_secs: Option<u64>,
+	#[schema(examples(150, 0))]
+	pub volume_coalescing_ms: Option<u64>,
+	#[schema(examples(50, 0))]
+	pub request_spacing_ms: Option<u64>,
+	#[schema(examples(30))]
+	pub resume_retention_days: Option<u64>,
+	/// Zero makes stream URLs permanent
+	#[schema(examples(0, 365))]
+	pub stream_token_ttl_days: Option<u64>,
+	#[schema(examples(1000, 0))]
+	pub history_max_entries: Option<usize>,
+	/// An empty value disables snapshots
+	#[schema(examples("/var/lib/polaris/sonos-snapshots"))]
+	pub snapshot_dir: Option<String>,
+	#[schema(examples(604800))]
+	pub snapshot_max_age_secs: Option<u64>,
+	#[schema(examples(true, false))]
+	pub webhook_enabled: Option<bool>,
+	#[schema(examples(60))]
+	pub webhook_cache_ttl_secs: Option<u64>,
+	#[schema(examples(true, false))]
+	pub smapi_enabled: Option<bool>,
+	#[schema(examples(true, false))]
+	pub enable_upnp_fallback: Option<bool>,
+	#[schema(examples(300))]
+	pub discovery_cache_ttl_secs: Option<u64>,
+	#[schema(examples(true, false))]
+	pub native_control: Option<bool>,
+	#[schema(examples(true, false))]
+	pub allow_url_override: Option<bool>,
+	#[schema(examples(true, false))]
+	pub enable_metrics: Option<bool>,
+	pub retry_policy: Option<SonosRetryPolicy>,
+	/// The password is read from the `POLARIS_SONOS_API_PASSWORD` environment variable
+	#[schema(examples("polaris"))]
+	pub username: Option<String>,
+	pub auth_header: Option<SonosAuthHeader>,
+	#[schema(examples(true, false))]
+	pub accept_invalid_certs: Option<bool>,
+	#[schema(examples("/etc/polaris/sonos-ca.pem"))]
+	pub ca_cert_path: Option<String>,
+	/// Defaults to update, keyed by room name. Speakers which are not listed keep their current defaults,
+	/// and speakers listed without any setting have their defaults removed.
+	pub speaker_defaults: Option<HashMap<String, SonosSpeakerDefaults>>,
+	/// Replaces the whole list of DLNA renderers
+	pub renderers: Option<Vec<SonosDlnaDevice>>,
+	/// Replaces the whole list of share mappings
+	pub share_mappings: Option<Vec<SonosShareMapping>>,
+}
+
+impl From<NewSonosSettings> for config::SonosConfigPatch {
+	fn from(s: NewSonosSettings) -> Self {
+		Self {
+			enabled: s.enabled,
+			api_url: s.api_url,
+			mp3_server: s.mp3_server,
+			default_speaker: s.default_speaker,
+			share_scheme: s.share_scheme.map(|s| s.into()),
+			crossfade_enabled: s.crossfade_enabled,
+			max_batch_size: s.max_batch_size,
+			poll_interval_ms: s.poll_interval_ms,
+			request_timeout_ms: s.request_timeout_ms,
+			handler_timeout_ms: s.handler_timeout_ms,
+			rate_limit_rps: s.rate_limit_rps,
+			speaker_cache_ttl_secs: s.speaker_cache_ttl_secs,
+			availability_check: s.availability_check,
+			art_cache_ttl_secs: s.art_cache_ttl_secs,
+			volume_coalescing_ms: s.volume_coalescing_ms,
+			request_spacing_ms: s.request_spacing_ms,
+			resume_retention_days: s.resume_retention_days,
+			stream_token_ttl_days: s.stream_token_ttl_days,
+			history_max_entries: s.history_max_entries,
+			snapshot_dir: s.snapshot_dir,
+			snapshot_max_age_secs: s.snapshot_max_age_secs,
+			webhook_enabled: s.webhook_enabled,
+			webhook_cache_ttl_secs: s.webhook_cache_ttl_secs,
+			smapi_enabled: s.smapi_enabled,
+			enable_upnp_fallback: s.enable_upnp_fallback,
+			discovery_cache_ttl_secs: s.discovery_cache_ttl_secs,
+			native_control: s.native_control,
+			allow_url_override: s.allow_url_override,
+			enable_metrics: s.enable_metrics,
+			retry_policy: s.retry_policy.map(|p| p.into()),
+			username: s.username,
+			auth_header: s.auth_header.map(|h| h.into()),
+			accept_invalid_certs: s.accept_invalid_certs,
+			ca_cert_path: s.ca_cert_path,
+			speaker_defaults: s.speaker_defaults.map(|d| {
+				d.into_iter()
+					.map(|(speaker, defaults)| (speaker, defaults.into()))
+					.collect()
+			}),
+			renderers: s
+				.renderers
+				.map(|r| r.into_iter().map(|d| d.into()).collect()),
+			share_mappings: s
+				.share_mappings
+				.map(|m| m.into_iter().map(|m| m.into()).collect()),
+		}
+	}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct SonosSettings {
+	#[schema(examples(true, false))]
+	pub configured: bool,
+	/// Whether the Sonos endpoints are available
+	#[schema(examples(true, false))]
+	pub enabled: bool,
+	#[schema(examples("http://192.168.0.5:5005"))]
+	pub api_url: Option<String>,
+	#[schema(examples("192.168.0.6/mp3"))]
+	pub mp3_server: Option<String>,
+	/// Speaker used by requests which do not name one
+	#[schema(examples("Living Room"))]
+	pub default_speaker: Option<String>,
+	pub share_scheme: SonosShareScheme,
+	#[schema(examples(true, false))]
+	pub crossfade_enabled: Option<bool>,
+	#[schema(examples(500))]
+	pub max_batch_size: usize,
+	#[schema(examples(1000))]
+	pub poll_interval_ms: u64,
+	#[schema(examples(5000))]
+	pub request_timeout_ms: Option<u64>,
+	/// Longest time an API request can wait for node-sonos-http-api before answering with a 504 status
+	#[schema(examples(8000))]
+	pub handler_timeout_ms: u64,
+	/// Requests per second each client can send to the Sonos endpoints. There is no limit if unset.
+	#[schema(examples(5))]
+	pub rate_limit_rps: Option<u32>,
+	#[schema(examples(30))]
+	pub speaker_cache_ttl_secs: u64,
+	#[schema(examples(true, false))]
+	pub availability_check: bool,
+	#[schema(examples(30))]
+	pub art_cache_ttl_secs: u64,
+	#[schema(examples(150, 0))]
+	pub volume_coalescing_ms: u64,
+	#[schema(examples(50, 0))]
+	pub request_spacing_ms: u64,
+	#[schema(examples(30))]
+	pub resume_retention_days: u64,
+	/// Number of days the URL of a track streamed over HTTP keeps working, or 0 if it never expires
+	#[schema(examples(0, 365))]
+	pub stream_token_ttl_days: u64,
+	/// Number of commands kept in the Sonos history
+	#[schema(examples(1000, 0))]
+	pub history_max_entries: usize,
+	#[schema(examples("/var/lib/polaris/sonos-snapshots"))]
+	pub snapshot_dir: Option<String>,
+	#[schema(examples(604800))]
+	pub snapshot_max_age_secs: u64,
+	#[schema(examples(true, false))]
+	pub webhook_enabled: bool,
+	#[schema(examples(60))]
+	pub webhook_cache_ttl_secs: u64,
+	#[schema(examples(true, false))]
+	pub smapi_enabled: bool,
+	/// Whether speakers are looked up on the local network when node-sonos-http-api does not report any
+	#[schema(examples(true, false))]
+	pub enable_upnp_fallback: bool,
+	/// Duration in seconds during which speakers found on the local network are reused
+	#[schema(examples(300))]
+	pub discovery_cache_ttl_secs: u64,
+	/// Whether speakers found on the local network are controlled directly over UPnP, without node-sonos-http-api
+	#[schema(examples(true, false))]
+	pub native_control: bool,
+	/// Whether requests may target another node-sonos-http-api instance with the `X-Sonos-Api-Override-Url` header
+	#[schema(examples(true, false))]
+	pub allow_url_override: bool,
+	/// Whether operations run on each speaker are counted in the Sonos metrics
+	#[schema(examples(true, false))]
+	pub enable_metrics: bool,
+	pub retry_policy: SonosRetryPolicy,
+	#[schema(examples("polaris"))]
+	pub username: Option<String>,
+	/// Whether a password was provided through the `POLARIS_SONOS_API_PASSWORD` environment variable
+	#[schema(examples(true, false))]
+	pub has_password: bool,
+	/// Whether a bearer token was provided through the `POLARIS_SONOS_API_TOKEN` environment variable
+	#[schema(examples(true, false))]
+	pub has_api_token: bool,
+	/// Name of the header sent to the bridge. Its value is never returned.
+	#[schema(examples("X-Api-Key"))]
+	pub auth_header_name: Option<String>,
+	#[schema(examples(true, false))]
+	pub accept_invalid_certs: bool,
+	#[schema(examples("/etc/polaris/sonos-ca.pem"))]
+	pub ca_cert_path: Option<String>,
+	/// Volume and play mode applied before playing on a speaker, keyed by room name
+	pub speaker_defaults: HashMap<String, SonosSpeakerDefaults>,
+	/// UPnP/DLNA renderers listed along with the Sonos speakers
+	pub renderers: Vec<SonosDlnaDevice>,
+	/// Shares Sonos reads the songs of some mounts from, instead of `mp3_server`
+	pub share_mappings: Vec<SonosShareMapping>,
+}
+
+impl From<config::SonosConfig> for SonosSettings {
+	fn from(c: config::SonosConfig) -> Self {
+		Self {
+			configured: c.is_configured(),
+			enabled: c.is_enabled(),
+			api_url: c.get_api_url(),
+			mp3_server: c.get_mp3_server(),
+			default_speaker: c.get_default_speaker(),
+			share_scheme: c.get_share_scheme().into(),
+			crossfade_enabled: c.crossfade_enabled,
+			max_batch_size: c.get_max_batch_size(),
+			poll_interval_ms: c.get_poll_interval().as_millis() as u64,
+			request_timeout_ms: c.request_timeout_ms,
+			handler_timeout_ms: c.get_handler_timeout().as_millis() as u64,
+			rate_limit_rps: c.get_rate_limit_rps(),
+			speaker_cache_ttl_secs: c.get_speaker_cache_ttl().as_secs(),
+			availability_check: c.is_availability_check_enabled(),
+			art_cache_ttl_secs: c.get_art_cache_ttl().as_secs(),
+			volume_coalescing_ms: c.get_volume_coalescing_window().as_millis() as u64,
+			request_spacing_ms: c.get_request_spacing().as_millis() as u64,
+			resume_retention_days: c.get_resume_retention_days(),
+			stream_token_ttl_days: c.get_stream_token_ttl_days(),
+			history_max_entries: c.get_history_max_entries(),
+			snapshot_dir: c
+				.snapshot_dir
+				.as_ref()
+				.map(|p| p.to_string_lossy().into_owned()),
+			snapshot_max_age_secs: c.get_snapshot_max_age().as_secs(),
+			webhook_enabled: c.webhook_enabled,
+			webhook_cache_ttl_secs: c.get_webhook_cache_ttl().as_secs(),
+			smapi_enabled: c.smapi_enabled,
+			enable_upnp_fallback: c.enable_upnp_fallback,
+			discovery_cache_ttl_secs: c.get_discovery_cache_ttl().as_secs(),
+			native_control: c.native_control,
+			allow_url_override: c.allow_url_override,
+			enable_metrics: c.enable_metrics,
+			retry_policy: c.get_retry_policy().into(),
+			accept_invalid_certs: c.accept_invalid_certs,
+			ca_cert_path: c
+				.ca_cert_path
+				.as_ref()
+				.map(|p| p.to_string_lossy().into_owned()),
+			has_password: c.get_password().is_some(),
+			has_api_token: c.api_token.is_some(),
+			auth_header_name: c.auth_header.map(|h| h.name),
+			speaker_defaults: c
+				.speaker_defaults
+				.into_iter()
+				.map(|(speaker, defaults)| (speaker, defaults.into()))
+				.collect(),
+			renderers: c.renderers.into_iter().map(|d| d.into()).collect(),
+			share_mappings: c.share_mappings.into_iter().map(|m| m.into()).collect(),
+			username: c.username,
+		}
+	}
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
 pub enum IndexState {
 	OutOfDate,
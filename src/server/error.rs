@@ -2,6 +2,7 @@ use std::path::PathBuf;
 use thiserror::Error;
 
 use crate::app;
+use crate::sonos::{SonosError, MAX_CROSSFADE_OVERLAP_SECS, MAX_SLEEP_TIMER_SECS};
 
 #[derive(Error, Debug)]
 pub enum APIError {
@@ -15,6 +16,10 @@ pub enum APIError {
 	AuthorizationTokenEncoding,
 	#[error("Administrator permission is required")]
 	AdminPermissionRequired,
+	#[error("This user is not allowed to control Sonos speakers")]
+	SonosPermissionRequired,
+	#[error("This user is not allowed to control Sonos speaker `{0}`")]
+	SonosSpeakerPermissionRequired(String),
 	#[error("Audio file could not be opened")]
 	AudioFileIOError,
 	#[error("Authentication is required")]
@@ -53,6 +58,8 @@ pub enum APIError {
 	InvalidAlbumArtPattern,
 	#[error("Could not parse DDNS update URL")]
 	InvalidDDNSURL,
+	#[error("Invalid Sonos settings: {0}")]
+	InvalidSonosSettings(String),
 	#[error("File I/O error for `{0}`:\n\n{1}")]
 	Io(PathBuf, std::io::Error),
 	#[error("Cannot remove your own admin privilege")]
@@ -61,6 +68,82 @@ pub enum APIError {
 	PasswordHashing,
 	#[error("Playlist not found")]
 	PlaylistNotFound,
+	#[error("Nothing to resume on this Sonos speaker")]
+	SonosResumePointNotFound,
+	#[error("Sonos speaker `{0}` has no playback session")]
+	SonosSessionNotFound(String),
+	#[error("Sonos speaker `{0}` is not playing an interrupting track")]
+	SonosInterruptionNotFound(String),
+	#[error("Sonos snapshots are disabled")]
+	SonosSnapshotsDisabled,
+	#[error("No snapshot of Sonos speaker `{0}`")]
+	SonosSnapshotNotFound(String),
+	#[error("The snapshot of Sonos speaker `{0}` is too old to be restored")]
+	SonosSnapshotExpired(String),
+	#[error("No Sonos favorite named `{0}`")]
+	SonosFavoriteNotFound(String),
+	#[error("No DLNA renderer named `{0}`")]
+	SonosRendererNotFound(String),
+	#[error("No Sonos speaker with UUID or room name `{0}`")]
+	SonosSpeakerNotFound(String),
+	#[error("Sonos speaker `{0}` cannot join its own group")]
+	SonosJoinOwnGroup(String),
+	#[error("No album of the collection can be played on Sonos")]
+	SonosNoPlayableAlbum,
+	#[error("Sonos speaker is playing something else")]
+	SonosSpeakerBusy,
+	#[error("Sonos is disabled")]
+	SonosDisabled,
+	#[error("Sonos scene not found: `{0}`")]
+	SonosSceneNotFound(String),
+	#[error("Sonos API URL override is disabled")]
+	SonosUrlOverrideDisabled,
+	#[error("Invalid Sonos API override URL: `{0}`")]
+	SonosUrlOverrideInvalid(String),
+	#[error("no default speaker configured")]
+	SonosNoDefaultSpeaker,
+	#[error("no Sonos file server configured")]
+	SonosNoMusicShare,
+	#[error("Could not connect to the Sonos service")]
+	SonosConnectionFailed,
+	#[error(
+		"Sonos service did not answer `{action}`{} in time",
+		speaker.as_deref().map(|s| format!(" for speaker `{s}`")).unwrap_or_default()
+	)]
+	SonosTimeout {
+		action: String,
+		speaker: Option<String>,
+	},
+	#[error("Sonos service returned HTTP status {0}")]
+	SonosHttpError(u16),
+	#[error("Could not parse Sonos service response")]
+	SonosInvalidResponse,
+	#[error("Not a valid URI: `{0}`")]
+	SonosInvalidUri(String),
+	#[error("Sonos webhook is disabled")]
+	SonosWebhookDisabled,
+	#[error("No album art available for the current Sonos track")]
+	SonosAlbumArtNotFound,
+	#[error("Invalid Sonos play request: {0}")]
+	SonosInvalidPlayRequest(String),
+	#[error("Sleep timer cannot exceed {max} seconds (requested {0})", max = MAX_SLEEP_TIMER_SECS)]
+	SonosSleepTimerTooLong(u32),
+	#[error("Crossfade overlap cannot exceed {max} seconds (requested {0})", max = MAX_CROSSFADE_OVERLAP_SECS)]
+	SonosCrossfadeOverlapTooLong(u8),
+	#[error("Invalid Sonos announcement: {0}")]
+	SonosInvalidAnnouncement(String),
+	#[error("Could not decode track path `{0}`")]
+	SonosInvalidTrackUrl(String),
+	#[error("A Sonos playlist named `{0}` already exists")]
+	SonosPlaylistExists(String),
+	#[error("Volume must be between 0 and 100, got {0}")]
+	SonosInvalidVolume(u8),
+	#[error("Could not change Sonos volume: {0}")]
+	SonosVolumeChangeFailed(String),
+	#[error("{0}")]
+	SonosQueuePositionOutOfRange(String),
+	#[error("{0}")]
+	SonosInvalidSeek(String),
 	#[error("Could not parse search query")]
 	SearchQueryParseError,
 	#[error("Could not decode thumbnail from flac file `{0}`:\n\n{1}")]
@@ -124,6 +207,33 @@ impl From<app::Error> for APIError {
 			app::Error::AuthenticationSecretInvalid => APIError::Internal,
 			app::Error::MiscSettingsNotFound => APIError::Internal,
 			app::Error::DDNSUpdateURLInvalid => APIError::InvalidDDNSURL,
+			e @ app::Error::SonosApiURLInvalid(_) => APIError::InvalidSonosSettings(e.to_string()),
+			e @ app::Error::SonosMp3ServerInvalid(_) => {
+				APIError::InvalidSonosSettings(e.to_string())
+			}
+			e @ app::Error::SonosStreamURLInvalid(_) => {
+				APIError::InvalidSonosSettings(e.to_string())
+			}
+			e @ app::Error::SonosShareMappingInvalid(_) => {
+				APIError::InvalidSonosSettings(e.to_string())
+			}
+			e @ app::Error::SonosAuthHeaderInvalid(_) => {
+				APIError::InvalidSonosSettings(e.to_string())
+			}
+			e @ app::Error::SonosCACertificateInvalid(_) => {
+				APIError::InvalidSonosSettings(e.to_string())
+			}
+			e @ app::Error::SonosDefaultVolumeInvalid(_, _) => {
+				APIError::InvalidSonosSettings(e.to_string())
+			}
+			e @ app::Error::SonosSceneNameEmpty => APIError::InvalidSonosSettings(e.to_string()),
+			e @ app::Error::SonosSceneVolumeInvalid(_, _, _) => {
+				APIError::InvalidSonosSettings(e.to_string())
+			}
+			app::Error::SonosSceneNotFound(name) => APIError::SonosSceneNotFound(name),
+			e @ app::Error::DlnaControlURLInvalid(_, _) => {
+				APIError::InvalidSonosSettings(e.to_string())
+			}
 			app::Error::IndexAlbumArtPatternInvalid => APIError::InvalidAlbumArtPattern,
 
 			app::Error::ConfigDeserialization(_) => APIError::Internal,
@@ -140,6 +250,7 @@ impl From<app::Error> for APIError {
 			app::Error::GenreNotFound => APIError::GenreNotFound,
 			app::Error::SongNotFound => APIError::SongNotFound,
 			app::Error::PlaylistNotFound => APIError::PlaylistNotFound,
+			app::Error::ResumePointNotFound => APIError::SonosResumePointNotFound,
 			app::Error::SearchQueryParseError => APIError::SearchQueryParseError,
 			app::Error::EmbeddedArtworkNotFound(_) => APIError::EmbeddedArtworkNotFound,
 
@@ -156,3 +267,49 @@ impl From<app::Error> for APIError {
 		}
 	}
 }
+
+impl From<SonosError> for APIError {
+	fn from(error: SonosError) -> APIError {
+		match error {
+			SonosError::ConnectionFailed(_) => APIError::SonosConnectionFailed,
+			SonosError::HttpError { status, .. } => APIError::SonosHttpError(status),
+			SonosError::InvalidResponse(_) => APIError::SonosInvalidResponse,
+			SonosError::InvalidZones(_) => APIError::SonosInvalidResponse,
+			SonosError::InvalidUri(u) => APIError::SonosInvalidUri(u),
+			SonosError::WebhookDisabled => APIError::SonosWebhookDisabled,
+			SonosError::AlbumArtNotFound => APIError::SonosAlbumArtNotFound,
+			SonosError::SleepTimerTooLong(s) => APIError::SonosSleepTimerTooLong(s),
+			SonosError::CrossfadeOverlapTooLong(s) => APIError::SonosCrossfadeOverlapTooLong(s),
+			SonosError::InvalidAnnouncement(m) => APIError::SonosInvalidAnnouncement(m),
+			SonosError::UrlDecode(p) => APIError::SonosInvalidTrackUrl(p),
+			SonosError::PlaylistExists(n) => APIError::SonosPlaylistExists(n),
+			SonosError::InvalidVolume(v) => APIError::SonosInvalidVolume(v),
+			SonosError::VolumeChangeFailed(e) => APIError::SonosVolumeChangeFailed(e),
+			SonosError::VolumeUnknown(_) => APIError::SonosInvalidResponse,
+			e @ SonosError::NotAudioUrl { .. } => APIError::SonosInvalidPlayRequest(e.to_string()),
+			e @ SonosError::NotOnShare(_) => APIError::SonosInvalidPlayRequest(e.to_string()),
+			e @ SonosError::QueuePositionOutOfRange { .. } => {
+				APIError::SonosQueuePositionOutOfRange(e.to_string())
+			}
+			e @ SonosError::SeekOutOfRange { .. } => APIError::SonosInvalidSeek(e.to_string()),
+			e @ SonosError::NotSeekable(_) => APIError::SonosInvalidSeek(e.to_string()),
+			SonosError::SessionNotFound(s) => APIError::SonosSessionNotFound(s),
+			SonosError::InterruptionNotFound(s) => APIError::SonosInterruptionNotFound(s),
+			SonosError::SnapshotsDisabled => APIError::SonosSnapshotsDisabled,
+			SonosError::SnapshotNotFound(s) => APIError::SonosSnapshotNotFound(s),
+			SonosError::SnapshotExpired(s) => APIError::SonosSnapshotExpired(s),
+			SonosError::SnapshotIo(p, e) => APIError::Io(p, e),
+			SonosError::InvalidSnapshot(_, _) => APIError::Internal,
+			SonosError::FavoriteNotFound(t) => APIError::SonosFavoriteNotFound(t),
+			SonosError::LibraryUnavailable => APIError::Internal,
+			SonosError::NoPlayableAlbum => APIError::SonosNoPlayableAlbum,
+			SonosError::Library(e) => e.into(),
+			SonosError::RendererNotFound(n) => APIError::SonosRendererNotFound(n),
+			SonosError::RendererConnectionFailed(_, _) => APIError::SonosConnectionFailed,
+			SonosError::RendererHttpError { status, .. } => APIError::SonosHttpError(status),
+			SonosError::InvalidRendererResponse(_) => APIError::SonosInvalidResponse,
+			SonosError::SpeakerNotFound(s) => APIError::SonosSpeakerNotFound(s),
+			SonosError::JoinOwnGroup(s) => APIError::SonosJoinOwnGroup(s),
+		}
+	}
+}
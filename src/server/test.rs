@@ -19,6 +19,7 @@ mod media;
 mod playlist;
 mod search;
 mod settings;
+mod sonos;
 mod user;
 mod web;
 
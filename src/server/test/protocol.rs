@@ -2,8 +2,15 @@ use http::{Method, Request};
 use percent_encoding::{percent_encode, NON_ALPHANUMERIC};
 use std::path::Path;
 
+use crate::app::url::PolarisUrlBuilder;
 use crate::server::dto;
 use crate::server::dto::ThumbnailSize;
+use crate::sonos::{
+	CrossfadeQueueRequest, JoinGroupRequest, MuteRequest, PlayAlbumRequest, PlayAlbumsRequest,
+	PlayDirectoryRequest, PlayFavoriteRequest, PlayPlaylistRequest, PlaySavedPlaylistRequest,
+	PlayTrackRequest, PlayUriRequest, RepeatMode, RepeatRequest, SeekRequest, ShuffleRequest,
+	VolumeAdjustRequest, VolumeRequest,
+};
 
 pub trait ProtocolVersion {
 	fn header_value() -> i32;
@@ -92,6 +99,394 @@ pub fn put_settings(settings: dto::NewSettings) -> Request<dto::NewSettings> {
 		.unwrap()
 }
 
+pub fn get_sonos_config() -> Request<()> {
+	Request::builder()
+		.method(Method::GET)
+		.uri("/api/sonos/config")
+		.body(())
+		.unwrap()
+}
+
+pub fn put_sonos_config(settings: dto::NewSonosSettings) -> Request<dto::NewSonosSettings> {
+	Request::builder()
+		.method(Method::PUT)
+		.uri("/api/sonos/config")
+		.body(settings)
+		.unwrap()
+}
+
+pub fn patch_sonos_config(settings: dto::NewSonosSettings) -> Request<dto::NewSonosSettings> {
+	Request::builder()
+		.method(Method::PATCH)
+		.uri("/api/sonos/config")
+		.body(settings)
+		.unwrap()
+}
+
+pub fn get_sonos_metrics() -> Request<()> {
+	Request::builder()
+		.method(Method::GET)
+		.uri("/api/sonos/metrics")
+		.body(())
+		.unwrap()
+}
+
+pub fn get_sonos_history(speaker_id: Option<&str>) -> Request<()> {
+	let query = speaker_id
+		.map(|s| format!("?speaker_id={}", url_encode(s)))
+		.unwrap_or_default();
+	Request::builder()
+		.method(Method::GET)
+		.uri(format!("/api/sonos/history{query}"))
+		.body(())
+		.unwrap()
+}
+
+pub fn get_sonos_speakers() -> Request<()> {
+	Request::builder()
+		.method(Method::GET)
+		.uri("/api/sonos/speakers")
+		.body(())
+		.unwrap()
+}
+
+pub fn get_sonos_firmware() -> Request<()> {
+	Request::builder()
+		.method(Method::GET)
+		.uri("/api/sonos/firmware")
+		.body(())
+		.unwrap()
+}
+
+pub fn get_sonos_speakers_by_name() -> Request<()> {
+	Request::builder()
+		.method(Method::GET)
+		.uri("/api/sonos/speakers/by-name")
+		.body(())
+		.unwrap()
+}
+
+pub fn sonos_play(request: PlayTrackRequest) -> Request<PlayTrackRequest> {
+	Request::builder()
+		.method(Method::POST)
+		.uri("/api/sonos/play")
+		.body(request)
+		.unwrap()
+}
+
+pub fn get_sonos_state(speaker_id: &str) -> Request<()> {
+	Request::builder()
+		.method(Method::GET)
+		.uri(format!("/api/sonos/state/{}", url_encode(speaker_id)))
+		.body(())
+		.unwrap()
+}
+
+pub fn get_sonos_now_playing() -> Request<()> {
+	Request::builder()
+		.method(Method::GET)
+		.uri("/api/sonos/now_playing")
+		.body(())
+		.unwrap()
+}
+
+pub fn end_sonos_interrupt(speaker_id: &str) -> Request<()> {
+	Request::builder()
+		.method(Method::POST)
+		.uri(format!(
+			"/api/sonos/{}/end_interrupt",
+			url_encode(speaker_id)
+		))
+		.body(())
+		.unwrap()
+}
+
+pub fn get_sonos_transport(speaker_id: &str) -> Request<()> {
+	Request::builder()
+		.method(Method::GET)
+		.uri(format!("/api/sonos/{}/transport", url_encode(speaker_id)))
+		.body(())
+		.unwrap()
+}
+
+pub fn post_sonos_snapshot(speaker_id: &str) -> Request<()> {
+	Request::builder()
+		.method(Method::POST)
+		.uri(format!("/api/sonos/{}/snapshot", url_encode(speaker_id)))
+		.body(())
+		.unwrap()
+}
+
+pub fn post_sonos_restore(speaker_id: &str) -> Request<()> {
+	Request::builder()
+		.method(Method::POST)
+		.uri(format!("/api/sonos/{}/restore", url_encode(speaker_id)))
+		.body(())
+		.unwrap()
+}
+
+pub fn get_sonos_session(speaker_id: &str) -> Request<()> {
+	Request::builder()
+		.method(Method::GET)
+		.uri(format!("/api/sonos/{}/session", url_encode(speaker_id)))
+		.body(())
+		.unwrap()
+}
+
+pub fn sonos_default_pause() -> Request<()> {
+	Request::builder()
+		.method(Method::POST)
+		.uri("/api/sonos/pause")
+		.body(())
+		.unwrap()
+}
+
+pub fn get_sonos_scenes() -> Request<()> {
+	Request::builder()
+		.method(Method::GET)
+		.uri("/api/sonos/scenes")
+		.body(())
+		.unwrap()
+}
+
+pub fn put_sonos_scene(
+	name: &str,
+	scene: dto::SaveSonosSceneInput,
+) -> Request<dto::SaveSonosSceneInput> {
+	Request::builder()
+		.method(Method::PUT)
+		.uri(format!("/api/sonos/scenes/{}", url_encode(name)))
+		.body(scene)
+		.unwrap()
+}
+
+pub fn get_sonos_scene(name: &str) -> Request<()> {
+	Request::builder()
+		.method(Method::GET)
+		.uri(format!("/api/sonos/scenes/{}", url_encode(name)))
+		.body(())
+		.unwrap()
+}
+
+pub fn delete_sonos_scene(name: &str) -> Request<()> {
+	Request::builder()
+		.method(Method::DELETE)
+		.uri(format!("/api/sonos/scenes/{}", url_encode(name)))
+		.body(())
+		.unwrap()
+}
+
+pub fn apply_sonos_scene(name: &str) -> Request<()> {
+	Request::builder()
+		.method(Method::POST)
+		.uri(format!("/api/sonos/scenes/{}/apply", url_encode(name)))
+		.body(())
+		.unwrap()
+}
+
+pub fn sonos_play_album(speaker_id: &str, request: PlayAlbumRequest) -> Request<PlayAlbumRequest> {
+	Request::builder()
+		.method(Method::POST)
+		.uri(format!("/api/sonos/{}/play-album", url_encode(speaker_id)))
+		.body(request)
+		.unwrap()
+}
+
+pub fn sonos_crossfade_queue(
+	speaker_id: &str,
+	request: CrossfadeQueueRequest,
+) -> Request<CrossfadeQueueRequest> {
+	Request::builder()
+		.method(Method::POST)
+		.uri(format!(
+			"/api/sonos/{}/crossfade-queue",
+			url_encode(speaker_id)
+		))
+		.body(request)
+		.unwrap()
+}
+
+pub fn sonos_play_playlist(
+	speaker_id: &str,
+	request: PlayPlaylistRequest,
+) -> Request<PlayPlaylistRequest> {
+	Request::builder()
+		.method(Method::POST)
+		.uri(format!(
+			"/api/sonos/{}/play-playlist",
+			url_encode(speaker_id)
+		))
+		.body(request)
+		.unwrap()
+}
+
+pub fn sonos_play_random_album(request: PlayAlbumsRequest) -> Request<PlayAlbumsRequest> {
+	Request::builder()
+		.method(Method::POST)
+		.uri("/api/sonos/play_random_album")
+		.body(request)
+		.unwrap()
+}
+
+pub fn sonos_play_recent_album(request: PlayAlbumsRequest) -> Request<PlayAlbumsRequest> {
+	Request::builder()
+		.method(Method::POST)
+		.uri("/api/sonos/play_recent_album")
+		.body(request)
+		.unwrap()
+}
+
+pub fn sonos_play_saved_playlist(
+	request: PlaySavedPlaylistRequest,
+) -> Request<PlaySavedPlaylistRequest> {
+	Request::builder()
+		.method(Method::PUT)
+		.uri("/api/sonos/play_playlist")
+		.body(request)
+		.unwrap()
+}
+
+pub fn sonos_play_directory(request: PlayDirectoryRequest) -> Request<PlayDirectoryRequest> {
+	Request::builder()
+		.method(Method::PUT)
+		.uri("/api/sonos/play_directory")
+		.body(request)
+		.unwrap()
+}
+
+pub fn sonos_play_favorite(speaker_id: &str, title: &str) -> Request<PlayFavoriteRequest> {
+	Request::builder()
+		.method(Method::POST)
+		.uri(format!(
+			"/api/sonos/{}/favorites/play",
+			url_encode(speaker_id)
+		))
+		.body(PlayFavoriteRequest {
+			title: title.to_owned(),
+		})
+		.unwrap()
+}
+
+pub fn sonos_play_uri(speaker_id: &str, uri: &str) -> Request<PlayUriRequest> {
+	Request::builder()
+		.method(Method::POST)
+		.uri(format!("/api/sonos/{}/play-uri", url_encode(speaker_id)))
+		.body(PlayUriRequest {
+			uri: uri.to_owned(),
+			..Default::default()
+		})
+		.unwrap()
+}
+
+pub fn sonos_volume(speaker_id: &str, volume: u8) -> Request<VolumeRequest> {
+	Request::builder()
+		.method(Method::PUT)
+		.uri(format!("/api/sonos/{}/volume", url_encode(speaker_id)))
+		.body(VolumeRequest { volume })
+		.unwrap()
+}
+
+pub fn sonos_adjust_volume(speaker_id: &str, delta: i8) -> Request<VolumeAdjustRequest> {
+	Request::builder()
+		.method(Method::POST)
+		.uri(format!(
+			"/api/sonos/{}/volume/adjust",
+			url_encode(speaker_id)
+		))
+		.body(VolumeAdjustRequest { delta })
+		.unwrap()
+}
+
+pub fn sonos_set_mute(speaker_id: &str, muted: bool) -> Request<MuteRequest> {
+	Request::builder()
+		.method(Method::PUT)
+		.uri(format!("/api/sonos/{}/mute", url_encode(speaker_id)))
+		.body(MuteRequest { muted })
+		.unwrap()
+}
+
+pub fn sonos_mute(speaker_id: &str) -> Request<()> {
+	Request::builder()
+		.method(Method::POST)
+		.uri(format!("/api/sonos/{}/mute", url_encode(speaker_id)))
+		.body(())
+		.unwrap()
+}
+
+pub fn sonos_join(speaker_id: &str, group_speaker_id: &str) -> Request<JoinGroupRequest> {
+	Request::builder()
+		.method(Method::POST)
+		.uri(format!("/api/sonos/{}/join", url_encode(speaker_id)))
+		.body(JoinGroupRequest {
+			speaker_id: group_speaker_id.to_owned(),
+		})
+		.unwrap()
+}
+
+pub fn sonos_leave(speaker_id: &str) -> Request<()> {
+	Request::builder()
+		.method(Method::POST)
+		.uri(format!("/api/sonos/{}/leave", url_encode(speaker_id)))
+		.body(())
+		.unwrap()
+}
+
+pub fn clear_sonos_queue(speaker_id: &str) -> Request<()> {
+	Request::builder()
+		.method(Method::DELETE)
+		.uri(format!("/api/sonos/{}/queue", url_encode(speaker_id)))
+		.body(())
+		.unwrap()
+}
+
+pub fn sonos_resume(speaker_id: &str) -> Request<()> {
+	Request::builder()
+		.method(Method::POST)
+		.uri(format!("/api/sonos/{}/resume", url_encode(speaker_id)))
+		.body(())
+		.unwrap()
+}
+
+pub fn sonos_next(speaker_id: &str) -> Request<()> {
+	Request::builder()
+		.method(Method::POST)
+		.uri(format!("/api/sonos/{}/next", url_encode(speaker_id)))
+		.body(())
+		.unwrap()
+}
+
+pub fn sonos_seek(speaker_id: &str, position: u32) -> Request<SeekRequest> {
+	Request::builder()
+		.method(Method::POST)
+		.uri(format!("/api/sonos/{}/seek", url_encode(speaker_id)))
+		.body(SeekRequest { position })
+		.unwrap()
+}
+
+pub fn sonos_shuffle(speaker_id: &str, enabled: bool) -> Request<ShuffleRequest> {
+	Request::builder()
+		.method(Method::PUT)
+		.uri(format!("/api/sonos/{}/shuffle", url_encode(speaker_id)))
+		.body(ShuffleRequest { enabled })
+		.unwrap()
+}
+
+pub fn sonos_repeat(speaker_id: &str, mode: RepeatMode) -> Request<RepeatRequest> {
+	Request::builder()
+		.method(Method::PUT)
+		.uri(format!("/api/sonos/{}/repeat", url_encode(speaker_id)))
+		.body(RepeatRequest { mode })
+		.unwrap()
+}
+
+pub fn sonos_previous(speaker_id: &str) -> Request<()> {
+	Request::builder()
+		.method(Method::POST)
+		.uri(format!("/api/sonos/{}/previous", url_encode(speaker_id)))
+		.body(())
+		.unwrap()
+}
+
 pub fn list_users() -> Request<()> {
 	Request::builder()
 		.method(Method::GET)
@@ -241,7 +636,7 @@ pub fn songs(songs: dto::GetSongsBulkInput) -> Request<dto::GetSongsBulkInput> {
 
 pub fn audio(path: &Path) -> Request<()> {
 	let path = path.to_string_lossy();
-	let endpoint = format!("/api/audio/{}", url_encode(path.as_ref()));
+	let endpoint = PolarisUrlBuilder::new("/api".to_owned()).audio_url(path.as_ref());
 	Request::builder()
 		.method(Method::GET)
 		.uri(&endpoint)
@@ -1,4 +1,4 @@
-use http::StatusCode;
+use http::{HeaderValue, StatusCode};
 
 use crate::server::test::{protocol, ServiceType, TestService};
 use crate::test_name;
@@ -10,3 +10,26 @@ async fn serves_web_client() {
 	let response = service.fetch_bytes(&request).await;
 	assert_eq!(response.status(), StatusCode::OK);
 }
+
+#[tokio::test]
+async fn responses_have_request_id() {
+	let mut service = ServiceType::new(&test_name!()).await;
+	let request = protocol::web_index();
+	let response = service.fetch(&request).await;
+	let request_id = response.headers().get("x-request-id").unwrap();
+	assert!(!request_id.is_empty());
+}
+
+#[tokio::test]
+async fn echoes_client_request_id() {
+	let mut service = ServiceType::new(&test_name!()).await;
+	let mut request = protocol::web_index();
+	request
+		.headers_mut()
+		.insert("x-request-id", HeaderValue::from_static("bedtime-42"));
+	let response = service.fetch(&request).await;
+	assert_eq!(
+		response.headers().get("x-request-id").unwrap(),
+		"bedtime-42"
+	);
+}
@@ -0,0 +1,654 @@
+//! Minimal implementation of the Sonos Music API (SMAPI), so the music collection can be browsed and played
+//! directly from the Sonos app.
+//!
+//! Sonos players talk to this service with SOAP requests. Browsing follows the directory structure of the
+//! collection, and songs are streamed by the speakers from the same network share used by the
+//! node-sonos-http-api integration.
+//!
+//! Players log in with the username and password of a Polaris user (the `UserId` authentication mode of
+//! SMAPI), and send the session they are given with every other request.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use quick_xml::escape::escape;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+
+use crate::app::{auth, config, config::MusicShare, index, scanner};
+use crate::sonos;
+use crate::utils::{get_audio_format, AudioFormat};
+
+pub const SMAPI_NAMESPACE: &str = "http://www.sonos.com/Services/1.1";
+pub const SOAP_ENVELOPE_NAMESPACE: &str = "http://schemas.xmlsoap.org/soap/envelope/";
+pub const SMAPI_CONTENT_TYPE: &str = "text/xml; charset=utf-8";
+
+/// How often (in seconds) the Sonos app should check whether the collection changed
+const POLL_INTERVAL_SECS: u64 = 300;
+const ROOT_ID: &str = "root";
+const DIRECTORY_ID_PREFIX: &str = "dir:";
+const TRACK_ID_PREFIX: &str = "track:";
+
+#[derive(thiserror::Error, Debug)]
+pub enum SmapiError {
+	#[error("The SMAPI service is disabled")]
+	Disabled,
+	#[error("Could not parse SOAP request")]
+	InvalidEnvelope,
+	#[error("Unsupported SMAPI operation `{0}`")]
+	UnsupportedOperation(String),
+	#[error("Missing parameter `{0}`")]
+	MissingParameter(String),
+	#[error("Invalid value for parameter `{0}`")]
+	InvalidParameter(String),
+	#[error("Item `{0}` not found")]
+	ItemNotFound(String),
+	#[error("Incorrect username or password")]
+	LoginInvalid,
+	#[error("The request does not carry a valid session")]
+	LoginUnauthorized,
+}
+
+impl SmapiError {
+	/// SOAP fault code, telling the player whether the request itself was at fault
+	pub fn fault_code(&self) -> &'static str {
+		match self {
+			SmapiError::Disabled => "s:Server",
+			SmapiError::LoginInvalid => "s:Client.LoginInvalid",
+			SmapiError::LoginUnauthorized => "s:Client.LoginUnauthorized",
+			_ => "s:Client",
+		}
+	}
+}
+
+/// Operation and parameters of a SOAP request sent by a Sonos player
+#[derive(Debug, PartialEq, Eq)]
+pub struct SmapiRequest {
+	pub operation: String,
+	pub params: HashMap<String, String>,
+	/// Session sent in the `credentials` header, as returned by `getSessionId`
+	pub session_id: Option<String>,
+}
+
+impl SmapiRequest {
+	fn param(&self, name: &str) -> Result<&str, SmapiError> {
+		self.params
+			.get(name)
+			.map(|v| v.as_str())
+			.ok_or_else(|| SmapiError::MissingParameter(name.to_owned()))
+	}
+
+	fn numeric_param(&self, name: &str, default: usize) -> Result<usize, SmapiError> {
+		match self.params.get(name) {
+			Some(value) => value
+				.parse()
+				.map_err(|_| SmapiError::InvalidParameter(name.to_owned())),
+			None => Ok(default),
+		}
+	}
+}
+
+/// Read the operation, its parameters and the session sent in the header from a SOAP envelope
+pub fn parse_request(xml: &str) -> Result<SmapiRequest, SmapiError> {
+	let mut reader = Reader::from_str(xml);
+	reader.config_mut().trim_text(true);
+
+	let mut stack: Vec<String> = Vec::new();
+	let mut operation = None;
+	let mut params = HashMap::new();
+	let mut session_id = None;
+
+	loop {
+		let event = reader
+			.read_event()
+			.map_err(|_| SmapiError::InvalidEnvelope)?;
+		match event {
+			Event::Start(e) => {
+				let name = local_name(e.local_name().as_ref())?;
+				if in_body(&stack, 2) {
+					set_operation(&mut operation, &name)?;
+				} else if in_body(&stack, 3) {
+					params.entry(name.clone()).or_default();
+				}
+				stack.push(name);
+			}
+			Event::Empty(e) => {
+				let name = local_name(e.local_name().as_ref())?;
+				if in_body(&stack, 2) {
+					set_operation(&mut operation, &name)?;
+				} else if in_body(&stack, 3) {
+					params.insert(name, String::new());
+				}
+			}
+			Event::Text(t) if in_body(&stack, 4) => {
+				let value = t.unescape().map_err(|_| SmapiError::InvalidEnvelope)?;
+				params.insert(stack[3].clone(), value.into_owned());
+			}
+			Event::Text(t) if in_credentials(&stack) => {
+				let value = t.unescape().map_err(|_| SmapiError::InvalidEnvelope)?;
+				session_id = Some(value.into_owned());
+			}
+			Event::End(_) => {
+				stack.pop();
+			}
+			Event::Eof => break,
+			_ => (),
+		}
+	}
+
+	match operation {
+		Some(operation) if stack.is_empty() => Ok(SmapiRequest {
+			operation,
+			params,
+			session_id,
+		}),
+		_ => Err(SmapiError::InvalidEnvelope),
+	}
+}
+
+fn local_name(name: &[u8]) -> Result<String, SmapiError> {
+	std::str::from_utf8(name)
+		.map(|n| n.to_owned())
+		.map_err(|_| SmapiError::InvalidEnvelope)
+}
+
+fn in_body(stack: &[String], depth: usize) -> bool {
+	stack.len() == depth
+		&& stack.first().is_some_and(|n| n == "Envelope")
+		&& stack.get(1).is_some_and(|n| n == "Body")
+}
+
+fn in_credentials(stack: &[String]) -> bool {
+	stack == ["Envelope", "Header", "credentials", "sessionId"]
+}
+
+fn set_operation(operation: &mut Option<String>, name: &str) -> Result<(), SmapiError> {
+	if operation.is_some() {
+		return Err(SmapiError::InvalidEnvelope);
+	}
+	*operation = Some(name.to_owned());
+	Ok(())
+}
+
+/// Wrap the body of a response in a SOAP envelope
+pub fn envelope(body: &str) -> String {
+	format!(
+		r#"<?xml version="1.0" encoding="utf-8"?><s:Envelope xmlns:s="{SOAP_ENVELOPE_NAMESPACE}"><s:Body>{body}</s:Body></s:Envelope>"#
+	)
+}
+
+/// SOAP fault describing why a request could not be answered
+pub fn fault(error: &SmapiError) -> String {
+	format!(
+		"<s:Fault><faultcode>{}</faultcode><faultstring>{}</faultstring></s:Fault>",
+		error.fault_code(),
+		escape(error.to_string().as_str())
+	)
+}
+
+#[derive(Clone)]
+pub struct SmapiService {
+	config_manager: config::Manager,
+	index_manager: index::Manager,
+	scanner: scanner::Scanner,
+	share: MusicShare,
+}
+
+impl SmapiService {
+	pub fn new(
+		config_manager: config::Manager,
+		index_manager: index::Manager,
+		scanner: scanner::Scanner,
+		share: MusicShare,
+	) -> Self {
+		Self {
+			config_manager,
+			index_manager,
+			scanner,
+			share,
+		}
+	}
+
+	/// Answer a SOAP request, returning the content of the response body
+	pub async fn handle(&self, xml: &str) -> Result<String, SmapiError> {
+		let request = parse_request(xml)?;
+		if request.operation == "getSessionId" {
+			return self
+				.get_session_id(request.param("username")?, request.param("password")?)
+				.await;
+		}
+		self.authenticate(&request).await?;
+		match request.operation.as_str() {
+			"getMetadata" => {
+				let id = request.param("id")?;
+				let index = request.numeric_param("index", 0)?;
+				let count = request.numeric_param("count", 100)?;
+				self.get_metadata(id, index, count).await
+			}
+			"getMediaMetadata" => self.get_media_metadata(request.param("id")?).await,
+			"getMediaURI" => self.get_media_uri(request.param("id")?).await,
+			"getLastUpdate" => Ok(self.get_last_update().await),
+			_ => Err(SmapiError::UnsupportedOperation(request.operation)),
+		}
+	}
+
+	/// Sessions are the authentication tokens Polaris issues to its users when they log in
+	async fn get_session_id(&self, username: &str, password: &str) -> Result<String, SmapiError> {
+		let auth::Token(token) = self
+			.config_manager
+			.login(username, password)
+			.await
+			.map_err(|_| SmapiError::LoginInvalid)?;
+		Ok(format!(
+			r#"<getSessionIdResponse xmlns="{SMAPI_NAMESPACE}"><getSessionIdResult>{}</getSessionIdResult></getSessionIdResponse>"#,
+			escape(token.as_str())
+		))
+	}
+
+	async fn authenticate(&self, request: &SmapiRequest) -> Result<(), SmapiError> {
+		let Some(session_id) = &request.session_id else {
+			return Err(SmapiError::LoginUnauthorized);
+		};
+		self.config_manager
+			.authenticate(&auth::Token(session_id.clone()), auth::Scope::PolarisAuth)
+			.await
+			.map(|_| ())
+			.map_err(|_| SmapiError::LoginUnauthorized)
+	}
+
+	async fn get_metadata(
+		&self,
+		id: &str,
+		index: usize,
+		count: usize,
+	) -> Result<String, SmapiError> {
+		let directory = if id == ROOT_ID {
+			PathBuf::new()
+		} else {
+			id.strip_prefix(DIRECTORY_ID_PREFIX)
+				.map(PathBuf::from)
+				.ok_or_else(|| SmapiError::ItemNotFound(id.to_owned()))?
+		};
+
+		let files = self
+			.index_manager
+			.browse(directory)
+			.await
+			.map_err(|_| SmapiError::ItemNotFound(id.to_owned()))?;
+		let total = files.len();
+		let page = files
+			.into_iter()
+			.skip(index)
+			.take(count)
+			.collect::<Vec<_>>();
+
+		let song_paths = page
+			.iter()
+			.filter_map(|f| match f {
+				index::File::Song(p) => Some(p.clone()),
+				index::File::Directory(_) => None,
+			})
+			.collect::<Vec<_>>();
+		let mut songs = self
+			.index_manager
+			.get_songs(song_paths)
+			.await
+			.into_iter()
+			.filter_map(|s| s.ok())
+			.map(|s| (s.virtual_path.clone(), s))
+			.collect::<HashMap<_, _>>();
+
+		let mut items = String::new();
+		for file in &page {
+			match file {
+				index::File::Directory(path) => items.push_str(&media_collection(path)),
+				index::File::Song(path) => {
+					if let Some(song) = songs.remove(path) {
+						items.push_str(&format!(
+							"<mediaMetadata>{}</mediaMetadata>",
+							media_metadata(&song)
+						));
+					}
+				}
+			}
+		}
+
+		Ok(format!(
+			r#"<getMetadataResponse xmlns="{SMAPI_NAMESPACE}"><getMetadataResult><index>{index}</index><count>{}</count><total>{total}</total>{items}</getMetadataResult></getMetadataResponse>"#,
+			page.len()
+		))
+	}
+
+	async fn get_media_metadata(&self, id: &str) -> Result<String, SmapiError> {
+		let song = self.find_song(id).await?;
+		Ok(format!(
+			r#"<getMediaMetadataResponse xmlns="{SMAPI_NAMESPACE}"><getMediaMetadataResult>{}</getMediaMetadataResult></getMediaMetadataResponse>"#,
+			media_metadata(&song)
+		))
+	}
+
+	async fn get_media_uri(&self, id: &str) -> Result<String, SmapiError> {
+		let song = self.find_song(id).await?;
+		let uri = sonos::path_to_share_uri(&song.virtual_path, &self.share)
+			.ok_or_else(|| SmapiError::ItemNotFound(id.to_owned()))?;
+		Ok(format!(
+			r#"<getMediaURIResponse xmlns="{SMAPI_NAMESPACE}"><getMediaURIResult>{}</getMediaURIResult></getMediaURIResponse>"#,
+			escape(uri.as_str())
+		))
+	}
+
+	/// The catalog version changes whenever a scan of the collection completes
+	async fn get_last_update(&self) -> String {
+		let catalog = self
+			.scanner
+			.get_status()
+			.await
+			.last_end_time
+			.and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+			.map(|d| d.as_secs())
+			.unwrap_or_default();
+		format!(
+			r#"<getLastUpdateResponse xmlns="{SMAPI_NAMESPACE}"><getLastUpdateResult><catalog>{catalog}</catalog><favorites>0</favorites><pollInterval>{POLL_INTERVAL_SECS}</pollInterval></getLastUpdateResult></getLastUpdateResponse>"#
+		)
+	}
+
+	async fn find_song(&self, id: &str) -> Result<index::Song, SmapiError> {
+		let path = id
+			.strip_prefix(TRACK_ID_PREFIX)
+			.map(PathBuf::from)
+			.ok_or_else(|| SmapiError::ItemNotFound(id.to_owned()))?;
+		self.index_manager
+			.get_songs(vec![path])
+			.await
+			.pop()
+			.and_then(|s| s.ok())
+			.ok_or_else(|| SmapiError::ItemNotFound(id.to_owned()))
+	}
+}
+
+fn path_to_id(prefix: &str, path: &Path) -> String {
+	let components = path
+		.components()
+		.map(|c| c.as_os_str().to_string_lossy())
+		.collect::<Vec<_>>();
+	format!("{prefix}{}", components.join("/"))
+}
+
+fn media_collection(path: &Path) -> String {
+	let title = path
+		.file_name()
+		.map(|n| n.to_string_lossy())
+		.unwrap_or_default();
+	format!(
+		"<mediaCollection><id>{}</id><itemType>container</itemType><title>{}</title><canPlay>false</canPlay></mediaCollection>",
+		escape(path_to_id(DIRECTORY_ID_PREFIX, path).as_str()),
+		escape(title.as_ref())
+	)
+}
+
+/// Content of a `mediaMetadata` element describing a song
+fn media_metadata(song: &index::Song) -> String {
+	let title = song.title.clone().unwrap_or_else(|| {
+		song.virtual_path
+			.file_stem()
+			.map(|s| s.to_string_lossy().into_owned())
+			.unwrap_or_default()
+	});
+
+	let mut track_metadata = String::new();
+	if let Some(artist) = song.artists.first() {
+		track_metadata.push_str(&format!("<artist>{}</artist>", escape(artist.as_str())));
+	}
+	if let Some(album) = &song.album {
+		track_metadata.push_str(&format!("<album>{}</album>", escape(album.as_str())));
+	}
+	if let Some(duration) = song.duration {
+		track_metadata.push_str(&format!("<duration>{duration}</duration>"));
+	}
+
+	format!(
+		"<id>{}</id><itemType>track</itemType><title>{}</title><mimeType>{}</mimeType><trackMetadata>{track_metadata}</trackMetadata>",
+		escape(path_to_id(TRACK_ID_PREFIX, &song.virtual_path).as_str()),
+		escape(title.as_str()),
+		mime_type(&song.virtual_path)
+	)
+}
+
+fn mime_type(path: &Path) -> &'static str {
+	match get_audio_format(path) {
+		Some(AudioFormat::AIFF) => "audio/aiff",
+		Some(AudioFormat::FLAC) => "audio/flac",
+		Some(AudioFormat::MP3) => "audio/mpeg",
+		Some(AudioFormat::MP4) | Some(AudioFormat::M4B) => "audio/mp4",
+		Some(AudioFormat::OGG) | Some(AudioFormat::OPUS) => "audio/ogg",
+		Some(AudioFormat::WAVE) => "audio/wav",
+		Some(AudioFormat::APE) | Some(AudioFormat::MPC) | None => "application/octet-stream",
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::{app::test, test_name};
+
+	const TEST_MOUNT_NAME: &str = "root";
+	const TEST_USERNAME: &str = "test_user";
+	const TEST_PASSWORD: &str = "password";
+
+	fn soap(body: &str) -> String {
+		soap_with_credentials("", body)
+	}
+
+	fn soap_with_session(session_id: &str, body: &str) -> String {
+		soap_with_credentials(&format!("<sessionId>{session_id}</sessionId>"), body)
+	}
+
+	fn soap_with_credentials(credentials: &str, body: &str) -> String {
+		format!(
+			r#"<?xml version="1.0" encoding="utf-8"?><s:Envelope xmlns:s="{SOAP_ENVELOPE_NAMESPACE}"><s:Header><credentials xmlns="{SMAPI_NAMESPACE}"><deviceId>00-0E-58-00-00-00:0</deviceId>{credentials}</credentials></s:Header><s:Body>{body}</s:Body></s:Envelope>"#
+		)
+	}
+
+	/// Service with a freshly scanned collection, and a session to send it requests with
+	async fn make_service(test_name: String) -> (SmapiService, String) {
+		let ctx = test::ContextBuilder::new(test_name)
+			.user(TEST_USERNAME, TEST_PASSWORD, false)
+			.mount(TEST_MOUNT_NAME, "test-data/small-collection")
+			.build()
+			.await;
+		ctx.scanner.run_scan().await.unwrap();
+		let auth::Token(session_id) = ctx
+			.config_manager
+			.login(TEST_USERNAME, TEST_PASSWORD)
+			.await
+			.unwrap();
+		let service = SmapiService::new(
+			ctx.config_manager,
+			ctx.index_manager,
+			ctx.scanner,
+			MusicShare {
+				server: "nas/music".to_owned(),
+				..Default::default()
+			},
+		);
+		(service, session_id)
+	}
+
+	#[test]
+	fn parses_request() {
+		let xml = soap(&format!(
+			r#"<ns:getMetadata xmlns:ns="{SMAPI_NAMESPACE}"><ns:id>dir:root/Khemmis &amp; co</ns:id><ns:index>0</ns:index><ns:count>10</ns:count><ns:recursive/></ns:getMetadata>"#
+		));
+		let request = parse_request(&xml).unwrap();
+		assert_eq!(request.operation, "getMetadata");
+		assert_eq!(request.params["id"], "dir:root/Khemmis & co");
+		assert_eq!(request.params["count"], "10");
+		assert_eq!(request.params["recursive"], "");
+		assert!(!request.params.contains_key("deviceId"));
+		assert_eq!(request.session_id, None);
+
+		let xml = soap_with_session("f00d", "<getLastUpdate/>");
+		assert_eq!(
+			parse_request(&xml).unwrap().session_id.as_deref(),
+			Some("f00d")
+		);
+	}
+
+	#[test]
+	fn rejects_invalid_envelopes() {
+		assert!(matches!(
+			parse_request("not xml"),
+			Err(SmapiError::InvalidEnvelope)
+		));
+		assert!(matches!(
+			parse_request(&soap("")),
+			Err(SmapiError::InvalidEnvelope)
+		));
+		assert!(matches!(
+			parse_request(&soap("<getLastUpdate>")),
+			Err(SmapiError::InvalidEnvelope)
+		));
+	}
+
+	#[test]
+	fn builds_faults() {
+		let xml = envelope(&fault(&SmapiError::UnsupportedOperation(
+			"<search>".to_owned(),
+		)));
+		assert!(xml.contains("<faultcode>s:Client</faultcode>"));
+		assert!(xml.contains("Unsupported SMAPI operation `&lt;search&gt;`"));
+	}
+
+	#[tokio::test]
+	async fn browses_collection() {
+		let (service, session_id) = make_service(test_name!()).await;
+
+		let root = service
+			.handle(&soap_with_session(
+				&session_id,
+				"<getMetadata><id>root</id><index>0</index><count>100</count></getMetadata>",
+			))
+			.await
+			.unwrap();
+		assert!(root.contains("<total>1</total>"));
+		assert!(root.contains("<id>dir:root</id>"));
+
+		let artists = service
+			.handle(&soap_with_session(
+				&session_id,
+				"<getMetadata><id>dir:root</id><index>1</index><count>1</count></getMetadata>",
+			))
+			.await
+			.unwrap();
+		assert!(artists.contains("<index>1</index><count>1</count><total>2</total>"));
+		assert!(artists.contains("<id>dir:root/Tobokegao</id>"));
+
+		let album = service
+			.handle(&soap_with_session(
+				&session_id,
+				"<getMetadata><id>dir:root/Khemmis/Hunted</id></getMetadata>",
+			))
+			.await
+			.unwrap();
+		assert!(album.contains("<total>5</total>"));
+		assert!(album.contains("<id>track:root/Khemmis/Hunted/02 - Candlelight.mp3</id>"));
+		assert!(album.contains("<title>Candlelight</title>"));
+		assert!(album.contains("<mimeType>audio/mpeg</mimeType>"));
+	}
+
+	#[tokio::test]
+	async fn serves_media() {
+		let (service, session_id) = make_service(test_name!()).await;
+		let id = "track:root/Khemmis/Hunted/02 - Candlelight.mp3";
+
+		let metadata = service
+			.handle(&soap_with_session(
+				&session_id,
+				&format!("<getMediaMetadata><id>{id}</id></getMediaMetadata>"),
+			))
+			.await
+			.unwrap();
+		assert!(metadata.contains("<artist>Khemmis</artist><album>Hunted</album>"));
+
+		let uri = service
+			.handle(&soap_with_session(
+				&session_id,
+				&format!("<getMediaURI><id>{id}</id></getMediaURI>"),
+			))
+			.await
+			.unwrap();
+		assert!(uri.contains(
+			"<getMediaURIResult>x-file-cifs://nas/music/root/Khemmis/Hunted/02 - Candlelight.mp3</getMediaURIResult>"
+		));
+
+		let missing = service
+			.handle(&soap_with_session(
+				&session_id,
+				"<getMediaURI><id>track:root/missing.mp3</id></getMediaURI>",
+			))
+			.await;
+		assert!(matches!(missing, Err(SmapiError::ItemNotFound(_))));
+	}
+
+	#[tokio::test]
+	async fn reports_last_update() {
+		let (service, session_id) = make_service(test_name!()).await;
+		let update = service
+			.handle(&soap_with_session(&session_id, "<getLastUpdate/>"))
+			.await
+			.unwrap();
+		assert!(update.contains("<pollInterval>300</pollInterval>"));
+		assert!(!update.contains("<catalog>0</catalog>"));
+	}
+
+	#[tokio::test]
+	async fn rejects_unsupported_operations() {
+		let (service, session_id) = make_service(test_name!()).await;
+		let result = service
+			.handle(&soap_with_session(
+				&session_id,
+				"<search><id>artists</id><term>k</term></search>",
+			))
+			.await;
+		assert!(matches!(
+			result,
+			Err(SmapiError::UnsupportedOperation(o)) if o == "search"
+		));
+	}
+
+	#[tokio::test]
+	async fn requires_session() {
+		let (service, session_id) = make_service(test_name!()).await;
+
+		let login = service
+			.handle(&soap(&format!(
+				"<getSessionId><username>{TEST_USERNAME}</username><password>{TEST_PASSWORD}</password></getSessionId>"
+			)))
+			.await
+			.unwrap();
+		assert!(login.contains("<getSessionIdResult>"));
+
+		let wrong_password = service
+			.handle(&soap(&format!(
+				"<getSessionId><username>{TEST_USERNAME}</username><password>nope</password></getSessionId>"
+			)))
+			.await;
+		assert!(matches!(wrong_password, Err(SmapiError::LoginInvalid)));
+
+		for xml in [
+			soap("<getLastUpdate/>"),
+			soap_with_session("not-a-session", "<getLastUpdate/>"),
+		] {
+			let result = service.handle(&xml).await;
+			assert!(matches!(result, Err(SmapiError::LoginUnauthorized)));
+		}
+		let xml = envelope(&fault(&SmapiError::LoginUnauthorized));
+		assert!(xml.contains("<faultcode>s:Client.LoginUnauthorized</faultcode>"));
+
+		let update = service
+			.handle(&soap_with_session(&session_id, "<getLastUpdate/>"))
+			.await;
+		assert!(update.is_ok());
+	}
+}
@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use tokio::sync::{Mutex, MutexGuard};
+
+use super::{SonosAlbumArt, SonosSpeaker, SonosState, SonosZone};
+
+/// Most recent speaker list and group topology fetched from node-sonos-http-api.
+/// Refreshes are serialized so concurrent cache misses only trigger one request to the bridge.
+#[derive(Clone, Default)]
+pub struct SpeakerCache {
+	speakers: Arc<tokio::sync::RwLock<Option<(Vec<SonosSpeaker>, Instant)>>>,
+	zones: Arc<tokio::sync::RwLock<Option<(Vec<SonosZone>, Instant)>>>,
+	/// Room names of every speaker seen so far, by UUID. They do not expire, as UUIDs never change.
+	room_names: Arc<RwLock<HashMap<String, String>>>,
+	refresh: Arc<Mutex<()>>,
+}
+
+impl SpeakerCache {
+	pub async fn get(&self, ttl: Duration) -> Option<Vec<SonosSpeaker>> {
+		let speakers = self.speakers.read().await;
+		let (speakers, updated) = speakers.as_ref()?;
+		(updated.elapsed() < ttl).then(|| speakers.clone())
+	}
+
+	pub async fn get_stale(&self) -> Option<Vec<SonosSpeaker>> {
+		let speakers = self.speakers.read().await;
+		speakers.as_ref().map(|(s, _)| s.clone())
+	}
+
+	pub async fn set(&self, speakers: Vec<SonosSpeaker>) {
+		self.remember_room_names(&speakers);
+		*self.speakers.write().await = Some((speakers, Instant::now()));
+	}
+
+	pub async fn get_zones(&self, ttl: Duration) -> Option<Vec<SonosZone>> {
+		let zones = self.zones.read().await;
+		let (zones, updated) = zones.as_ref()?;
+		(updated.elapsed() < ttl).then(|| zones.clone())
+	}
+
+	pub async fn set_zones(&self, zones: Vec<SonosZone>) {
+		for zone in &zones {
+			self.remember_room_names(std::slice::from_ref(&zone.coordinator));
+			self.remember_room_names(&zone.members);
+		}
+		*self.zones.write().await = Some((zones, Instant::now()));
+	}
+
+	pub async fn clear(&self) {
+		*self.speakers.write().await = None;
+		*self.zones.write().await = None;
+		self.room_names.write().unwrap().clear();
+	}
+
+	/// Forget the speakers and groups, after a speaker joined or left a group. Room names are kept.
+	pub async fn forget_groups(&self) {
+		*self.speakers.write().await = None;
+		*self.zones.write().await = None;
+	}
+
+	pub fn remember_room_names(&self, speakers: &[SonosSpeaker]) {
+		let mut room_names = self.room_names.write().unwrap();
+		for speaker in speakers {
+			room_names.insert(speaker.id.clone(), speaker.name.clone());
+		}
+	}
+
+	pub fn room_name(&self, speaker_id: &str) -> Option<String> {
+		self.room_names.read().unwrap().get(speaker_id).cloned()
+	}
+
+	/// UUID of a speaker seen so far, identified by its UUID or by its room name ignoring case
+	pub fn speaker_uuid(&self, name_or_uuid: &str) -> Option<String> {
+		let room_names = self.room_names.read().unwrap();
+		if room_names.contains_key(name_or_uuid) {
+			return Some(name_or_uuid.to_owned());
+		}
+		let name = name_or_uuid.to_lowercase();
+		room_names
+			.iter()
+			.find(|(_, room_name)| room_name.to_lowercase() == name)
+			.map(|(uuid, _)| uuid.clone())
+	}
+
+	/// Must be held while fetching speakers from the bridge
+	pub async fn lock_refresh(&self) -> MutexGuard<'_, ()> {
+		self.refresh.lock().await
+	}
+}
+
+/// Speaker list and playback states reported by node-sonos-http-api webhook events, keyed by room name.
+#[derive(Clone, Default)]
+pub struct SonosStateCache {
+	data: Arc<RwLock<Data>>,
+}
+
+#[derive(Default)]
+struct Data {
+	speakers: Option<(Instant, Vec<SonosSpeaker>)>,
+	states: HashMap<String, (Instant, SonosState)>,
+}
+
+impl SonosStateCache {
+	pub fn get_speakers(&self, ttl: Duration) -> Option<Vec<SonosSpeaker>> {
+		let data = self.data.read().unwrap();
+		let (updated, speakers) = data.speakers.as_ref()?;
+		(updated.elapsed() < ttl).then(|| speakers.clone())
+	}
+
+	pub fn set_speakers(&self, speakers: Vec<SonosSpeaker>) {
+		let mut data = self.data.write().unwrap();
+		data.states
+			.retain(|room_name, _| speakers.iter().any(|s| &s.name == room_name));
+		data.speakers = Some((Instant::now(), speakers));
+	}
+
+	/// Identifier of the speaker in `room_name`
+	pub fn speaker_id(&self, room_name: &str) -> Option<String> {
+		let data = self.data.read().unwrap();
+		let (_, speakers) = data.speakers.as_ref()?;
+		speakers
+			.iter()
+			.find(|s| s.name == room_name)
+			.map(|s| s.id.clone())
+	}
+
+	pub fn is_known_speaker(&self, room_name: &str) -> bool {
+		let data = self.data.read().unwrap();
+		data.speakers
+			.as_ref()
+			.is_some_and(|(_, speakers)| speakers.iter().any(|s| s.name == room_name))
+	}
+
+	pub fn get_state(&self, room_name: &str, ttl: Duration) -> Option<SonosState> {
+		let data = self.data.read().unwrap();
+		let (updated, state) = data.states.get(room_name)?;
+		(updated.elapsed() < ttl).then(|| state.clone())
+	}
+
+	pub fn clear(&self) {
+		*self.data.write().unwrap() = Data::default();
+	}
+
+	pub fn set_state(&self, room_name: &str, state: SonosState) {
+		let mut data = self.data.write().unwrap();
+		data.states
+			.insert(room_name.to_owned(), (Instant::now(), state));
+	}
+
+	pub fn update_speaker<F: FnOnce(&mut SonosSpeaker)>(&self, room_name: &str, op: F) {
+		let mut data = self.data.write().unwrap();
+		let Some((_, speakers)) = data.speakers.as_mut() else {
+			return;
+		};
+		if let Some(speaker) = speakers.iter_mut().find(|s| s.name == room_name) {
+			op(speaker);
+		}
+	}
+}
+
+/// Album art recently downloaded from Sonos speakers, keyed by URL.
+/// Clients showing every speaker at once would otherwise fetch the same images over and over.
+#[derive(Clone, Default)]
+pub struct AlbumArtCache {
+	entries: Arc<RwLock<HashMap<String, (Instant, SonosAlbumArt)>>>,
+}
+
+impl AlbumArtCache {
+	pub fn get(&self, url: &str, ttl: Duration) -> Option<SonosAlbumArt> {
+		let entries = self.entries.read().unwrap();
+		let (fetched, art) = entries.get(url)?;
+		(fetched.elapsed() < ttl).then(|| art.clone())
+	}
+
+	/// Expired entries are dropped when new art is added
+	pub fn set(&self, url: &str, art: SonosAlbumArt, ttl: Duration) {
+		let mut entries = self.entries.write().unwrap();
+		entries.retain(|_, (fetched, _)| fetched.elapsed() < ttl);
+		entries.insert(url.to_owned(), (Instant::now(), art));
+	}
+
+	pub fn clear(&self) {
+		self.entries.write().unwrap().clear();
+	}
+}
+
+/// Speakers most recently found on the local network with SSDP, along with the groups they belong to.
+/// Searching takes seconds, so the result is shared by every listing and refreshed in the background.
+#[derive(Clone, Default)]
+pub struct DiscoveryCache {
+	speakers: Arc<RwLock<Option<(Instant, Vec<SonosSpeaker>)>>>,
+}
+
+impl DiscoveryCache {
+	pub fn get(&self, ttl: Duration) -> Option<Vec<SonosSpeaker>> {
+		let speakers = self.speakers.read().unwrap();
+		let (found, speakers) = speakers.as_ref()?;
+		(found.elapsed() < ttl).then(|| speakers.clone())
+	}
+
+	pub fn set(&self, speakers: Vec<SonosSpeaker>) {
+		*self.speakers.write().unwrap() = Some((Instant::now(), speakers));
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn speaker(name: &str) -> SonosSpeaker {
+		SonosSpeaker {
+			id: name.to_owned(),
+			name: name.to_owned(),
+			available: true,
+			..Default::default()
+		}
+	}
+
+	#[tokio::test]
+	async fn speaker_cache_keeps_stale_entries() {
+		let cache = SpeakerCache::default();
+		assert!(cache.get_stale().await.is_none());
+		cache.set(vec![speaker("Kitchen")]).await;
+		assert!(cache.get(Duration::from_secs(60)).await.is_some());
+		assert!(cache.get(Duration::ZERO).await.is_none());
+		assert_eq!(cache.get_stale().await.unwrap()[0].name, "Kitchen");
+	}
+
+	#[tokio::test]
+	async fn speaker_cache_remembers_room_names() {
+		let cache = SpeakerCache::default();
+		let kitchen = SonosSpeaker {
+			id: "RINCON_000E58A0000002400".to_owned(),
+			..speaker("Kitchen")
+		};
+		cache.set(vec![kitchen]).await;
+		cache.set(Vec::new()).await;
+		assert_eq!(
+			cache.room_name("RINCON_000E58A0000002400").as_deref(),
+			Some("Kitchen")
+		);
+		assert_eq!(cache.room_name("Kitchen"), None);
+		cache.clear().await;
+		assert_eq!(cache.room_name("RINCON_000E58A0000002400"), None);
+	}
+
+	#[test]
+	fn serves_fresh_states_only() {
+		let cache = SonosStateCache::default();
+		cache.set_speakers(vec![speaker("Kitchen")]);
+		cache.set_state("Kitchen", SonosState::default());
+		assert!(cache
+			.get_state("Kitchen", Duration::from_secs(60))
+			.is_some());
+		assert!(cache.get_state("Kitchen", Duration::ZERO).is_none());
+	}
+
+	#[test]
+	fn forgets_states_of_removed_speakers() {
+		let cache = SonosStateCache::default();
+		cache.set_speakers(vec![speaker("Kitchen")]);
+		cache.set_state("Kitchen", SonosState::default());
+		cache.set_speakers(vec![speaker("Bedroom")]);
+		assert!(!cache.is_known_speaker("Kitchen"));
+		assert!(cache
+			.get_state("Kitchen", Duration::from_secs(60))
+			.is_none());
+	}
+
+	#[test]
+	fn can_update_speakers() {
+		let cache = SonosStateCache::default();
+		cache.set_speakers(vec![speaker("Kitchen")]);
+		cache.update_speaker("Kitchen", |s| s.volume = Some(55));
+		let speakers = cache.get_speakers(Duration::from_secs(60)).unwrap();
+		assert_eq!(speakers[0].volume, Some(55));
+	}
+}
@@ -0,0 +1,133 @@
+use quick_xml::escape::escape;
+
+/// Details shown on the now-playing display of a speaker, sent along with the track URI
+/// so that Sonos does not have to look them up itself
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrackMetadata {
+	pub title: String,
+	pub artist: String,
+	pub album: Option<String>,
+	pub album_art_url: Option<String>,
+}
+
+/// DIDL-Lite document describing `uri`, as expected by `setavtransporturi` and `addtoqueue`
+pub fn build_didl_lite(uri: &str, meta: &TrackMetadata) -> String {
+	let scheme = uri.split_once(':').map(|(s, _)| s).unwrap_or_default();
+	let protocol = match scheme {
+		"http" | "https" => "http-get",
+		scheme => scheme,
+	};
+
+	let mut item = format!(
+		"<res protocolInfo=\"{}:*:*:*\">{}</res><dc:title>{}</dc:title><dc:creator>{}</dc:creator><upnp:class>object.item.audioItem.musicTrack</upnp:class>",
+		escape(protocol),
+		escape(uri),
+		escape(meta.title.as_str()),
+		escape(meta.artist.as_str())
+	);
+	if let Some(album) = &meta.album {
+		item.push_str(&format!(
+			"<upnp:album>{}</upnp:album>",
+			escape(album.as_str())
+		));
+	}
+	if let Some(album_art_url) = &meta.album_art_url {
+		item.push_str(&format!(
+			"<upnp:albumArtURI>{}</upnp:albumArtURI>",
+			escape(album_art_url.as_str())
+		));
+	}
+
+	format!(
+		"<DIDL-Lite xmlns:dc=\"http://purl.org/dc/elements/1.1/\" xmlns:upnp=\"urn:schemas-upnp-org:metadata-1-0/upnp/\" xmlns:r=\"urn:schemas-rinconnetworks-com:metadata-1-0/\" xmlns=\"urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/\"><item id=\"-1\" parentID=\"-1\" restricted=\"true\">{item}</item></DIDL-Lite>"
+	)
+}
+
+#[cfg(test)]
+mod test {
+	use quick_xml::events::Event;
+	use quick_xml::Reader;
+
+	use super::*;
+
+	/// Names and text of every element of `xml`, failing if it is not well-formed
+	fn parse(xml: &str) -> Vec<(String, String)> {
+		let mut reader = Reader::from_str(xml);
+		reader.config_mut().check_end_names = true;
+		let mut stack = Vec::new();
+		let mut elements = Vec::new();
+		loop {
+			match reader.read_event().unwrap() {
+				Event::Start(e) => {
+					let name = String::from_utf8(e.name().as_ref().to_vec()).unwrap();
+					stack.push(name);
+				}
+				Event::Text(t) => {
+					let name = stack.last().cloned().unwrap();
+					elements.push((name, t.unescape().unwrap().into_owned()));
+				}
+				Event::End(_) => {
+					stack.pop().unwrap();
+				}
+				Event::Eof => break,
+				_ => (),
+			}
+		}
+		assert!(stack.is_empty(), "unclosed elements: {stack:?}");
+		elements
+	}
+
+	fn text<'a>(elements: &'a [(String, String)], name: &str) -> Option<&'a str> {
+		elements
+			.iter()
+			.find(|(n, _)| n == name)
+			.map(|(_, t)| t.as_str())
+	}
+
+	#[test]
+	fn describes_track() {
+		let meta = TrackMetadata {
+			title: "Yesterday".to_owned(),
+			artist: "The Beatles".to_owned(),
+			album: Some("Help!".to_owned()),
+			album_art_url: Some("http://192.168.0.5:5050/api/thumbnail/help.jpg".to_owned()),
+		};
+		let didl = build_didl_lite("x-file-cifs://nas/mp3/Help/13 - Yesterday.mp3", &meta);
+		assert!(didl.starts_with("<DIDL-Lite "));
+		assert!(didl.contains("protocolInfo=\"x-file-cifs:*:*:*\""));
+
+		let elements = parse(&didl);
+		assert_eq!(
+			text(&elements, "res"),
+			Some("x-file-cifs://nas/mp3/Help/13 - Yesterday.mp3")
+		);
+		assert_eq!(text(&elements, "dc:title"), Some("Yesterday"));
+		assert_eq!(text(&elements, "dc:creator"), Some("The Beatles"));
+		assert_eq!(text(&elements, "upnp:album"), Some("Help!"));
+		assert_eq!(
+			text(&elements, "upnp:albumArtURI"),
+			Some("http://192.168.0.5:5050/api/thumbnail/help.jpg")
+		);
+	}
+
+	#[test]
+	fn escapes_special_characters() {
+		let meta = TrackMetadata {
+			title: "<Intro> & \"Outro\"".to_owned(),
+			artist: "Simon & Garfunkel".to_owned(),
+			..Default::default()
+		};
+		let didl = build_didl_lite("http://nas/music/a.mp3?user=me&token=1", &meta);
+		assert!(didl.contains("protocolInfo=\"http-get:*:*:*\""));
+
+		let elements = parse(&didl);
+		assert_eq!(
+			text(&elements, "res"),
+			Some("http://nas/music/a.mp3?user=me&token=1")
+		);
+		assert_eq!(text(&elements, "dc:title"), Some("<Intro> & \"Outro\""));
+		assert_eq!(text(&elements, "dc:creator"), Some("Simon & Garfunkel"));
+		assert_eq!(text(&elements, "upnp:album"), None);
+		assert_eq!(text(&elements, "upnp:albumArtURI"), None);
+	}
+}
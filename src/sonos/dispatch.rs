@@ -0,0 +1,124 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::{mpsc, oneshot};
+
+/// Number of requests which can wait for their turn before callers have to wait to join the queue
+const QUEUE_CAPACITY: usize = 256;
+
+/// Caller waiting for its turn, and how long the bridge should rest after its request
+struct Turn {
+	spacing: Duration,
+	grant: oneshot::Sender<DispatchPermit>,
+}
+
+/// Sends requests which change the state of speakers to node-sonos-http-api one at a time, in the
+/// order they were made. The bridge gets unreliable when it handles several of them at once.
+#[derive(Clone, Default)]
+pub struct BridgeDispatcher {
+	queue: Arc<Mutex<Option<mpsc::Sender<Turn>>>>,
+}
+
+/// Right to send a request to the bridge. The next request waits until this is dropped.
+pub struct DispatchPermit {
+	_done: oneshot::Sender<()>,
+}
+
+impl BridgeDispatcher {
+	/// Wait until every request queued before this one was sent, and at least `spacing` has passed since the last one
+	pub async fn acquire(&self, spacing: Duration) -> DispatchPermit {
+		loop {
+			let (grant, granted) = oneshot::channel();
+			let turn = Turn { spacing, grant };
+			let queue = self.queue();
+			if queue.send(turn).await.is_err() {
+				// The worker stopped along with the runtime it was spawned on
+				self.queue.lock().unwrap().take();
+				continue;
+			}
+			if let Ok(permit) = granted.await {
+				return permit;
+			}
+		}
+	}
+
+	fn queue(&self) -> mpsc::Sender<Turn> {
+		let mut queue = self.queue.lock().unwrap();
+		match queue.as_ref() {
+			Some(sender) if !sender.is_closed() => sender.clone(),
+			_ => {
+				let (sender, receiver) = mpsc::channel(QUEUE_CAPACITY);
+				tokio::spawn(dispatch(receiver));
+				queue.insert(sender).clone()
+			}
+		}
+	}
+}
+
+async fn dispatch(mut turns: mpsc::Receiver<Turn>) {
+	while let Some(Turn { spacing, grant }) = turns.recv().await {
+		let (done, finished) = oneshot::channel();
+		if grant.send(DispatchPermit { _done: done }).is_err() {
+			// The caller gave up waiting
+			continue;
+		}
+		// Resolves with an error once the permit is dropped
+		let _ = finished.await;
+		if !spacing.is_zero() {
+			tokio::time::sleep(spacing).await;
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	use super::*;
+
+	#[tokio::test]
+	async fn grants_one_permit_at_a_time() {
+		let dispatcher = BridgeDispatcher::default();
+		let in_flight = Arc::new(AtomicUsize::new(0));
+		let order = Arc::new(Mutex::new(Vec::new()));
+
+		let mut tasks = tokio::task::JoinSet::new();
+		for i in 0..20 {
+			let dispatcher = dispatcher.clone();
+			let in_flight = in_flight.clone();
+			let order = order.clone();
+			tasks.spawn(async move {
+				let _permit = dispatcher.acquire(Duration::ZERO).await;
+				assert_eq!(in_flight.fetch_add(1, Ordering::SeqCst), 0);
+				order.lock().unwrap().push(i);
+				tokio::time::sleep(Duration::from_millis(1)).await;
+				in_flight.fetch_sub(1, Ordering::SeqCst);
+			});
+		}
+		tasks.join_all().await;
+		assert_eq!(*order.lock().unwrap(), (0..20).collect::<Vec<_>>());
+	}
+
+	#[tokio::test]
+	async fn spaces_out_requests() {
+		let dispatcher = BridgeDispatcher::default();
+		drop(dispatcher.acquire(Duration::from_millis(50)).await);
+		let started = tokio::time::Instant::now();
+		drop(dispatcher.acquire(Duration::ZERO).await);
+		assert!(started.elapsed() >= Duration::from_millis(40));
+	}
+
+	#[tokio::test]
+	async fn skips_abandoned_requests() {
+		let dispatcher = BridgeDispatcher::default();
+		let permit = dispatcher.acquire(Duration::ZERO).await;
+		let abandoned = tokio::time::timeout(
+			Duration::from_millis(10),
+			dispatcher.acquire(Duration::ZERO),
+		)
+		.await;
+		assert!(abandoned.is_err());
+		drop(permit);
+		drop(dispatcher.acquire(Duration::ZERO).await);
+	}
+}
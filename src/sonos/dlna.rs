@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+
+use quick_xml::escape::escape;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use tracing::{debug, warn};
+
+use super::renderer::{Renderer, RendererFuture};
+use super::{
+	build_didl_lite, parse_hms_to_seconds, SonosError, SonosResponse, SonosSpeaker, SonosState,
+	SpeakerBackend, TrackMetadata,
+};
+use crate::app::config::DlnaDevice;
+
+/// UPnP service renderers are controlled through
+pub(super) const AV_TRANSPORT: &str = "urn:schemas-upnp-org:service:AVTransport:1";
+
+/// Plays tracks on UPnP/DLNA renderers by sending SOAP actions to their AVTransport service.
+/// Renderers fetch tracks themselves, so they are given Polaris audio URLs rather than music share paths.
+#[derive(Clone)]
+pub struct DlnaRenderer {
+	client: reqwest::Client,
+	devices: Vec<DlnaDevice>,
+}
+
+impl DlnaRenderer {
+	pub fn new(client: reqwest::Client, devices: Vec<DlnaDevice>) -> Self {
+		Self { client, devices }
+	}
+
+	pub fn has_renderer(&self, name: &str) -> bool {
+		self.devices.iter().any(|d| d.name == name)
+	}
+
+	fn device(&self, name: &str) -> Result<&DlnaDevice, SonosError> {
+		self.devices
+			.iter()
+			.find(|d| d.name == name)
+			.ok_or_else(|| SonosError::RendererNotFound(name.to_owned()))
+	}
+
+	pub async fn play_uri(&self, name: &str, uri: &str) -> Result<SonosResponse, SonosError> {
+		let device = self.device(name)?;
+		match reqwest::Url::parse(uri) {
+			Ok(url) if matches!(url.scheme(), "http" | "https") => (),
+			_ => return Err(SonosError::InvalidUri(uri.to_owned())),
+		}
+		let metadata = build_didl_lite(uri, &TrackMetadata::default());
+		let arguments = format!(
+			"<CurrentURI>{}</CurrentURI><CurrentURIMetaData>{}</CurrentURIMetaData>",
+			escape(uri),
+			escape(metadata.as_str())
+		);
+		self.send(device, "SetAVTransportURI", &arguments).await?;
+		self.send(device, "Play", "<Speed>1</Speed>").await?;
+		Ok(SonosResponse {
+			success: true,
+			message: "Started playing on DLNA renderer".to_owned(),
+			..Default::default()
+		})
+	}
+
+	pub async fn pause(&self, name: &str) -> Result<SonosResponse, SonosError> {
+		let device = self.device(name)?;
+		self.send(device, "Pause", "").await?;
+		Ok(SonosResponse {
+			success: true,
+			message: "Playback paused".to_owned(),
+			..Default::default()
+		})
+	}
+
+	pub async fn get_state(&self, name: &str) -> Result<SonosState, SonosError> {
+		let device = self.device(name)?;
+		let transport = self.send(device, "GetTransportInfo", "").await?;
+		let position = self.send(device, "GetPositionInfo", "").await?;
+		parse_transport_state(name, &transport, &position)
+	}
+
+	/// Send an AVTransport action to `device`, returning the body of its response
+	async fn send(
+		&self,
+		device: &DlnaDevice,
+		action: &str,
+		arguments: &str,
+	) -> Result<String, SonosError> {
+		send_soap_action(
+			&self.client,
+			&device.name,
+			&device.control_url,
+			AV_TRANSPORT,
+			action,
+			&format!("<InstanceID>0</InstanceID>{arguments}"),
+		)
+		.await
+	}
+}
+
+/// Send a SOAP `action` of `service` to the control endpoint of the UPnP device `name`, returning the body of its response
+pub(super) async fn send_soap_action(
+	client: &reqwest::Client,
+	name: &str,
+	control_url: &str,
+	service: &str,
+	action: &str,
+	arguments: &str,
+) -> Result<String, SonosError> {
+	debug!("UPnP device `{name}`: {action}");
+	let body = format!(
+		"<?xml version=\"1.0\" encoding=\"utf-8\"?><s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\"><s:Body><u:{action} xmlns:u=\"{service}\">{arguments}</u:{action}></s:Body></s:Envelope>"
+	);
+	let response = client
+		.post(control_url)
+		.header(reqwest::header::CONTENT_TYPE, "text/xml; charset=\"utf-8\"")
+		.header("SOAPACTION", format!("\"{service}#{action}\""))
+		.body(body)
+		.send()
+		.await
+		.map_err(|e| SonosError::RendererConnectionFailed(name.to_owned(), e))?;
+
+	let status = response.status();
+	let body = response
+		.text()
+		.await
+		.map_err(|_| SonosError::InvalidRendererResponse(name.to_owned()))?;
+	if !status.is_success() {
+		warn!("UPnP device `{name}` returned HTTP status {status} for {action}");
+		return Err(SonosError::RendererHttpError {
+			name: name.to_owned(),
+			status: status.as_u16(),
+			body,
+		});
+	}
+	Ok(body)
+}
+
+/// Playback state of the UPnP device `name`, from its answers to `GetTransportInfo` and `GetPositionInfo`
+pub(super) fn parse_transport_state(
+	name: &str,
+	transport: &str,
+	position: &str,
+) -> Result<SonosState, SonosError> {
+	let invalid = || SonosError::InvalidRendererResponse(name.to_owned());
+	let transport = parse_fields(transport).ok_or_else(invalid)?;
+	let position = parse_fields(position).ok_or_else(invalid)?;
+	let metadata = position
+		.get("TrackMetaData")
+		.and_then(|m| parse_fields(m))
+		.unwrap_or_default();
+
+	let playback_state = transport.get("CurrentTransportState").cloned();
+	let position_secs = position.get("RelTime").and_then(|t| parse_time(t));
+	Ok(SonosState {
+		is_playing: playback_state.as_deref() == Some("PLAYING"),
+		playback_state,
+		artist: metadata.get("creator").cloned(),
+		title: metadata.get("title").cloned(),
+		position: position_secs,
+		position_ms: position_secs.map(|s| u64::from(s) * 1000),
+		duration: position.get("TrackDuration").and_then(|t| parse_time(t)),
+		album_art_uri: metadata.get("albumArtURI").cloned(),
+		track_uri: position.get("TrackURI").cloned(),
+		..Default::default()
+	})
+}
+
+impl Renderer for DlnaRenderer {
+	fn list(&self) -> RendererFuture<'_, Result<Vec<SonosSpeaker>, SonosError>> {
+		let speakers = self
+			.devices
+			.iter()
+			.map(|d| SonosSpeaker {
+				id: d.name.clone(),
+				name: d.name.clone(),
+				available: true,
+				backend: SpeakerBackend::Dlna,
+				..Default::default()
+			})
+			.collect();
+		Box::pin(std::future::ready(Ok(speakers)))
+	}
+
+	fn play<'a>(
+		&'a self,
+		speaker_id: &'a str,
+		uri: &'a str,
+	) -> RendererFuture<'a, Result<SonosResponse, SonosError>> {
+		Box::pin(self.play_uri(speaker_id, uri))
+	}
+
+	fn pause<'a>(
+		&'a self,
+		speaker_id: &'a str,
+	) -> RendererFuture<'a, Result<SonosResponse, SonosError>> {
+		Box::pin(DlnaRenderer::pause(self, speaker_id))
+	}
+
+	fn state<'a>(
+		&'a self,
+		speaker_id: &'a str,
+	) -> RendererFuture<'a, Result<SonosState, SonosError>> {
+		Box::pin(self.get_state(speaker_id))
+	}
+}
+
+/// Text of the elements of an XML document which only contain text, by local name.
+/// The first element of each name wins.
+pub(super) fn parse_fields(xml: &str) -> Option<HashMap<String, String>> {
+	let mut reader = Reader::from_str(xml);
+	let mut fields = HashMap::new();
+	let mut current = None::<String>;
+	loop {
+		match reader.read_event().ok()? {
+			Event::Start(e) => {
+				current = Some(String::from_utf8_lossy(e.local_name().as_ref()).into_owned());
+			}
+			Event::Text(t) => {
+				if let Some(name) = current.take() {
+					let text = t.unescape().ok()?.trim().to_owned();
+					if !text.is_empty() {
+						fields.entry(name).or_insert(text);
+					}
+				}
+			}
+			Event::End(_) => current = None,
+			Event::Eof => break,
+			_ => (),
+		}
+	}
+	Some(fields)
+}
+
+/// Seconds in a `H:MM:SS` time, which renderers may report with a fraction of a second
+fn parse_time(time: &str) -> Option<u32> {
+	let whole = time.split('.').next()?;
+	parse_hms_to_seconds(whole).and_then(|s| u32::try_from(s).ok())
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::sonos::mock::{MockBridge, DLNA_CONTROL_PATH};
+
+	fn renderer(bridge: &MockBridge) -> DlnaRenderer {
+		DlnaRenderer::new(
+			reqwest::Client::new(),
+			vec![DlnaDevice {
+				name: "Receiver".to_owned(),
+				control_url: format!("{}{DLNA_CONTROL_PATH}", bridge.url),
+			}],
+		)
+	}
+
+	#[test]
+	fn parses_times() {
+		assert_eq!(parse_time("0:01:05"), Some(65));
+		assert_eq!(parse_time("1:00:00.250"), Some(3600));
+		assert_eq!(parse_time("NOT_IMPLEMENTED"), None);
+	}
+
+	#[tokio::test]
+	async fn plays_uri() {
+		let bridge = MockBridge::start().await;
+		let renderer = renderer(&bridge);
+
+		let uri = "http://192.168.0.5:5050/api/v8/audio/Beatles%2FHelp%2F13%20-%20Yesterday.mp3";
+		let response = renderer.play_uri("Receiver", uri).await.unwrap();
+		assert!(response.success);
+
+		let requests = bridge.requests();
+		let actions = requests
+			.iter()
+			.map(|r| r.headers["soapaction"].to_str().unwrap())
+			.collect::<Vec<_>>();
+		assert_eq!(
+			actions,
+			vec![
+				"\"urn:schemas-upnp-org:service:AVTransport:1#SetAVTransportURI\"",
+				"\"urn:schemas-upnp-org:service:AVTransport:1#Play\""
+			]
+		);
+		assert_eq!(requests[0].path, DLNA_CONTROL_PATH);
+		assert!(requests[0]
+			.body
+			.contains(&format!("<CurrentURI>{uri}</CurrentURI>")));
+		assert!(requests[0].body.contains("&lt;DIDL-Lite"));
+	}
+
+	#[tokio::test]
+	async fn rejects_non_http_uris() {
+		let bridge = MockBridge::start().await;
+		let renderer = renderer(&bridge);
+		assert!(matches!(
+			renderer
+				.play_uri("Receiver", "x-file-cifs://nas/mp3/song.mp3")
+				.await,
+			Err(SonosError::InvalidUri(_))
+		));
+		assert!(matches!(
+			renderer.pause("Kitchen").await,
+			Err(SonosError::RendererNotFound(n)) if n == "Kitchen"
+		));
+		assert!(bridge.requests().is_empty());
+	}
+
+	#[tokio::test]
+	async fn reads_state() {
+		let bridge = MockBridge::start().await;
+		let state = renderer(&bridge).get_state("Receiver").await.unwrap();
+		assert_eq!(
+			state,
+			SonosState {
+				is_playing: true,
+				playback_state: Some("PLAYING".to_owned()),
+				artist: Some("The Beatles".to_owned()),
+				title: Some("Yesterday".to_owned()),
+				position: Some(65),
+				position_ms: Some(65000),
+				duration: Some(125),
+				track_uri: Some("http://192.168.0.5:5050/api/v8/audio/song.mp3".to_owned()),
+				..Default::default()
+			}
+		);
+	}
+
+	#[tokio::test]
+	async fn reports_failed_actions() {
+		let bridge = MockBridge::start().await;
+		bridge.fail(DLNA_CONTROL_PATH);
+		assert!(matches!(
+			renderer(&bridge).pause("Receiver").await,
+			Err(SonosError::RendererHttpError { status: 500, .. })
+		));
+	}
+}
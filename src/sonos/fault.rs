@@ -0,0 +1,126 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+/// Advice for users about the UPnP and AVTransport error codes speakers commonly report
+const ERROR_CODES: &[(u16, &str)] = &[
+	(
+		402,
+		"The speaker rejected the request as invalid — check that the track URI is well-formed",
+	),
+	(
+		701,
+		"The speaker cannot do this right now — it may be playing from a TV or line-in source",
+	),
+	(
+		714,
+		"The speaker could not access the file on the share — check the CIFS path and permissions",
+	),
+	(
+		716,
+		"The speaker could not find the file on the share — check that the music directory is shared",
+	),
+	(
+		800,
+		"The speaker was denied access — check the share credentials",
+	),
+];
+
+/// Error code of the UPnP fault reported by a speaker in a node-sonos-http-api error body.
+/// The bridge sends either JSON such as `{"status":"error","error":"UPnPError errorCode 714"}`,
+/// or the plain text or SOAP fault it received from the speaker.
+pub fn parse_error_code(body: &str) -> Option<u16> {
+	match serde_json::from_str::<serde_json::Value>(body) {
+		Ok(json) => json_error_code(&json),
+		Err(_) => text_error_code(body),
+	}
+}
+
+/// Message explaining a UPnP error code, if it is a common one
+pub fn describe_error_code(code: u16) -> Option<&'static str> {
+	ERROR_CODES
+		.iter()
+		.find(|(c, _)| *c == code)
+		.map(|(_, message)| *message)
+}
+
+fn json_error_code(json: &serde_json::Value) -> Option<u16> {
+	match json {
+		serde_json::Value::Object(fields) => {
+			if let Some(code) = fields.get("errorCode").and_then(code_value) {
+				return Some(code);
+			}
+			fields.values().find_map(json_error_code)
+		}
+		serde_json::Value::String(text) => text_error_code(text),
+		_ => None,
+	}
+}
+
+fn code_value(value: &serde_json::Value) -> Option<u16> {
+	match value {
+		serde_json::Value::Number(n) => n.as_u64().and_then(|n| u16::try_from(n).ok()),
+		serde_json::Value::String(s) => s.trim().parse().ok(),
+		_ => None,
+	}
+}
+
+fn text_error_code(text: &str) -> Option<u16> {
+	static ERROR_CODE: LazyLock<Regex> =
+		LazyLock::new(|| Regex::new(r#"(?i)error[_ ]?code[\s"':=>]{0,4}(\d{3,4})\b"#).unwrap());
+	ERROR_CODE.captures(text).and_then(|c| c[1].parse().ok())
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn parses_json_bodies() {
+		assert_eq!(
+			parse_error_code(r#"{"status":"error","error":"UPnPError errorCode 714"}"#),
+			Some(714)
+		);
+		assert_eq!(
+			parse_error_code(
+				r#"{"status":"error","error":{"errorCode":"402","message":"Invalid Args"}}"#
+			),
+			Some(402)
+		);
+		assert_eq!(
+			parse_error_code(r#"{"status":"error","errorCode":701}"#),
+			Some(701)
+		);
+		assert_eq!(
+			parse_error_code(
+				r#"{"status":"error","error":"<s:Fault><detail><UPnPError xmlns=\"urn:schemas-upnp-org:control-1-0\"><errorCode>800</errorCode></UPnPError></detail></s:Fault>"}"#
+			),
+			Some(800)
+		);
+		assert_eq!(
+			parse_error_code(
+				r#"{"status":"error","error":"Got status 500 when invoking /MediaRenderer/AVTransport/Control"}"#
+			),
+			None
+		);
+	}
+
+	#[test]
+	fn parses_text_bodies() {
+		assert_eq!(parse_error_code("UPnPError errorCode 714"), Some(714));
+		assert_eq!(
+			parse_error_code("<s:Envelope><s:Body><s:Fault><detail><UPnPError><errorCode>402</errorCode></UPnPError></detail></s:Fault></s:Body></s:Envelope>"),
+			Some(402)
+		);
+		assert_eq!(parse_error_code("error code: 701"), Some(701));
+		assert_eq!(parse_error_code("Speaker not found"), None);
+		assert_eq!(parse_error_code(""), None);
+	}
+
+	#[test]
+	fn describes_common_codes() {
+		assert!(describe_error_code(714).unwrap().contains("CIFS path"));
+		assert!(describe_error_code(402).is_some());
+		assert_eq!(describe_error_code(999), None);
+	}
+}
@@ -0,0 +1,1064 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, Mutex, Notify};
+use tracing::{debug, error, warn};
+use utoipa::ToSchema;
+
+use crate::app::config;
+use crate::app::library::MusicLibrary;
+
+use super::{
+	parse_state, parse_zones, track_url_to_share_uri, AlbumArtCache, BridgeDispatcher,
+	CrossfadeChains, DiscoveryCache, DlnaRenderer, Interruption, MusicShare, NativeSonos,
+	NativeSpeakerCache, SessionStore, SessionUpdate, SonosError, SonosMetrics, SonosResponse,
+	SonosService, SonosSession, SonosState, SonosStateCache, SonosStatus, SonosWebhookPayload,
+	SpeakerCache, TrackMetadata, UPnPDiscovery, VolumeCoalescer,
+};
+
+/// A change in the playback state of a Sonos speaker
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct SonosEvent {
+	/// The speaker whose state changed
+	#[schema(examples("Living Room", "Kitchen"))]
+	pub speaker_id: String,
+	/// The new playback state of the speaker
+	pub state: SonosState,
+	/// What changed since the previous event about this speaker. Everything is listed in the first event about a speaker.
+	#[serde(default)]
+	#[schema(examples(json!(["track", "position"])))]
+	pub changes: Vec<SonosChange>,
+	/// Volume of the speaker (0-100), when known
+	#[serde(default)]
+	#[schema(examples(35))]
+	pub volume: Option<u8>,
+	/// Whether the speaker is muted, when known
+	#[serde(default)]
+	#[schema(examples(false, true))]
+	pub muted: Option<bool>,
+	/// When the change was detected, in milliseconds since the Unix epoch
+	#[schema(examples(1718130000000u64))]
+	pub timestamp: u64,
+}
+
+/// Part of the state of a Sonos speaker reported as changed by a `SonosEvent`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SonosChange {
+	/// The speaker started playing, paused or stopped
+	Playback,
+	/// Another track is playing
+	Track,
+	/// The position within the track moved, or its duration is known
+	Position,
+	/// The volume or mute state changed
+	Volume,
+	/// Crossfade, shuffle, repeat or the sleep timer changed
+	Settings,
+}
+
+impl SonosChange {
+	/// Parts of the playback state which differ between `previous` and `state`.
+	/// Without a previous state, every part of the playback state is listed.
+	pub fn between(previous: Option<&SonosState>, state: &SonosState) -> Vec<SonosChange> {
+		let Some(previous) = previous else {
+			return vec![
+				SonosChange::Playback,
+				SonosChange::Track,
+				SonosChange::Position,
+				SonosChange::Settings,
+			];
+		};
+		let mut changes = Vec::new();
+		if (previous.is_playing, &previous.playback_state)
+			!= (state.is_playing, &state.playback_state)
+		{
+			changes.push(SonosChange::Playback);
+		}
+		let track = |s: &SonosState| {
+			(
+				s.track_uri.clone(),
+				s.title.clone(),
+				s.artist.clone(),
+				s.album_art_uri.clone(),
+			)
+		};
+		if track(previous) != track(state) {
+			changes.push(SonosChange::Track);
+		}
+		let position = |s: &SonosState| (s.position, s.position_ms, s.duration);
+		if position(previous) != position(state) {
+			changes.push(SonosChange::Position);
+		}
+		let settings = |s: &SonosState| {
+			(
+				s.crossfade_enabled,
+				s.shuffle,
+				s.repeat,
+				s.sleep_timer_remaining,
+			)
+		};
+		if settings(previous) != settings(state) {
+			changes.push(SonosChange::Settings);
+		}
+		changes
+	}
+}
+
+impl SonosEvent {
+	pub fn new(speaker_id: String, state: SonosState) -> Self {
+		let timestamp = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_millis() as u64)
+			.unwrap_or_default();
+		Self {
+			speaker_id,
+			state,
+			changes: Vec::new(),
+			volume: None,
+			muted: None,
+			timestamp,
+		}
+	}
+
+	pub fn with_changes(mut self, changes: Vec<SonosChange>) -> Self {
+		self.changes = changes;
+		self
+	}
+
+	pub fn with_volume(mut self, volume: Option<u8>, muted: Option<bool>) -> Self {
+		self.volume = volume;
+		self.muted = muted;
+		self
+	}
+}
+
+#[derive(Clone)]
+pub struct Manager {
+	config_manager: config::Manager,
+	library: Arc<dyn MusicLibrary>,
+	events: broadcast::Sender<SonosEvent>,
+	new_subscriber: Arc<Notify>,
+	bridge: Arc<Mutex<Bridge>>,
+	crossfade_applied: Arc<std::sync::Mutex<HashSet<String>>>,
+	crossfade_chains: CrossfadeChains,
+	speaker_cache: SpeakerCache,
+	state_cache: SonosStateCache,
+	album_art_cache: AlbumArtCache,
+	volume_coalescer: VolumeCoalescer,
+	dispatcher: BridgeDispatcher,
+	metrics: SonosMetrics,
+	sessions: SessionStore,
+	interruptions: Arc<std::sync::Mutex<HashMap<String, Interruption>>>,
+	native_speakers: NativeSpeakerCache,
+	discovered_speakers: DiscoveryCache,
+}
+
+/// Connection to the node-sonos-http-api bridge used by the services built from the current settings
+struct Bridge {
+	api_url: Option<String>,
+	client_settings: config::SonosClientSettings,
+	client: reqwest::Client,
+	/// Number of HTTP clients built since the manager was created
+	clients_built: usize,
+	/// Service shared by every request until the settings it was built from change
+	service: Option<(config::SonosConfig, Arc<SonosService>)>,
+}
+
+impl Manager {
+	pub fn new(
+		config_manager: config::Manager,
+		library: Arc<dyn MusicLibrary>,
+		client: reqwest::Client,
+	) -> Self {
+		let (events, _) = broadcast::channel(64);
+		Self {
+			config_manager,
+			library,
+			events,
+			new_subscriber: Arc::default(),
+			bridge: Arc::new(Mutex::new(Bridge {
+				api_url: None,
+				client_settings: config::SonosClientSettings::default(),
+				client,
+				clients_built: 0,
+				service: None,
+			})),
+			crossfade_applied: Arc::default(),
+			crossfade_chains: CrossfadeChains::default(),
+			speaker_cache: SpeakerCache::default(),
+			state_cache: SonosStateCache::default(),
+			album_art_cache: AlbumArtCache::default(),
+			volume_coalescer: VolumeCoalescer::default(),
+			dispatcher: BridgeDispatcher::default(),
+			metrics: SonosMetrics::default(),
+			sessions: SessionStore::default(),
+			interruptions: Arc::default(),
+			native_speakers: NativeSpeakerCache::default(),
+			discovered_speakers: DiscoveryCache::default(),
+		}
+	}
+
+	/// Service for the current Sonos settings. The same service is handed out to every caller,
+	/// and a new one is only built once the settings change.
+	pub async fn service(&self) -> Arc<SonosService> {
+		let config = self.config_manager.get_sonos_config().await;
+		let mut bridge = self.bridge.lock().await;
+		if let Some((built_from, service)) = &bridge.service {
+			if *built_from == config {
+				return service.clone();
+			}
+		}
+
+		let client = self.update_bridge(&mut bridge, &config).await;
+		let service = Arc::new(self.build_service(client, &config));
+		bridge.service = Some((config, service.clone()));
+		service
+	}
+
+	fn build_service(&self, client: reqwest::Client, config: &config::SonosConfig) -> SonosService {
+		let discovery = config
+			.enable_upnp_fallback
+			.then(|| self.upnp_discovery(client.clone(), config));
+		let mut service = SonosService::with_shared_client(client, config)
+			.with_speaker_cache(self.speaker_cache.clone(), config.get_speaker_cache_ttl())
+			.with_availability_check(config.is_availability_check_enabled())
+			.with_album_art_cache(self.album_art_cache.clone(), config.get_art_cache_ttl())
+			.with_volume_coalescing(
+				self.volume_coalescer.clone(),
+				config.get_volume_coalescing_window(),
+			)
+			.with_dispatcher(self.dispatcher.clone(), config.get_request_spacing())
+			.with_metrics(self.metrics.clone())
+			.with_operation_metrics(config.enable_metrics)
+			.with_library(self.library.clone())
+			.with_crossfade_chains(self.crossfade_chains.clone());
+		if let Some(enabled) = config.crossfade_enabled {
+			service = service.with_default_crossfade(enabled, self.crossfade_applied.clone());
+		}
+		if let Some(discovery) = discovery {
+			service = service.with_upnp_fallback(discovery);
+		}
+		if config.webhook_enabled {
+			service =
+				service.with_state_cache(self.state_cache.clone(), config.get_webhook_cache_ttl());
+		}
+		if let Some(dir) = &config.snapshot_dir {
+			service = service.with_snapshots(dir.clone(), config.get_snapshot_max_age());
+		}
+		service
+	}
+
+	/// Search for speakers on the local network, sharing the speakers found with every other search
+	fn upnp_discovery(
+		&self,
+		client: reqwest::Client,
+		config: &config::SonosConfig,
+	) -> UPnPDiscovery {
+		UPnPDiscovery::new(client).with_cache(
+			self.discovered_speakers.clone(),
+			config.get_discovery_cache_ttl(),
+		)
+	}
+
+	/// Renderer for the DLNA devices of the current settings, sharing the HTTP client of the bridge
+	pub async fn dlna_renderer(&self) -> DlnaRenderer {
+		let config = self.config_manager.get_sonos_config().await;
+		let client = self
+			.update_bridge(&mut *self.bridge.lock().await, &config)
+			.await;
+		DlnaRenderer::new(client, config.renderers)
+	}
+
+	/// Control of the speakers on the local network without node-sonos-http-api, when the current settings ask for it.
+	/// Speakers found on the network are shared by every caller, and searched for again once they are older than
+	/// the speaker cache TTL.
+	pub async fn native_sonos(&self) -> Option<NativeSonos> {
+		let config = self.config_manager.get_sonos_config().await;
+		if !config.native_control {
+			return None;
+		}
+		let client = self
+			.update_bridge(&mut *self.bridge.lock().await, &config)
+			.await;
+		Some(NativeSonos::new(
+			client.clone(),
+			UPnPDiscovery::new(client),
+			self.native_speakers.clone(),
+			config.get_speaker_cache_ttl(),
+		))
+	}
+
+	/// Build a service sending requests to `api_url` instead of the configured bridge.
+	/// It shares no cached data with the services built by `service`, as they describe other speakers,
+	/// and the credentials of the configured bridge are not sent along.
+	pub async fn service_with_api_url(&self, api_url: &str) -> Arc<SonosService> {
+		let config = self.config_manager.get_sonos_config().await;
+		let client = self
+			.update_bridge(&mut *self.bridge.lock().await, &config)
+			.await;
+		let service = SonosService::new(api_url.to_owned())
+			.with_client(client)
+			.with_retry_policy(config.get_retry_policy())
+			.with_metrics(self.metrics.clone())
+			.with_operation_metrics(config.enable_metrics)
+			.with_library(self.library.clone());
+		Arc::new(service)
+	}
+
+	/// Forget data about the previous bridge when the API URL changes, and only rebuild
+	/// the HTTP client when its timeout or TLS settings change.
+	async fn update_bridge(
+		&self,
+		bridge: &mut Bridge,
+		config: &config::SonosConfig,
+	) -> reqwest::Client {
+		let api_url = config.get_api_url();
+		if bridge.api_url != api_url {
+			self.speaker_cache.clear().await;
+			self.state_cache.clear();
+			self.album_art_cache.clear();
+			bridge.api_url = api_url;
+		}
+
+		let client_settings = config.get_client_settings();
+		if bridge.client_settings != client_settings {
+			if let Some(client) = build_client(&client_settings) {
+				bridge.client = client;
+				bridge.clients_built += 1;
+				debug!("Built Sonos HTTP client #{}", bridge.clients_built);
+			}
+			bridge.client_settings = client_settings;
+		}
+
+		bridge.client.clone()
+	}
+
+	/// Checks whether node-sonos-http-api can be reached, without using cached data
+	pub async fn status(&self) -> SonosStatus {
+		let config = self.config_manager.get_sonos_config().await;
+		let service = self.service().await;
+		let mut status = SonosStatus {
+			configured: config.is_configured(),
+			..Default::default()
+		};
+		match service.refresh_speakers().await {
+			Ok(speakers) => {
+				status.bridge_reachable = true;
+				status.speaker_count = speakers.len();
+				status.bridge_version = service.get_bridge_version().await;
+			}
+			Err(e) => status.last_error = Some(e.to_string()),
+		}
+		status
+	}
+
+	/// Records speaker changes reported by node-sonos-http-api, and broadcasts playback state changes to subscribers.
+	/// Events about unknown speakers are ignored so the bridge keeps the webhook registered.
+	pub async fn handle_webhook(&self, payload: SonosWebhookPayload) -> Result<(), SonosError> {
+		let config = self.config_manager.get_sonos_config().await;
+		if !config.webhook_enabled {
+			return Err(SonosError::WebhookDisabled);
+		}
+
+		if payload.kind == "topology-change" {
+			self.state_cache.set_speakers(parse_zones(&payload.data));
+			return Ok(());
+		}
+
+		let Some(room_name) = payload.data.get("roomName").and_then(|r| r.as_str()) else {
+			debug!(
+				"Ignoring Sonos `{}` event without a room name",
+				payload.kind
+			);
+			return Ok(());
+		};
+
+		if !self.state_cache.is_known_speaker(room_name) {
+			warn!(
+				"Ignoring Sonos `{}` event for unknown speaker `{room_name}`",
+				payload.kind
+			);
+			return Ok(());
+		}
+
+		match payload.kind.as_str() {
+			"transport-state" => {
+				if let Some(state) = payload.data.get("state") {
+					let state = parse_state(state);
+					let previous = self
+						.state_cache
+						.get_state(room_name, config.get_webhook_cache_ttl());
+					self.state_cache.set_state(room_name, state.clone());
+					let service = self.service().await;
+					self.advance_session(&service, room_name, &state).await;
+					let mut changes = SonosChange::between(previous.as_ref(), &state);
+					if previous.is_none() {
+						changes.push(SonosChange::Volume);
+					}
+					if !changes.is_empty() {
+						let (volume, muted) = self.webhook_volume(room_name);
+						let event = SonosEvent::new(self.webhook_speaker_id(room_name), state)
+							.with_changes(changes)
+							.with_volume(volume, muted);
+						let _ = self.events.send(event);
+					}
+				}
+			}
+			"volume-change" => {
+				let volume = payload.data.get("newVolume").and_then(|v| v.as_u64());
+				self.state_cache
+					.update_speaker(room_name, |s| s.volume = volume.map(|v| v as u8));
+				self.broadcast_webhook_volume(room_name);
+			}
+			"mute-change" => {
+				let muted = payload.data.get("newMute").and_then(|m| m.as_bool());
+				self.state_cache
+					.update_speaker(room_name, |s| s.muted = muted);
+				self.broadcast_webhook_volume(room_name);
+			}
+			kind => debug!("Ignoring Sonos `{kind}` event"),
+		}
+
+		Ok(())
+	}
+
+	fn webhook_speaker_id(&self, room_name: &str) -> String {
+		self.state_cache
+			.speaker_id(room_name)
+			.unwrap_or_else(|| room_name.to_owned())
+	}
+
+	/// Volume and mute state of a speaker, as last reported by node-sonos-http-api webhook events
+	fn webhook_volume(&self, room_name: &str) -> (Option<u8>, Option<bool>) {
+		self.state_cache
+			.get_speakers(Duration::MAX)
+			.unwrap_or_default()
+			.into_iter()
+			.find(|s| s.name == room_name)
+			.map(|s| (s.volume, s.muted))
+			.unwrap_or_default()
+	}
+
+	fn broadcast_webhook_volume(&self, room_name: &str) {
+		let (volume, muted) = self.webhook_volume(room_name);
+		let state = self
+			.state_cache
+			.get_state(room_name, Duration::MAX)
+			.unwrap_or_default();
+		let event = SonosEvent::new(self.webhook_speaker_id(room_name), state)
+			.with_changes(vec![SonosChange::Volume])
+			.with_volume(volume, muted);
+		let _ = self.events.send(event);
+	}
+
+	/// Play `uri` on a speaker, followed by `next_uris` one at a time.
+	/// Any session previously running on the speaker is replaced.
+	/// Sessions are kept by room name, which webhook events name speakers by.
+	pub async fn play_session(
+		&self,
+		speaker_id: &str,
+		uri: &str,
+		next_uris: Vec<String>,
+	) -> Result<SonosResponse, SonosError> {
+		let service = self.service().await;
+		let room_name = service.room_name(speaker_id).await;
+		self.sessions.remove(&room_name);
+		let response = service.play_uri(speaker_id, uri).await?;
+		if !next_uris.is_empty() {
+			let mut tracks = vec![uri.to_owned()];
+			tracks.extend(next_uris);
+			self.sessions.start(&room_name, tracks);
+			// The poller may be waiting for subscribers
+			self.new_subscriber.notify_one();
+		}
+		Ok(response)
+	}
+
+	/// Play a track on a speaker, then go back to what the speaker was playing when the track ends
+	/// or `end_interrupt` is called. Interrupting a speaker again keeps the content it first played.
+	pub async fn interrupt(
+		&self,
+		speaker_id: &str,
+		track_url: &str,
+		share: &MusicShare,
+		metadata: Option<&TrackMetadata>,
+	) -> Result<SonosResponse, SonosError> {
+		let uri = track_url_to_share_uri(track_url, share)?;
+		let service = self.service().await;
+		let room_name = service.room_name(speaker_id).await;
+		let interrupted = self.interruptions.lock().unwrap().get(&room_name).cloned();
+		let previous = match interrupted {
+			Some(previous) => Some(previous),
+			None => match service.get_state(speaker_id).await {
+				Ok(state) => Interruption::from_state(&state),
+				Err(e) => {
+					warn!("Could not read state of Sonos speaker `{speaker_id}` before interrupting it: {e}");
+					None
+				}
+			},
+		};
+
+		self.sessions.remove(&room_name);
+		let response = service
+			.play_track(speaker_id, track_url, share, metadata)
+			.await?;
+		match previous {
+			Some(previous) if response.success => {
+				self.interruptions
+					.lock()
+					.unwrap()
+					.insert(room_name.clone(), previous);
+				// The interrupting track is followed like a session, to notice when it ends
+				self.sessions.start(&room_name, vec![uri]);
+				self.new_subscriber.notify_one();
+			}
+			_ => {
+				self.interruptions.lock().unwrap().remove(&room_name);
+			}
+		}
+		Ok(response)
+	}
+
+	/// Stop the track interrupting a speaker, and go back to what it was playing before
+	pub async fn end_interrupt(&self, speaker_id: &str) -> Result<SonosResponse, SonosError> {
+		let service = self.service().await;
+		let room_name = service.room_name(speaker_id).await;
+		let previous = self
+			.interruptions
+			.lock()
+			.unwrap()
+			.remove(&room_name)
+			.ok_or_else(|| SonosError::InterruptionNotFound(speaker_id.to_owned()))?;
+		self.sessions.remove(&room_name);
+		restore(&service, speaker_id, &previous).await
+	}
+
+	pub fn get_session(&self, speaker_id: &str) -> Result<SonosSession, SonosError> {
+		self.sessions
+			.get(speaker_id)
+			.ok_or_else(|| SonosError::SessionNotFound(speaker_id.to_owned()))
+	}
+
+	/// Play the next track of the session of `speaker_id` if the current one just ended
+	async fn advance_session(&self, service: &SonosService, speaker_id: &str, state: &SonosState) {
+		match self.sessions.update(speaker_id, state, Instant::now()) {
+			SessionUpdate::Unchanged => {}
+			SessionUpdate::Advance(uri) => {
+				debug!("Playing next track of the session on Sonos speaker `{speaker_id}`");
+				if let Err(e) = service.play_uri(speaker_id, &uri).await {
+					warn!("Could not play next track of the session on Sonos speaker `{speaker_id}`: {e}");
+					self.sessions.remove(speaker_id);
+				}
+			}
+			SessionUpdate::Finished => {
+				debug!("Session ended on Sonos speaker `{speaker_id}`");
+				let previous = self.interruptions.lock().unwrap().remove(speaker_id);
+				if let Some(previous) = previous {
+					if let Err(e) = restore(service, speaker_id, &previous).await {
+						warn!("Could not go back to what Sonos speaker `{speaker_id}` was playing before it was interrupted: {e}");
+					}
+				}
+			}
+			SessionUpdate::Cancelled => {
+				debug!(
+					"Sonos speaker `{speaker_id}` is playing something else, ending its session"
+				);
+				self.interruptions.lock().unwrap().remove(speaker_id);
+			}
+		}
+	}
+
+	pub fn clear_album_art_cache(&self) {
+		self.album_art_cache.clear();
+	}
+
+	/// Requests sent to node-sonos-http-api by the services built from this manager
+	pub fn metrics(&self) -> &SonosMetrics {
+		&self.metrics
+	}
+
+	pub fn subscribe(&self) -> broadcast::Receiver<SonosEvent> {
+		let receiver = self.events.subscribe();
+		self.new_subscriber.notify_one();
+		receiver
+	}
+
+	/// Polls every known speaker, broadcasts state changes to subscribers and advances playback sessions.
+	/// Polling is suspended while nobody is subscribed and no session is running, Sonos is not configured,
+	/// or state changes are pushed through the webhook instead.
+	pub fn begin_polling(&self) {
+		tokio::spawn({
+			let manager = self.clone();
+			async move {
+				let mut last_events = HashMap::<String, SonosEvent>::new();
+				loop {
+					if manager.events.receiver_count() == 0 && manager.sessions.is_empty() {
+						last_events.clear();
+						manager.new_subscriber.notified().await;
+						continue;
+					}
+
+					let config = manager.config_manager.get_sonos_config().await;
+					if config.is_enabled() && !config.webhook_enabled {
+						manager.poll(&mut last_events).await;
+					}
+					tokio::time::sleep(config.get_poll_interval()).await;
+				}
+			}
+		});
+	}
+
+	/// Searches the local network for speakers while the UPnP fallback is enabled, so that speaker listings
+	/// are answered from the discovery cache instead of waiting for a search.
+	/// Speakers are searched for again halfway through the discovery cache TTL.
+	pub fn begin_discovery(&self) {
+		tokio::spawn({
+			let manager = self.clone();
+			async move {
+				loop {
+					let config = manager.config_manager.get_sonos_config().await;
+					if config.is_enabled() && config.enable_upnp_fallback {
+						let client = manager
+							.update_bridge(&mut *manager.bridge.lock().await, &config)
+							.await;
+						manager.upnp_discovery(client, &config).refresh().await;
+					}
+					let interval = config.get_discovery_cache_ttl() / 2;
+					tokio::time::sleep(interval.max(Duration::from_secs(1))).await;
+				}
+			}
+		});
+	}
+
+	async fn poll(&self, last_events: &mut HashMap<String, SonosEvent>) {
+		let service = self.service().await;
+
+		let speakers = match service.get_speakers().await {
+			Ok(s) => s,
+			Err(e) => {
+				warn!("Could not list Sonos speakers while polling: {e}");
+				return;
+			}
+		};
+
+		last_events.retain(|id, _| speakers.iter().any(|s| &s.id == id));
+
+		for speaker in speakers {
+			let state = match service.get_state(&speaker.id).await {
+				Ok(s) => s,
+				Err(e) => {
+					debug!(
+						"Could not read state of Sonos speaker `{}`: {e}",
+						speaker.id
+					);
+					continue;
+				}
+			};
+
+			self.advance_session(&service, &speaker.name, &state).await;
+
+			let previous = last_events.get(&speaker.id);
+			let mut changes = SonosChange::between(previous.map(|e| &e.state), &state);
+			if previous.is_none_or(|e| (e.volume, e.muted) != (speaker.volume, speaker.muted)) {
+				changes.push(SonosChange::Volume);
+			}
+			if changes.is_empty() {
+				continue;
+			}
+
+			let event = SonosEvent::new(speaker.id.clone(), state)
+				.with_changes(changes)
+				.with_volume(speaker.volume, speaker.muted);
+			last_events.insert(speaker.id, event.clone());
+			let _ = self.events.send(event);
+		}
+	}
+}
+
+/// Play what a speaker was playing before it was interrupted, from where it was.
+/// Content which cannot be sought, such as radio streams, is only tuned in again.
+async fn restore(
+	service: &SonosService,
+	speaker_id: &str,
+	previous: &Interruption,
+) -> Result<SonosResponse, SonosError> {
+	debug!("Restoring interrupted content on Sonos speaker `{speaker_id}`");
+	let response = match previous.position {
+		Some(position) => {
+			service
+				.resume_uri(speaker_id, &previous.uri, position)
+				.await?
+		}
+		None => service.play_uri(speaker_id, &previous.uri).await?,
+	};
+	if !previous.was_playing {
+		service.pause(speaker_id).await?;
+	}
+	Ok(response)
+}
+
+fn build_client(settings: &config::SonosClientSettings) -> Option<reqwest::Client> {
+	let mut builder = reqwest::Client::builder();
+
+	if let Some(timeout) = settings.timeout {
+		builder = builder.timeout(timeout);
+	}
+
+	if let Some(path) = &settings.ca_cert_path {
+		let certificate = std::fs::read(path)
+			.map_err(|e| e.to_string())
+			.and_then(|pem| reqwest::Certificate::from_pem(&pem).map_err(|e| e.to_string()));
+		match certificate {
+			Ok(certificate) => builder = builder.add_root_certificate(certificate),
+			Err(e) => {
+				error!("Could not load Sonos CA certificate `{path:#?}`: {e}");
+				return None;
+			}
+		}
+	}
+
+	if settings.accept_invalid_certs {
+		warn!("TLS certificate validation is DISABLED for node-sonos-http-api. Only use this on a trusted network.");
+		builder = builder.danger_accept_invalid_certs(true);
+	}
+
+	match builder.build() {
+		Ok(client) => Some(client),
+		Err(e) => {
+			error!("Could not create Sonos HTTP client: {e}");
+			None
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::app::library::MockMusicLibrary;
+	use crate::app::test;
+	use crate::sonos::mock;
+	use crate::test_name;
+
+	#[tokio::test]
+	async fn service_follows_settings_changes() {
+		let ctx = test::ContextBuilder::new(test_name!()).build().await;
+		let manager = Manager::new(
+			ctx.config_manager.clone(),
+			Arc::new(MockMusicLibrary::default()),
+			reqwest::Client::new(),
+		);
+
+		let sonos = config::SonosConfig {
+			api_url: Some("http://bridge-a:5005".to_owned()),
+			..Default::default()
+		};
+		ctx.config_manager.set_sonos_config(sonos).await.unwrap();
+		assert_eq!(manager.service().await.base_url, "http://bridge-a:5005");
+
+		manager.speaker_cache.set(vec![]).await;
+		assert!(manager.speaker_cache.get_stale().await.is_some());
+
+		let sonos = config::SonosConfig {
+			api_url: Some("http://bridge-b:5005".to_owned()),
+			..Default::default()
+		};
+		ctx.config_manager.set_sonos_config(sonos).await.unwrap();
+		assert_eq!(manager.service().await.base_url, "http://bridge-b:5005");
+		assert!(manager.speaker_cache.get_stale().await.is_none());
+	}
+
+	#[tokio::test]
+	async fn shares_one_service_between_requests() {
+		let ctx = test::ContextBuilder::new(test_name!()).build().await;
+		let manager = Manager::new(
+			ctx.config_manager.clone(),
+			Arc::new(MockMusicLibrary::default()),
+			reqwest::Client::new(),
+		);
+		let sonos = config::SonosConfig {
+			api_url: Some("http://bridge-a:5005".to_owned()),
+			request_timeout_ms: Some(5000),
+			..Default::default()
+		};
+		ctx.config_manager.set_sonos_config(sonos).await.unwrap();
+
+		let mut requests = tokio::task::JoinSet::new();
+		for _ in 0..20 {
+			let manager = manager.clone();
+			requests.spawn(async move { manager.service().await });
+		}
+		let services = requests.join_all().await;
+		assert!(services.iter().all(|s| Arc::ptr_eq(s, &services[0])));
+		assert_eq!(manager.bridge.lock().await.clients_built, 1);
+
+		let sonos = config::SonosConfig {
+			api_url: Some("http://bridge-a:5005".to_owned()),
+			request_timeout_ms: Some(5000),
+			enable_metrics: true,
+			..Default::default()
+		};
+		ctx.config_manager.set_sonos_config(sonos).await.unwrap();
+		let service = manager.service().await;
+		assert!(!Arc::ptr_eq(&service, &services[0]));
+		assert!(service.operation_metrics);
+		assert_eq!(manager.bridge.lock().await.clients_built, 1);
+	}
+
+	#[tokio::test]
+	async fn services_list_speakers_found_in_the_background() {
+		let ctx = test::ContextBuilder::new(test_name!()).build().await;
+		let bridge = mock::MockBridge::start().await;
+		bridge.set_zones(serde_json::json!([]));
+		let manager = Manager::new(
+			ctx.config_manager.clone(),
+			Arc::new(MockMusicLibrary::default()),
+			reqwest::Client::new(),
+		);
+		let sonos = config::SonosConfig {
+			api_url: Some(bridge.url.clone()),
+			enable_upnp_fallback: true,
+			..Default::default()
+		};
+		ctx.config_manager.set_sonos_config(sonos).await.unwrap();
+
+		let kitchen = crate::sonos::SonosSpeaker {
+			id: "RINCON_B8E937000002401400".to_owned(),
+			name: "Kitchen".to_owned(),
+			group_id: Some("RINCON_949F3E000001401400".to_owned()),
+			..Default::default()
+		};
+		manager.discovered_speakers.set(vec![kitchen]);
+
+		let speakers = manager.service().await.get_speakers().await.unwrap();
+		assert_eq!(speakers.len(), 1);
+		assert_eq!(
+			speakers[0].group_id.as_deref(),
+			Some("RINCON_949F3E000001401400")
+		);
+	}
+
+	#[tokio::test]
+	async fn webhook_broadcasts_state_changes() {
+		let ctx = test::ContextBuilder::new(test_name!()).build().await;
+		let manager = Manager::new(
+			ctx.config_manager.clone(),
+			Arc::new(MockMusicLibrary::default()),
+			reqwest::Client::new(),
+		);
+		let sonos = config::SonosConfig {
+			webhook_enabled: true,
+			..Default::default()
+		};
+		ctx.config_manager.set_sonos_config(sonos).await.unwrap();
+
+		let webhook = |kind: &str, data: serde_json::Value| SonosWebhookPayload {
+			kind: kind.to_owned(),
+			data,
+		};
+		let transport_state = serde_json::json!({ "roomName": "Kitchen", "state": mock::state() });
+
+		let mut events = manager.subscribe();
+		manager
+			.handle_webhook(webhook("topology-change", mock::zones()))
+			.await
+			.unwrap();
+		manager
+			.handle_webhook(webhook("transport-state", transport_state.clone()))
+			.await
+			.unwrap();
+
+		let event = events.try_recv().unwrap();
+		assert_eq!(event.speaker_id, "RINCON_000E58A0000002400");
+		assert_eq!(event.state.title.as_deref(), Some("Yesterday"));
+		assert!(event.changes.contains(&SonosChange::Track));
+		assert_eq!(event.volume, Some(35));
+		assert!(event.timestamp > 0);
+
+		// Unchanged states are not broadcast again
+		manager
+			.handle_webhook(webhook("transport-state", transport_state))
+			.await
+			.unwrap();
+		assert!(events.try_recv().is_err());
+
+		let volume_change = serde_json::json!({ "roomName": "Kitchen", "newVolume": 40 });
+		manager
+			.handle_webhook(webhook("volume-change", volume_change))
+			.await
+			.unwrap();
+		let event = events.try_recv().unwrap();
+		assert_eq!(event.changes, vec![SonosChange::Volume]);
+		assert_eq!(event.volume, Some(40));
+		assert_eq!(event.state.title.as_deref(), Some("Yesterday"));
+	}
+
+	#[test]
+	fn lists_state_changes() {
+		let paused = SonosState {
+			is_playing: false,
+			playback_state: Some("PAUSED_PLAYBACK".to_owned()),
+			title: Some("Yesterday".to_owned()),
+			position: Some(65),
+			..Default::default()
+		};
+		assert!(SonosChange::between(Some(&paused), &paused).is_empty());
+		assert_eq!(SonosChange::between(None, &paused).len(), 4);
+
+		let playing = SonosState {
+			is_playing: true,
+			playback_state: Some("PLAYING".to_owned()),
+			position: Some(70),
+			..paused.clone()
+		};
+		assert_eq!(
+			SonosChange::between(Some(&paused), &playing),
+			vec![SonosChange::Playback, SonosChange::Position]
+		);
+
+		let next_track = SonosState {
+			title: Some("Help!".to_owned()),
+			position: Some(0),
+			..playing.clone()
+		};
+		assert_eq!(
+			SonosChange::between(Some(&playing), &next_track),
+			vec![SonosChange::Track, SonosChange::Position]
+		);
+	}
+
+	#[tokio::test]
+	async fn interruption_restores_previous_track() {
+		let ctx = test::ContextBuilder::new(test_name!()).build().await;
+		let bridge = mock::MockBridge::start().await;
+		let manager = Manager::new(
+			ctx.config_manager.clone(),
+			Arc::new(MockMusicLibrary::default()),
+			reqwest::Client::new(),
+		);
+		let sonos = config::SonosConfig {
+			api_url: Some(bridge.url.clone()),
+			webhook_enabled: true,
+			..Default::default()
+		};
+		ctx.config_manager.set_sonos_config(sonos).await.unwrap();
+
+		let webhook = |state: serde_json::Value| SonosWebhookPayload {
+			kind: "transport-state".to_owned(),
+			data: serde_json::json!({ "roomName": "Kitchen", "state": state }),
+		};
+		manager
+			.handle_webhook(webhook(mock::state()))
+			.await
+			.unwrap();
+
+		let share = MusicShare {
+			server: "nas/mp3".to_owned(),
+			scheme: config::ShareScheme::Cifs,
+			mappings: Vec::new(),
+			stream_signer: None,
+		};
+		let response = manager
+			.interrupt(
+				"Kitchen",
+				"http://localhost:5050/api/v8/audio/news.mp3",
+				&share,
+				None,
+			)
+			.await
+			.unwrap();
+		assert!(response.success);
+		assert_eq!(
+			bridge.count("/Kitchen/setavtransporturi/x-file-cifs%3A%2F%2Fnas%2Fmp3%2Fnews.mp3"),
+			1
+		);
+
+		let mut state = mock::state();
+		state["currentTrack"]["uri"] = serde_json::json!("x-file-cifs://nas/mp3/news.mp3");
+		state["relTime"] = serde_json::json!("0:02:04");
+		manager
+			.handle_webhook(webhook(state.clone()))
+			.await
+			.unwrap();
+		state["playbackState"] = serde_json::json!("STOPPED");
+		manager.handle_webhook(webhook(state)).await.unwrap();
+
+		assert_eq!(
+			bridge.count("/Kitchen/setavtransporturi/x-file-cifs%3A%2F%2Fnas%2Fmp3%2Fsong.mp3"),
+			1
+		);
+		assert_eq!(bridge.count("/Kitchen/timeseek/65"), 1);
+		assert!(matches!(
+			manager.end_interrupt("Kitchen").await,
+			Err(SonosError::InterruptionNotFound(_))
+		));
+	}
+
+	#[tokio::test]
+	async fn session_plays_next_track_when_current_ends() {
+		let ctx = test::ContextBuilder::new(test_name!()).build().await;
+		let bridge = mock::MockBridge::start().await;
+		let manager = Manager::new(
+			ctx.config_manager.clone(),
+			Arc::new(MockMusicLibrary::default()),
+			reqwest::Client::new(),
+		);
+		let sonos = config::SonosConfig {
+			api_url: Some(bridge.url.clone()),
+			webhook_enabled: true,
+			..Default::default()
+		};
+		ctx.config_manager.set_sonos_config(sonos).await.unwrap();
+
+		let webhook = |state: serde_json::Value| SonosWebhookPayload {
+			kind: "transport-state".to_owned(),
+			data: serde_json::json!({ "roomName": "Kitchen", "state": state }),
+		};
+		manager
+			.handle_webhook(SonosWebhookPayload {
+				kind: "topology-change".to_owned(),
+				data: mock::zones(),
+			})
+			.await
+			.unwrap();
+
+		let next_uri = "x-file-cifs://nas/mp3/next.mp3";
+		manager
+			.play_session(
+				"Kitchen",
+				"x-file-cifs://nas/mp3/song.mp3",
+				vec![next_uri.to_owned()],
+			)
+			.await
+			.unwrap();
+		let session = manager.get_session("Kitchen").unwrap();
+		assert_eq!(session.upcoming(), [next_uri]);
+
+		let mut state = mock::state();
+		state["relTime"] = serde_json::json!("0:02:04");
+		manager
+			.handle_webhook(webhook(state.clone()))
+			.await
+			.unwrap();
+		state["playbackState"] = serde_json::json!("STOPPED");
+		manager.handle_webhook(webhook(state)).await.unwrap();
+
+		let next_path = format!(
+			"/Kitchen/setavtransporturi/{}",
+			urlencoding::encode(next_uri)
+		);
+		assert_eq!(bridge.count(&next_path), 1);
+		assert_eq!(manager.get_session("Kitchen").unwrap().current_index, 1);
+
+		// Starting something else from the Sonos app ends the session
+		let mut state = mock::state();
+		state["currentTrack"]["uri"] = serde_json::json!("x-sonosapi-stream:s17488?sid=254");
+		manager.handle_webhook(webhook(state)).await.unwrap();
+		assert!(matches!(
+			manager.get_session("Kitchen"),
+			Err(SonosError::SessionNotFound(_))
+		));
+	}
+}
@@ -0,0 +1,384 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// node-sonos-http-api actions which do not target a speaker, such as `/zones`
+const GLOBAL_ACTIONS: &[&str] = &[
+	"clipall",
+	"favorites",
+	"lockvolumes",
+	"pauseall",
+	"playlists",
+	"preset",
+	"resumeall",
+	"sayall",
+	"unlockvolumes",
+	"version",
+	"zones",
+];
+
+/// node-sonos-http-api actions sent to a speaker, such as `/Kitchen/state`
+const SPEAKER_ACTIONS: &[&str] = &[
+	"addtoqueue",
+	"bass",
+	"clearqueue",
+	"clip",
+	"crossfade",
+	"deleteplaylist",
+	"favorite",
+	"join",
+	"leave",
+	"loudness",
+	"mute",
+	"next",
+	"pause",
+	"play",
+	"playlist",
+	"previous",
+	"queue",
+	"repeat",
+	"savequeue",
+	"say",
+	"seek",
+	"setavtransporturi",
+	"shuffle",
+	"sleep",
+	"state",
+	"stop",
+	"timeseek",
+	"treble",
+	"trackseek",
+	"unmute",
+	"volume",
+];
+
+/// Number of speakers operations are counted for separately, by UUID. Operations on any other speaker,
+/// and on speakers which are not known, such as rooms which do not exist, are counted together under `OTHER_SPEAKERS`.
+const MAX_SPEAKER_LABELS: usize = 64;
+
+const OTHER_SPEAKERS: &str = "other";
+
+/// Upper bounds in seconds of the request duration histogram buckets
+const DURATION_BUCKETS: [f64; 11] = [
+	0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// How a request to node-sonos-http-api ended
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum RequestOutcome {
+	Success,
+	HttpError,
+	ConnectionError,
+}
+
+impl RequestOutcome {
+	fn label(&self) -> &'static str {
+		match self {
+			RequestOutcome::Success => "success",
+			RequestOutcome::HttpError => "http_error",
+			RequestOutcome::ConnectionError => "connection_error",
+		}
+	}
+}
+
+/// How a `SonosService` operation ended
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum OperationStatus {
+	Success,
+	Failure,
+}
+
+impl OperationStatus {
+	fn label(&self) -> &'static str {
+		match self {
+			OperationStatus::Success => "success",
+			OperationStatus::Failure => "failure",
+		}
+	}
+}
+
+#[derive(Default)]
+struct Series {
+	count: u64,
+	sum_secs: f64,
+	buckets: [u64; DURATION_BUCKETS.len()],
+}
+
+impl Series {
+	fn observe(&mut self, duration: Duration) {
+		let secs = duration.as_secs_f64();
+		self.count += 1;
+		self.sum_secs += secs;
+		for (bucket, bound) in self.buckets.iter_mut().zip(DURATION_BUCKETS) {
+			if secs <= bound {
+				*bucket += 1;
+			}
+		}
+	}
+
+	fn render(&self, out: &mut String, name: &str, labels: &str) {
+		for (bound, count) in DURATION_BUCKETS.iter().zip(self.buckets) {
+			let _ = writeln!(out, "{name}_bucket{{{labels},le=\"{bound}\"}} {count}");
+		}
+		let _ = writeln!(out, "{name}_bucket{{{labels},le=\"+Inf\"}} {}", self.count);
+		let _ = writeln!(out, "{name}_sum{{{labels}}} {}", self.sum_secs);
+		let _ = writeln!(out, "{name}_count{{{labels}}} {}", self.count);
+	}
+}
+
+#[derive(Default)]
+struct Operations {
+	counts: BTreeMap<(&'static str, String, OperationStatus), u64>,
+	durations: BTreeMap<&'static str, Series>,
+	speakers: BTreeSet<String>,
+}
+
+/// Number and duration of requests sent to node-sonos-http-api, by action and outcome.
+/// Speaker names are never used as labels of request series, so their number stays bounded.
+/// Operations such as playing a track or setting a volume are also counted by speaker UUID, when the
+/// services recording them have operation metrics enabled.
+#[derive(Clone, Default)]
+pub struct SonosMetrics {
+	series: Arc<Mutex<BTreeMap<(&'static str, RequestOutcome), Series>>>,
+	operations: Arc<Mutex<Operations>>,
+}
+
+impl SonosMetrics {
+	pub fn record(&self, action: &'static str, outcome: RequestOutcome, duration: Duration) {
+		let mut series = self.series.lock().unwrap();
+		series
+			.entry((action, outcome))
+			.or_default()
+			.observe(duration);
+	}
+
+	/// Count an operation run on the speaker with the UUID `speaker_id`, or on an unknown speaker
+	pub fn record_operation(
+		&self,
+		operation: &'static str,
+		speaker_id: Option<&str>,
+		status: OperationStatus,
+		duration: Duration,
+	) {
+		let mut operations = self.operations.lock().unwrap();
+		let speaker = match speaker_id {
+			Some(uuid) if operations.speakers.contains(uuid) => uuid,
+			Some(uuid) if operations.speakers.len() < MAX_SPEAKER_LABELS => {
+				operations.speakers.insert(uuid.to_owned());
+				uuid
+			}
+			_ => OTHER_SPEAKERS,
+		};
+		*operations
+			.counts
+			.entry((operation, speaker.to_owned(), status))
+			.or_default() += 1;
+		operations
+			.durations
+			.entry(operation)
+			.or_default()
+			.observe(duration);
+	}
+
+	/// Metrics in the Prometheus text exposition format
+	pub fn render(&self) -> String {
+		let series = self.series.lock().unwrap();
+		let mut out = String::new();
+
+		out.push_str("# HELP polaris_sonos_requests_total Requests sent to node-sonos-http-api.\n");
+		out.push_str("# TYPE polaris_sonos_requests_total counter\n");
+		for ((action, outcome), s) in series.iter() {
+			let _ = writeln!(
+				out,
+				"polaris_sonos_requests_total{{action=\"{action}\",outcome=\"{}\"}} {}",
+				outcome.label(),
+				s.count
+			);
+		}
+
+		out.push_str("# HELP polaris_sonos_request_duration_seconds Duration of requests sent to node-sonos-http-api.\n");
+		out.push_str("# TYPE polaris_sonos_request_duration_seconds histogram\n");
+		for ((action, outcome), s) in series.iter() {
+			let labels = format!("action=\"{action}\",outcome=\"{}\"", outcome.label());
+			s.render(&mut out, "polaris_sonos_request_duration_seconds", &labels);
+		}
+		drop(series);
+
+		let operations = self.operations.lock().unwrap();
+		if operations.counts.is_empty() {
+			return out;
+		}
+
+		out.push_str("# HELP polaris_sonos_operations_total Operations run on Sonos speakers.\n");
+		out.push_str("# TYPE polaris_sonos_operations_total counter\n");
+		for ((operation, speaker_id, status), count) in operations.counts.iter() {
+			let _ = writeln!(
+				out,
+				"polaris_sonos_operations_total{{operation=\"{operation}\",speaker_id=\"{}\",status=\"{}\"}} {count}",
+				escape_label(speaker_id),
+				status.label()
+			);
+		}
+
+		out.push_str("# HELP polaris_sonos_operation_duration_seconds Duration of operations run on Sonos speakers.\n");
+		out.push_str("# TYPE polaris_sonos_operation_duration_seconds histogram\n");
+		for (operation, s) in operations.durations.iter() {
+			let labels = format!("operation=\"{operation}\"");
+			s.render(
+				&mut out,
+				"polaris_sonos_operation_duration_seconds",
+				&labels,
+			);
+		}
+
+		out
+	}
+}
+
+/// Label value with the characters the Prometheus text format reserves escaped
+fn escape_label(value: &str) -> String {
+	value
+		.replace('\\', "\\\\")
+		.replace('"', "\\\"")
+		.replace('\n', "\\n")
+}
+
+/// Action label of a request URL. Requests which are not sent to the bridge at `base_url`
+/// are album art downloads from the speakers themselves.
+pub(super) fn action_label(base_url: &str, url: &str) -> &'static str {
+	let Some(path) = url.strip_prefix(base_url.trim_end_matches('/')) else {
+		return "albumart";
+	};
+	let path = path.split(['?', '#']).next().unwrap_or_default();
+	let mut segments = path.trim_start_matches('/').split('/');
+	let first = segments.next().unwrap_or_default();
+	if let Some(action) = known(GLOBAL_ACTIONS, first) {
+		return action;
+	}
+	segments
+		.next()
+		.and_then(|action| known(SPEAKER_ACTIONS, action))
+		.unwrap_or("other")
+}
+
+fn known(actions: &[&'static str], action: &str) -> Option<&'static str> {
+	actions
+		.iter()
+		.find(|a| a.eq_ignore_ascii_case(action))
+		.copied()
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn labels_requests_by_action() {
+		let base = "http://localhost:5005";
+		let label = |path: &str| action_label(base, &format!("{base}{path}"));
+		assert_eq!(label("/zones"), "zones");
+		assert_eq!(label("/Kitchen/state"), "state");
+		assert_eq!(label("/Living%20Room/volume/30"), "volume");
+		assert_eq!(
+			label("/Kitchen/setavtransporturi/x-file-cifs%3A%2F%2Fnas"),
+			"setavtransporturi"
+		);
+		assert_eq!(label("/Kitchen/something/else"), "other");
+		assert_eq!(label("/Zones"), "zones");
+		assert_eq!(
+			action_label(base, "http://192.168.1.20:1400/getaa?s=1&u=song.mp3"),
+			"albumart"
+		);
+	}
+
+	#[test]
+	fn renders_prometheus_text() {
+		let metrics = SonosMetrics::default();
+		metrics.record("zones", RequestOutcome::Success, Duration::from_millis(20));
+		metrics.record("zones", RequestOutcome::Success, Duration::from_millis(200));
+		metrics.record(
+			"state",
+			RequestOutcome::ConnectionError,
+			Duration::from_secs(20),
+		);
+
+		let text = metrics.render();
+		assert!(
+			text.contains("polaris_sonos_requests_total{action=\"zones\",outcome=\"success\"} 2\n")
+		);
+		assert!(text.contains(
+			"polaris_sonos_requests_total{action=\"state\",outcome=\"connection_error\"} 1\n"
+		));
+		assert!(text.contains(
+			"polaris_sonos_request_duration_seconds_bucket{action=\"zones\",outcome=\"success\",le=\"0.025\"} 1\n"
+		));
+		assert!(text.contains(
+			"polaris_sonos_request_duration_seconds_bucket{action=\"zones\",outcome=\"success\",le=\"0.25\"} 2\n"
+		));
+		assert!(text.contains(
+			"polaris_sonos_request_duration_seconds_bucket{action=\"state\",outcome=\"connection_error\",le=\"10\"} 0\n"
+		));
+		assert!(text.contains(
+			"polaris_sonos_request_duration_seconds_count{action=\"state\",outcome=\"connection_error\"} 1\n"
+		));
+		assert!(!text.contains("polaris_sonos_operations_total"));
+	}
+
+	#[test]
+	fn renders_operations_by_speaker() {
+		let metrics = SonosMetrics::default();
+		let fast = Duration::from_millis(20);
+		let kitchen = Some("RINCON_000E58A0000002400");
+		metrics.record_operation("pause", kitchen, OperationStatus::Success, fast);
+		metrics.record_operation("pause", kitchen, OperationStatus::Failure, fast);
+		metrics.record_operation(
+			"pause",
+			Some("Bob's \"Den\""),
+			OperationStatus::Success,
+			fast,
+		);
+		metrics.record_operation("pause", None, OperationStatus::Success, fast);
+
+		let text = metrics.render();
+		assert!(text.contains(
+			"polaris_sonos_operations_total{operation=\"pause\",speaker_id=\"RINCON_000E58A0000002400\",status=\"success\"} 1\n"
+		));
+		assert!(text.contains(
+			"polaris_sonos_operations_total{operation=\"pause\",speaker_id=\"RINCON_000E58A0000002400\",status=\"failure\"} 1\n"
+		));
+		assert!(text.contains("speaker_id=\"Bob's \\\"Den\\\"\""));
+		assert!(text.contains(
+			"polaris_sonos_operations_total{operation=\"pause\",speaker_id=\"other\",status=\"success\"} 1\n"
+		));
+		assert!(text
+			.contains("polaris_sonos_operation_duration_seconds_count{operation=\"pause\"} 4\n"));
+	}
+
+	#[test]
+	fn bounds_speaker_labels() {
+		let metrics = SonosMetrics::default();
+		for i in 0..MAX_SPEAKER_LABELS + 10 {
+			metrics.record_operation(
+				"mute",
+				Some(&format!("RINCON_{i}")),
+				OperationStatus::Success,
+				Duration::ZERO,
+			);
+		}
+		metrics.record_operation(
+			"mute",
+			Some("RINCON_0"),
+			OperationStatus::Success,
+			Duration::ZERO,
+		);
+
+		let text = metrics.render();
+		assert!(text.contains(
+			"polaris_sonos_operations_total{operation=\"mute\",speaker_id=\"RINCON_0\",status=\"success\"} 2\n"
+		));
+		assert!(text.contains(
+			"polaris_sonos_operations_total{operation=\"mute\",speaker_id=\"other\",status=\"success\"} 10\n"
+		));
+	}
+}
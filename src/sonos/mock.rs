@@ -0,0 +1,359 @@
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::{
+	extract::ConnectInfo,
+	http::{
+		header::{AUTHORIZATION, CONTENT_TYPE},
+		HeaderMap, StatusCode, Uri,
+	},
+	response::IntoResponse,
+	Json, Router,
+};
+use bytes::Bytes;
+use serde_json::{json, Value};
+
+/// Fake node-sonos-http-api bridge listening on a local port, which records the requests it receives.
+pub struct MockBridge {
+	pub url: String,
+	requests: Arc<Mutex<Vec<RecordedRequest>>>,
+	failing_paths: Arc<Mutex<HashMap<String, String>>>,
+	serve_album_art: Arc<Mutex<bool>>,
+	zones: Arc<Mutex<Value>>,
+	state: Arc<Mutex<Value>>,
+	delay: Arc<Mutex<Duration>>,
+	in_flight: Arc<Mutex<(usize, usize)>>,
+	authorization: Arc<Mutex<Option<String>>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct RecordedRequest {
+	pub path: String,
+	pub headers: HeaderMap,
+	pub peer: SocketAddr,
+	pub body: String,
+}
+
+impl MockBridge {
+	pub async fn start() -> Self {
+		let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let url = format!("http://{}", listener.local_addr().unwrap());
+
+		let requests = Arc::new(Mutex::new(Vec::<RecordedRequest>::new()));
+		let failing_paths = Arc::new(Mutex::new(HashMap::<String, String>::new()));
+		let serve_album_art = Arc::new(Mutex::new(false));
+		let zones = Arc::new(Mutex::new(zones()));
+		let state = Arc::new(Mutex::new(state()));
+		let delay = Arc::new(Mutex::new(Duration::ZERO));
+		let in_flight = Arc::new(Mutex::new((0, 0)));
+		let authorization = Arc::new(Mutex::new(None::<String>));
+		let router = Router::new().fallback({
+			let url = url.clone();
+			let requests = requests.clone();
+			let failing_paths = failing_paths.clone();
+			let serve_album_art = serve_album_art.clone();
+			let zones = zones.clone();
+			let state = state.clone();
+			let delay = delay.clone();
+			let in_flight = in_flight.clone();
+			let authorization = authorization.clone();
+			move |ConnectInfo(peer): ConnectInfo<SocketAddr>,
+			      uri: Uri,
+			      headers: HeaderMap,
+			      body: Bytes| {
+				let url = url.clone();
+				let requests = requests.clone();
+				let failing_paths = failing_paths.clone();
+				let serve_album_art = serve_album_art.clone();
+				let zones = zones.clone();
+				let state = state.clone();
+				let delay = delay.clone();
+				let in_flight = in_flight.clone();
+				let authorization = authorization.clone();
+				async move {
+					let authorized = match &*authorization.lock().unwrap() {
+						Some(expected) => headers
+							.get(AUTHORIZATION)
+							.is_some_and(|h| h.as_bytes() == expected.as_bytes()),
+						None => true,
+					};
+					let soap_action = headers
+						.get("soapaction")
+						.and_then(|h| h.to_str().ok())
+						.map(|a| a.trim_matches('"').to_owned());
+					requests.lock().unwrap().push(RecordedRequest {
+						path: uri.path().to_owned(),
+						headers,
+						peer,
+						body: String::from_utf8_lossy(&body).into_owned(),
+					});
+					if !authorized {
+						return StatusCode::UNAUTHORIZED.into_response();
+					}
+					let delay = *delay.lock().unwrap();
+					if !delay.is_zero() {
+						{
+							let (current, max) = &mut *in_flight.lock().unwrap();
+							*current += 1;
+							*max = (*max).max(*current);
+						}
+						tokio::time::sleep(delay).await;
+						in_flight.lock().unwrap().0 -= 1;
+					}
+					if let Some(body) = failing_paths.lock().unwrap().get(uri.path()) {
+						return (StatusCode::INTERNAL_SERVER_ERROR, body.clone()).into_response();
+					}
+					if *serve_album_art.lock().unwrap() {
+						if uri.path() == "/getaa" {
+							return ([(CONTENT_TYPE, "image/jpeg")], ALBUM_ART).into_response();
+						}
+						if uri.path().ends_with("/state") {
+							let mut state = state.lock().unwrap().clone();
+							state["currentTrack"]["absoluteAlbumArtUri"] =
+								json!(format!("{url}/getaa?s=1&u=song.mp3"));
+							return Json(state).into_response();
+						}
+					}
+					if [
+						DLNA_CONTROL_PATH,
+						RENDERING_CONTROL_PATH,
+						ZONE_GROUP_TOPOLOGY_PATH,
+					]
+					.contains(&uri.path())
+					{
+						let response = respond_soap(soap_action.as_deref().unwrap_or_default());
+						return ([(CONTENT_TYPE, "text/xml")], response).into_response();
+					}
+					if uri.path() == "/xml/device_description.xml" {
+						return ([(CONTENT_TYPE, "text/xml")], DEVICE_DESCRIPTION).into_response();
+					}
+					if uri.path() == "/zones" {
+						// Leave time for concurrent requests to pile up
+						tokio::time::sleep(Duration::from_millis(50)).await;
+						let zones = zones.lock().unwrap().clone();
+						return Json(zones).into_response();
+					}
+					if uri.path().ends_with("/state") {
+						let state = state.lock().unwrap().clone();
+						return Json(state).into_response();
+					}
+					respond(uri.path()).into_response()
+				}
+			}
+		});
+
+		let service = router.into_make_service_with_connect_info::<SocketAddr>();
+		tokio::spawn(async move { axum::serve(listener, service).await.unwrap() });
+
+		Self {
+			url,
+			requests,
+			failing_paths,
+			serve_album_art,
+			zones,
+			state,
+			delay,
+			in_flight,
+			authorization,
+		}
+	}
+
+	/// Answer requests which do not send `authorization` in their `Authorization` header with 401 from now on
+	pub fn require_authorization(&self, authorization: &str) {
+		*self.authorization.lock().unwrap() = Some(authorization.to_owned());
+	}
+
+	/// Take `delay` to answer every request from now on, keeping track of how many are handled at once
+	pub fn set_delay(&self, delay: Duration) {
+		*self.delay.lock().unwrap() = delay;
+	}
+
+	/// Largest number of requests handled at the same time while answers were delayed
+	pub fn max_in_flight(&self) -> usize {
+		self.in_flight.lock().unwrap().1
+	}
+
+	/// Report `state` from the `/{speaker}/state` endpoint of every speaker from now on
+	pub fn set_state(&self, state: Value) {
+		*self.state.lock().unwrap() = state;
+	}
+
+	/// Report `zones` from the `/zones` endpoint from now on
+	pub fn set_zones(&self, zones: Value) {
+		*self.zones.lock().unwrap() = zones;
+	}
+
+	/// Serve album art from the bridge itself, and point speaker states to it
+	pub fn serve_album_art(&self) {
+		*self.serve_album_art.lock().unwrap() = true;
+	}
+
+	/// Answer requests to `path` with an error from now on
+	pub fn fail(&self, path: &str) {
+		self.fail_with(path, "Speaker not found");
+	}
+
+	/// Answer requests to `path` with an error carrying `body` from now on
+	pub fn fail_with(&self, path: &str, body: &str) {
+		self.failing_paths
+			.lock()
+			.unwrap()
+			.insert(path.to_owned(), body.to_owned());
+	}
+
+	pub fn requests(&self) -> Vec<RecordedRequest> {
+		self.requests.lock().unwrap().clone()
+	}
+
+	pub fn count(&self, path: &str) -> usize {
+		self.requests().iter().filter(|r| r.path == path).count()
+	}
+
+	/// Number of distinct TCP connections requests were received on
+	pub fn connections(&self) -> usize {
+		let peers: HashSet<SocketAddr> = self.requests().iter().map(|r| r.peer).collect();
+		peers.len()
+	}
+}
+
+pub const ALBUM_ART: &[u8] = b"\xFF\xD8\xFF\xE0 not quite a jpeg";
+
+/// Stand-in for Sonos speakers answering SSDP searches: `answers` responses pointing to `location` are sent
+/// back for the first M-SEARCH request received on the returned address
+pub async fn answer_ssdp(location: String, answers: usize) -> SocketAddr {
+	let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+	let address = socket.local_addr().unwrap();
+	tokio::spawn(async move {
+		let mut buffer = [0; 2048];
+		let (size, from) = socket.recv_from(&mut buffer).await.unwrap();
+		let request = String::from_utf8_lossy(&buffer[..size]);
+		let valid = request.starts_with("M-SEARCH * HTTP/1.1\r\n")
+			&& request.contains("ST: urn:schemas-upnp-org:device:ZonePlayer:1\r\n");
+		if !valid {
+			return;
+		}
+		let response = format!(
+			"HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age = 1800\r\nEXT:\r\nLOCATION: {location}\r\nSERVER: Linux UPnP/1.0 Sonos/70.3-35220 (ZPS13)\r\nST: urn:schemas-upnp-org:device:ZonePlayer:1\r\nUSN: uuid:RINCON_949F3E000001401400::urn:schemas-upnp-org:device:ZonePlayer:1\r\n\r\n"
+		);
+		for _ in 0..answers {
+			socket.send_to(response.as_bytes(), from).await.unwrap();
+		}
+	});
+	address
+}
+
+/// Device description served by Sonos speakers on port 1400
+pub const DEVICE_DESCRIPTION: &str = include_str!("../../test-data/sonos/device_description.xml");
+
+/// Path of the AVTransport control endpoint of the DLNA renderer served by the mock bridge
+pub const DLNA_CONTROL_PATH: &str = "/MediaRenderer/AVTransport/Control";
+
+/// Path of the RenderingControl endpoint of the Sonos speaker served by the mock bridge
+pub const RENDERING_CONTROL_PATH: &str = "/MediaRenderer/RenderingControl/Control";
+
+/// Path of the ZoneGroupTopology endpoint of the Sonos speaker served by the mock bridge
+pub const ZONE_GROUP_TOPOLOGY_PATH: &str = "/ZoneGroupTopology/Control";
+
+/// Rooms and groups reported by the ZoneGroupTopology service of the Sonos speaker served by the mock bridge
+pub const ZONE_GROUP_STATE: &str = include_str!("../../test-data/sonos/zone_group_state.xml");
+
+/// Answer to the AVTransport, RenderingControl or ZoneGroupTopology `action` of a renderer playing a Polaris audio URL
+fn respond_soap(action: &str) -> String {
+	let name = action.rsplit('#').next().unwrap_or_default();
+	let arguments = match name {
+		"GetTransportInfo" => "<CurrentTransportState>PLAYING</CurrentTransportState><CurrentTransportStatus>OK</CurrentTransportStatus><CurrentSpeed>1</CurrentSpeed>".to_owned(),
+		"GetPositionInfo" => "<Track>1</Track><TrackDuration>0:02:05</TrackDuration><TrackMetaData>&lt;DIDL-Lite xmlns=&quot;urn:schemas-upnp-org:metadata-1-0/DIDL-Lite/&quot; xmlns:dc=&quot;http://purl.org/dc/elements/1.1/&quot;&gt;&lt;item&gt;&lt;dc:title&gt;Yesterday&lt;/dc:title&gt;&lt;dc:creator&gt;The Beatles&lt;/dc:creator&gt;&lt;/item&gt;&lt;/DIDL-Lite&gt;</TrackMetaData><TrackURI>http://192.168.0.5:5050/api/v8/audio/song.mp3</TrackURI><RelTime>0:01:05</RelTime><AbsTime>NOT_IMPLEMENTED</AbsTime>".to_owned(),
+		"GetZoneGroupState" => format!(
+			"<ZoneGroupState>{}</ZoneGroupState>",
+			quick_xml::escape::escape(ZONE_GROUP_STATE)
+		),
+		_ => String::new(),
+	};
+	format!(
+		"<?xml version=\"1.0\"?><s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\"><s:Body><u:{name}Response xmlns:u=\"urn:schemas-upnp-org:service:AVTransport:1\">{arguments}</u:{name}Response></s:Body></s:Envelope>"
+	)
+}
+
+fn respond(path: &str) -> Json<Value> {
+	if path.ends_with("/playlists") {
+		return Json(json!(["Morning", "Bedtime"]));
+	}
+	if path.ends_with("/favorites/detailed") {
+		return Json(favorites());
+	}
+	if path.ends_with("/queue") {
+		return Json(queue());
+	}
+	Json(json!({ "status": "success" }))
+}
+
+pub fn favorites() -> Value {
+	json!([
+		{
+			"title": "Radio Paradise",
+			"uri": "x-rincon-mp3radio://stream.radioparadise.com/mp3-192",
+			"albumArtUri": "https://img.radioparadise.com/logo.png"
+		},
+		{
+			"title": "AC/DC Classics",
+			"uri": "x-rincon-cpcontainer:1006206cspotify%3aplaylist%3a37i9dQZF1DX1spT6G94GFC",
+			"albumArtUri": "https://i.scdn.co/image/acdc.jpg"
+		},
+		{
+			"title": "Morning",
+			"uri": "file:///jffs/settings/savedqueues.rsq#3",
+			"albumArtUri": ""
+		}
+	])
+}
+
+pub fn zones() -> Value {
+	json!([
+		{
+			"uuid": "RINCON_000E58A0000001400",
+			"coordinator": {
+				"uuid": "RINCON_000E58A0000001400",
+				"roomName": "Living Room",
+				"state": { "volume": 20, "mute": false }
+			},
+			"members": []
+		},
+		{
+			"uuid": "RINCON_000E58A0000002400",
+			"coordinator": {
+				"uuid": "RINCON_000E58A0000002400",
+				"roomName": "Kitchen",
+				"state": { "volume": 35, "mute": true }
+			},
+			"members": []
+		}
+	])
+}
+
+pub fn queue() -> Value {
+	json!([
+		{ "uri": "x-file-cifs://nas/mp3/1.mp3", "title": "One", "artist": "The Band" },
+		{ "uri": "x-file-cifs://nas/mp3/2.mp3", "title": "Two", "artist": "The Band" },
+		{ "uri": "x-file-cifs://nas/mp3/3.mp3", "title": "Three", "artist": "The Band" }
+	])
+}
+
+pub fn state() -> Value {
+	json!({
+		"volume": 20,
+		"mute": false,
+		"playbackState": "PLAYING",
+		"relTime": "0:01:05",
+		"playMode": { "repeat": "none", "shuffle": false, "crossfade": true },
+		"equalizer": { "bass": 2, "treble": -1, "loudness": true, "nightMode": false },
+		"currentTrack": {
+			"artist": "The Beatles",
+			"title": "Yesterday",
+			"duration": "0:02:05",
+			"uri": "x-file-cifs://nas/mp3/song.mp3",
+			"absoluteAlbumArtUri": "http://192.168.1.20:1400/getaa?s=1&u=song.mp3"
+		}
+	})
+}
@@ -0,0 +1,370 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use quick_xml::escape::escape;
+use tokio::sync::Mutex;
+use tracing::debug;
+
+use super::dlna::{parse_transport_state, send_soap_action, AV_TRANSPORT};
+use super::renderer::{Renderer, RendererFuture};
+use super::{
+	build_didl_lite, seconds_to_hms, SonosError, SonosResponse, SonosSpeaker, SonosState,
+	SonosVolumeResponse, TrackMetadata, UPnPDiscovery,
+};
+
+/// UPnP service Sonos speakers change their volume through
+const RENDERING_CONTROL: &str = "urn:schemas-upnp-org:service:RenderingControl:1";
+
+/// Control endpoints of a Sonos speaker, relative to the address its device description is served from
+const AV_TRANSPORT_PATH: &str = "/MediaRenderer/AVTransport/Control";
+const RENDERING_CONTROL_PATH: &str = "/MediaRenderer/RenderingControl/Control";
+
+/// Sonos speakers found on the network, kept from one request to the next as searching takes a while
+#[derive(Clone, Default)]
+pub struct NativeSpeakerCache {
+	found: Arc<Mutex<Option<(Instant, Vec<NativeSpeaker>)>>>,
+}
+
+#[derive(Clone)]
+struct NativeSpeaker {
+	speaker: SonosSpeaker,
+	/// Address the services of the speaker are served from, such as `http://192.168.0.20:1400`
+	base_url: String,
+}
+
+impl NativeSpeaker {
+	async fn send(
+		&self,
+		client: &reqwest::Client,
+		(service, path): (&str, &str),
+		action: &str,
+		arguments: &str,
+	) -> Result<String, SonosError> {
+		let control_url = format!("{}{path}", self.base_url);
+		send_soap_action(
+			client,
+			&self.speaker.name,
+			&control_url,
+			service,
+			action,
+			&format!("<InstanceID>0</InstanceID>{arguments}"),
+		)
+		.await
+	}
+}
+
+/// Controls Sonos speakers without node-sonos-http-api, by sending UPnP SOAP actions to the speakers themselves.
+/// Speakers are found with SSDP. Only playback, volume and state are available this way: grouping, queues,
+/// favorites and the other features of node-sonos-http-api are not.
+#[derive(Clone)]
+pub struct NativeSonos {
+	client: reqwest::Client,
+	discovery: UPnPDiscovery,
+	cache: NativeSpeakerCache,
+	cache_ttl: Duration,
+}
+
+impl NativeSonos {
+	pub fn new(
+		client: reqwest::Client,
+		discovery: UPnPDiscovery,
+		cache: NativeSpeakerCache,
+		cache_ttl: Duration,
+	) -> Self {
+		Self {
+			client,
+			discovery,
+			cache,
+			cache_ttl,
+		}
+	}
+
+	/// Speakers found on the network. They are searched for again once the previous search is older than the cache TTL.
+	async fn speakers(&self) -> Vec<NativeSpeaker> {
+		let mut found = self.cache.found.lock().await;
+		if let Some((searched_at, speakers)) = &*found {
+			if searched_at.elapsed() < self.cache_ttl {
+				return speakers.clone();
+			}
+		}
+
+		let speakers = self
+			.discovery
+			.discover_devices()
+			.await
+			.into_iter()
+			.filter_map(|(mut speaker, location)| {
+				let base_url = reqwest::Url::parse(&location)
+					.ok()?
+					.origin()
+					.ascii_serialization();
+				speaker.available = true;
+				Some(NativeSpeaker { speaker, base_url })
+			})
+			.collect::<Vec<_>>();
+		debug!("Found {} Sonos speakers on the network", speakers.len());
+		*found = Some((Instant::now(), speakers.clone()));
+		speakers
+	}
+
+	/// Speaker identified by `speaker_id`, which is either its UUID or its room name
+	async fn find(&self, speaker_id: &str) -> Option<NativeSpeaker> {
+		self.speakers()
+			.await
+			.into_iter()
+			.find(|s| s.speaker.id == speaker_id || s.speaker.name.eq_ignore_ascii_case(speaker_id))
+	}
+
+	async fn speaker(&self, speaker_id: &str) -> Result<NativeSpeaker, SonosError> {
+		self.find(speaker_id)
+			.await
+			.ok_or_else(|| SonosError::SpeakerNotFound(speaker_id.to_owned()))
+	}
+
+	pub async fn has_speaker(&self, speaker_id: &str) -> bool {
+		self.find(speaker_id).await.is_some()
+	}
+
+	pub async fn get_speakers(&self) -> Vec<SonosSpeaker> {
+		self.speakers()
+			.await
+			.into_iter()
+			.map(|s| s.speaker)
+			.collect()
+	}
+
+	pub async fn play_uri(&self, speaker_id: &str, uri: &str) -> Result<SonosResponse, SonosError> {
+		let speaker = self.speaker(speaker_id).await?;
+		let metadata = build_didl_lite(uri, &TrackMetadata::default());
+		let arguments = format!(
+			"<CurrentURI>{}</CurrentURI><CurrentURIMetaData>{}</CurrentURIMetaData>",
+			escape(uri),
+			escape(metadata.as_str())
+		);
+		let av_transport = (AV_TRANSPORT, AV_TRANSPORT_PATH);
+		speaker
+			.send(&self.client, av_transport, "SetAVTransportURI", &arguments)
+			.await?;
+		speaker
+			.send(&self.client, av_transport, "Play", "<Speed>1</Speed>")
+			.await?;
+		Ok(SonosResponse {
+			success: true,
+			message: "Track started playing on Sonos".to_owned(),
+			..Default::default()
+		})
+	}
+
+	pub async fn pause(&self, speaker_id: &str) -> Result<SonosResponse, SonosError> {
+		let speaker = self.speaker(speaker_id).await?;
+		speaker
+			.send(&self.client, (AV_TRANSPORT, AV_TRANSPORT_PATH), "Pause", "")
+			.await?;
+		Ok(SonosResponse {
+			success: true,
+			message: "Playback paused".to_owned(),
+			..Default::default()
+		})
+	}
+
+	pub async fn seek(&self, speaker_id: &str, position: u32) -> Result<SonosResponse, SonosError> {
+		let speaker = self.speaker(speaker_id).await?;
+		let target = seconds_to_hms(position as u64);
+		let arguments = format!("<Unit>REL_TIME</Unit><Target>{target}</Target>");
+		speaker
+			.send(
+				&self.client,
+				(AV_TRANSPORT, AV_TRANSPORT_PATH),
+				"Seek",
+				&arguments,
+			)
+			.await?;
+		Ok(SonosResponse {
+			success: true,
+			message: format!("Jumped to {target}"),
+			..Default::default()
+		})
+	}
+
+	pub async fn set_volume(
+		&self,
+		speaker_id: &str,
+		volume: u8,
+	) -> Result<SonosVolumeResponse, SonosError> {
+		if volume > 100 {
+			return Err(SonosError::InvalidVolume(volume));
+		}
+		let speaker = self.speaker(speaker_id).await?;
+		let arguments = format!("<Channel>Master</Channel><DesiredVolume>{volume}</DesiredVolume>");
+		speaker
+			.send(
+				&self.client,
+				(RENDERING_CONTROL, RENDERING_CONTROL_PATH),
+				"SetVolume",
+				&arguments,
+			)
+			.await?;
+		Ok(SonosVolumeResponse {
+			success: true,
+			message: format!("Volume set to {volume}"),
+			volume,
+		})
+	}
+
+	pub async fn get_state(&self, speaker_id: &str) -> Result<SonosState, SonosError> {
+		let speaker = self.speaker(speaker_id).await?;
+		let av_transport = (AV_TRANSPORT, AV_TRANSPORT_PATH);
+		let transport = speaker
+			.send(&self.client, av_transport, "GetTransportInfo", "")
+			.await?;
+		let position = speaker
+			.send(&self.client, av_transport, "GetPositionInfo", "")
+			.await?;
+		parse_transport_state(&speaker.speaker.name, &transport, &position)
+	}
+}
+
+impl Renderer for NativeSonos {
+	fn list(&self) -> RendererFuture<'_, Result<Vec<SonosSpeaker>, SonosError>> {
+		Box::pin(async { Ok(self.get_speakers().await) })
+	}
+
+	fn play<'a>(
+		&'a self,
+		speaker_id: &'a str,
+		uri: &'a str,
+	) -> RendererFuture<'a, Result<SonosResponse, SonosError>> {
+		Box::pin(self.play_uri(speaker_id, uri))
+	}
+
+	fn pause<'a>(
+		&'a self,
+		speaker_id: &'a str,
+	) -> RendererFuture<'a, Result<SonosResponse, SonosError>> {
+		Box::pin(NativeSonos::pause(self, speaker_id))
+	}
+
+	fn state<'a>(
+		&'a self,
+		speaker_id: &'a str,
+	) -> RendererFuture<'a, Result<SonosState, SonosError>> {
+		Box::pin(self.get_state(speaker_id))
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::sonos::mock::{answer_ssdp, MockBridge, DLNA_CONTROL_PATH, RENDERING_CONTROL_PATH};
+
+	async fn native(bridge: &MockBridge) -> NativeSonos {
+		let location = format!("{}/xml/device_description.xml", bridge.url);
+		let target = answer_ssdp(location, 1).await;
+		let discovery = UPnPDiscovery::new(reqwest::Client::new())
+			.with_target(target)
+			.with_timeout(Duration::from_millis(200));
+		NativeSonos::new(
+			reqwest::Client::new(),
+			discovery,
+			NativeSpeakerCache::default(),
+			Duration::from_secs(60),
+		)
+	}
+
+	fn actions(bridge: &MockBridge) -> Vec<(String, String)> {
+		bridge
+			.requests()
+			.into_iter()
+			.filter_map(|r| {
+				let action = r.headers.get("soapaction")?.to_str().ok()?;
+				let action = action.trim_matches('"').rsplit('#').next()?.to_owned();
+				Some((r.path, action))
+			})
+			.collect()
+	}
+
+	#[tokio::test]
+	async fn lists_discovered_speakers() {
+		let bridge = MockBridge::start().await;
+		let native = native(&bridge).await;
+
+		let speakers = native.get_speakers().await;
+		assert_eq!(speakers.len(), 1);
+		assert_eq!(speakers[0].id, "RINCON_949F3E000001401400");
+		assert_eq!(speakers[0].name, "Living Room");
+		assert!(speakers[0].available);
+
+		// Speakers are not searched for again while the cache is fresh
+		assert!(native.has_speaker("living room").await);
+		assert!(native.has_speaker("RINCON_949F3E000001401400").await);
+		assert!(!native.has_speaker("Kitchen").await);
+		assert_eq!(bridge.count("/xml/device_description.xml"), 1);
+	}
+
+	#[tokio::test]
+	async fn controls_speakers() {
+		let bridge = MockBridge::start().await;
+		let native = native(&bridge).await;
+
+		let uri = "http://192.168.0.5:5050/api/v8/audio/Beatles%2FHelp%2F13%20-%20Yesterday.mp3";
+		assert!(native.play_uri("Living Room", uri).await.unwrap().success);
+		Renderer::pause(&native, "Living Room").await.unwrap();
+		native.seek("Living Room", 90).await.unwrap();
+		let response = native
+			.set_volume("RINCON_949F3E000001401400", 30)
+			.await
+			.unwrap();
+		assert_eq!(response.volume, 30);
+
+		let control = |path: &str, action: &str| (path.to_owned(), action.to_owned());
+		assert_eq!(
+			actions(&bridge),
+			vec![
+				control(DLNA_CONTROL_PATH, "SetAVTransportURI"),
+				control(DLNA_CONTROL_PATH, "Play"),
+				control(DLNA_CONTROL_PATH, "Pause"),
+				control(DLNA_CONTROL_PATH, "Seek"),
+				control(RENDERING_CONTROL_PATH, "SetVolume"),
+			]
+		);
+		let requests = bridge.requests();
+		assert!(requests
+			.iter()
+			.any(|r| r.body.contains(&format!("<CurrentURI>{uri}</CurrentURI>"))));
+		assert!(requests
+			.iter()
+			.any(|r| r.body.contains("<DesiredVolume>30</DesiredVolume>")));
+		assert!(requests
+			.iter()
+			.any(|r| r.body.contains("<Target>0:01:30</Target>")));
+	}
+
+	#[tokio::test]
+	async fn reads_state() {
+		let bridge = MockBridge::start().await;
+		let state = native(&bridge)
+			.await
+			.get_state("Living Room")
+			.await
+			.unwrap();
+		assert!(state.is_playing);
+		assert_eq!(state.title.as_deref(), Some("Yesterday"));
+		assert_eq!(state.position, Some(65));
+		assert_eq!(state.duration, Some(125));
+	}
+
+	#[tokio::test]
+	async fn rejects_unknown_speakers_and_volumes() {
+		let bridge = MockBridge::start().await;
+		let native = native(&bridge).await;
+		assert!(matches!(
+			native.pause("Kitchen").await,
+			Err(SonosError::SpeakerNotFound(id)) if id == "Kitchen"
+		));
+		assert!(matches!(
+			native.set_volume("Living Room", 101).await,
+			Err(SonosError::InvalidVolume(101))
+		));
+		assert!(actions(&bridge).is_empty());
+	}
+}
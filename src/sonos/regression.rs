@@ -0,0 +1,257 @@
+//! Runs `SonosService` against a bridge answering with payloads captured from node-sonos-http-api,
+//! and checks both the values returned and the exact requests sent.
+//! Any change to how bridge URLs are built shows up here.
+
+use serde_json::{json, Value};
+
+use super::mock::MockBridge;
+use super::*;
+
+fn fixture(name: &str) -> Value {
+	let payload = std::fs::read_to_string(format!("test-data/sonos/{name}")).unwrap();
+	serde_json::from_str(&payload).unwrap()
+}
+
+/// The living room group and the office stereo pair
+fn two_zones() -> Value {
+	let mut zones = fixture("zones.json");
+	zones.as_array_mut().unwrap().truncate(2);
+	zones
+}
+
+fn paths(bridge: &MockBridge) -> Vec<String> {
+	bridge.requests().into_iter().map(|r| r.path).collect()
+}
+
+#[tokio::test]
+async fn get_speakers_lists_zone_coordinators() {
+	let bridge = MockBridge::start().await;
+	bridge.set_zones(two_zones());
+	let service = SonosService::new(bridge.url.clone());
+
+	let speakers = service.get_speakers().await.unwrap();
+	assert_eq!(
+		serde_json::to_value(&speakers).unwrap(),
+		json!([
+			{
+				"id": "RINCON_949F3E000001401400",
+				"name": "Living Room",
+				"available": true,
+				"volume": 18,
+				"muted": false,
+				"role": "Standalone",
+				"stereo_pair_id": null,
+				"model_name": "Sonos Beam",
+				"icon": "x-rincon-roomicon:tvroom",
+				"firmware_version": "70.3-35220",
+				"ip_address": null,
+				"group_id": "RINCON_949F3E000001401400",
+				"group_members": ["Living Room", "Kitchen"],
+				"is_stereo_pair": false,
+				"has_battery": false,
+				"battery_level": null,
+				"is_default": false,
+				"backend": "Sonos"
+			},
+			{
+				"id": "RINCON_5CAAFD000003401400",
+				"name": "Office",
+				"available": true,
+				"volume": 30,
+				"muted": false,
+				"role": "LeftChannel",
+				"stereo_pair_id": "RINCON_5CAAFD000003401400",
+				"model_name": "Sonos One",
+				"icon": "x-rincon-roomicon:office",
+				"firmware_version": "69.1-33120",
+				"ip_address": null,
+				"group_id": "RINCON_5CAAFD000003401400",
+				"group_members": ["Office"],
+				"is_stereo_pair": true,
+				"has_battery": false,
+				"battery_level": null,
+				"is_default": false,
+				"backend": "Sonos"
+			}
+		])
+	);
+	assert_eq!(paths(&bridge), vec!["/zones"]);
+}
+
+#[tokio::test]
+async fn play_track_sends_cifs_uri() {
+	let bridge = MockBridge::start().await;
+	bridge.set_zones(two_zones());
+	let service = SonosService::new(bridge.url.clone());
+	let share = MusicShare {
+		server: "192.168.0.6/mp3".to_owned(),
+		scheme: ShareScheme::Cifs,
+		mappings: Vec::new(),
+		stream_signer: None,
+	};
+
+	let response = service
+		.play_track(
+			"Living Room",
+			"http://192.168.0.5:5050/api/v8/audio/Beatles%2FHelp%2F13%20-%20Yesterday.mp3",
+			&share,
+			None,
+		)
+		.await
+		.unwrap();
+	assert!(response.success);
+	assert_eq!(response.message, "Track started playing on Sonos");
+	assert_eq!(
+		paths(&bridge),
+		vec![
+			"/zones",
+			"/Living%20Room/setavtransporturi/x-file-cifs%3A%2F%2F192.168.0.6%2Fmp3%2FBeatles%2FHelp%2F13%20-%20Yesterday.mp3"
+		]
+	);
+}
+
+#[tokio::test]
+async fn get_state_reads_playing_speaker() {
+	let bridge = MockBridge::start().await;
+	bridge.set_state(fixture("state-playing.json"));
+	let service = SonosService::new(bridge.url.clone());
+
+	let state = service.get_state("Living Room").await.unwrap();
+	assert_eq!(
+		state,
+		SonosState {
+			is_playing: true,
+			playback_state: Some("PLAYING".to_owned()),
+			artist: Some("The Beatles".to_owned()),
+			title: Some("Yesterday".to_owned()),
+			position: Some(42),
+			position_ms: Some(42000),
+			duration: Some(125),
+			album_art_uri: Some("http://192.168.0.20:1400/getaa?s=1&u=x-file-cifs%3a%2f%2f192.168.0.6%2fmp3%2fBeatles%2fHelp%2f13%2520-%2520Yesterday.mp3".to_owned()),
+			crossfade_enabled: Some(false),
+			shuffle: Some(false),
+			repeat: Some(RepeatMode::Off),
+			sleep_timer_remaining: None,
+			track_uri: Some(
+				"x-file-cifs://192.168.0.6/mp3/Beatles/Help/13%20-%20Yesterday.mp3".to_owned()
+			),
+		}
+	);
+	assert_eq!(paths(&bridge), vec!["/Living%20Room/state"]);
+}
+
+#[tokio::test]
+async fn get_state_reads_paused_speaker() {
+	let bridge = MockBridge::start().await;
+	bridge.set_state(fixture("state-paused.json"));
+	let service = SonosService::new(bridge.url.clone());
+
+	let state = service.get_state("Bedroom").await.unwrap();
+	assert_eq!(
+		state,
+		SonosState {
+			is_playing: false,
+			playback_state: Some("PAUSED_PLAYBACK".to_owned()),
+			artist: Some("Miles Davis".to_owned()),
+			title: Some("So What".to_owned()),
+			position: Some(301),
+			position_ms: Some(301000),
+			duration: Some(562),
+			album_art_uri: Some("http://192.168.0.22:1400/getaa?s=1&u=x-file-cifs%3a%2f%2f192.168.0.6%2fmp3%2fMiles%2520Davis%2fKind%2520of%2520Blue%2f01%2520-%2520So%2520What.flac".to_owned()),
+			crossfade_enabled: Some(true),
+			shuffle: Some(true),
+			repeat: Some(RepeatMode::All),
+			sleep_timer_remaining: None,
+			track_uri: Some(
+				"x-file-cifs://192.168.0.6/mp3/Miles%20Davis/Kind%20of%20Blue/01%20-%20So%20What.flac"
+					.to_owned()
+			),
+		}
+	);
+	assert_eq!(paths(&bridge), vec!["/Bedroom/state"]);
+}
+
+#[tokio::test]
+async fn set_volume_sends_volume() {
+	let bridge = MockBridge::start().await;
+	let service = SonosService::new(bridge.url.clone());
+
+	let response = service.set_volume("Living Room", 35).await.unwrap();
+	assert!(response.success);
+	assert_eq!(response.message, "Volume set to 35");
+	assert_eq!(response.volume, 35);
+	assert_eq!(paths(&bridge), vec!["/Living%20Room/volume/35"]);
+}
+
+#[tokio::test]
+async fn get_state_reads_bridge_1_7_position() {
+	let bridge = MockBridge::start().await;
+	bridge.set_state(fixture("state-1.7.json"));
+	let service = SonosService::new(bridge.url.clone());
+
+	let state = service.get_state("Living Room").await.unwrap();
+	assert_eq!(state.position, Some(77));
+	assert_eq!(state.position_ms, Some(77000));
+	assert_eq!(state.duration, Some(185));
+}
+
+#[tokio::test]
+async fn get_state_reads_bridge_1_8_position() {
+	let bridge = MockBridge::start().await;
+	bridge.set_state(fixture("state-1.8.json"));
+	let service = SonosService::new(bridge.url.clone());
+
+	let state = service.get_state("Living Room").await.unwrap();
+	assert_eq!(state.position, Some(77));
+	assert_eq!(state.position_ms, Some(77000));
+	assert_eq!(state.duration, Some(185));
+}
+
+#[tokio::test]
+async fn get_state_reads_radio_stream() {
+	let bridge = MockBridge::start().await;
+	bridge.set_state(fixture("state-radio.json"));
+	let service = SonosService::new(bridge.url.clone());
+
+	let state = service.get_state("Office").await.unwrap();
+	assert!(state.is_playing);
+	assert_eq!(state.position, None);
+	assert_eq!(state.position_ms, None);
+	assert_eq!(
+		state.track_uri.as_deref(),
+		Some("x-sonosapi-stream:s15200?sid=254&flags=8224&sn=0")
+	);
+}
+
+#[tokio::test]
+async fn get_transport_settings_reads_play_mode() {
+	let bridge = MockBridge::start().await;
+	bridge.set_state(fixture("state-paused.json"));
+	let service = SonosService::new(bridge.url.clone());
+
+	let settings = service.get_transport_settings("Bedroom").await.unwrap();
+	assert_eq!(
+		settings,
+		TransportSettings {
+			play_mode: PlayMode::ShuffleRepeatAll,
+			crossfade: true,
+		}
+	);
+	assert_eq!(paths(&bridge), vec!["/Bedroom/state"]);
+}
+
+#[tokio::test]
+async fn get_transport_settings_reads_bridge_1_7_play_mode() {
+	let bridge = MockBridge::start().await;
+	bridge.set_state(fixture("state-1.7.json"));
+	let service = SonosService::new(bridge.url.clone());
+
+	let settings = service.get_transport_settings("Living Room").await.unwrap();
+	assert_eq!(
+		settings,
+		TransportSettings {
+			play_mode: PlayMode::Normal,
+			crossfade: false,
+		}
+	);
+}
@@ -0,0 +1,74 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use super::{SonosError, SonosResponse, SonosService, SonosSpeaker, SonosState};
+
+pub type RendererFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Speakers of one kind Polaris can push tracks to, identified by the IDs `list` reports them with
+pub trait Renderer: Send + Sync {
+	/// Speakers which can be played on, tagged with this backend
+	fn list(&self) -> RendererFuture<'_, Result<Vec<SonosSpeaker>, SonosError>>;
+
+	/// Play `uri` on a speaker in place of what it is playing
+	fn play<'a>(
+		&'a self,
+		speaker_id: &'a str,
+		uri: &'a str,
+	) -> RendererFuture<'a, Result<SonosResponse, SonosError>>;
+
+	fn pause<'a>(
+		&'a self,
+		speaker_id: &'a str,
+	) -> RendererFuture<'a, Result<SonosResponse, SonosError>>;
+
+	fn state<'a>(
+		&'a self,
+		speaker_id: &'a str,
+	) -> RendererFuture<'a, Result<SonosState, SonosError>>;
+}
+
+impl Renderer for SonosService {
+	fn list(&self) -> RendererFuture<'_, Result<Vec<SonosSpeaker>, SonosError>> {
+		Box::pin(self.get_speakers())
+	}
+
+	fn play<'a>(
+		&'a self,
+		speaker_id: &'a str,
+		uri: &'a str,
+	) -> RendererFuture<'a, Result<SonosResponse, SonosError>> {
+		Box::pin(self.play_uri(speaker_id, uri))
+	}
+
+	fn pause<'a>(
+		&'a self,
+		speaker_id: &'a str,
+	) -> RendererFuture<'a, Result<SonosResponse, SonosError>> {
+		Box::pin(SonosService::pause(self, speaker_id))
+	}
+
+	fn state<'a>(
+		&'a self,
+		speaker_id: &'a str,
+	) -> RendererFuture<'a, Result<SonosState, SonosError>> {
+		Box::pin(self.read_state(speaker_id))
+	}
+}
+
+/// Speakers of every backend, Sonos speakers first. A speaker whose ID is taken by an earlier backend is left out,
+/// and so is one named like a Sonos room, as room names are accepted as IDs.
+pub async fn list_all(renderers: &[&dyn Renderer]) -> Result<Vec<SonosSpeaker>, SonosError> {
+	let mut speakers = Vec::<SonosSpeaker>::new();
+	for renderer in renderers {
+		for speaker in renderer.list().await? {
+			if !speakers
+				.iter()
+				.any(|s| s.id == speaker.id || s.name == speaker.id)
+			{
+				speakers.push(speaker);
+			}
+		}
+	}
+	Ok(speakers)
+}
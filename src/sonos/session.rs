@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::SonosState;
+
+/// How close to the end of a track playback must have been for a stop to count as the track finishing
+const TRACK_END_MARGIN: Duration = Duration::from_secs(2);
+
+/// Tracks handed over to a speaker one at a time with `setavtransporturi`, rather than through its queue.
+/// Polaris plays the next one when the current track ends.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[schema(examples(json!({
+	"speaker_id": "Kitchen",
+	"tracks": [
+		"http://192.168.0.5:5050/api/v8/audio/Beatles%2FHelp%2F01%20-%20Help!.mp3",
+		"http://192.168.0.5:5050/api/v8/audio/Beatles%2FHelp%2F02%20-%20The%20Night%20Before.mp3"
+	],
+	"current_index": 0
+})))]
+pub struct SonosSession {
+	/// The speaker playing the tracks
+	#[schema(examples("Kitchen", "Living Room"))]
+	pub speaker_id: String,
+	/// URIs of every track of the session, in playback order
+	#[schema(examples(
+		json!(["http://192.168.0.5:5050/api/v8/audio/a.mp3", "http://192.168.0.5:5050/api/v8/audio/b.mp3"]),
+		json!(["https://stream.example.com/episode-1.mp3"])
+	))]
+	pub tracks: Vec<String>,
+	/// Position in `tracks` of the track being played
+	#[schema(examples(0, 3))]
+	pub current_index: usize,
+}
+
+impl SonosSession {
+	pub fn current_track(&self) -> Option<&str> {
+		self.tracks.get(self.current_index).map(String::as_str)
+	}
+
+	/// Tracks which will play after the current one
+	pub fn upcoming(&self) -> &[String] {
+		self.tracks
+			.get(self.current_index + 1..)
+			.unwrap_or_default()
+	}
+}
+
+/// Crossfade chains playing in the background, at most one per group coordinator
+#[derive(Clone, Default)]
+pub struct CrossfadeChains {
+	chains: Arc<Mutex<HashMap<String, tokio::task::AbortHandle>>>,
+}
+
+impl CrossfadeChains {
+	/// Keep track of the chain playing on `coordinator`, stopping the one it replaces
+	pub fn start(&self, coordinator: &str, chain: tokio::task::AbortHandle) {
+		if let Some(previous) = self
+			.chains
+			.lock()
+			.unwrap()
+			.insert(coordinator.to_owned(), chain)
+		{
+			previous.abort();
+		}
+	}
+
+	/// Stop the chain playing on `coordinator`, if any
+	pub fn cancel(&self, coordinator: &str) {
+		if let Some(chain) = self.chains.lock().unwrap().remove(coordinator) {
+			chain.abort();
+		}
+	}
+
+	pub fn cancel_all(&self) {
+		for (_, chain) in self.chains.lock().unwrap().drain() {
+			chain.abort();
+		}
+	}
+}
+
+/// What a speaker was playing before a track interrupted it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Interruption {
+	pub uri: String,
+	/// Seconds into `uri` playback was at, if it can be resumed from there
+	pub position: Option<u32>,
+	pub was_playing: bool,
+}
+
+impl Interruption {
+	/// What to go back to after interrupting a speaker in `state`, if it was playing anything
+	pub fn from_state(state: &SonosState) -> Option<Self> {
+		let uri = state.track_uri.clone().filter(|u| !u.is_empty())?;
+		// Radio streams have no duration and cannot be sought
+		let seekable = state.duration.is_some_and(|d| d > 0);
+		Some(Self {
+			uri,
+			position: state.position.filter(|_| seekable),
+			was_playing: state.is_playing,
+		})
+	}
+}
+
+/// What should happen to the session of a speaker after its state changed
+#[derive(Debug, PartialEq, Eq)]
+pub enum SessionUpdate {
+	Unchanged,
+	/// The current track ended, this URI should play next
+	Advance(String),
+	/// The last track ended
+	Finished,
+	/// The speaker started playing something else
+	Cancelled,
+}
+
+struct Entry {
+	session: SonosSession,
+	/// Latest state seen for the speaker, and when
+	last_state: Option<(SonosState, Instant)>,
+}
+
+#[derive(Clone, Default)]
+pub struct SessionStore {
+	sessions: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl SessionStore {
+	/// Replace the session of `speaker_id`. The first track is expected to be playing already.
+	pub fn start(&self, speaker_id: &str, tracks: Vec<String>) {
+		let session = SonosSession {
+			speaker_id: speaker_id.to_owned(),
+			tracks,
+			current_index: 0,
+		};
+		self.sessions.lock().unwrap().insert(
+			speaker_id.to_owned(),
+			Entry {
+				session,
+				last_state: None,
+			},
+		);
+	}
+
+	pub fn get(&self, speaker_id: &str) -> Option<SonosSession> {
+		let sessions = self.sessions.lock().unwrap();
+		sessions.get(speaker_id).map(|e| e.session.clone())
+	}
+
+	pub fn remove(&self, speaker_id: &str) {
+		self.sessions.lock().unwrap().remove(speaker_id);
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.sessions.lock().unwrap().is_empty()
+	}
+
+	/// Move the session of `speaker_id` along after the speaker was seen in `state` at `now`
+	pub fn update(&self, speaker_id: &str, state: &SonosState, now: Instant) -> SessionUpdate {
+		let mut sessions = self.sessions.lock().unwrap();
+		let Some(entry) = sessions.get_mut(speaker_id) else {
+			return SessionUpdate::Unchanged;
+		};
+
+		let current = entry.session.current_track().unwrap_or_default();
+		if let Some(track_uri) = &state.track_uri {
+			if !same_uri(track_uri, current) {
+				sessions.remove(speaker_id);
+				return SessionUpdate::Cancelled;
+			}
+		}
+
+		let previous = entry.last_state.replace((state.clone(), now));
+		let stopped = state.playback_state.as_deref() == Some("STOPPED");
+		let ended = previous.is_some_and(|(previous, seen_at)| {
+			previous.is_playing && reached_end(&previous, now.duration_since(seen_at))
+		});
+		if !stopped || !ended {
+			return SessionUpdate::Unchanged;
+		}
+
+		let session = &mut entry.session;
+		session.current_index += 1;
+		entry.last_state = None;
+		match session.current_track() {
+			Some(next) => SessionUpdate::Advance(next.to_owned()),
+			None => {
+				sessions.remove(speaker_id);
+				SessionUpdate::Finished
+			}
+		}
+	}
+}
+
+/// Whether a speaker playing in `state` got to the end of its track after `elapsed`
+fn reached_end(state: &SonosState, elapsed: Duration) -> bool {
+	let (Some(position), Some(duration)) = (state.position, state.duration) else {
+		return false;
+	};
+	let position = Duration::from_secs(position as u64) + elapsed;
+	duration > 0 && position + TRACK_END_MARGIN >= Duration::from_secs(duration as u64)
+}
+
+/// Speakers may report the URI they were given with different percent-encoding
+fn same_uri(a: &str, b: &str) -> bool {
+	let decode = |u: &str| urlencoding::decode(u).map(|d| d.into_owned()).ok();
+	a == b || matches!((decode(a), decode(b)), (Some(a), Some(b)) if a == b)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn state(uri: &str, playback_state: &str, position: u32) -> SonosState {
+		SonosState {
+			is_playing: playback_state == "PLAYING",
+			playback_state: Some(playback_state.to_owned()),
+			position: Some(position),
+			duration: Some(180),
+			track_uri: Some(uri.to_owned()),
+			..Default::default()
+		}
+	}
+
+	fn store() -> SessionStore {
+		let store = SessionStore::default();
+		store.start(
+			"Kitchen",
+			vec![
+				"http://nas/My%20Song.mp3".to_owned(),
+				"http://nas/b.mp3".to_owned(),
+			],
+		);
+		store
+	}
+
+	#[test]
+	fn remembers_interrupted_content() {
+		let interruption = Interruption::from_state(&state("http://nas/a.mp3", "PLAYING", 42));
+		assert_eq!(
+			interruption,
+			Some(Interruption {
+				uri: "http://nas/a.mp3".to_owned(),
+				position: Some(42),
+				was_playing: true,
+			})
+		);
+
+		let radio = SonosState {
+			is_playing: true,
+			track_uri: Some("x-sonosapi-stream:s15200?sid=254".to_owned()),
+			..Default::default()
+		};
+		assert_eq!(Interruption::from_state(&radio).unwrap().position, None);
+		assert_eq!(Interruption::from_state(&SonosState::default()), None);
+	}
+
+	#[test]
+	fn advances_when_track_ends() {
+		let store = store();
+		let start = Instant::now();
+		let update = store.update(
+			"Kitchen",
+			&state("http://nas/My Song.mp3", "PLAYING", 179),
+			start,
+		);
+		assert_eq!(update, SessionUpdate::Unchanged);
+		let update = store.update(
+			"Kitchen",
+			&state("http://nas/My Song.mp3", "STOPPED", 0),
+			start + Duration::from_secs(1),
+		);
+		assert_eq!(
+			update,
+			SessionUpdate::Advance("http://nas/b.mp3".to_owned())
+		);
+		let session = store.get("Kitchen").unwrap();
+		assert_eq!(session.current_index, 1);
+		assert!(session.upcoming().is_empty());
+
+		// Only the playback start is reported when state changes come through the webhook
+		store.update("Kitchen", &state("http://nas/b.mp3", "PLAYING", 0), start);
+		let update = store.update(
+			"Kitchen",
+			&state("http://nas/b.mp3", "STOPPED", 0),
+			start + Duration::from_secs(180),
+		);
+		assert_eq!(update, SessionUpdate::Finished);
+		assert!(store.is_empty());
+	}
+
+	#[test]
+	fn ignores_stops_before_track_end() {
+		let uri = "http://nas/My%20Song.mp3";
+		let start = Instant::now();
+		let later = start + Duration::from_secs(1);
+		for (previous, next) in [
+			(state(uri, "PLAYING", 60), state(uri, "STOPPED", 60)),
+			(
+				state(uri, "PLAYING", 179),
+				state(uri, "PAUSED_PLAYBACK", 179),
+			),
+			(state(uri, "PLAYING", 178), state(uri, "PLAYING", 179)),
+			(state(uri, "PAUSED_PLAYBACK", 179), state(uri, "STOPPED", 0)),
+		] {
+			let store = store();
+			store.update("Kitchen", &previous, start);
+			let update = store.update("Kitchen", &next, later);
+			assert_eq!(update, SessionUpdate::Unchanged);
+			assert_eq!(store.get("Kitchen").unwrap().current_index, 0);
+			assert_eq!(store.get("Kitchen").unwrap().upcoming().len(), 1);
+		}
+	}
+
+	#[test]
+	fn cancels_when_other_content_plays() {
+		let store = store();
+		let update = store.update(
+			"Kitchen",
+			&state("x-sonosapi-stream:s17488?sid=254", "PLAYING", 0),
+			Instant::now(),
+		);
+		assert_eq!(update, SessionUpdate::Cancelled);
+		assert!(store.get("Kitchen").is_none());
+	}
+
+	#[test]
+	fn ignores_speakers_without_session() {
+		let store = store();
+		let update = store.update(
+			"Bedroom",
+			&state("http://nas/c.mp3", "STOPPED", 0),
+			Instant::now(),
+		);
+		assert_eq!(update, SessionUpdate::Unchanged);
+	}
+}
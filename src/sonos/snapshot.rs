@@ -0,0 +1,136 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use super::{SonosError, SonosQueueEntry, SonosState};
+use crate::app::config::PlayMode;
+
+/// What a speaker was playing, saved to disk so that it can be played again later
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlaybackSnapshot {
+	pub speaker_id: String,
+	/// Seconds since the Unix epoch
+	pub taken_at: u64,
+	pub state: SonosState,
+	pub queue: Vec<SonosQueueEntry>,
+	/// Position in the queue of the current track, starting at 1
+	pub track_no: u32,
+	pub play_mode: PlayMode,
+	pub crossfade: bool,
+}
+
+impl PlaybackSnapshot {
+	pub fn age(&self) -> Duration {
+		Duration::from_secs(unix_now().saturating_sub(self.taken_at))
+	}
+
+	/// Position in `queue` of the track being played, if it was played from the queue rather than with `setavtransporturi`
+	pub fn queue_index(&self) -> Option<u32> {
+		let entry = self.queue.get(self.track_no.checked_sub(1)? as usize)?;
+		match &self.state.track_uri {
+			Some(uri) if *uri != entry.uri => None,
+			_ => Some(self.track_no),
+		}
+	}
+}
+
+pub(super) fn unix_now() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or_default()
+}
+
+/// File of `dir` holding the snapshot of `speaker_id`
+fn snapshot_path(dir: &Path, speaker_id: &str) -> PathBuf {
+	dir.join(format!("{}.json", urlencoding::encode(speaker_id)))
+}
+
+/// Save `snapshot` in `dir`, replacing the previous snapshot of the same speaker
+pub async fn write_snapshot(dir: &Path, snapshot: &PlaybackSnapshot) -> Result<(), SonosError> {
+	tokio::fs::create_dir_all(dir)
+		.await
+		.map_err(|e| SonosError::SnapshotIo(dir.to_owned(), e))?;
+	let path = snapshot_path(dir, &snapshot.speaker_id);
+	let data = serde_json::to_vec_pretty(snapshot)
+		.map_err(|e| SonosError::InvalidSnapshot(path.clone(), e))?;
+	tokio::fs::write(&path, data)
+		.await
+		.map_err(|e| SonosError::SnapshotIo(path, e))
+}
+
+pub async fn read_snapshot(dir: &Path, speaker_id: &str) -> Result<PlaybackSnapshot, SonosError> {
+	let path = snapshot_path(dir, speaker_id);
+	match tokio::fs::read(&path).await {
+		Ok(data) => serde_json::from_slice(&data).map_err(|e| SonosError::InvalidSnapshot(path, e)),
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+			Err(SonosError::SnapshotNotFound(speaker_id.to_owned()))
+		}
+		Err(e) => Err(SonosError::SnapshotIo(path, e)),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::test::prepare_test_directory;
+	use crate::test_name;
+
+	fn snapshot(speaker_id: &str) -> PlaybackSnapshot {
+		PlaybackSnapshot {
+			speaker_id: speaker_id.to_owned(),
+			taken_at: unix_now(),
+			state: SonosState {
+				track_uri: Some("x-file-cifs://nas/mp3/2.mp3".to_owned()),
+				..Default::default()
+			},
+			queue: vec![
+				SonosQueueEntry {
+					position: 1,
+					uri: "x-file-cifs://nas/mp3/1.mp3".to_owned(),
+					..Default::default()
+				},
+				SonosQueueEntry {
+					position: 2,
+					uri: "x-file-cifs://nas/mp3/2.mp3".to_owned(),
+					..Default::default()
+				},
+			],
+			track_no: 2,
+			play_mode: PlayMode::RepeatAll,
+			crossfade: true,
+		}
+	}
+
+	#[tokio::test]
+	async fn reads_written_snapshots() {
+		let dir = prepare_test_directory(test_name!()).join("snapshots");
+		let living_room = snapshot("Living Room");
+		write_snapshot(&dir, &living_room).await.unwrap();
+		write_snapshot(&dir, &snapshot("../Kitchen")).await.unwrap();
+
+		assert_eq!(
+			read_snapshot(&dir, "Living Room").await.unwrap(),
+			living_room
+		);
+		assert!(dir.join("..%2FKitchen.json").is_file());
+		assert!(matches!(
+			read_snapshot(&dir, "Bedroom").await,
+			Err(SonosError::SnapshotNotFound(s)) if s == "Bedroom"
+		));
+	}
+
+	#[test]
+	fn finds_current_track_in_queue() {
+		let mut snapshot = snapshot("Kitchen");
+		assert_eq!(snapshot.queue_index(), Some(2));
+
+		snapshot.state.track_uri = Some("x-rincon-mp3radio://radio.example.com".to_owned());
+		assert_eq!(snapshot.queue_index(), None);
+
+		snapshot.state.track_uri = None;
+		snapshot.track_no = 3;
+		assert_eq!(snapshot.queue_index(), None);
+	}
+}
@@ -0,0 +1,62 @@
+/// Parse durations formatted as `MM:SS` or `H:MM:SS` (as reported by Sonos) into seconds
+pub fn parse_hms_to_seconds(s: &str) -> Option<u64> {
+	let parts = s
+		.split(':')
+		.map(|p| p.parse::<u64>().ok())
+		.collect::<Option<Vec<_>>>()?;
+	match parts[..] {
+		[minutes, seconds] => Some(minutes * 60 + seconds),
+		[hours, minutes, seconds] => Some(hours * 3600 + minutes * 60 + seconds),
+		_ => None,
+	}
+}
+
+/// Format a duration in seconds as `H:MM:SS`, which is what Sonos expects when seeking
+pub fn seconds_to_hms(s: u64) -> String {
+	format!("{}:{:02}:{:02}", s / 3600, (s / 60) % 60, s % 60)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn parses_minutes_and_seconds() {
+		assert_eq!(parse_hms_to_seconds("0:00"), Some(0));
+		assert_eq!(parse_hms_to_seconds("2:30"), Some(150));
+	}
+
+	#[test]
+	fn parses_hours_minutes_and_seconds() {
+		assert_eq!(parse_hms_to_seconds("1:00:00"), Some(3600));
+		assert_eq!(parse_hms_to_seconds("0:02:30"), Some(150));
+		assert_eq!(parse_hms_to_seconds("23:59:59"), Some(86399));
+		assert_eq!(parse_hms_to_seconds("100:00:00"), Some(360000));
+	}
+
+	#[test]
+	fn rejects_invalid_input() {
+		assert_eq!(parse_hms_to_seconds(""), None);
+		assert_eq!(parse_hms_to_seconds("42"), None);
+		assert_eq!(parse_hms_to_seconds("1:2:3:4"), None);
+		assert_eq!(parse_hms_to_seconds("a:00"), None);
+		assert_eq!(parse_hms_to_seconds("0:b0:00"), None);
+		assert_eq!(parse_hms_to_seconds("-1:00"), None);
+		assert_eq!(parse_hms_to_seconds("1::00"), None);
+	}
+
+	#[test]
+	fn formats_seconds() {
+		assert_eq!(seconds_to_hms(0), "0:00:00");
+		assert_eq!(seconds_to_hms(150), "0:02:30");
+		assert_eq!(seconds_to_hms(86399), "23:59:59");
+		assert_eq!(seconds_to_hms(360000), "100:00:00");
+	}
+
+	#[test]
+	fn round_trips() {
+		for n in 0..=86400 {
+			assert_eq!(parse_hms_to_seconds(&seconds_to_hms(n)), Some(n));
+		}
+	}
+}
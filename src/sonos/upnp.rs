@@ -0,0 +1,447 @@
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use tokio::net::UdpSocket;
+use tracing::{debug, warn};
+
+use super::dlna::{parse_fields, send_soap_action};
+use super::{DiscoveryCache, SonosSpeaker};
+
+/// Multicast address UPnP devices listen to for SSDP searches
+const SSDP_ADDRESS: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(239, 255, 255, 250), 1900);
+
+/// Device type announced by Sonos speakers
+const ZONE_PLAYER: &str = "urn:schemas-upnp-org:device:ZonePlayer:1";
+
+/// UPnP service through which Sonos speakers describe every room of the system and how they are grouped
+const ZONE_GROUP_TOPOLOGY: &str = "urn:schemas-upnp-org:service:ZoneGroupTopology:1";
+
+/// Control endpoint of the group topology service, relative to the address a device description is served from
+const ZONE_GROUP_TOPOLOGY_PATH: &str = "/ZoneGroupTopology/Control";
+
+/// How long to wait for speakers to answer a search
+const DEFAULT_DISCOVERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Finds Sonos speakers on the local network with SSDP, for when node-sonos-http-api cannot list them.
+/// Speakers found this way cannot be controlled, so they are all reported as unavailable.
+#[derive(Clone)]
+pub struct UPnPDiscovery {
+	client: reqwest::Client,
+	target: SocketAddr,
+	timeout: Duration,
+	cache: Option<(DiscoveryCache, Duration)>,
+}
+
+impl UPnPDiscovery {
+	pub fn new(client: reqwest::Client) -> Self {
+		Self {
+			client,
+			target: SocketAddr::V4(SSDP_ADDRESS),
+			timeout: DEFAULT_DISCOVERY_TIMEOUT,
+			cache: None,
+		}
+	}
+
+	/// Send searches to `target` instead of the SSDP multicast address
+	pub fn with_target(mut self, target: SocketAddr) -> Self {
+		self.target = target;
+		self
+	}
+
+	pub fn with_timeout(mut self, timeout: Duration) -> Self {
+		self.timeout = timeout;
+		self
+	}
+
+	/// Reuse the speakers found by any discovery sharing `cache` for `ttl`
+	pub fn with_cache(mut self, cache: DiscoveryCache, ttl: Duration) -> Self {
+		self.cache = Some((cache, ttl));
+		self
+	}
+
+	/// Every room of the Sonos system with the group it belongs to, sorted by UUID, as reported by the speakers
+	/// which answered a search. If they cannot report groups, only the speakers which answered are listed.
+	/// Speakers found less than the cache TTL ago are reused.
+	pub async fn discover(&self) -> Vec<SonosSpeaker> {
+		if let Some(speakers) = self.cache.as_ref().and_then(|(c, ttl)| c.get(*ttl)) {
+			return speakers;
+		}
+		self.refresh().await
+	}
+
+	/// Search for speakers like `discover`, ignoring previously found speakers
+	pub async fn refresh(&self) -> Vec<SonosSpeaker> {
+		let devices = self.discover_devices().await;
+		let mut topology = None;
+		for (_, location) in &devices {
+			topology = self.read_topology(location).await;
+			if topology.is_some() {
+				break;
+			}
+		}
+
+		let mut speakers = match topology {
+			Some(mut rooms) => {
+				for room in &mut rooms {
+					if let Some((device, _)) = devices.iter().find(|(d, _)| d.id == room.id) {
+						room.model_name = device.model_name.clone();
+						room.firmware_version = device.firmware_version.clone();
+					}
+				}
+				rooms
+			}
+			None => devices.into_iter().map(|(speaker, _)| speaker).collect(),
+		};
+		speakers.sort_by(|a, b| a.id.cmp(&b.id));
+		debug!("Found {} Sonos speakers on the network", speakers.len());
+		if let Some((cache, _)) = &self.cache {
+			cache.set(speakers.clone());
+		}
+		speakers
+	}
+
+	/// Speakers which answered a search like `discover`, along with the URL of their description
+	pub async fn discover_devices(&self) -> Vec<(SonosSpeaker, String)> {
+		let locations = match self.search().await {
+			Ok(locations) => locations,
+			Err(e) => {
+				warn!("Could not search for Sonos speakers with SSDP: {e}");
+				return Vec::new();
+			}
+		};
+
+		let mut speakers = Vec::<(SonosSpeaker, String)>::new();
+		for location in locations {
+			match self.describe(&location).await {
+				Some(speaker) if !speakers.iter().any(|(s, _)| s.id == speaker.id) => {
+					speakers.push((speaker, location))
+				}
+				Some(_) => (),
+				None => debug!("Could not read Sonos device description at `{location}`"),
+			}
+		}
+		speakers.sort_by(|(a, _), (b, _)| a.id.cmp(&b.id));
+		speakers
+	}
+
+	/// Description URLs of the speakers which answered an M-SEARCH before the timeout
+	async fn search(&self) -> std::io::Result<Vec<String>> {
+		let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+		socket.set_multicast_ttl_v4(2)?;
+		socket
+			.send_to(search_request(self.timeout).as_bytes(), self.target)
+			.await?;
+
+		let deadline = tokio::time::Instant::now() + self.timeout;
+		let mut locations = Vec::new();
+		let mut buffer = [0; 2048];
+		while let Ok(received) =
+			tokio::time::timeout_at(deadline, socket.recv_from(&mut buffer)).await
+		{
+			let (size, _) = received?;
+			let response = String::from_utf8_lossy(&buffer[..size]);
+			if let Some(location) = parse_location(&response) {
+				if !locations.contains(&location) {
+					locations.push(location);
+				}
+			}
+		}
+		Ok(locations)
+	}
+
+	async fn describe(&self, location: &str) -> Option<SonosSpeaker> {
+		let response = self.client.get(location).send().await.ok()?;
+		let description = response.error_for_status().ok()?.text().await.ok()?;
+		let mut speaker = parse_description(&description)?;
+		speaker.ip_address = host(location);
+		Some(speaker)
+	}
+
+	/// Rooms of the Sonos system, as reported by the speaker whose description is at `location`
+	async fn read_topology(&self, location: &str) -> Option<Vec<SonosSpeaker>> {
+		let base_url = reqwest::Url::parse(location)
+			.ok()?
+			.origin()
+			.ascii_serialization();
+		let response = send_soap_action(
+			&self.client,
+			location,
+			&format!("{base_url}{ZONE_GROUP_TOPOLOGY_PATH}"),
+			ZONE_GROUP_TOPOLOGY,
+			"GetZoneGroupState",
+			"",
+		)
+		.await
+		.inspect_err(|e| debug!("Could not read Sonos groups from `{location}`: {e}"))
+		.ok()?;
+		let state = parse_fields(&response)?.remove("ZoneGroupState")?;
+		parse_zone_group_state(&state).filter(|rooms| !rooms.is_empty())
+	}
+}
+
+/// Host name or IP address of a URL
+fn host(url: &str) -> Option<String> {
+	reqwest::Url::parse(url).ok()?.host_str().map(str::to_owned)
+}
+
+fn search_request(timeout: Duration) -> String {
+	// Speakers answer after a random delay of up to MX seconds
+	let mx = timeout.as_secs().clamp(1, 5);
+	format!(
+		"M-SEARCH * HTTP/1.1\r\nHOST: {SSDP_ADDRESS}\r\nMAN: \"ssdp:discover\"\r\nMX: {mx}\r\nST: {ZONE_PLAYER}\r\n\r\n"
+	)
+}
+
+/// Value of the `LOCATION` header of an SSDP response about a Sonos speaker
+fn parse_location(response: &str) -> Option<String> {
+	let mut lines = response.lines();
+	if !lines.next()?.contains(" 200 ") {
+		return None;
+	}
+
+	let mut location = None;
+	for line in lines {
+		let Some((name, value)) = line.split_once(':') else {
+			continue;
+		};
+		let value = value.trim();
+		match name.trim().to_ascii_lowercase().as_str() {
+			"location" => location = Some(value.to_owned()),
+			"st" if value != ZONE_PLAYER => return None,
+			_ => (),
+		}
+	}
+	location.filter(|l| !l.is_empty())
+}
+
+/// Speaker described by the device description XML of a Sonos speaker.
+/// Only the root device is read, the nested media server and renderer are ignored.
+fn parse_description(xml: &str) -> Option<SonosSpeaker> {
+	let mut reader = Reader::from_str(xml);
+	let mut stack = Vec::<String>::new();
+	let mut room_name = None;
+	let mut model_name = None;
+	let mut software_version = None;
+	let mut uuid = None;
+	loop {
+		match reader.read_event().ok()? {
+			Event::Start(e) => {
+				stack.push(String::from_utf8_lossy(e.local_name().as_ref()).into_owned());
+			}
+			Event::End(_) => {
+				stack.pop();
+			}
+			Event::Text(t) if stack.len() == 3 && stack[1] == "device" => {
+				let text = t.unescape().ok()?.trim().to_owned();
+				match stack[2].as_str() {
+					"roomName" => room_name = Some(text),
+					"modelName" => model_name = Some(text),
+					"softwareVersion" => software_version = Some(text),
+					"UDN" => uuid = text.strip_prefix("uuid:").map(str::to_owned),
+					_ => (),
+				}
+			}
+			Event::Eof => break,
+			_ => (),
+		}
+	}
+
+	let room_name = room_name.filter(|r| !r.is_empty())?;
+	Some(SonosSpeaker {
+		id: uuid
+			.filter(|u| !u.is_empty())
+			.unwrap_or_else(|| room_name.clone()),
+		name: room_name,
+		available: false,
+		model_name: model_name.filter(|m| !m.is_empty()),
+		firmware_version: software_version.filter(|v| !v.is_empty()),
+		..Default::default()
+	})
+}
+
+/// Rooms listed in the `ZoneGroupState` of a Sonos speaker, each with the coordinator of its group.
+/// Invisible members, such as the second speaker of a stereo pair or the sub of a home theater, are left out.
+fn parse_zone_group_state(xml: &str) -> Option<Vec<SonosSpeaker>> {
+	let mut reader = Reader::from_str(xml);
+	let mut rooms = Vec::new();
+	let mut coordinator = None;
+	loop {
+		match reader.read_event().ok()? {
+			Event::Start(e) | Event::Empty(e) => match e.local_name().as_ref() {
+				b"ZoneGroup" => coordinator = attribute(&e, "Coordinator"),
+				b"ZoneGroupMember" if attribute(&e, "Invisible").as_deref() != Some("1") => {
+					let (Some(uuid), Some(zone_name)) =
+						(attribute(&e, "UUID"), attribute(&e, "ZoneName"))
+					else {
+						continue;
+					};
+					rooms.push(SonosSpeaker {
+						id: uuid,
+						name: zone_name,
+						available: false,
+						ip_address: attribute(&e, "Location").as_deref().and_then(host),
+						group_id: coordinator.clone(),
+						..Default::default()
+					});
+				}
+				_ => (),
+			},
+			Event::Eof => break,
+			_ => (),
+		}
+	}
+	let groups = rooms
+		.iter()
+		.map(|r| (r.group_id.clone(), r.name.clone()))
+		.collect::<Vec<_>>();
+	for room in &mut rooms {
+		room.group_members = groups
+			.iter()
+			.filter(|(group_id, _)| room.group_id.is_some() && *group_id == room.group_id)
+			.map(|(_, name)| name.clone())
+			.collect();
+	}
+	Some(rooms)
+}
+
+fn attribute(element: &BytesStart, name: &str) -> Option<String> {
+	let value = element.try_get_attribute(name).ok()??;
+	let value = value.unescape_value().ok()?;
+	Some(value.into_owned()).filter(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::sonos::mock::{
+		answer_ssdp, MockBridge, DEVICE_DESCRIPTION, ZONE_GROUP_STATE, ZONE_GROUP_TOPOLOGY_PATH,
+	};
+
+	#[test]
+	fn parses_location() {
+		let response = format!(
+			"HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age = 1800\r\nEXT:\r\nLOCATION: http://192.168.0.20:1400/xml/device_description.xml\r\nST: {ZONE_PLAYER}\r\n\r\n"
+		);
+		assert_eq!(
+			parse_location(&response),
+			Some("http://192.168.0.20:1400/xml/device_description.xml".to_owned())
+		);
+		assert_eq!(
+			parse_location("HTTP/1.1 200 OK\r\nLocation: http://nas:8200/rootDesc.xml\r\nST: urn:schemas-upnp-org:device:MediaServer:1\r\n\r\n"),
+			None
+		);
+		assert_eq!(parse_location("NOTIFY * HTTP/1.1\r\n\r\n"), None);
+	}
+
+	#[test]
+	fn parses_description() {
+		let speaker = parse_description(DEVICE_DESCRIPTION).unwrap();
+		assert_eq!(speaker.id, "RINCON_949F3E000001401400");
+		assert_eq!(speaker.name, "Living Room");
+		assert_eq!(speaker.model_name.as_deref(), Some("Sonos One"));
+		assert_eq!(speaker.firmware_version.as_deref(), Some("70.3-35220"));
+		assert!(!speaker.available);
+
+		assert!(parse_description("<root><device></device></root>").is_none());
+		assert!(parse_description("not xml <").is_none());
+	}
+
+	#[tokio::test]
+	async fn discovers_speakers() {
+		let bridge = MockBridge::start().await;
+		let location = format!("{}/xml/device_description.xml", bridge.url);
+		// Speakers may answer the same search more than once
+		let target = answer_ssdp(location, 2).await;
+
+		let discovery = UPnPDiscovery::new(reqwest::Client::new())
+			.with_target(target)
+			.with_timeout(Duration::from_millis(200));
+		let speakers = discovery.discover().await;
+		let ids = speakers.iter().map(|s| s.id.as_str()).collect::<Vec<_>>();
+		assert_eq!(
+			ids,
+			vec![
+				"RINCON_000E58000005401400",
+				"RINCON_5CAAFD000003401400",
+				"RINCON_949F3E000001401400",
+				"RINCON_B8E937000002401400"
+			]
+		);
+		assert!(speakers.iter().all(|s| !s.available));
+		assert_eq!(bridge.count("/xml/device_description.xml"), 1);
+		assert_eq!(bridge.count(ZONE_GROUP_TOPOLOGY_PATH), 1);
+
+		// Only the speaker which answered the search described itself
+		let living_room = &speakers[2];
+		assert_eq!(living_room.model_name.as_deref(), Some("Sonos One"));
+		assert_eq!(living_room.ip_address.as_deref(), Some("192.168.0.20"));
+		assert_eq!(
+			living_room.group_id.as_deref(),
+			Some("RINCON_949F3E000001401400")
+		);
+		let kitchen = &speakers[3];
+		assert_eq!(kitchen.model_name, None);
+		assert_eq!(kitchen.group_id, living_room.group_id);
+		assert_eq!(kitchen.group_members, living_room.group_members);
+		assert!(kitchen.group_members.contains(&"Kitchen".to_owned()));
+	}
+
+	#[tokio::test]
+	async fn reuses_cached_speakers() {
+		let bridge = MockBridge::start().await;
+		let location = format!("{}/xml/device_description.xml", bridge.url);
+		let target = answer_ssdp(location, 1).await;
+
+		let cache = DiscoveryCache::default();
+		let discovery = UPnPDiscovery::new(reqwest::Client::new())
+			.with_target(target)
+			.with_timeout(Duration::from_millis(200))
+			.with_cache(cache.clone(), Duration::from_secs(60));
+		assert_eq!(discovery.discover().await.len(), 4);
+		assert_eq!(discovery.discover().await.len(), 4);
+		assert_eq!(bridge.count(ZONE_GROUP_TOPOLOGY_PATH), 1);
+		assert_eq!(cache.get(Duration::from_secs(60)).unwrap().len(), 4);
+
+		// The mock only answers the first search
+		assert!(discovery.refresh().await.is_empty());
+		assert!(cache.get(Duration::from_secs(60)).unwrap().is_empty());
+	}
+
+	#[test]
+	fn parses_zone_group_state() {
+		let rooms = parse_zone_group_state(ZONE_GROUP_STATE).unwrap();
+		let rooms = rooms
+			.iter()
+			.map(|r| {
+				(
+					r.name.as_str(),
+					r.ip_address.as_deref().unwrap(),
+					r.group_id.as_deref().unwrap(),
+				)
+			})
+			.collect::<Vec<_>>();
+		assert_eq!(
+			rooms,
+			vec![
+				("Living Room", "192.168.0.20", "RINCON_949F3E000001401400"),
+				("Kitchen", "192.168.0.21", "RINCON_949F3E000001401400"),
+				("Office", "192.168.0.22", "RINCON_5CAAFD000003401400"),
+				("Bedroom", "192.168.0.24", "RINCON_000E58000005401400"),
+			]
+		);
+		assert!(parse_zone_group_state("<ZoneGroups></ZoneGroups>")
+			.unwrap()
+			.is_empty());
+	}
+
+	#[tokio::test]
+	async fn discovers_nothing_without_answers() {
+		let target = answer_ssdp(String::new(), 0).await;
+		let discovery = UPnPDiscovery::new(reqwest::Client::new())
+			.with_target(target)
+			.with_timeout(Duration::from_millis(50));
+		assert!(discovery.discover().await.is_empty());
+	}
+}
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tracing::Instrument;
+
+/// Most recent volume requested for a speaker, numbered so callers can tell when their request was handled
+type Request = (u64, u8);
+
+/// Number of the last request handled for a speaker, with the volume which was applied or why it could not be
+type Outcome = (u64, Result<u8, String>);
+
+struct Pending {
+	requested: watch::Sender<Request>,
+	applied: watch::Receiver<Outcome>,
+}
+
+/// Merges volume changes requested in quick succession, such as while a slider is dragged,
+/// so that only the most recent volume within a time window is sent to each speaker.
+#[derive(Clone, Default)]
+pub struct VolumeCoalescer {
+	pending: Arc<Mutex<HashMap<String, Pending>>>,
+}
+
+impl VolumeCoalescer {
+	/// Request a volume change, and wait until the latest volume requested for the speaker has been applied.
+	/// Returns the applied volume, which may come from a more recent request than this one.
+	pub async fn set<F, Fut>(
+		&self,
+		speaker_id: &str,
+		volume: u8,
+		window: Duration,
+		apply: F,
+	) -> Result<u8, String>
+	where
+		F: Fn(u8) -> Fut + Send + 'static,
+		Fut: Future<Output = Result<(), String>> + Send + 'static,
+	{
+		let (number, mut applied) = {
+			let mut pending = self.pending.lock().unwrap();
+			match pending.get(speaker_id) {
+				Some(p) => {
+					let mut number = 0;
+					p.requested.send_modify(|request| {
+						*request = (request.0 + 1, volume);
+						number = request.0;
+					});
+					(number, p.applied.clone())
+				}
+				None => {
+					let (requested, requested_receiver) = watch::channel((1, volume));
+					let (applied_sender, applied) = watch::channel((0, Ok(volume)));
+					pending.insert(
+						speaker_id.to_owned(),
+						Pending {
+							requested,
+							applied: applied.clone(),
+						},
+					);
+					tokio::spawn(
+						apply_latest(
+							self.clone(),
+							speaker_id.to_owned(),
+							window,
+							requested_receiver,
+							applied_sender,
+							apply,
+						)
+						.in_current_span(),
+					);
+					(1, applied)
+				}
+			}
+		};
+
+		match applied.wait_for(|(handled, _)| *handled >= number).await {
+			Ok(outcome) => outcome.1.clone(),
+			Err(_) => Err("Volume change was abandoned".to_owned()),
+		}
+	}
+}
+
+/// Send the latest requested volume once per window, until no new request comes in
+async fn apply_latest<F, Fut>(
+	coalescer: VolumeCoalescer,
+	speaker_id: String,
+	window: Duration,
+	mut requested: watch::Receiver<Request>,
+	applied: watch::Sender<Outcome>,
+	apply: F,
+) where
+	F: Fn(u8) -> Fut,
+	Fut: Future<Output = Result<(), String>>,
+{
+	loop {
+		tokio::time::sleep(window).await;
+		let (number, volume) = *requested.borrow_and_update();
+		let result = apply(volume).await.map(|()| volume);
+		applied.send_replace((number, result));
+
+		// Requests are only added while holding this lock, so none can be missed
+		let mut pending = coalescer.pending.lock().unwrap();
+		if !requested.has_changed().unwrap_or(false) {
+			pending.remove(&speaker_id);
+			return;
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[tokio::test]
+	async fn applies_latest_volume_once() {
+		let coalescer = VolumeCoalescer::default();
+		let sent = Arc::new(Mutex::new(Vec::new()));
+		let set = |volume: u8| {
+			let sent = sent.clone();
+			coalescer.set("Kitchen", volume, Duration::from_millis(50), move |v| {
+				let sent = sent.clone();
+				async move {
+					sent.lock().unwrap().push(v);
+					Ok(())
+				}
+			})
+		};
+
+		let results = tokio::join!(set(10), set(20), set(30));
+		assert_eq!(results, (Ok(30), Ok(30), Ok(30)));
+		assert_eq!(*sent.lock().unwrap(), vec![30]);
+
+		assert_eq!(set(40).await, Ok(40));
+		assert_eq!(*sent.lock().unwrap(), vec![30, 40]);
+	}
+
+	#[tokio::test]
+	async fn reports_failures_to_every_request() {
+		let coalescer = VolumeCoalescer::default();
+		let set = |volume: u8| {
+			coalescer.set("Kitchen", volume, Duration::from_millis(50), |_| async {
+				Err("Speaker not found".to_owned())
+			})
+		};
+		let results = tokio::join!(set(10), set(20));
+		assert_eq!(
+			results,
+			(
+				Err("Speaker not found".to_owned()),
+				Err("Speaker not found".to_owned())
+			)
+		);
+	}
+}
@@ -0,0 +1,316 @@
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use super::{parse_channel_map, SonosSpeaker, SpeakerRole};
+
+/// Group of Sonos speakers playing the same audio
+#[derive(Clone, Debug, Default, Serialize, Deserialize, ToSchema)]
+pub struct SonosZone {
+	/// Speaker which controls playback for the whole group
+	pub coordinator: SonosSpeaker,
+	/// Every speaker of the group, including the coordinator
+	pub members: Vec<SonosSpeaker>,
+	/// Whether the group is made of two speakers playing the left and right channels
+	#[schema(examples(true, false))]
+	pub is_stereo_pair: bool,
+}
+
+impl SonosZone {
+	/// Whether the speaker identified by `speaker_id`, its UUID or room name, is part of the group
+	pub fn has_member(&self, speaker_id: &str) -> bool {
+		let is_speaker = |s: &SonosSpeaker| s.id == speaker_id || s.name == speaker_id;
+		is_speaker(&self.coordinator) || self.members.iter().any(is_speaker)
+	}
+}
+
+/// Room coordinating the group `speaker_id` belongs to
+pub fn find_coordinator<'a>(zones: &'a [SonosZone], speaker_id: &str) -> Option<&'a str> {
+	zones
+		.iter()
+		.find(|z| z.has_member(speaker_id))
+		.map(|z| z.coordinator.name.as_str())
+}
+
+/// Sonos models which run on battery
+const BATTERY_MODELS: &[&str] = &["Roam", "Move"];
+
+/// Model and power details of a speaker, found in `/zones` coordinator and member objects
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct DeviceInfo {
+	model_name: Option<String>,
+	icon: Option<String>,
+	software_version: Option<String>,
+	battery_level: Option<u8>,
+}
+
+impl DeviceInfo {
+	/// Details of a coordinator or member object. Missing or malformed details are left out.
+	pub(super) fn parse(member: &serde_json::Value) -> Self {
+		Self::deserialize(member).unwrap_or_default()
+	}
+
+	pub(super) fn apply(&self, speaker: &mut SonosSpeaker) {
+		speaker.model_name = self.model_name.clone();
+		speaker.icon = self.icon.clone();
+		speaker.firmware_version = self.software_version.clone();
+		speaker.battery_level = self.battery_level;
+		speaker.has_battery = self.battery_level.is_some()
+			|| self
+				.model_name
+				.as_deref()
+				.is_some_and(|m| BATTERY_MODELS.iter().any(|b| m.contains(b)));
+	}
+}
+
+/// Zones as reported by the node-sonos-http-api `/zones` endpoint.
+/// Fields Polaris does not use are ignored.
+#[derive(Debug, Deserialize)]
+pub struct ZoneTopology(Vec<RawZone>);
+
+#[derive(Debug, Deserialize)]
+struct RawZone {
+	coordinator: RawMember,
+	#[serde(default)]
+	members: Vec<RawMember>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawMember {
+	uuid: String,
+	room_name: String,
+	#[serde(default)]
+	state: RawMemberState,
+	channel_map_set: Option<String>,
+	#[serde(flatten)]
+	device: DeviceInfo,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RawMemberState {
+	volume: Option<u8>,
+	mute: Option<bool>,
+}
+
+impl ZoneTopology {
+	pub fn parse(zones: serde_json::Value) -> Result<Self, serde_json::Error> {
+		serde_json::from_value(zones)
+	}
+
+	pub fn zones(&self) -> Vec<SonosZone> {
+		self.0.iter().map(RawZone::to_zone).collect()
+	}
+}
+
+impl RawZone {
+	fn to_zone(&self) -> SonosZone {
+		// node-sonos-http-api lists the coordinator among the members, with more details
+		let coordinator = self
+			.members
+			.iter()
+			.find(|m| m.uuid == self.coordinator.uuid)
+			.unwrap_or(&self.coordinator);
+
+		let mut group_members = vec![coordinator.room_name.clone()];
+		for member in &self.members {
+			if !group_members.contains(&member.room_name) {
+				group_members.push(member.room_name.clone());
+			}
+		}
+
+		let in_group = |member: &RawMember| SonosSpeaker {
+			group_id: Some(coordinator.uuid.clone()),
+			group_members: group_members.clone(),
+			..member.to_speaker()
+		};
+		let mut members = self.members.iter().map(in_group).collect::<Vec<_>>();
+		if members.is_empty() {
+			members.push(in_group(coordinator));
+		}
+
+		SonosZone {
+			coordinator: in_group(coordinator),
+			is_stereo_pair: coordinator.stereo_pair_id().is_some(),
+			members,
+		}
+	}
+}
+
+impl RawMember {
+	fn channel_map(&self) -> Vec<(&str, SpeakerRole)> {
+		self.channel_map_set
+			.as_deref()
+			.map(parse_channel_map)
+			.unwrap_or_default()
+	}
+
+	/// Identifier of the stereo pair this speaker is part of, if any
+	fn stereo_pair_id(&self) -> Option<String> {
+		let channel_map = self.channel_map();
+		let has_role = |role| channel_map.iter().any(|(_, r)| *r == role);
+		if !has_role(SpeakerRole::LeftChannel) || !has_role(SpeakerRole::RightChannel) {
+			return None;
+		}
+		channel_map.iter().map(|(u, _)| *u).min().map(str::to_owned)
+	}
+
+	fn to_speaker(&self) -> SonosSpeaker {
+		let stereo_pair_id = self.stereo_pair_id();
+		let mut speaker = SonosSpeaker {
+			id: self.uuid.clone(),
+			name: self.room_name.clone(),
+			available: true,
+			volume: self.state.volume,
+			muted: self.state.mute,
+			role: self
+				.channel_map()
+				.iter()
+				.find(|(u, _)| *u == self.uuid)
+				.map(|(_, role)| *role)
+				.unwrap_or_default(),
+			is_stereo_pair: stereo_pair_id.is_some(),
+			stereo_pair_id,
+			..Default::default()
+		};
+		self.device.apply(&mut speaker);
+		speaker
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::sonos::parse_zones;
+
+	fn fixture() -> ZoneTopology {
+		let payload = std::fs::read_to_string("test-data/sonos/zones.json").unwrap();
+		ZoneTopology::parse(serde_json::from_str(&payload).unwrap()).unwrap()
+	}
+
+	fn names(speakers: &[SonosSpeaker]) -> Vec<&str> {
+		speakers.iter().map(|s| s.name.as_str()).collect()
+	}
+
+	#[test]
+	fn parses_groups() {
+		let zones = fixture().zones();
+		assert_eq!(zones.len(), 3);
+
+		let living_room = &zones[0];
+		assert_eq!(living_room.coordinator.id, "RINCON_949F3E000001401400");
+		assert_eq!(living_room.coordinator.name, "Living Room");
+		assert_eq!(names(&living_room.members), vec!["Living Room", "Kitchen"]);
+		assert_eq!(living_room.members[1].id, "RINCON_B8E937000002401400");
+		assert_eq!(living_room.members[1].volume, Some(27));
+		assert_eq!(living_room.members[1].muted, Some(true));
+		assert!(!living_room.is_stereo_pair);
+
+		let bedroom = &zones[2];
+		assert_eq!(bedroom.coordinator.id, "RINCON_000E58000005401400");
+		assert_eq!(names(&bedroom.members), vec!["Bedroom"]);
+		assert_eq!(bedroom.coordinator.role, SpeakerRole::Standalone);
+	}
+
+	#[test]
+	fn parses_stereo_pairs() {
+		let zones = fixture().zones();
+		let office = &zones[1];
+		assert!(office.is_stereo_pair);
+		assert_eq!(office.coordinator.role, SpeakerRole::LeftChannel);
+		assert_eq!(
+			office.coordinator.stereo_pair_id.as_deref(),
+			Some("RINCON_5CAAFD000003401400")
+		);
+	}
+
+	#[test]
+	fn members_know_their_group() {
+		let zones = fixture().zones();
+		let living_room = &zones[0];
+		assert_eq!(
+			living_room.coordinator.group_id.as_deref(),
+			Some("RINCON_949F3E000001401400")
+		);
+		assert!(living_room
+			.members
+			.iter()
+			.all(|m| m.group_id == living_room.coordinator.group_id));
+		assert!(living_room
+			.members
+			.iter()
+			.all(|m| m.group_members == ["Living Room", "Kitchen"]));
+		assert_eq!(zones[1].coordinator.group_members, ["Office"]);
+		assert_eq!(
+			zones[2].members[0].group_id.as_deref(),
+			Some("RINCON_000E58000005401400")
+		);
+	}
+
+	#[test]
+	fn parses_device_metadata() {
+		let zones = fixture().zones();
+
+		let beam = &zones[0].coordinator;
+		assert_eq!(beam.model_name.as_deref(), Some("Sonos Beam"));
+		assert_eq!(beam.icon.as_deref(), Some("x-rincon-roomicon:tvroom"));
+		assert_eq!(beam.firmware_version.as_deref(), Some("70.3-35220"));
+		assert!(!beam.is_stereo_pair);
+		assert!(!beam.has_battery);
+
+		let roam = &zones[0].members[1];
+		assert_eq!(roam.model_name.as_deref(), Some("Sonos Roam"));
+		assert!(roam.has_battery);
+		assert_eq!(roam.battery_level, Some(64));
+
+		let one = &zones[1].coordinator;
+		assert_eq!(one.model_name.as_deref(), Some("Sonos One"));
+		assert!(one.is_stereo_pair);
+		assert!(!one.has_battery);
+		assert_eq!(one.battery_level, None);
+
+		// Older bridges do not report any of this
+		let bedroom = &zones[2].coordinator;
+		assert_eq!(bedroom.model_name, None);
+		assert_eq!(bedroom.icon, None);
+		assert_eq!(bedroom.firmware_version, None);
+		assert!(!bedroom.has_battery);
+	}
+
+	#[test]
+	fn speaker_list_includes_device_metadata() {
+		let payload = std::fs::read_to_string("test-data/sonos/zones.json").unwrap();
+		let speakers = parse_zones(&serde_json::from_str(&payload).unwrap());
+		assert_eq!(speakers[0].model_name.as_deref(), Some("Sonos Beam"));
+		assert!(speakers[1].is_stereo_pair);
+		assert_eq!(
+			speakers[1].icon.as_deref(),
+			Some("x-rincon-roomicon:office")
+		);
+	}
+
+	#[test]
+	fn tolerates_missing_members() {
+		let topology = ZoneTopology::parse(serde_json::json!([
+			{ "coordinator": { "uuid": "RINCON_A", "roomName": "Kitchen" } }
+		]))
+		.unwrap();
+		let zones = topology.zones();
+		assert_eq!(names(&zones[0].members), vec!["Kitchen"]);
+	}
+
+	#[test]
+	fn finds_coordinators_by_uuid_or_room_name() {
+		let zones = fixture().zones();
+		for speaker in ["Kitchen", "RINCON_B8E937000002401400"] {
+			assert_eq!(find_coordinator(&zones, speaker), Some("Living Room"));
+		}
+		assert_eq!(find_coordinator(&zones, "Garage"), None);
+	}
+
+	#[test]
+	fn rejects_malformed_payloads() {
+		assert!(ZoneTopology::parse(serde_json::json!({ "zones": [] })).is_err());
+		assert!(ZoneTopology::parse(serde_json::json!([{ "coordinator": {} }])).is_err());
+	}
+}